@@ -0,0 +1,604 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+use std::collections::BTreeMap;
+use axum::{Extension, Json, Router};
+use axum::extract::{Path, Query, State};
+use axum::http::{header, Request, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, get, post, put};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use crate::{audit, check_json_var, events, handler, maintenance, signing, spam, ResponseData, ResponseStatus};
+use crate::audit::{AuditEntry, AuditQuery};
+use crate::events::SubmissionEvent;
+use crate::service::AppState;
+use crate::store::{self, DailyCount, DeliveryStatus, SearchQuery, Submission, SubmissionStore};
+use crate::senders::{self, ListKind, SenderLists};
+use crate::suppression::{self, Suppression, SuppressionReason};
+
+lazy_static!(
+    /// Token for each admin, by name, so the audit log can record who did what. `ADMIN_TOKEN` is
+    /// named `admin`. An empty token is treated as not set, as it would match an empty `Bearer`.
+    static ref TOKENS: Vec<(String, String)> = {
+        let mut tokens: Vec<(String, String)> = std::env::var("ADMIN_TOKENS")
+            .ok()
+            .and_then(|tokens| serde_json::from_str::<BTreeMap<String, String>>(&tokens).ok())
+            .unwrap_or_default()
+            .into_iter()
+            .collect();
+        if let Ok(token) = std::env::var("ADMIN_TOKEN") {
+            tokens.push(("admin".to_string(), token));
+        }
+        tokens.retain(|(_, token)| !token.trim().is_empty());
+        tokens
+    };
+);
+
+/// The name of the admin making the request
+#[derive(Clone)]
+struct Operator(String);
+
+pub fn enabled() -> bool {
+    !TOKENS.is_empty()
+}
+
+/// Before anything asks whether the admin API is enabled
+pub fn init() -> Result<(), String> {
+    check_json_var::<BTreeMap<String, String>>("ADMIN_TOKENS", "a JSON object of names to tokens")?;
+    Ok(())
+}
+
+#[derive(Serialize, ToSchema)]
+struct SubmissionList {
+    total: usize,
+    submissions: Vec<Submission>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct Stats {
+    total: usize,
+    by_status: BTreeMap<DeliveryStatus, usize>,
+    per_day: Vec<DailyCount>,
+}
+
+/// The admin API is only mounted when an admin token is configured, which also guarantees that the
+/// store is enabled.
+fn store() -> &'static SubmissionStore {
+    store::get().expect("the admin API requires the submission store")
+}
+
+/// Compares in constant time so the token can't be guessed a byte at a time via response timings
+pub(crate) fn tokens_match(expected: &str, provided: &str) -> bool {
+    expected.len() == provided.len()
+        && expected.bytes().zip(provided.bytes()).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+async fn require_token<B>(mut req: Request<B>, next: Next<B>) -> Response {
+    let provided = req.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    let operator = provided.and_then(|provided| TOKENS.iter().find(|(_, expected)| tokens_match(expected, provided)));
+    match operator {
+        Some((name, _)) => {
+            req.extensions_mut().insert(Operator(name.clone()));
+            next.run(req).await
+        }
+        None => (StatusCode::UNAUTHORIZED, Json(ResponseData { status: ResponseStatus::Unauthorized, message: Some("missing or invalid admin token".to_string()), errors: None, retry_after: None })).into_response(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/submissions",
+    tag = "admin",
+    params(SearchQuery),
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Matching submissions, newest first", body = SubmissionList),
+        (status = 401, description = "Missing or invalid admin token", body = ResponseData),
+    ),
+)]
+async fn list_submissions(Query(query): Query<SearchQuery>) -> Json<SubmissionList> {
+    let (total, submissions) = store().search(&query);
+    Json(SubmissionList { total, submissions })
+}
+
+#[derive(Deserialize, IntoParams)]
+struct EraseQuery {
+    /// Every submission from this address is deleted
+    email: String,
+}
+
+#[derive(Serialize, ToSchema)]
+struct Erased {
+    deleted: usize,
+}
+
+/// For right-to-erasure requests
+#[utoipa::path(
+    delete,
+    path = "/admin/submissions",
+    tag = "admin",
+    params(EraseQuery),
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "How many submissions were deleted", body = Erased),
+        (status = 401, description = "Missing or invalid admin token", body = ResponseData),
+    ),
+)]
+async fn erase_submissions(Extension(Operator(operator)): Extension<Operator>, Query(query): Query<EraseQuery>) -> Json<Erased> {
+    let deleted = store().erase(&query.email);
+    // Not the address, or the audit log would undo the erasure
+    audit::record(&operator, "submissions.erased", None, Some(format!("{} submission(s)", deleted)));
+    Json(Erased { deleted })
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/submissions/{id}",
+    tag = "admin",
+    params(("id" = String, Path, description = "Submission ID")),
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "The submission", body = Submission),
+        (status = 401, description = "Missing or invalid admin token", body = ResponseData),
+        (status = 404, description = "No such submission", body = ResponseData),
+    ),
+)]
+async fn get_submission(Path(id): Path<String>) -> Response {
+    match store().get(&id) {
+        Some(submission) => Json(submission).into_response(),
+        None => (StatusCode::NOT_FOUND, Json(ResponseData { status: ResponseStatus::NotFound, message: Some(format!("no submission with id {}", id)), errors: None, retry_after: None })).into_response(),
+    }
+}
+
+fn mark(operator: &str, id: &str, spam: bool) -> Response {
+    let classifier = match spam::classifier() {
+        Some(classifier) => classifier,
+        None => return (StatusCode::NOT_FOUND, Json(ResponseData { status: ResponseStatus::NotFound, message: Some("the spam classifier isn't enabled".to_string()), errors: None, retry_after: None })).into_response(),
+    };
+    match store().get(id) {
+        Some(submission) => {
+            classifier.train(&submission, spam);
+            audit::record(operator, if spam { "submission.marked_spam" } else { "submission.marked_ham" }, Some(id), None);
+            StatusCode::NO_CONTENT.into_response()
+        }
+        None => (StatusCode::NOT_FOUND, Json(ResponseData { status: ResponseStatus::NotFound, message: Some(format!("no submission with id {}", id)), errors: None, retry_after: None })).into_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/submissions/{id}/mark-spam",
+    tag = "admin",
+    params(("id" = String, Path, description = "Submission ID")),
+    security(("admin_token" = [])),
+    responses(
+        (status = 204, description = "The spam classifier has learned from the submission"),
+        (status = 401, description = "Missing or invalid admin token", body = ResponseData),
+        (status = 404, description = "No such submission, or the spam classifier isn't enabled", body = ResponseData),
+    ),
+)]
+async fn mark_spam(Extension(Operator(operator)): Extension<Operator>, Path(id): Path<String>) -> Response {
+    mark(&operator, &id, true)
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/submissions/{id}/mark-ham",
+    tag = "admin",
+    params(("id" = String, Path, description = "Submission ID")),
+    security(("admin_token" = [])),
+    responses(
+        (status = 204, description = "The spam classifier has learned from the submission"),
+        (status = 401, description = "Missing or invalid admin token", body = ResponseData),
+        (status = 404, description = "No such submission, or the spam classifier isn't enabled", body = ResponseData),
+    ),
+)]
+async fn mark_ham(Extension(Operator(operator)): Extension<Operator>, Path(id): Path<String>) -> Response {
+    mark(&operator, &id, false)
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/stats",
+    tag = "admin",
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Submission counts by delivery status, and per day", body = Stats),
+        (status = 401, description = "Missing or invalid admin token", body = ResponseData),
+    ),
+)]
+async fn stats() -> Json<Stats> {
+    let per_day = store().daily_counts();
+    let mut by_status = BTreeMap::new();
+    for day in per_day.iter() {
+        for (status, count) in day.by_status.iter() {
+            *by_status.entry(*status).or_insert(0) += count;
+        }
+    }
+    Json(Stats { total: per_day.iter().map(|day| day.total).sum(), by_status, per_day })
+}
+
+fn not_quarantined(id: &str) -> Response {
+    (StatusCode::NOT_FOUND, Json(ResponseData { status: ResponseStatus::NotFound, message: Some(format!("no quarantined submission with id {}", id)), errors: None, retry_after: None })).into_response()
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/quarantine",
+    tag = "admin",
+    params(SearchQuery),
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Matching quarantined submissions, newest first, with why each was quarantined as its `status_message`", body = SubmissionList),
+        (status = 401, description = "Missing or invalid admin token", body = ResponseData),
+    ),
+)]
+async fn list_quarantine(Query(mut query): Query<SearchQuery>) -> Json<SubmissionList> {
+    query.status = Some(DeliveryStatus::Quarantined);
+    list_submissions(Query(query)).await
+}
+
+#[utoipa::path(
+    delete,
+    path = "/admin/quarantine",
+    tag = "admin",
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "How many quarantined submissions were deleted", body = Erased),
+        (status = 401, description = "Missing or invalid admin token", body = ResponseData),
+    ),
+)]
+async fn purge_quarantine(Extension(Operator(operator)): Extension<Operator>) -> Json<Erased> {
+    let deleted = store().remove(DeliveryStatus::Quarantined, None);
+    audit::record(&operator, "quarantine.purged", None, Some(format!("{} submission(s)", deleted)));
+    Json(Erased { deleted })
+}
+
+/// Sends the submission as if it had never been quarantined. If it was quarantined by the spam
+/// classifier, it's worth marking it as ham too, so the classifier learns from its mistake.
+#[utoipa::path(
+    post,
+    path = "/admin/quarantine/{id}/release",
+    tag = "admin",
+    params(("id" = String, Path, description = "Submission ID")),
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "The submission, with the outcome of sending it", body = Submission),
+        (status = 401, description = "Missing or invalid admin token", body = ResponseData),
+        (status = 404, description = "No such submission, or it isn't quarantined", body = ResponseData),
+    ),
+)]
+async fn release_submission(State(state): State<AppState>, Extension(Operator(operator)): Extension<Operator>, Path(id): Path<String>) -> Response {
+    let submission = match store().get(&id).filter(|s| s.status == DeliveryStatus::Quarantined) {
+        Some(submission) => submission,
+        None => return not_quarantined(&id),
+    };
+    // Left as it is if the submission's queued (e.g. for a digest), so mark it as on its way first
+    store().update_status(&id, DeliveryStatus::Pending, None, &operator);
+    audit::record(&operator, "submission.released", Some(&id), None);
+    handler::process(&state, &submission, &operator).await;
+    match store().get(&id) {
+        Some(submission) => Json(submission).into_response(),
+        // Only if it's been erased in the meantime
+        None => not_quarantined(&id),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/admin/quarantine/{id}",
+    tag = "admin",
+    params(("id" = String, Path, description = "Submission ID")),
+    security(("admin_token" = [])),
+    responses(
+        (status = 204, description = "The submission was deleted"),
+        (status = 401, description = "Missing or invalid admin token", body = ResponseData),
+        (status = 404, description = "No such submission, or it isn't quarantined", body = ResponseData),
+    ),
+)]
+async fn purge_submission(Extension(Operator(operator)): Extension<Operator>, Path(id): Path<String>) -> Response {
+    match store().remove(DeliveryStatus::Quarantined, Some(&id)) {
+        0 => not_quarantined(&id),
+        _ => {
+            audit::record(&operator, "submission.purged", Some(&id), None);
+            StatusCode::NO_CONTENT.into_response()
+        }
+    }
+}
+
+#[derive(Default, Deserialize, ToSchema)]
+struct NewSuppression {
+    note: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/suppressions",
+    tag = "admin",
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Every address mail won't be sent to", body = [Suppression]),
+        (status = 401, description = "Missing or invalid admin token", body = ResponseData),
+    ),
+)]
+async fn list_suppressions() -> Json<Vec<Suppression>> {
+    Json(suppression::suppressions().list())
+}
+
+#[utoipa::path(
+    put,
+    path = "/admin/suppressions/{address}",
+    tag = "admin",
+    params(("address" = String, Path, description = "Email address")),
+    request_body(content = Option<NewSuppression>, description = "An optional note on why the address was suppressed"),
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "The address was added (or its entry replaced)", body = Suppression),
+        (status = 401, description = "Missing or invalid admin token", body = ResponseData),
+    ),
+)]
+async fn add_suppression(Extension(Operator(operator)): Extension<Operator>, Path(address): Path<String>, body: Option<Json<NewSuppression>>) -> Json<Suppression> {
+    let note = body.map(|Json(body)| body).unwrap_or_default().note;
+    audit::record(&operator, "suppression.added", None, Some(address.clone()));
+    Json(suppression::suppressions().add(&address, SuppressionReason::Manual, note))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/admin/suppressions/{address}",
+    tag = "admin",
+    params(("address" = String, Path, description = "Email address")),
+    security(("admin_token" = [])),
+    responses(
+        (status = 204, description = "Mail will be sent to the address again"),
+        (status = 401, description = "Missing or invalid admin token", body = ResponseData),
+        (status = 404, description = "The address wasn't suppressed", body = ResponseData),
+    ),
+)]
+async fn remove_suppression(Extension(Operator(operator)): Extension<Operator>, Path(address): Path<String>) -> Response {
+    match suppression::suppressions().remove(&address) {
+        true => {
+            audit::record(&operator, "suppression.removed", None, Some(address));
+            StatusCode::NO_CONTENT.into_response()
+        }
+        false => (StatusCode::NOT_FOUND, Json(ResponseData { status: ResponseStatus::NotFound, message: Some(format!("{} isn't suppressed", address)), errors: None, retry_after: None })).into_response(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/senders",
+    tag = "admin",
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "The addresses and domains submissions are only accepted from, and those they're never accepted from", body = SenderLists),
+        (status = 401, description = "Missing or invalid admin token", body = ResponseData),
+    ),
+)]
+async fn list_senders() -> Json<SenderLists> {
+    Json(senders::senders().get())
+}
+
+#[utoipa::path(
+    put,
+    path = "/admin/senders/{list}/{entry}",
+    tag = "admin",
+    params(
+        ("list" = ListKind, Path, description = "`allow` or `block`"),
+        ("entry" = String, Path, description = "Email address, or domain (which covers its subdomains too)"),
+    ),
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "The entry was added (if it wasn't already there)", body = SenderLists),
+        (status = 401, description = "Missing or invalid admin token", body = ResponseData),
+    ),
+)]
+async fn add_sender(Extension(Operator(operator)): Extension<Operator>, Path((list, entry)): Path<(ListKind, String)>) -> Json<SenderLists> {
+    if senders::senders().add(list, &entry) {
+        audit::record(&operator, if list == ListKind::Allow { "sender.allowed" } else { "sender.blocked" }, None, Some(entry));
+    }
+    Json(senders::senders().get())
+}
+
+#[utoipa::path(
+    delete,
+    path = "/admin/senders/{list}/{entry}",
+    tag = "admin",
+    params(
+        ("list" = ListKind, Path, description = "`allow` or `block`"),
+        ("entry" = String, Path, description = "Email address or domain"),
+    ),
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "The entry was removed", body = SenderLists),
+        (status = 401, description = "Missing or invalid admin token", body = ResponseData),
+        (status = 404, description = "The entry isn't on the list", body = ResponseData),
+    ),
+)]
+async fn remove_sender(Extension(Operator(operator)): Extension<Operator>, Path((list, entry)): Path<(ListKind, String)>) -> Response {
+    match senders::senders().remove(list, &entry) {
+        true => {
+            audit::record(&operator, if list == ListKind::Allow { "sender.unallowed" } else { "sender.unblocked" }, None, Some(entry));
+            Json(senders::senders().get()).into_response()
+        }
+        false => {
+            let name = if list == ListKind::Allow { "allow" } else { "block" };
+            (StatusCode::NOT_FOUND, Json(ResponseData { status: ResponseStatus::NotFound, message: Some(format!("{} isn't on the {} list", entry, name)), errors: None, retry_after: None })).into_response()
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+struct Recipient {
+    name: String,
+    address: String,
+    /// To use as the value of a form's `_to` field
+    token: String,
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/recipients",
+    tag = "admin",
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Every recipient in `RECIPIENTS`, with its token", body = [Recipient]),
+        (status = 401, description = "Missing or invalid admin token", body = ResponseData),
+    ),
+)]
+async fn list_recipients() -> Json<Vec<Recipient>> {
+    Json(signing::RECIPIENTS.iter()
+        // Recipients are only accepted with a signing secret, so there's always a token
+        .filter_map(|(name, address)| Some(Recipient { name: name.clone(), address: address.clone(), token: signing::recipient_token(name)? }))
+        .collect())
+}
+
+#[derive(Serialize, ToSchema)]
+struct Maintenance {
+    enabled: bool,
+    /// How many submissions are waiting to be sent once maintenance is over
+    held: usize,
+}
+
+fn maintenance_status() -> Json<Maintenance> {
+    Json(Maintenance { enabled: maintenance::active(), held: maintenance::held() })
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/maintenance",
+    tag = "admin",
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Whether maintenance mode is on", body = Maintenance),
+        (status = 401, description = "Missing or invalid admin token", body = ResponseData),
+    ),
+)]
+async fn get_maintenance() -> Json<Maintenance> {
+    maintenance_status()
+}
+
+#[utoipa::path(
+    put,
+    path = "/admin/maintenance",
+    tag = "admin",
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Maintenance mode is on", body = Maintenance),
+        (status = 401, description = "Missing or invalid admin token", body = ResponseData),
+    ),
+)]
+async fn start_maintenance(Extension(Operator(operator)): Extension<Operator>) -> Json<Maintenance> {
+    maintenance::start();
+    audit::record(&operator, "maintenance.started", None, None);
+    maintenance_status()
+}
+
+/// Any submissions held during maintenance are sent in the background
+#[utoipa::path(
+    delete,
+    path = "/admin/maintenance",
+    tag = "admin",
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Maintenance mode is off", body = Maintenance),
+        (status = 401, description = "Missing or invalid admin token", body = ResponseData),
+    ),
+)]
+async fn end_maintenance(Extension(Operator(operator)): Extension<Operator>) -> Json<Maintenance> {
+    let held = maintenance::held();
+    maintenance::end();
+    audit::record(&operator, "maintenance.ended", None, Some(format!("{} held submission(s) released", held)));
+    maintenance_status()
+}
+
+/// Only events from after the request are sent - use `GET /admin/submissions` to catch up first
+#[utoipa::path(
+    get,
+    path = "/admin/events",
+    tag = "admin",
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "A stream of `received` and `status_changed` events as they happen, each with a JSON `SubmissionEvent` as its data", content_type = "text/event-stream", body = SubmissionEvent),
+        (status = 401, description = "Missing or invalid admin token", body = ResponseData),
+    ),
+)]
+async fn event_stream() -> Response {
+    events::stream().into_response()
+}
+
+#[derive(Serialize, ToSchema)]
+struct AuditList {
+    total: usize,
+    entries: Vec<AuditEntry>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/audit",
+    tag = "admin",
+    params(AuditQuery),
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Matching audit log entries, newest first", body = AuditList),
+        (status = 401, description = "Missing or invalid admin token", body = ResponseData),
+    ),
+)]
+async fn audit_log(Query(query): Query<AuditQuery>) -> Json<AuditList> {
+    let log = audit::get().expect("the admin API guarantees the audit log is kept");
+    let (total, entries) = log.search(&query);
+    Json(AuditList { total, entries })
+}
+
+pub fn router(state: AppState) -> Router {
+    Router::new()
+        .route("/submissions", get(list_submissions).delete(erase_submissions))
+        .route("/submissions/:id", get(get_submission))
+        .route("/submissions/:id/mark-spam", post(mark_spam))
+        .route("/submissions/:id/mark-ham", post(mark_ham))
+        .route("/stats", get(stats))
+        .route("/audit", get(audit_log))
+        .route("/events", get(event_stream))
+        .route("/suppressions", get(list_suppressions))
+        .route("/suppressions/:address", put(add_suppression).delete(remove_suppression))
+        .route("/senders", get(list_senders))
+        .route("/senders/:list/:entry", put(add_sender).delete(remove_sender))
+        .route("/recipients", get(list_recipients))
+        .route("/quarantine", get(list_quarantine).delete(purge_quarantine))
+        .route("/quarantine/:id", delete(purge_submission))
+        .route("/quarantine/:id/release", post(release_submission))
+        .route("/maintenance", get(get_maintenance).put(start_maintenance).delete(end_maintenance))
+        .route_layer(middleware::from_fn(require_token))
+        .with_state(state)
+}
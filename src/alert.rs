@@ -0,0 +1,143 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use lazy_static::lazy_static;
+use log::{error, info, warn};
+use serde::Serialize;
+use crate::{check_var, CLIENT};
+
+lazy_static!(
+    /// How many failures within the window trigger an alert. Alerting is off if this isn't set.
+    static ref THRESHOLD: Option<usize> = std::env::var("ALERT_FAILURE_THRESHOLD").ok()
+        .and_then(|threshold| threshold.trim().parse().ok());
+    static ref WINDOW: Duration = Duration::from_secs(std::env::var("ALERT_WINDOW_SECS").ok()
+        .and_then(|secs| secs.trim().parse().ok())
+        .unwrap_or(DEFAULT_WINDOW_SECS));
+    static ref WEBHOOK_URL: Option<String> = std::env::var("ALERT_WEBHOOK_URL").ok();
+    static ref SLACK_WEBHOOK_URL: Option<String> = std::env::var("ALERT_SLACK_WEBHOOK_URL").ok();
+    static ref STATE: Mutex<AlertState> = Mutex::new(AlertState::default());
+);
+
+const DEFAULT_WINDOW_SECS: u64 = 3600;
+
+#[derive(Default)]
+struct AlertState {
+    /// When each failure within the window happened, oldest first
+    failures: VecDeque<Instant>,
+    last_alert: Option<Instant>,
+}
+
+#[derive(Serialize)]
+struct AlertPayload<'a> {
+    event: &'static str,
+    failures: usize,
+    window_secs: u64,
+    last_error: &'a str,
+}
+
+#[derive(Serialize)]
+struct SlackMessage {
+    text: String,
+}
+
+/// Checks that there's somewhere to send alerts, if they're enabled
+pub fn init() -> Result<(), String> {
+    if check_var::<usize>("ALERT_FAILURE_THRESHOLD", "a positive number")? == Some(0) {
+        return Err("\"ALERT_FAILURE_THRESHOLD\" must be at least 1".to_string());
+    }
+    check_var::<u64>("ALERT_WINDOW_SECS", "a number of seconds")?;
+    let threshold = match *THRESHOLD {
+        Some(threshold) => threshold,
+        None => return Ok(()),
+    };
+    if WEBHOOK_URL.is_none() && SLACK_WEBHOOK_URL.is_none() {
+        return Err("\"ALERT_FAILURE_THRESHOLD\" is set, but neither \"ALERT_WEBHOOK_URL\" nor \"ALERT_SLACK_WEBHOOK_URL\" is, so there's nowhere to send alerts".to_string());
+    }
+    info!("Will alert after {} delivery failure(s) within {}s", threshold, WINDOW.as_secs());
+    Ok(())
+}
+
+/// Records a failed delivery, alerting if that takes the failures over the threshold. Only one
+/// alert is sent per window, however many failures there are.
+pub fn failure(reason: &str) {
+    let threshold = match *THRESHOLD {
+        Some(threshold) => threshold,
+        None => return,
+    };
+    let now = Instant::now();
+    let failures = {
+        let mut state = STATE.lock().unwrap();
+        state.failures.push_back(now);
+        while state.failures.front().map(|failure| now.duration_since(*failure) > *WINDOW).unwrap_or(false) {
+            state.failures.pop_front();
+        }
+        let recently_alerted = state.last_alert.map(|alerted| now.duration_since(alerted) < *WINDOW).unwrap_or(false);
+        if state.failures.len() < threshold || recently_alerted {
+            return;
+        }
+        state.last_alert = Some(now);
+        state.failures.len()
+    };
+    warn!("{} delivery failure(s) in the last {}s, sending an alert", failures, WINDOW.as_secs());
+    let reason = reason.to_string();
+    tokio::spawn(async move {
+        if let Err(e) = send(failures, &reason).await {
+            error!("Unable to send alert: {}", e);
+        }
+    });
+}
+
+async fn send(failures: usize, reason: &str) -> Result<(), String> {
+    if let Some(url) = WEBHOOK_URL.as_deref() {
+        let payload = AlertPayload { event: "delivery.failures", failures, window_secs: WINDOW.as_secs(), last_error: reason };
+        post(url, &payload).await?;
+    }
+    if let Some(url) = SLACK_WEBHOOK_URL.as_deref() {
+        let message = SlackMessage {
+            text: format!("*Contact form deliveries are failing*\n{} failure(s) in the last {}s. The last error was:\n>>> {}", failures, WINDOW.as_secs(), reason),
+        };
+        post(url, &message).await?;
+    }
+    Ok(())
+}
+
+async fn post(url: &str, body: &impl Serialize) -> Result<(), String> {
+    let response = CLIENT.post(url)
+        .json(body)
+        .send()
+        .await
+        .map_err(|e| format!("Error calling {}: {}", url, e))?;
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        Err(format!("{} returned {}", url, response.status()))
+    }
+}
@@ -0,0 +1,115 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+use std::collections::HashMap;
+use axum::http::HeaderMap;
+use lazy_static::lazy_static;
+use log::info;
+use serde::Deserialize;
+use crate::check_json_var;
+use crate::csrf::request_origin;
+use crate::ratelimit::{Quota, RateLimiter};
+
+pub const HEADER: &str = "x-api-key";
+
+#[derive(Deserialize)]
+pub struct ApiKey {
+    /// The origins the key may be used from. Any origin if empty.
+    #[serde(default)]
+    origins: Vec<String>,
+    /// How many submissions may be made with the key per day (UTC)
+    daily_quota: Option<u32>,
+    /// How many submissions may be made with the key per minute
+    per_minute: Option<u32>,
+}
+
+lazy_static!(
+    /// The configured keys, if they're required
+    static ref KEYS: Option<HashMap<String, ApiKey>> = std::env::var("API_KEYS").ok()
+        .and_then(|json| serde_json::from_str(&json).ok());
+    static ref DAILY: RateLimiter = RateLimiter::new(24 * 60 * 60);
+    static ref PER_MINUTE: RateLimiter = RateLimiter::new(60);
+);
+
+pub enum KeyError {
+    Missing,
+    Unknown,
+    WrongOrigin,
+    /// Holds the limit that was hit, and when it resets
+    RateLimited(Quota),
+    QuotaExceeded(Quota),
+}
+
+impl KeyError {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            KeyError::Missing => "missing",
+            KeyError::Unknown => "unknown",
+            KeyError::WrongOrigin => "used from the wrong origin",
+            KeyError::RateLimited(_) => "rate limited",
+            KeyError::QuotaExceeded(_) => "over its daily quota",
+        }
+    }
+}
+
+/// Parses the keys now, so mistakes are reported at startup
+pub fn init() -> Result<(), String> {
+    check_json_var::<HashMap<String, ApiKey>>("API_KEYS", "a JSON object of keys to their settings")?;
+    if let Some(keys) = KEYS.as_ref() {
+        info!("Requiring one of {} API keys", keys.len());
+    }
+    Ok(())
+}
+
+/// Checks the key from the `X-Api-Key` header or `_key` field, and counts the submission against
+/// its limits, returning where it stands against each. Always succeeds if no keys are configured.
+pub fn check(headers: &HeaderMap, field: Option<&str>) -> Result<Vec<Quota>, KeyError> {
+    let keys = match KEYS.as_ref() {
+        Some(keys) => keys,
+        None => return Ok(Vec::new()),
+    };
+    let key = headers.get(HEADER)
+        .and_then(|value| value.to_str().ok())
+        .or(field)
+        .ok_or(KeyError::Missing)?;
+    let settings = keys.get(key).ok_or(KeyError::Unknown)?;
+    if !settings.origins.is_empty() {
+        let origin = request_origin(headers).ok_or(KeyError::WrongOrigin)?;
+        if !settings.origins.iter().any(|allowed| allowed.trim_end_matches('/') == origin) {
+            return Err(KeyError::WrongOrigin);
+        }
+    }
+    let mut quotas = Vec::new();
+    if let Some(limit) = settings.per_minute {
+        quotas.push(PER_MINUTE.hit(key, limit).map_err(KeyError::RateLimited)?);
+    }
+    if let Some(limit) = settings.daily_quota {
+        quotas.push(DAILY.hit(key, limit).map_err(KeyError::QuotaExceeded)?);
+    }
+    Ok(quotas)
+}
@@ -0,0 +1,76 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+//! Serving a frontend from a directory, so it can be deployed along with the service
+
+use std::path::PathBuf;
+use axum::Router;
+use lazy_static::lazy_static;
+use log::info;
+use axum::routing::get_service;
+use tower_http::services::{ServeDir, ServeFile};
+
+lazy_static!(
+    static ref DIR: Option<PathBuf> = std::env::var("STATIC_DIR").ok().map(PathBuf::from);
+    /// Where the files are served from - the root by default, where anything that isn't one of the
+    /// service's own routes is looked for in the directory
+    static ref PATH: String = std::env::var("STATIC_PATH")
+        .map(|path| format!("/{}", path.trim_matches('/')))
+        .unwrap_or("/".to_string());
+);
+
+/// Checks the directory exists now, rather than serving nothing but `404`s
+pub fn init() -> Result<(), String> {
+    match DIR.as_deref() {
+        Some(dir) if !dir.is_dir() => Err(format!("\"STATIC_DIR\" is set to {}, which isn't a directory", dir.display())),
+        Some(dir) => {
+            info!("Serving static files from {} at {}", dir.display(), *PATH);
+            Ok(())
+        }
+        None => Ok(()),
+    }
+}
+
+/// Whether the directory's `index.html` should be served at `/` instead of the example form
+pub fn replaces_index() -> bool {
+    DIR.as_deref().is_some_and(|dir| PATH.as_str() == "/" && dir.join("index.html").is_file())
+}
+
+pub fn mount(app: Router) -> Router {
+    let dir = match DIR.as_deref() {
+        Some(dir) => dir,
+        None => return app,
+    };
+    let files = ServeDir::new(dir);
+    match PATH.as_str() {
+        // `/` already has a route (for the form), so the fallback would never be used for it
+        "/" if replaces_index() => app.route("/", get_service(ServeFile::new(dir.join("index.html")))).fallback_service(files),
+        "/" => app.fallback_service(files),
+        path => app.nest_service(path, files),
+    }
+}
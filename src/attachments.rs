@@ -0,0 +1,220 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+//! Files attached to submissions, which are uploaded to S3-compatible storage and linked to from
+//! the email rather than attached to it, so emails stay small and well under Mailgun's size limit
+
+use std::sync::OnceLock;
+use std::time::Duration;
+use chrono::Utc;
+use lazy_static::lazy_static;
+use log::{error, info};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use crate::CLIENT;
+use crate::multipart::Upload;
+use crate::s3::{self, Bucket};
+use crate::validation::{FieldError, FieldProblem};
+
+const DEFAULT_MAX_BYTES: usize = 10 * 1024 * 1024;
+const DEFAULT_MAX_FILES: usize = 5;
+/// Room for the rest of the form, on top of the attachments
+const FIELDS_ALLOWANCE: usize = 1024 * 1024;
+/// Enough to tell files apart by, without ending up with unwieldy keys
+const MAX_FILE_NAME_LENGTH: usize = 100;
+
+lazy_static!(
+    static ref MAX_BYTES: usize = std::env::var("ATTACHMENTS_MAX_BYTES").ok()
+        .and_then(|bytes| bytes.parse().ok())
+        .unwrap_or(DEFAULT_MAX_BYTES);
+    static ref MAX_FILES: usize = std::env::var("ATTACHMENTS_MAX_FILES").ok()
+        .and_then(|files| files.parse().ok())
+        .unwrap_or(DEFAULT_MAX_FILES);
+    /// How long download links last, which S3 won't let be longer than a week
+    static ref LINK_TTL: Duration = std::env::var("ATTACHMENTS_LINK_TTL_SECS").ok()
+        .and_then(|secs| secs.parse().ok())
+        .map(Duration::from_secs)
+        .unwrap_or(s3::MAX_EXPIRY)
+        .min(s3::MAX_EXPIRY);
+    static ref PREFIX: String = std::env::var("ATTACHMENTS_S3_PREFIX").unwrap_or_default();
+);
+
+/// Only set if `ATTACHMENTS_S3_BUCKET` is
+static BUCKET: OnceLock<Bucket> = OnceLock::new();
+
+/// A stored file, as kept with its submission
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct Attachment {
+    pub file_name: String,
+    pub content_type: String,
+    pub size: usize,
+    /// Where it is in the bucket
+    pub key: String,
+}
+
+pub fn init() -> Result<(), String> {
+    let name = match std::env::var("ATTACHMENTS_S3_BUCKET") {
+        Ok(name) => name,
+        Err(_) => return Ok(()),
+    };
+    let credential = |name: &str, fallback: &str| std::env::var(name).or_else(|_| std::env::var(fallback))
+        .map_err(|_| format!("\"ATTACHMENTS_S3_BUCKET\" is set, so \"{}\" (or \"{}\") must be too", name, fallback));
+    let region = std::env::var("ATTACHMENTS_S3_REGION").unwrap_or("us-east-1".to_string());
+    let endpoint = std::env::var("ATTACHMENTS_S3_ENDPOINT").unwrap_or(format!("https://s3.{}.amazonaws.com", region));
+    let bucket = Bucket {
+        endpoint: Url::parse(&endpoint).map_err(|e| format!("\"ATTACHMENTS_S3_ENDPOINT\" isn't a valid URL: {}", e))?,
+        name,
+        region,
+        access_key_id: credential("ATTACHMENTS_S3_ACCESS_KEY_ID", "AWS_ACCESS_KEY_ID")?,
+        secret_access_key: credential("ATTACHMENTS_S3_SECRET_ACCESS_KEY", "AWS_SECRET_ACCESS_KEY")?,
+    };
+    info!("Storing up to {} attachment(s) per submission, of up to {} bytes in total, in the {} bucket at {}", *MAX_FILES, *MAX_BYTES, bucket.name, bucket.endpoint);
+    let _ = BUCKET.set(bucket);
+    Ok(())
+}
+
+pub fn enabled() -> bool {
+    BUCKET.get().is_some()
+}
+
+/// How big the form's body can be, with the largest attachments allowed
+pub fn body_limit() -> usize {
+    *MAX_BYTES + FIELDS_ALLOWANCE
+}
+
+/// Problems with the files, reported alongside any with the other fields
+pub fn check(files: &[Upload]) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+    let field = |index: usize| files[index].field.as_str();
+    if files.is_empty() {
+        return errors;
+    }
+    if !enabled() {
+        errors.push(FieldError::new(field(0), FieldProblem::AttachmentsNotAccepted));
+    } else if files.len() > *MAX_FILES {
+        errors.push(FieldError::new(field(*MAX_FILES), FieldProblem::TooManyAttachments));
+    } else if files.iter().map(|file| file.data.len()).sum::<usize>() > *MAX_BYTES {
+        errors.push(FieldError::new(field(files.len() - 1), FieldProblem::AttachmentsTooLarge));
+    }
+    errors
+}
+
+/// Keeps keys to characters that never need escaping, so they're readable in links and listings
+fn safe_file_name(file_name: &str) -> String {
+    // Browsers used to send the whole path
+    let base = file_name.rsplit(['/', '\\']).next().unwrap_or_default();
+    let safe: String = base.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_') { c } else { '_' })
+        .take(MAX_FILE_NAME_LENGTH)
+        .collect();
+    match safe.trim_matches('.') {
+        "" => "file".to_string(),
+        safe => safe.to_string(),
+    }
+}
+
+/// Uploads the submission's files, returning what to keep with it. The files are kept under the
+/// submission's ID, so can all be found (or cleaned up) together.
+pub async fn upload(submission_id: &str, files: Vec<Upload>) -> Result<Vec<Attachment>, String> {
+    let bucket = BUCKET.get().ok_or("attachments aren't enabled")?;
+    let mut stored = Vec::new();
+    for (index, file) in files.into_iter().enumerate() {
+        let key = format!("{}{}/{}-{}", *PREFIX, submission_id, index + 1, safe_file_name(&file.file_name));
+        let url = bucket.presign("PUT", &key, Duration::from_secs(60), Utc::now());
+        let size = file.data.len();
+        let response = CLIENT.put(url)
+            .header(reqwest::header::CONTENT_TYPE, file.content_type.as_str())
+            .body(file.data)
+            .send()
+            .await;
+        let error = match response {
+            Ok(response) if response.status().is_success() => None,
+            Ok(response) => {
+                let status = response.status();
+                let body = response.text().await.unwrap_or_default();
+                Some(format!("storage responded to uploading {} with {}: {}", key, status, body))
+            }
+            Err(e) => Some(format!("error uploading {}: {}", key, e)),
+        };
+        if let Some(error) = error {
+            // The submission's turned away, so whatever did make it there isn't needed
+            discard(stored);
+            return Err(error);
+        }
+        stored.push(Attachment { file_name: file.file_name, content_type: file.content_type, size, key });
+    }
+    info!("Stored {} attachment(s) for submission {}", stored.len(), submission_id);
+    Ok(stored)
+}
+
+/// Deletes the files from storage in the background, now their submission's gone (or been
+/// anonymized). Failures are only logged, as there's nothing left to retry them from.
+pub fn discard(attachments: Vec<Attachment>) {
+    let bucket = match BUCKET.get() {
+        Some(bucket) if !attachments.is_empty() => bucket,
+        _ => return,
+    };
+    tokio::spawn(async move {
+        for attachment in attachments {
+            let url = bucket.presign("DELETE", &attachment.key, Duration::from_secs(60), Utc::now());
+            match CLIENT.delete(url).send().await {
+                Ok(response) if response.status().is_success() => {}
+                Ok(response) => error!("Storage responded to deleting {} with {}", attachment.key, response.status()),
+                Err(e) => error!("Error deleting {}: {}", attachment.key, e),
+            }
+        }
+    });
+}
+
+fn describe_size(bytes: usize) -> String {
+    match bytes {
+        bytes if bytes < 1024 => format!("{} bytes", bytes),
+        bytes if bytes < 1024 * 1024 => format!("{:.1} KB", bytes as f64 / 1024.0),
+        bytes => format!("{:.1} MB", bytes as f64 / (1024.0 * 1024.0)),
+    }
+}
+
+/// Lines listing each attachment with a fresh download link, for the end of a plain-text message
+pub fn links(attachments: &[Attachment]) -> String {
+    let bucket = match BUCKET.get() {
+        Some(bucket) if !attachments.is_empty() => bucket,
+        _ => return String::new(),
+    };
+    let days = LINK_TTL.as_secs() / (24 * 60 * 60);
+    let expiry = match days {
+        0 => format!("{} hour(s)", (LINK_TTL.as_secs() / (60 * 60)).max(1)),
+        days => format!("{} day(s)", days),
+    };
+    let mut text = format!("Attachments (the links expire in {}):\n", expiry);
+    for attachment in attachments {
+        let url = bucket.presign("GET", &attachment.key, *LINK_TTL, Utc::now());
+        text.push_str(&format!("- {} ({}): {}\n", attachment.file_name, describe_size(attachment.size), url));
+    }
+    text
+}
@@ -0,0 +1,159 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+use chrono::{DateTime, Utc};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct AuditEntry {
+    pub at: DateTime<Utc>,
+    /// The admin (named by `ADMIN_TOKENS`), or the part of the service, that did it
+    pub actor: String,
+    pub action: String,
+    #[serde(default)]
+    pub submission_id: Option<String>,
+    #[serde(default)]
+    pub detail: Option<String>,
+}
+
+#[derive(Default, Deserialize, IntoParams)]
+pub struct AuditQuery {
+    pub submission_id: Option<String>,
+    pub actor: Option<String>,
+    pub action: Option<String>,
+    pub offset: Option<usize>,
+    pub limit: Option<usize>,
+}
+
+const DEFAULT_LIMIT: usize = 100;
+
+/// Every entry is kept in memory for querying, and - if a file has been configured - appended to
+/// it as a line of JSON. Unlike the submission store, the file is never rewritten.
+pub struct AuditLog {
+    file: Option<Mutex<File>>,
+    entries: Mutex<Vec<AuditEntry>>,
+}
+
+impl AuditLog {
+    pub fn open(path: Option<PathBuf>) -> Result<Self, String> {
+        let (file, entries) = match path {
+            Some(path) => {
+                let mut entries = Vec::new();
+                if path.exists() {
+                    let reader = BufReader::new(File::open(&path).map_err(|e| format!("Unable to read audit log {}: {}", path.display(), e))?);
+                    for (number, line) in reader.lines().enumerate() {
+                        let line = line.map_err(|e| format!("Unable to read audit log {}: {}", path.display(), e))?;
+                        if line.trim().is_empty() {
+                            continue;
+                        }
+                        entries.push(serde_json::from_str(&line)
+                            .map_err(|e| format!("Unable to parse line {} of audit log {}: {}", number + 1, path.display(), e))?);
+                    }
+                }
+                let file = OpenOptions::new().create(true).append(true).open(&path)
+                    .map_err(|e| format!("Unable to open audit log {}: {}", path.display(), e))?;
+                (Some(Mutex::new(file)), entries)
+            }
+            None => (None, Vec::new()),
+        };
+        Ok(AuditLog { file, entries: Mutex::new(entries) })
+    }
+
+    pub fn record(&self, entry: AuditEntry) {
+        if let Some(file) = self.file.as_ref() {
+            let result = serde_json::to_string(&entry)
+                .map_err(|e| e.to_string())
+                .and_then(|line| writeln!(file.lock().unwrap(), "{}", line).map_err(|e| e.to_string()));
+            if let Err(e) = result {
+                error!("Unable to write to the audit log: {}", e);
+            }
+        }
+        self.entries.lock().unwrap().push(entry);
+    }
+
+    /// Returns the total number of matches, along with the requested page of them, newest first
+    pub fn search(&self, query: &AuditQuery) -> (usize, Vec<AuditEntry>) {
+        let entries = self.entries.lock().unwrap();
+        let matches = |entry: &&AuditEntry| {
+            (query.submission_id.is_none() || entry.submission_id == query.submission_id)
+                && query.actor.as_ref().map(|actor| &entry.actor == actor).unwrap_or(true)
+                && query.action.as_ref().map(|action| &entry.action == action).unwrap_or(true)
+        };
+        let matching: Vec<&AuditEntry> = entries.iter().rev().filter(matches).collect();
+        let page = matching.iter()
+            .skip(query.offset.unwrap_or(0))
+            .take(query.limit.unwrap_or(DEFAULT_LIMIT))
+            .map(|entry| (*entry).clone())
+            .collect();
+        (matching.len(), page)
+    }
+}
+
+static LOG: OnceLock<AuditLog> = OnceLock::new();
+
+/// Opens the audit log now, so one that can't be read stops the service starting. Like the store,
+/// it's only kept if there's a file to write to or an admin API to read it from.
+pub fn init() -> Result<(), String> {
+    if LOG.get().is_some() {
+        return Ok(());
+    }
+    let log = match std::env::var("AUDIT_LOG_FILE") {
+        Ok(path) => {
+            info!("Appending an audit trail to {}", path);
+            AuditLog::open(Some(PathBuf::from(path)))?
+        }
+        Err(_) if crate::admin::enabled() => AuditLog::open(None)?,
+        Err(_) => return Ok(()),
+    };
+    let _ = LOG.set(log);
+    Ok(())
+}
+
+/// The audit log, if [init] has opened one
+pub fn get() -> Option<&'static AuditLog> {
+    LOG.get()
+}
+
+/// Records that `actor` did `action`, optionally to a particular submission
+pub fn record(actor: &str, action: &str, submission_id: Option<&str>, detail: Option<String>) {
+    if let Some(log) = get() {
+        log.record(AuditEntry {
+            at: Utc::now(),
+            actor: actor.to_string(),
+            action: action.to_string(),
+            submission_id: submission_id.map(|id| id.to_string()),
+            detail,
+        });
+    }
+}
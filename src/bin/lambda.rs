@@ -0,0 +1,72 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+//! Runs the service as an AWS Lambda function, behind API Gateway or an Application Load Balancer
+
+use std::net::{IpAddr, SocketAddr};
+use axum::extract::ConnectInfo;
+use lambda_http::request::RequestContext;
+use lambda_http::{Error, Request, RequestExt};
+use mailgun_contact_form::ContactFormService;
+use tower::ServiceExt;
+
+/// API Gateway and load balancers know where the request came from, even though there's no
+/// connection to ask
+fn source_ip(request: &Request) -> Option<IpAddr> {
+    let ip = match request.request_context_ref()? {
+        RequestContext::ApiGatewayV1(context) => context.identity.source_ip.as_deref(),
+        RequestContext::ApiGatewayV2(context) => context.http.source_ip.as_deref(),
+        RequestContext::WebSocket(context) => context.identity.source_ip.as_deref(),
+        // Load balancers add the client's address to the end of X-Forwarded-For, so only the last
+        // one is theirs - anything before it came from the client
+        RequestContext::Alb(_) => request.headers().get_all("x-forwarded-for").iter()
+            .filter_map(|value| value.to_str().ok())
+            .next_back()
+            .and_then(|value| value.rsplit(',').next())
+            .map(|ip| ip.trim()),
+    };
+    ip?.parse().ok()
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    env_logger::init();
+    let router = ContactFormService::builder().build().await?.router();
+
+    lambda_http::run(tower::service_fn(move |mut request: Request| {
+        match source_ip(&request) {
+            Some(ip) => {
+                request.extensions_mut().insert(ConnectInfo(SocketAddr::new(ip, 0)));
+            }
+            // Otherwise IP rate limits, blocks and country filters would quietly stop working
+            None => log::warn!("Couldn't tell where a request came from, so IP-based limits don't apply to it"),
+        }
+        // The router only takes hyper's bodies, but Lambda requests arrive all at once anyway
+        router.clone().oneshot(request.map(|body| axum::body::Body::from(body.to_vec())))
+    })).await
+}
@@ -0,0 +1,102 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+use std::sync::OnceLock;
+use async_trait::async_trait;
+use log::{error, info};
+use crate::store::Submission;
+
+/// Somewhere to publish accepted submissions to, for deployments that want to process them
+/// asynchronously. Only NATS is supported for now, but other brokers just need to implement this.
+#[async_trait]
+pub trait Broker: Send + Sync {
+    async fn publish(&self, payload: Vec<u8>) -> Result<(), String>;
+}
+
+static BROKER: OnceLock<Box<dyn Broker>> = OnceLock::new();
+
+/// Connects to whichever broker is configured (if any). Must be called before the server starts.
+pub async fn init() -> Result<(), String> {
+    let url = match std::env::var("NATS_URL") {
+        Ok(url) => url,
+        Err(_) => return Ok(()),
+    };
+    let broker = connect_nats(&url).await?;
+    BROKER.set(broker).map_err(|_| "broker already initialised".to_string())
+}
+
+#[cfg(feature = "nats")]
+async fn connect_nats(url: &str) -> Result<Box<dyn Broker>, String> {
+    let subject = std::env::var("NATS_SUBJECT").unwrap_or(nats::DEFAULT_SUBJECT.to_string());
+    let client = async_nats::connect(url).await.map_err(|e| format!("Unable to connect to NATS at {}: {}", url, e))?;
+    info!("Connected to NATS at {}, will publish submissions to {}", url, subject);
+    Ok(Box::new(nats::NatsBroker { client, subject }))
+}
+
+#[cfg(not(feature = "nats"))]
+async fn connect_nats(_url: &str) -> Result<Box<dyn Broker>, String> {
+    Err("\"NATS_URL\" is set, but this build doesn't include NATS support - rebuild with `--features nats`".to_string())
+}
+
+/// Publishes the submission in the background, if a broker is configured
+pub fn publish(submission: &Submission) {
+    let broker = match BROKER.get() {
+        Some(broker) => broker,
+        None => return,
+    };
+    let id = submission.id.clone();
+    let payload = crate::webhook::event_json(submission).into_bytes();
+    tokio::spawn(async move {
+        match broker.publish(payload).await {
+            Ok(()) => info!("Submission {} published", id),
+            Err(e) => error!("Error publishing submission {}: {}", id, e),
+        }
+    });
+}
+
+#[cfg(feature = "nats")]
+mod nats {
+    use async_trait::async_trait;
+    use super::Broker;
+
+    pub const DEFAULT_SUBJECT: &str = "contact_form.submissions";
+
+    pub struct NatsBroker {
+        pub client: async_nats::Client,
+        pub subject: String,
+    }
+
+    #[async_trait]
+    impl Broker for NatsBroker {
+        async fn publish(&self, payload: Vec<u8>) -> Result<(), String> {
+            self.client.publish(self.subject.clone(), payload.into()).await.map_err(|e| e.to_string())?;
+            // Publishing only buffers the message, so flush to find out whether it actually went
+            self.client.flush().await.map_err(|e| e.to_string())
+        }
+    }
+}
@@ -0,0 +1,246 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+//! Daily and monthly caps on how many submissions are sent, overall and per form, so a flood of
+//! spam can't run up the mail provider's bill
+
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
+use lazy_static::lazy_static;
+use log::{info, warn};
+use crate::{form_override, form_var, handler};
+use crate::outbox::{outbox, Entry, Pending};
+use crate::service::AppState;
+use crate::store::Submission;
+
+/// How often to check whether queued submissions can be sent yet
+const RELEASE_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Period {
+    Day,
+    Month,
+}
+
+impl Period {
+    const ALL: [Period; 2] = [Period::Day, Period::Month];
+
+    fn var(self) -> &'static str {
+        match self {
+            Period::Day => "SEND_CAP_DAILY",
+            Period::Month => "SEND_CAP_MONTHLY",
+        }
+    }
+
+    pub fn describe(self) -> &'static str {
+        match self {
+            Period::Day => "daily",
+            Period::Month => "monthly",
+        }
+    }
+
+    /// Identifies the (UTC) day or month `now` is in
+    fn current(self, now: DateTime<Utc>) -> String {
+        match self {
+            Period::Day => now.format("%Y-%m-%d").to_string(),
+            Period::Month => now.format("%Y-%m").to_string(),
+        }
+    }
+
+    /// How long until the next day or month starts
+    fn reset_secs(self, now: DateTime<Utc>) -> u64 {
+        let today = now.date_naive();
+        let next = match self {
+            Period::Day => today.succ_opt(),
+            Period::Month if today.month() == 12 => NaiveDate::from_ymd_opt(today.year() + 1, 1, 1),
+            Period::Month => NaiveDate::from_ymd_opt(today.year(), today.month() + 1, 1),
+        };
+        let next = next.expect("the next day or month is a real date").and_hms_opt(0, 0, 0).expect("midnight exists").and_utc();
+        (next - now).num_seconds().max(1) as u64
+    }
+}
+
+/// The cap that was reached, and how long until it resets
+pub struct CapReached {
+    pub period: Period,
+    pub reset_secs: u64,
+}
+
+/// Which cap a count is for - a form's own, or (with `None`) the overall one
+type CapKey = (Option<String>, Period);
+
+lazy_static!(
+    /// How many submissions have been sent this period against each cap, along with which period it is
+    static ref SENT: Mutex<HashMap<CapKey, (String, u32)>> = Mutex::new(HashMap::new());
+);
+
+/// What queued submissions are sent with
+static STATE: OnceLock<AppState> = OnceLock::new();
+
+fn cap(value: Option<String>, name: &str) -> Result<Option<u32>, String> {
+    value.map(|value| value.parse().map_err(|_| format!("\"{}\" must be a number, not {}", name, value))).transpose()
+}
+
+/// Checks every cap is a number, and starts sending queued submissions (including any from before
+/// a restart) as caps reset
+pub fn init(state: &AppState) -> Result<(), String> {
+    let mut capped = false;
+    for (name, value) in std::env::vars() {
+        if Period::ALL.iter().any(|period| name == period.var() || (name.starts_with("FORM_") && name.ends_with(&format!("_{}", period.var())))) {
+            cap(Some(value.clone()), &name)?;
+            info!("Sending at most {} submission(s) {}", value, describe(&name));
+            capped = true;
+        }
+    }
+    for (name, action) in std::env::vars() {
+        let is_action = name == "SEND_CAP_ACTION" || (name.starts_with("FORM_") && name.ends_with("_SEND_CAP_ACTION"));
+        if is_action && !matches!(action.as_str(), "reject" | "queue") {
+            return Err(format!("\"{}\" must be `reject` or `queue`, not {}", name, action));
+        }
+    }
+    // Only the first service built in a process gets to send queued submissions. Ones queued before
+    // a restart still need sending if the caps have since been taken away.
+    if (capped || queued() > 0) && STATE.set(state.clone()).is_ok() {
+        tokio::spawn(async {
+            let mut interval = tokio::time::interval(RELEASE_INTERVAL);
+            loop {
+                interval.tick().await;
+                release().await;
+            }
+        });
+    }
+    Ok(())
+}
+
+fn describe(name: &str) -> String {
+    let period = if name.ends_with(Period::Day.var()) { "per day" } else { "per month" };
+    match name.strip_prefix("FORM_").and_then(|name| name.rsplit_once("_SEND_CAP_")) {
+        Some((form, _)) => format!("{} for form {}", period, form),
+        None => format!("{} overall", period),
+    }
+}
+
+/// Counts the submission against the overall caps and the form's own, unless any of them have
+/// been reached, in which case nothing is counted
+pub fn hit(form: Option<&str>) -> Result<(), CapReached> {
+    let now = Utc::now();
+    let mut caps = Vec::new();
+    for period in Period::ALL {
+        if let Ok(Some(limit)) = cap(std::env::var(period.var()).ok(), period.var()) {
+            caps.push((None, period, limit));
+        }
+        if let Ok(Some(limit)) = cap(form_override(form, period.var()), period.var()) {
+            caps.push((form.map(|form| form.to_string()), period, limit));
+        }
+    }
+    if caps.is_empty() {
+        return Ok(());
+    }
+    let mut sent = SENT.lock().unwrap();
+    for (form, period, limit) in caps.iter() {
+        let current = period.current(now);
+        let count = match sent.get(&(form.clone(), *period)) {
+            Some((counted, count)) if *counted == current => *count,
+            _ => 0,
+        };
+        if count >= *limit {
+            return Err(CapReached { period: *period, reset_secs: period.reset_secs(now) });
+        }
+    }
+    for (form, period, _) in caps {
+        let current = period.current(now);
+        let (counted, count) = sent.entry((form, period)).or_insert((current.clone(), 0));
+        if *counted != current {
+            *counted = current;
+            *count = 0;
+        }
+        *count += 1;
+    }
+    Ok(())
+}
+
+/// Whether to hold submissions over a cap until it resets, rather than turning them away
+pub fn queueing(form: Option<&str>) -> bool {
+    form_var(form, "SEND_CAP_ACTION").as_deref() == Some("queue")
+}
+
+/// The outbox entry for a queued submission, which is kept apart from its digest entry (if it goes
+/// on to be sent in one)
+fn entry_id(submission_id: &str) -> String {
+    format!("send_cap:{}", submission_id)
+}
+
+/// Holds the submission until the cap it's over resets, in `reset_secs`
+pub fn queue(submission: Submission, reset_secs: u64) {
+    let entry = Entry {
+        id: entry_id(&submission.id),
+        due_at: Utc::now() + chrono::Duration::seconds(reset_secs as i64),
+        pending: Pending::Capped { submission: Box::new(submission) },
+    };
+    // Still over a cap when it was released, so it goes back to wait for the next reset
+    if outbox().claimed(&entry.id) {
+        outbox().release(entry);
+    } else {
+        outbox().add(entry.id, entry.due_at, entry.pending);
+    }
+}
+
+fn queued() -> usize {
+    outbox().entries().iter().filter(|entry| matches!(entry.pending, Pending::Capped { .. })).count()
+}
+
+/// Tries sending everything whose cap should have reset again, which queues anything still over
+/// one once more
+async fn release() {
+    let state = match STATE.get() {
+        Some(state) => state,
+        None => return,
+    };
+    let now = Utc::now();
+    let due = outbox().claim_all(|entry| matches!(entry.pending, Pending::Capped { .. }) && entry.due_at <= now);
+    if due.is_empty() {
+        return;
+    }
+    info!("Sending {} submission(s) queued by send caps", due.len());
+    for entry in due {
+        let submission = match entry.pending {
+            Pending::Capped { submission } => submission,
+            _ => continue,
+        };
+        let (status, _) = handler::process(state, &submission, "send_cap").await;
+        if !status.is_success() {
+            warn!("Submission {} queued by send caps couldn't be sent: {}", submission.id, status);
+        }
+        // Unless it's been queued again
+        if outbox().claimed(&entry.id) {
+            outbox().complete(&entry.id);
+        }
+    }
+}
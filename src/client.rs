@@ -0,0 +1,99 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+use std::time::Duration;
+use lazy_static::lazy_static;
+use log::info;
+use reqwest::{Certificate, Client, Proxy};
+
+lazy_static!(
+    static ref CONFIGURED: Result<Client, String> = from_env();
+);
+
+const END_CERTIFICATE: &str = "-----END CERTIFICATE-----";
+
+fn var(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+fn number<T: std::str::FromStr>(name: &str) -> Result<Option<T>, String> {
+    var(name).map(|value| value.parse().map_err(|_| format!("\"{}\" must be a number", name))).transpose()
+}
+
+/// Splits a bundle of PEM certificates, as reqwest only parses one at a time
+fn certificates(pem: &str) -> Result<Vec<Certificate>, reqwest::Error> {
+    pem.split_inclusive(END_CERTIFICATE)
+        .filter(|certificate| certificate.contains(END_CERTIFICATE))
+        .map(|certificate| Certificate::from_pem(certificate.trim().as_bytes()))
+        .collect()
+}
+
+/// The client used for every outbound request - to Mailgun, notifiers, webhooks and so on. As well
+/// as the standard `HTTPS_PROXY` (etc.) variables, which reqwest already respects, it can be
+/// configured with `HTTP_CLIENT_*` variables.
+fn from_env() -> Result<Client, String> {
+    let mut builder = Client::builder();
+    if let Some(proxy) = var("HTTP_CLIENT_PROXY") {
+        info!("Sending outbound requests via proxy {}", proxy);
+        builder = builder.proxy(Proxy::all(&proxy).map_err(|e| format!("Invalid \"HTTP_CLIENT_PROXY\": {}", e))?);
+    }
+    if let Some(path) = var("HTTP_CLIENT_CA_BUNDLE") {
+        let pem = std::fs::read_to_string(&path).map_err(|e| format!("Unable to read CA bundle {}: {}", path, e))?;
+        let certificates = certificates(&pem).map_err(|e| format!("Unable to parse CA bundle {}: {}", path, e))?;
+        if certificates.is_empty() {
+            return Err(format!("CA bundle {} doesn't contain any certificates", path));
+        }
+        info!("Trusting {} extra CA certificate(s) from {}", certificates.len(), path);
+        for certificate in certificates {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+    if let Some(max) = number("HTTP_CLIENT_POOL_MAX_IDLE")? {
+        builder = builder.pool_max_idle_per_host(max);
+    }
+    if let Some(secs) = number("HTTP_CLIENT_POOL_IDLE_TIMEOUT_SECS")? {
+        builder = builder.pool_idle_timeout(Duration::from_secs(secs));
+    }
+    if let Some(secs) = number("HTTP_CLIENT_CONNECT_TIMEOUT_SECS")? {
+        builder = builder.connect_timeout(Duration::from_secs(secs));
+    }
+    if let Some(secs) = number("HTTP_CLIENT_TIMEOUT_SECS")? {
+        builder = builder.timeout(Duration::from_secs(secs));
+    }
+    builder.build().map_err(|e| format!("Unable to create the HTTP client: {}", e))
+}
+
+/// Checks the configuration, so a bad one is an error when the service is built rather than a panic
+/// when the client is first used
+pub fn init() -> Result<(), String> {
+    CONFIGURED.as_ref().map(|_| ()).map_err(|e| e.clone())
+}
+
+pub fn configured() -> Client {
+    CONFIGURED.clone().expect("the HTTP client configuration is checked by init")
+}
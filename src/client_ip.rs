@@ -0,0 +1,167 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+use std::net::IpAddr;
+use std::str::FromStr;
+use axum::http::HeaderMap;
+use lazy_static::lazy_static;
+use crate::check_var;
+
+/// Which header the trusted proxies put the client's address in. Only that one is read, as a proxy
+/// that only adds to one passes the other through from the client untouched.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum IpHeader {
+    Forwarded,
+    XForwardedFor,
+}
+
+impl FromStr for IpHeader {
+    type Err = ();
+
+    fn from_str(name: &str) -> Result<Self, ()> {
+        match name.to_lowercase().as_str() {
+            "forwarded" => Ok(IpHeader::Forwarded),
+            "x-forwarded-for" => Ok(IpHeader::XForwardedFor),
+            _ => Err(()),
+        }
+    }
+}
+
+lazy_static!(
+    static ref HEADER: IpHeader = std::env::var("CLIENT_IP_HEADER").ok()
+        .and_then(|header| header.trim().parse().ok())
+        .unwrap_or(IpHeader::XForwardedFor);
+    /// Proxies whose `CLIENT_IP_HEADER` can be believed
+    static ref TRUSTED_PROXIES: Vec<Cidr> = std::env::var("TRUSTED_PROXIES")
+        .map(|proxies| proxies.split(',')
+            .map(|proxy| proxy.trim())
+            .filter(|proxy| !proxy.is_empty())
+            .filter_map(Cidr::parse)
+            .collect())
+        .unwrap_or_default();
+);
+
+/// An address range, like `10.0.0.0/8`. A plain address is a range of one.
+struct Cidr {
+    network: IpAddr,
+    prefix: u32,
+}
+
+impl Cidr {
+    fn parse(cidr: &str) -> Option<Cidr> {
+        let (network, prefix): (IpAddr, Option<u32>) = match cidr.split_once('/') {
+            Some((network, prefix)) => (network.parse().ok()?, Some(prefix.parse().ok()?)),
+            None => (cidr.parse().ok()?, None),
+        };
+        let bits = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = prefix.unwrap_or(bits);
+        (prefix <= bits).then_some(Cidr { network, prefix })
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        let mask = |bits: u32| if self.prefix == 0 { 0 } else { u128::MAX << (bits - self.prefix) };
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = mask(32) as u32;
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = mask(128);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Parses the settings now, so mistakes are reported at startup
+pub fn init() -> Result<(), String> {
+    check_var::<IpHeader>("CLIENT_IP_HEADER", "`forwarded` or `x-forwarded-for`")?;
+    let proxies = std::env::var("TRUSTED_PROXIES").unwrap_or_default();
+    match proxies.split(',').map(|proxy| proxy.trim()).find(|proxy| !proxy.is_empty() && Cidr::parse(proxy).is_none()) {
+        Some(invalid) => Err(format!("\"TRUSTED_PROXIES\" has an invalid entry: {}", invalid)),
+        None => {
+            lazy_static::initialize(&TRUSTED_PROXIES);
+            Ok(())
+        }
+    }
+}
+
+fn trusted(ip: IpAddr) -> bool {
+    TRUSTED_PROXIES.iter().any(|proxy| proxy.contains(ip))
+}
+
+/// Works out the address of the client that made the request. If it came from a trusted proxy,
+/// that's the last address in `CLIENT_IP_HEADER` that isn't also a trusted proxy, as anything
+/// before it could have been made up by the client. Otherwise, it's the peer address.
+pub fn resolve(peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+    if !trusted(peer) {
+        return peer;
+    }
+    let chain = match *HEADER {
+        IpHeader::Forwarded => forwarded(headers),
+        IpHeader::XForwardedFor => forwarded_for(headers),
+    };
+    chain.iter().rev()
+        .find(|ip| !trusted(**ip))
+        .or(chain.first())
+        .copied()
+        .unwrap_or(peer)
+}
+
+/// The `for` addresses from the standard `Forwarded` header, e.g.
+/// `for=192.0.2.60;proto=https, for="[2001:db8:cafe::17]:4711"`
+fn forwarded(headers: &HeaderMap) -> Vec<IpAddr> {
+    headers.get_all("forwarded").iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|element| element.split(';')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("for"))
+            .and_then(|(_, node)| parse_node(node.trim().trim_matches('"'))))
+        .collect()
+}
+
+fn forwarded_for(headers: &HeaderMap) -> Vec<IpAddr> {
+    headers.get_all("x-forwarded-for").iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|node| parse_node(node.trim()))
+        .collect()
+}
+
+/// Parses an address that may have a port, and if IPv6, brackets
+fn parse_node(node: &str) -> Option<IpAddr> {
+    if let Ok(ip) = node.parse() {
+        return Some(ip);
+    }
+    if let Some(rest) = node.strip_prefix('[') {
+        return rest.split_once(']').and_then(|(ip, _)| ip.parse().ok());
+    }
+    node.rsplit_once(':').and_then(|(ip, _)| ip.parse().ok())
+}
@@ -0,0 +1,101 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+//! Limits how many emails are being sent at once, so a burst of submissions can't open hundreds
+//! of connections to the mail provider at the same time
+
+use std::time::Duration;
+use lazy_static::lazy_static;
+use log::{info, warn};
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+/// How long a submission waits by default for a slot before being turned away
+const DEFAULT_QUEUE_SECS: u64 = 5;
+
+lazy_static!(
+    /// Unset (the default) for no limit
+    static ref MAX_CONCURRENT: Option<usize> = std::env::var("MAIL_MAX_CONCURRENT_SENDS").ok()
+        .and_then(|max| max.parse().ok());
+    static ref QUEUE_TIMEOUT: Duration = Duration::from_secs(std::env::var("MAIL_SEND_QUEUE_SECS").ok()
+        .and_then(|secs| secs.parse().ok())
+        .unwrap_or(DEFAULT_QUEUE_SECS));
+    static ref SLOTS: Option<Semaphore> = MAX_CONCURRENT.map(Semaphore::new);
+);
+
+/// Every slot was taken for longer than `MAIL_SEND_QUEUE_SECS`
+#[derive(Debug)]
+pub struct Busy {
+    /// Roughly how long to wait before trying again
+    pub retry_after: u64,
+}
+
+/// Held while sending; the slot is freed when it's dropped. `None` when there's no limit.
+pub type Slot = Option<SemaphorePermit<'static>>;
+
+pub fn init() -> Result<(), String> {
+    for name in ["MAIL_MAX_CONCURRENT_SENDS", "MAIL_SEND_QUEUE_SECS"] {
+        if let Ok(value) = std::env::var(name) {
+            value.parse::<u64>().map_err(|_| format!("\"{}\" must be a whole number, not {}", name, value))?;
+        }
+    }
+    match *MAX_CONCURRENT {
+        Some(0) => return Err("\"MAIL_MAX_CONCURRENT_SENDS\" must be at least 1".to_string()),
+        Some(max) => info!("Sending at most {} email(s) at once, with others waiting up to {}s for a turn", max, QUEUE_TIMEOUT.as_secs()),
+        None => {}
+    }
+    Ok(())
+}
+
+/// Waits for a slot for up to `MAIL_SEND_QUEUE_SECS` (so not at all if it's `0`), for sends
+/// someone is waiting on the response to
+pub async fn acquire() -> Result<Slot, Busy> {
+    let slots = match SLOTS.as_ref() {
+        Some(slots) => slots,
+        None => return Ok(None),
+    };
+    if let Ok(permit) = slots.try_acquire() {
+        return Ok(Some(permit));
+    }
+    if !QUEUE_TIMEOUT.is_zero() {
+        if let Ok(permit) = tokio::time::timeout(*QUEUE_TIMEOUT, slots.acquire()).await {
+            // Never closed
+            return Ok(Some(permit.expect("send slots closed")));
+        }
+    }
+    warn!("Turning a send away, as all {} slot(s) have been in use for {}s", MAX_CONCURRENT.unwrap_or(0), QUEUE_TIMEOUT.as_secs());
+    Err(Busy { retry_after: QUEUE_TIMEOUT.as_secs().max(1) })
+}
+
+/// Waits for as long as it takes to get a slot, for sends in the background (like digests and
+/// retries) that nobody is waiting on
+pub async fn wait() -> Slot {
+    match SLOTS.as_ref() {
+        Some(slots) => Some(slots.acquire().await.expect("send slots closed")),
+        None => None,
+    }
+}
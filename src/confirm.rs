@@ -0,0 +1,216 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+//! Double opt-in: holding a submission until the submitter confirms (by following a link emailed to
+//! them) that they really sent it, so nobody can send messages in someone else's name
+
+use std::sync::Arc;
+use std::time::Duration;
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::Response;
+use chrono::Utc;
+use lazy_static::lazy_static;
+use log::{error, info};
+use crate::{check_var, concurrency, form_flag, handler, i18n, maintenance, page, stats, suppression};
+use crate::outbox::{outbox, Pending};
+use crate::provider::{self, Email, MailProvider};
+use crate::service::AppState;
+use crate::store::{self, DeliveryStatus, SearchQuery, Submission};
+
+lazy_static!(
+    static ref FROM: Option<String> = std::env::var("CONFIRMATION_FROM_ADDRESS").ok()
+        .or_else(|| std::env::var("MAILGUN_DOMAIN").ok().map(|domain| format!("Contact form <postmaster@{}>", domain)));
+    static ref TTL: Duration = Duration::from_secs(std::env::var("CONFIRMATION_TTL_SECS").ok()
+        .and_then(|secs| secs.trim().parse().ok())
+        .unwrap_or(24 * 60 * 60));
+);
+
+/// Whether double opt-in is on for any form, checked at startup so the confirmation route is only
+/// mounted when it's needed
+pub fn enabled() -> bool {
+    std::env::vars().any(|(name, value)| {
+        (name == "DOUBLE_OPT_IN" || (name.starts_with("FORM_") && name.ends_with("_DOUBLE_OPT_IN")))
+            && !matches!(value.to_lowercase().as_str(), "false" | "no" | "0")
+    })
+}
+
+/// Whether the form's submissions need confirming
+pub fn required(form: Option<&str>) -> bool {
+    form_flag(form, "DOUBLE_OPT_IN", false)
+}
+
+/// Confirmation emails need somewhere to come from, and something to send them
+pub fn init(provider: Option<&Arc<dyn MailProvider>>) -> Result<(), String> {
+    check_var::<u64>("CONFIRMATION_TTL_SECS", "a number of seconds")?;
+    if !enabled() {
+        return Ok(());
+    }
+    if provider.is_none() {
+        return Err("\"DOUBLE_OPT_IN\" is set, but email is turned off, so confirmation emails can't be sent".to_string());
+    }
+    if FROM.is_none() {
+        return Err("\"DOUBLE_OPT_IN\" is set, but there's no \"CONFIRMATION_FROM_ADDRESS\" (or \"MAILGUN_DOMAIN\" to default it from)".to_string());
+    }
+    info!("Submissions to forms with double opt-in will be held for up to {}s until they're confirmed", TTL.as_secs());
+    expire();
+    forget_lost();
+    Ok(())
+}
+
+/// Where a submission waits to be confirmed in the outbox
+fn outbox_id(token: &str) -> String {
+    format!("confirm:{}", token)
+}
+
+/// Forgets submissions that were never confirmed
+fn expire() {
+    let now = Utc::now();
+    for entry in outbox().claim_all(|entry| matches!(entry.pending, Pending::Unconfirmed { .. }) && entry.due_at <= now) {
+        if let Pending::Unconfirmed { submission } = &entry.pending {
+            info!("Submission {} was never confirmed", submission.id);
+            stats::record_status(submission.form.as_deref(), DeliveryStatus::Failed);
+            if let Some(store) = store::get() {
+                store.update_status(&submission.id, DeliveryStatus::Failed, Some("never confirmed".to_string()), "confirmation");
+            }
+        }
+        outbox().complete(&entry.id);
+    }
+}
+
+/// Gives up on stored submissions that were waiting to be confirmed when the service last stopped,
+/// if the outbox they were waiting in wasn't kept, as their links can't work any more
+fn forget_lost() {
+    let store = match store::get() {
+        Some(store) => store,
+        None => return,
+    };
+    let waiting: Vec<String> = outbox().entries().into_iter()
+        .filter_map(|entry| match entry.pending {
+            Pending::Unconfirmed { submission } => Some(submission.id),
+            _ => None,
+        })
+        .collect();
+    let query = SearchQuery { status: Some(DeliveryStatus::Unconfirmed), limit: Some(usize::MAX), ..Default::default() };
+    for submission in store.search(&query).1.into_iter().filter(|submission| !waiting.contains(&submission.id)) {
+        info!("Submission {} can't be confirmed any more, as it was waiting when the service stopped", submission.id);
+        store.update_status(&submission.id, DeliveryStatus::Failed, Some("never confirmed".to_string()), "confirmation");
+    }
+}
+
+/// Emails the submitter a link to `<endpoint>confirm/<token>`, and holds the submission until it's
+/// followed. `endpoint` is the form endpoint's URL, ending with a `/`.
+pub async fn request(provider: &Arc<dyn MailProvider>, submission: &Submission, endpoint: &str, lang: &str) -> Result<(), String> {
+    if suppression::is_suppressed(&submission.from_email) {
+        return Err(format!("{} is on the suppression list", submission.from_email));
+    }
+    let token = uuid::Uuid::new_v4().simple().to_string();
+    let link = format!("{}confirm/{}", endpoint, token);
+    let text = i18n::text(lang, "confirm_body")
+        .replace("{{name}}", &submission.from_name)
+        .replace("{{title}}", &submission.title)
+        .replace("{{link}}", &link);
+    let email = Email {
+        from: FROM.clone().expect("checked at startup"),
+        to: provider::mailbox(&submission.from_name, &submission.from_email),
+        subject: i18n::text(lang, "confirm_subject"),
+        text,
+        // Delivery events are about the submission's own email, not this one
+        submission_ids: Vec::new(),
+        headers: Default::default(),
+        attachments: Vec::new(),
+    };
+    let _slot = concurrency::acquire().await.map_err(|_| "too many emails are being sent at once".to_string())?;
+    provider.send(&email).await.map_err(|e| e.to_string())?;
+    expire();
+    let expires_at = Utc::now() + chrono::Duration::from_std(*TTL).expect("the TTL is a sensible length");
+    outbox().add(outbox_id(&token), expires_at, Pending::Unconfirmed { submission: Box::new(submission.clone()) });
+    Ok(())
+}
+
+/// Sends the submission on, and shows the success page - or the error page, if the link is wrong or
+/// has expired
+#[utoipa::path(
+    get,
+    path = "/confirm/{token}",
+    tag = "form",
+    params(("token" = String, Path, description = "From the link in the confirmation email")),
+    responses(
+        (status = 200, description = "Submission confirmed and sent", content_type = "text/html", body = String),
+        (status = 202, description = "Submission confirmed, and held until maintenance is over", content_type = "text/html", body = String),
+        (status = 404, description = "The token is unknown, already used, or has expired", content_type = "text/html", body = String),
+        (status = 500, description = "Submission confirmed, but couldn't be sent", content_type = "text/html", body = String),
+    ),
+)]
+pub async fn confirm(State(state): State<AppState>, Path(token): Path<String>, headers: HeaderMap) -> Response {
+    let lang = i18n::negotiate(None, &headers);
+    expire();
+    let id = outbox_id(&token);
+    let submission = outbox().claim_all(|entry| entry.id == id && matches!(entry.pending, Pending::Unconfirmed { .. }))
+        .into_iter()
+        .next()
+        .and_then(|entry| {
+            outbox().complete(&entry.id);
+            match entry.pending {
+                Pending::Unconfirmed { submission } => Some(*submission),
+                _ => None,
+            }
+        });
+    let submission = match submission {
+        Some(submission) => submission,
+        None => {
+            let message = i18n::text(lang, "confirmation_invalid");
+            return page::render_response(StatusCode::NOT_FOUND, None, lang, &[("message", &message), ("back_url", "javascript:history.back()")]);
+        }
+    };
+    info!("Submission {} confirmed", submission.id);
+    if maintenance::holding() {
+        info!("Holding submission {} until maintenance is over", submission.id);
+        if let Some(store) = store::get() {
+            store.update_status(&submission.id, DeliveryStatus::Pending, None, "confirmation");
+        }
+        maintenance::hold(submission.clone());
+        return page::render_response(StatusCode::ACCEPTED, submission.form.as_deref(), lang, &[
+            ("name", &submission.from_name),
+            ("title", &submission.title),
+            ("message", ""),
+            ("back_url", "javascript:history.back()"),
+        ]);
+    }
+    let (status, data) = handler::process(&state, &submission, "confirmation").await;
+    let message = data.message.map(|key| i18n::text(lang, &key)).unwrap_or_default();
+    if !status.is_success() {
+        error!("Confirmed submission {} couldn't be sent: {}", submission.id, message);
+    }
+    page::render_response(status, submission.form.as_deref(), lang, &[
+        ("name", &submission.from_name),
+        ("title", &submission.title),
+        ("message", &message),
+        ("back_url", "javascript:history.back()"),
+    ])
+}
@@ -0,0 +1,169 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use axum::Json;
+use axum::http::{header, HeaderMap};
+use axum::response::{IntoResponse, Response};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use lazy_static::lazy_static;
+use reqwest::Url;
+use serde::Serialize;
+use sha2::Sha256;
+use utoipa::ToSchema;
+use crate::check_var;
+
+lazy_static!(
+    /// Tokens are only issued, and required, when this is set
+    pub static ref SECRET: Option<String> = std::env::var("CSRF_SECRET").ok();
+    static ref TTL_SECS: i64 = std::env::var("CSRF_TOKEN_TTL_SECS").ok()
+        .and_then(|secs| secs.trim().parse().ok())
+        .unwrap_or(DEFAULT_TTL_SECS);
+    /// Nonces of tokens that have been used, and when they expire - after which they'd be rejected
+    /// anyway, so can be forgotten
+    static ref USED: Mutex<HashMap<String, i64>> = Mutex::new(HashMap::new());
+);
+
+const DEFAULT_TTL_SECS: i64 = 3600;
+
+pub fn init() -> Result<(), String> {
+    check_var::<u32>("CSRF_TOKEN_TTL_SECS", "a number of seconds")?;
+    Ok(())
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct TokenResponse {
+    token: String,
+    expires_at: DateTime<Utc>,
+}
+
+/// A valid token that hasn't been used yet
+pub struct Token {
+    nonce: String,
+    expires: i64,
+}
+
+pub enum TokenError {
+    Missing,
+    Invalid,
+    Expired,
+    AlreadyUsed,
+}
+
+/// Browsers send `Origin` on cross-origin requests and on all POSTs, but fall back to the origin of
+/// the `Referer` for same-origin GETs, which don't have one
+pub fn request_origin(headers: &HeaderMap) -> Option<String> {
+    let header = |name| headers.get(name).and_then(|value: &header::HeaderValue| value.to_str().ok());
+    header(header::ORIGIN)
+        .filter(|origin| *origin != "null")
+        .map(|origin| origin.to_string())
+        .or_else(|| header(header::REFERER)
+            .and_then(|referer| Url::parse(referer).ok())
+            .map(|referer| referer.origin().ascii_serialization()))
+}
+
+/// The origin isn't included in the token, so it can only be verified by a request from the same origin
+fn mac(secret: &str, origin: &str, expires: i64, nonce: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(format!("{}|{}|{}", origin, expires, nonce).as_bytes());
+    mac
+}
+
+#[utoipa::path(
+    get,
+    path = "/token",
+    tag = "form",
+    responses(
+        (status = 200, description = "A single-use token to submit with the form as `_token`, valid only for requests from the same origin", body = TokenResponse),
+        (status = 404, description = "Tokens aren't enabled"),
+    ),
+)]
+pub async fn issue(headers: HeaderMap) -> Response {
+    let secret = SECRET.as_deref().expect("the token endpoint is only mounted when a secret is set");
+    let origin = request_origin(&headers).unwrap_or_default();
+    let expires = Utc::now().timestamp() + *TTL_SECS;
+    let nonce = uuid::Uuid::new_v4().simple().to_string();
+    let token = format!("{}.{}.{}", expires, nonce, hex::encode(mac(secret, &origin, expires, &nonce).finalize().into_bytes()));
+    let expires_at = DateTime::from_timestamp(expires, 0).unwrap_or_default();
+    ([(header::CACHE_CONTROL, "no-store")], Json(TokenResponse { token, expires_at })).into_response()
+}
+
+/// Checks the token, without using it up - so a submission turned away by a later check can be
+/// sent again with the same one. Always succeeds (with nothing to [Token::consume]) if tokens aren't
+/// enabled.
+pub fn verify(headers: &HeaderMap, token: Option<&str>) -> Result<Option<Token>, TokenError> {
+    let secret = match SECRET.as_deref() {
+        Some(secret) => secret,
+        None => return Ok(None),
+    };
+    let token = token.ok_or(TokenError::Missing)?;
+    let mut parts = token.splitn(3, '.');
+    let (expires, nonce, signature) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(expires), Some(nonce), Some(signature)) => (expires, nonce, signature),
+        _ => return Err(TokenError::Invalid),
+    };
+    let expires: i64 = expires.parse().map_err(|_| TokenError::Invalid)?;
+    let origin = request_origin(headers).unwrap_or_default();
+    let signature = hex::decode(signature).map_err(|_| TokenError::Invalid)?;
+    // Constant-time comparison
+    mac(secret, &origin, expires, nonce).verify_slice(&signature).map_err(|_| TokenError::Invalid)?;
+
+    let now = Utc::now().timestamp();
+    if expires < now {
+        return Err(TokenError::Expired);
+    }
+    let mut used = USED.lock().unwrap();
+    used.retain(|_, expires| *expires >= now);
+    if used.contains_key(nonce) {
+        return Err(TokenError::AlreadyUsed);
+    }
+    Ok(Some(Token { nonce: nonce.to_string(), expires }))
+}
+
+impl Token {
+    /// Marks the token as used, unless another submission has used it since it was checked
+    pub fn consume(self) -> Result<(), TokenError> {
+        match USED.lock().unwrap().insert(self.nonce, self.expires) {
+            Some(_) => Err(TokenError::AlreadyUsed),
+            None => Ok(()),
+        }
+    }
+}
+
+impl TokenError {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            TokenError::Missing => "missing",
+            TokenError::Invalid => "invalid (or from a different origin)",
+            TokenError::Expired => "expired",
+            TokenError::AlreadyUsed => "already used",
+        }
+    }
+}
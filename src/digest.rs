@@ -0,0 +1,171 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+use lazy_static::lazy_static;
+use log::{error, info};
+use crate::{alert, concurrency, extra_headers, stats, timestamps, TO};
+use crate::provider::{Email, MailProvider};
+use crate::outbox::{outbox, Pending};
+use crate::store::{self, DeliveryStatus, Submission};
+
+lazy_static!(
+    /// How often to send digests. Submissions are emailed individually if this isn't set.
+    pub static ref INTERVAL: Option<Duration> = std::env::var("DIGEST_INTERVAL").ok().and_then(|interval| parse_interval(&interval));
+    pub static ref FROM: Option<String> = std::env::var("DIGEST_FROM_ADDRESS").ok()
+        .or_else(|| std::env::var("MAILGUN_DOMAIN").ok().map(|domain| format!("Contact form <postmaster@{}>", domain)));
+);
+
+fn parse_interval(interval: &str) -> Option<Duration> {
+    match interval.trim() {
+        "hourly" => Some(Duration::from_secs(60 * 60)),
+        "daily" => Some(Duration::from_secs(24 * 60 * 60)),
+        secs => secs.parse().ok().filter(|secs| *secs > 0).map(Duration::from_secs),
+    }
+}
+
+/// Checks `DIGEST_INTERVAL`, whether or not there's anything to send digests with
+pub fn init() -> Result<(), String> {
+    match std::env::var("DIGEST_INTERVAL") {
+        Ok(interval) if parse_interval(&interval).is_none() => {
+            Err(format!("\"DIGEST_INTERVAL\" must be `hourly`, `daily` or a number of seconds, not {}", interval))
+        }
+        _ => Ok(()),
+    }
+}
+
+pub fn enabled() -> bool {
+    INTERVAL.is_some()
+}
+
+/// Adds the submission to the next digest, by way of the outbox
+pub fn queue(submission: Submission) {
+    let due_at = chrono::Utc::now() + chrono::Duration::from_std(INTERVAL.unwrap_or_default()).unwrap_or(chrono::Duration::zero());
+    outbox().add(submission.id.clone(), due_at, Pending::Digest { submission: Box::new(submission) });
+}
+
+fn waiting() -> usize {
+    outbox().entries().iter().filter(|entry| matches!(entry.pending, Pending::Digest { .. })).count()
+}
+
+/// Starts sending digests in the background, if enabled
+pub fn start(provider: Arc<dyn MailProvider>) -> Result<(), String> {
+    let interval = match *INTERVAL {
+        Some(interval) => interval,
+        None => return Ok(()),
+    };
+    let from = FROM.clone().ok_or("\"DIGEST_INTERVAL\" is set, but there's no \"DIGEST_FROM_ADDRESS\" (or \"MAILGUN_DOMAIN\" to default it from)")?;
+    info!("Sending submissions as a digest every {}s", interval.as_secs());
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(interval);
+        // The first tick completes immediately, when there's only something to send if it was
+        // waiting when the service last stopped
+        ticks.tick().await;
+        if waiting() > 0 {
+            info!("Sending the digest that was waiting before the last restart");
+            send(provider.as_ref(), &from).await;
+        }
+        loop {
+            ticks.tick().await;
+            send(provider.as_ref(), &from).await;
+        }
+    });
+    Ok(())
+}
+
+/// Emails everything that's been queued, as one digest per recipient. Anything that can't be sent
+/// is left in the outbox for next time.
+pub async fn send(provider: &dyn MailProvider, from: &str) {
+    let mut by_recipient: BTreeMap<String, Vec<Submission>> = BTreeMap::new();
+    let claimed = outbox().claim_all(|entry| matches!(entry.pending, Pending::Digest { .. }));
+    for entry in claimed {
+        if let Pending::Digest { submission } = entry.pending {
+            let to = submission.to.clone().unwrap_or_else(|| TO.clone());
+            by_recipient.entry(to).or_default().push(*submission);
+        }
+    }
+    for (to, submissions) in by_recipient {
+        let subject = match submissions.len() {
+            1 => "1 new contact form submission".to_string(),
+            count => format!("{} new contact form submissions", count),
+        };
+        let email = Email {
+            from: from.to_string(),
+            to: to.clone(),
+            subject,
+            text: render(&submissions),
+            submission_ids: submissions.iter().map(|submission| submission.id.clone()).collect(),
+            // Not threaded, as it covers several submitters, so doesn't belong in any of their threads
+            headers: extra_headers::configured(None),
+            attachments: Vec::new(),
+        };
+        let sent = {
+            let _slot = concurrency::wait().await;
+            provider.send(&email).await
+        };
+        match sent {
+            Ok(()) => {
+                info!("Sent a digest of {} submission(s) to {}", submissions.len(), to);
+                for submission in submissions.iter() {
+                    stats::record_status(submission.form.as_deref(), DeliveryStatus::Sent);
+                }
+                if let Some(store) = store::get() {
+                    for submission in submissions.iter() {
+                        store.update_status(&submission.id, DeliveryStatus::Sent, None, "digest");
+                    }
+                }
+                for submission in submissions.iter() {
+                    outbox().complete(&submission.id);
+                }
+            }
+            Err(e) => {
+                error!("Couldn't send a digest to {}, will try again next time: {}", to, e);
+                alert::failure(&e.to_string());
+                for entry in outbox().entries().into_iter().filter(|entry| submissions.iter().any(|submission| submission.id == entry.id)) {
+                    outbox().release(entry);
+                }
+            }
+        }
+    }
+}
+
+fn render(submissions: &[Submission]) -> String {
+    submissions.iter()
+        .map(|submission| {
+            let mut text = format!("From: {} <{}>\n", submission.from_name, submission.from_email);
+            if let Some(form) = submission.form.as_deref() {
+                text.push_str(&format!("Form: {}\n", form));
+            }
+            text.push_str(&format!("Received: {}\nSubject: {}\n\n{}\n", timestamps::format(submission.received_at), submission.title, submission.text()));
+            text
+        })
+        .collect::<Vec<_>>()
+        .join("\n----------------------------------------\n\n")
+}
@@ -0,0 +1,111 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+use chrono::Utc;
+use log::info;
+use serde::Serialize;
+use crate::{form_var, truncate, CLIENT};
+use crate::store::Submission;
+
+// Discord rejects embeds that exceed any of these
+const MAX_TITLE_LENGTH: usize = 256;
+const MAX_DESCRIPTION_LENGTH: usize = 4096;
+const MAX_FIELD_LENGTH: usize = 1024;
+
+#[derive(Serialize)]
+struct DiscordMessage {
+    embeds: Vec<Embed>,
+    allowed_mentions: AllowedMentions,
+}
+
+/// An empty list stops "@everyone" and friends in a submission from pinging the whole server
+#[derive(Serialize)]
+struct AllowedMentions {
+    parse: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct Embed {
+    title: String,
+    description: String,
+    fields: Vec<EmbedField>,
+    timestamp: String,
+}
+
+#[derive(Serialize)]
+struct EmbedField {
+    name: &'static str,
+    value: String,
+    inline: bool,
+}
+
+impl EmbedField {
+    fn new(name: &'static str, value: &str) -> Self {
+        EmbedField { name, value: truncate(value, MAX_FIELD_LENGTH), inline: true }
+    }
+}
+
+/// The webhook for the given form, falling back to the global `DISCORD_WEBHOOK_URL`
+pub fn webhook_url(form: Option<&str>) -> Option<String> {
+    form_var(form, "DISCORD_WEBHOOK_URL")
+}
+
+/// Whether any form at all might send to Discord
+pub fn configured() -> bool {
+    std::env::vars().any(|(name, _)| name == "DISCORD_WEBHOOK_URL" || (name.starts_with("FORM_") && name.ends_with("_DISCORD_WEBHOOK_URL")))
+}
+
+pub async fn notify(url: &str, submission: &Submission) -> Result<(), String> {
+    let mut fields = vec![EmbedField::new("Name", &submission.from_name), EmbedField::new("Email", &submission.from_email)];
+    if let Some(form) = &submission.form {
+        fields.push(EmbedField::new("Form", form));
+    }
+    let message = DiscordMessage {
+        embeds: vec![Embed {
+            title: truncate(&submission.title, MAX_TITLE_LENGTH),
+            description: truncate(&submission.body, MAX_DESCRIPTION_LENGTH),
+            fields,
+            timestamp: Utc::now().to_rfc3339(),
+        }],
+        allowed_mentions: AllowedMentions { parse: Vec::new() },
+    };
+    let response = CLIENT.post(url)
+        .json(&message)
+        .send()
+        .await
+        .map_err(|e| format!("Error calling Discord: {}", e))?;
+
+    if response.status().is_success() {
+        info!("Discord notification sent successfully");
+        Ok(())
+    } else {
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        Err(format!("Discord returned {}: {}", status, body))
+    }
+}
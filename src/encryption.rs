@@ -0,0 +1,153 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+//! Encrypts the submissions file, so a copy of it leaking doesn't leak every submitter's message.
+//! Each save gets a new random data key, encrypted (AES-256-GCM, as is the data) with the
+//! configured key, so the configured key can be rotated without re-encrypting anything up front.
+
+use std::sync::OnceLock;
+use log::info;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+const ALGORITHM: &str = "AES-256-GCM";
+const KEY_LEN: usize = 32;
+
+/// The current key comes first, followed by any previous ones, which are only used for reading
+static KEYS: OnceLock<Vec<Key>> = OnceLock::new();
+
+struct Key {
+    /// Identifies the key without giving anything away, so the right one can be picked to decrypt with
+    id: String,
+    key: LessSafeKey,
+}
+
+impl Key {
+    fn parse(hex_key: &str) -> Result<Self, String> {
+        let bytes = hex::decode(hex_key.trim()).map_err(|_| "must be hex".to_string())?;
+        if bytes.len() != KEY_LEN {
+            return Err(format!("must be {} bytes ({} hex characters), not {}", KEY_LEN, KEY_LEN * 2, bytes.len()));
+        }
+        let id = hex::encode(&Sha256::digest(&bytes)[..4]);
+        Ok(Key { id, key: LessSafeKey::new(UnboundKey::new(&AES_256_GCM, &bytes).expect("key is the right length")) })
+    }
+}
+
+/// What's written in place of the plaintext
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Envelope {
+    algorithm: String,
+    /// Which of the configured keys the data key was encrypted with
+    key_id: String,
+    /// The data key, encrypted with the configured key, as hex
+    data_key: String,
+    /// The contents, encrypted with the data key, as hex
+    data: String,
+}
+
+/// Reads `ENCRYPTION_KEY` (or the file at `ENCRYPTION_KEY_FILE`, for keys from a secrets manager)
+/// and `ENCRYPTION_PREVIOUS_KEYS`
+pub fn init() -> Result<(), String> {
+    let current = match (std::env::var("ENCRYPTION_KEY"), std::env::var("ENCRYPTION_KEY_FILE")) {
+        (Ok(key), _) => key,
+        (Err(_), Ok(path)) => std::fs::read_to_string(&path).map_err(|e| format!("Unable to read \"ENCRYPTION_KEY_FILE\" {}: {}", path, e))?,
+        (Err(_), Err(_)) => {
+            if std::env::var("ENCRYPTION_PREVIOUS_KEYS").is_ok() {
+                return Err("\"ENCRYPTION_PREVIOUS_KEYS\" is set, but \"ENCRYPTION_KEY\" isn't".to_string());
+            }
+            return Ok(());
+        }
+    };
+    let mut keys = vec![Key::parse(&current).map_err(|e| format!("The encryption key {}", e))?];
+    for previous in std::env::var("ENCRYPTION_PREVIOUS_KEYS").unwrap_or_default().split(',').filter(|key| !key.trim().is_empty()) {
+        keys.push(Key::parse(previous).map_err(|e| format!("\"ENCRYPTION_PREVIOUS_KEYS\" entries {}", e))?);
+    }
+    info!("Encrypting stored submissions with key {}", keys[0].id);
+    let _ = KEYS.set(keys);
+    Ok(())
+}
+
+fn seal_with(key: &LessSafeKey, aad: &str, plaintext: &[u8]) -> Result<Vec<u8>, String> {
+    let mut nonce = [0; NONCE_LEN];
+    SystemRandom::new().fill(&mut nonce).map_err(|_| "no randomness available")?;
+    let mut sealed = plaintext.to_vec();
+    key.seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::from(aad.as_bytes()), &mut sealed)
+        .map_err(|_| "encryption failed")?;
+    Ok([nonce.as_slice(), &sealed].concat())
+}
+
+fn open_with(key: &LessSafeKey, aad: &str, sealed: &[u8]) -> Result<Vec<u8>, String> {
+    if sealed.len() < NONCE_LEN {
+        return Err("it's truncated".to_string());
+    }
+    let (nonce, sealed) = sealed.split_at(NONCE_LEN);
+    let mut opened = sealed.to_vec();
+    let nonce = Nonce::try_assume_unique_for_key(nonce).expect("nonce is the right length");
+    let plaintext = key.open_in_place(nonce, Aad::from(aad.as_bytes()), &mut opened)
+        .map_err(|_| "it's been tampered with, or encrypted with a different key")?;
+    Ok(plaintext.to_vec())
+}
+
+/// The contents to write - encrypted if there's a key, or as they are if not
+pub fn seal(plaintext: Vec<u8>) -> Result<Vec<u8>, String> {
+    let key = match KEYS.get().and_then(|keys| keys.first()) {
+        Some(key) => key,
+        None => return Ok(plaintext),
+    };
+    let mut data_key = [0; KEY_LEN];
+    SystemRandom::new().fill(&mut data_key).map_err(|_| "no randomness available")?;
+    let data = seal_with(&LessSafeKey::new(UnboundKey::new(&AES_256_GCM, &data_key).expect("key is the right length")), ALGORITHM, &plaintext)?;
+    let envelope = Envelope {
+        algorithm: ALGORITHM.to_string(),
+        key_id: key.id.clone(),
+        data_key: hex::encode(seal_with(&key.key, &key.id, &data_key)?),
+        data: hex::encode(data),
+    };
+    serde_json::to_vec(&envelope).map_err(|e| e.to_string())
+}
+
+/// The plaintext of what was read. Anything that isn't encrypted is returned as it is, so turning
+/// encryption on doesn't lose what was stored before - it's encrypted the next time it's saved.
+pub fn open(contents: Vec<u8>) -> Result<Vec<u8>, String> {
+    let envelope: Envelope = match serde_json::from_slice(&contents) {
+        Ok(envelope) => envelope,
+        Err(_) => return Ok(contents),
+    };
+    if envelope.algorithm != ALGORITHM {
+        return Err(format!("it's encrypted with {}, which isn't supported", envelope.algorithm));
+    }
+    let key = KEYS.get().into_iter().flatten().find(|key| key.id == envelope.key_id)
+        .ok_or_else(|| format!("it's encrypted with key {}, which isn't \"ENCRYPTION_KEY\" or in \"ENCRYPTION_PREVIOUS_KEYS\"", envelope.key_id))?;
+    let hex = |value: &str| hex::decode(value).map_err(|_| "it's corrupt".to_string());
+    let data_key = open_with(&key.key, &key.id, &hex(&envelope.data_key)?)?;
+    let data_key = UnboundKey::new(&AES_256_GCM, &data_key).map_err(|_| "it's corrupt".to_string())?;
+    open_with(&LessSafeKey::new(data_key), ALGORITHM, &hex(&envelope.data)?)
+}
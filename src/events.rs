@@ -0,0 +1,92 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+//! A live feed of what's happening to submissions, streamed to admins as Server-Sent Events
+
+use std::convert::Infallible;
+use std::time::Duration;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use lazy_static::lazy_static;
+use serde::Serialize;
+use tokio::sync::broadcast;
+use tokio_stream::{Stream, StreamExt};
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use utoipa::ToSchema;
+use crate::store::{DeliveryStatus, Submission};
+
+/// How many events a slow listener can fall behind by before it starts missing them
+const CAPACITY: usize = 256;
+/// Often enough to stop proxies closing an idle stream
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+lazy_static!(
+    static ref CHANNEL: broadcast::Sender<SubmissionEvent> = broadcast::channel(CAPACITY).0;
+);
+
+#[derive(Clone, Serialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SubmissionEvent {
+    /// A new submission, sent as the `received` event
+    Received { submission: Box<Submission> },
+    /// A submission's delivery status has changed, sent as the `status_changed` event
+    StatusChanged {
+        id: String,
+        form: Option<String>,
+        status: DeliveryStatus,
+        message: Option<String>,
+    },
+}
+
+impl SubmissionEvent {
+    fn name(&self) -> &'static str {
+        match self {
+            SubmissionEvent::Received { .. } => "received",
+            SubmissionEvent::StatusChanged { .. } => "status_changed",
+        }
+    }
+}
+
+/// Sends the event to everyone listening, if anyone is
+pub fn publish(event: SubmissionEvent) {
+    // Only fails when there's nobody listening
+    let _ = CHANNEL.send(event);
+}
+
+/// Every event from now on, as a stream for the admin API. A listener that falls too far behind is
+/// sent a `lagged` event with how many events it missed.
+pub fn stream() -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let events = BroadcastStream::new(CHANNEL.subscribe()).map(|event| Ok(match event {
+        Ok(event) => Event::default()
+            .event(event.name())
+            .json_data(&event)
+            .expect("submission events can be serialized"),
+        Err(BroadcastStreamRecvError::Lagged(missed)) => Event::default().event("lagged").data(missed.to_string()),
+    }));
+    Sse::new(events).keep_alive(KeepAlive::new().interval(KEEP_ALIVE))
+}
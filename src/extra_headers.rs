@@ -0,0 +1,69 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+//! Static headers added to every outgoing email, so mail rules can pick out contact form traffic
+
+use std::collections::BTreeMap;
+use log::{error, info};
+use crate::form_var;
+
+/// Set from the submission itself, so they can't be overridden
+const RESERVED: &[&str] = &["from", "to", "cc", "bcc", "subject"];
+
+fn parse(json: &str) -> Result<BTreeMap<String, String>, String> {
+    let headers: BTreeMap<String, String> = serde_json::from_str(json).map_err(|e| format!("must be a JSON object of header names to values: {}", e))?;
+    match headers.keys().find(|name| RESERVED.contains(&name.to_lowercase().as_str())) {
+        Some(name) => Err(format!("can't set {}", name)),
+        None => Ok(headers),
+    }
+}
+
+/// Checks `EMAIL_HEADERS`, and every per-form override of it, now, so mistakes are reported at
+/// startup rather than silently leaving the headers off
+pub fn init() -> Result<(), String> {
+    for (name, json) in std::env::vars() {
+        if name == "EMAIL_HEADERS" || (name.starts_with("FORM_") && name.ends_with("_EMAIL_HEADERS")) {
+            let headers = parse(&json).map_err(|e| format!("\"{}\" {}", name, e))?;
+            info!("Adding {} header(s) from {} to emails", headers.len(), name);
+        }
+    }
+    Ok(())
+}
+
+/// The (per-form) `EMAIL_HEADERS`
+pub fn configured(form: Option<&str>) -> BTreeMap<String, String> {
+    match form_var(form, "EMAIL_HEADERS").map(|json| parse(&json)) {
+        Some(Ok(headers)) => headers,
+        Some(Err(e)) => {
+            // Only possible if the environment's changed since startup
+            error!("Ignoring \"EMAIL_HEADERS\", which {}", e);
+            BTreeMap::new()
+        }
+        None => BTreeMap::new(),
+    }
+}
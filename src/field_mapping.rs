@@ -0,0 +1,97 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+//! Renaming fields as they're received, so frontends built for other form services (with fields
+//! like `email` and `message`) can be pointed at this one without changing their HTML
+
+use std::collections::{BTreeMap, HashMap};
+use log::{error, info};
+use crate::form_var;
+use crate::validation::FieldError;
+
+/// The only hidden field that can be mapped to - the others configure or protect the form, so have
+/// to be sent under their own names
+const MAPPABLE_HIDDEN_FIELDS: &[&str] = &["_redirect"];
+
+fn parse(json: &str) -> Result<BTreeMap<String, String>, String> {
+    let mapping: BTreeMap<String, String> = serde_json::from_str(json).map_err(|e| format!("must be a JSON object of field names to the names to use instead: {}", e))?;
+    match mapping.values().find(|target| target.starts_with('_') && !MAPPABLE_HIDDEN_FIELDS.contains(&target.as_str())) {
+        Some(target) => Err(format!("can't map to {}", target)),
+        None => Ok(mapping),
+    }
+}
+
+/// Checks `FIELD_MAPPING`, and every per-form override of it, now, so mistakes are reported at
+/// startup rather than leaving every submission missing its fields
+pub fn init() -> Result<(), String> {
+    for (name, json) in std::env::vars() {
+        if name == "FIELD_MAPPING" || (name.starts_with("FORM_") && name.ends_with("_FIELD_MAPPING")) {
+            let mapping = parse(&json).map_err(|e| format!("\"{}\" {}", name, e))?;
+            info!("Renaming {} field(s) from {}", mapping.len(), name);
+        }
+    }
+    Ok(())
+}
+
+/// The (per-form) `FIELD_MAPPING`
+fn configured(form: Option<&str>) -> BTreeMap<String, String> {
+    match form_var(form, "FIELD_MAPPING").map(|json| parse(&json)) {
+        Some(Ok(mapping)) => mapping,
+        Some(Err(e)) => {
+            // Only possible if the environment's changed since startup
+            error!("Ignoring \"FIELD_MAPPING\", which {}", e);
+            BTreeMap::new()
+        }
+        None => BTreeMap::new(),
+    }
+}
+
+/// Renames the submitted fields as the form's mapping says. A field that's also been sent under the
+/// name it'd be mapped to is left as it is.
+pub fn apply(mut fields: HashMap<String, String>) -> HashMap<String, String> {
+    for (source, target) in configured(fields.get("_form").map(|form| form.as_str())) {
+        if fields.contains_key(&target) {
+            continue;
+        }
+        if let Some(value) = fields.remove(&source) {
+            fields.insert(target, value);
+        }
+    }
+    fields
+}
+
+/// Reports problems with mapped fields under the names they were sent as, so the frontend can
+/// highlight the right inputs
+pub fn unmap(form: Option<&str>, errors: &mut [FieldError]) {
+    let mapping = configured(form);
+    for error in errors.iter_mut() {
+        if let Some((source, _)) = mapping.iter().find(|(_, target)| **target == error.field) {
+            error.field = source.clone();
+        }
+    }
+}
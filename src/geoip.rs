@@ -0,0 +1,143 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use lazy_static::lazy_static;
+
+lazy_static!(
+    static ref ALLOW: Vec<String> = countries("GEOIP_ALLOW_COUNTRIES");
+    static ref DENY: Vec<String> = countries("GEOIP_DENY_COUNTRIES");
+    /// Multipliers for `IP_RATE_LIMIT`, by country
+    static ref RATE_MULTIPLIERS: HashMap<String, f64> = std::env::var("GEOIP_RATE_MULTIPLIERS")
+        .map(|multipliers| multipliers.split(',')
+            .filter_map(|pair| pair.split_once('='))
+            .filter_map(|(country, multiplier)| Some((country.trim().to_uppercase(), multiplier.trim().parse().ok()?)))
+            .collect())
+        .unwrap_or_default();
+);
+
+fn countries(name: &str) -> Vec<String> {
+    std::env::var(name)
+        .map(|countries| countries.split(',')
+            .map(|country| country.trim().to_uppercase())
+            .filter(|country| !country.is_empty())
+            .collect())
+        .unwrap_or_default()
+}
+
+/// Loads the database, if configured. Must be called before the server starts.
+pub fn init() -> Result<(), String> {
+    let multipliers = std::env::var("GEOIP_RATE_MULTIPLIERS").unwrap_or_default();
+    for pair in multipliers.split(',').filter(|pair| !pair.trim().is_empty()) {
+        if pair.split_once('=').and_then(|(_, multiplier)| multiplier.trim().parse::<f64>().ok()).is_none() {
+            return Err(format!("\"GEOIP_RATE_MULTIPLIERS\" must look like `CN=0.1,RU=0.5`, not {}", multipliers));
+        }
+    }
+    match std::env::var("GEOIP_DATABASE") {
+        Ok(path) => maxmind::init(&path),
+        Err(_) if !ALLOW.is_empty() || !DENY.is_empty() || !RATE_MULTIPLIERS.is_empty() => {
+            Err("Country filtering is configured, but \"GEOIP_DATABASE\" isn't set".to_string())
+        }
+        Err(_) => Ok(()),
+    }
+}
+
+/// The ISO code of the country the address is in, if there's a database and it knows
+pub fn country(ip: IpAddr) -> Option<String> {
+    maxmind::country(ip)
+}
+
+/// Whether submissions are accepted from the country. Addresses that can't be located (such as
+/// private ones) are always allowed.
+pub fn allowed(country: Option<&str>) -> bool {
+    match country {
+        Some(country) => (ALLOW.is_empty() || ALLOW.iter().any(|allowed| allowed == country))
+            && !DENY.iter().any(|denied| denied == country),
+        None => true,
+    }
+}
+
+/// How much to scale the per-IP rate limit by for addresses in the country
+pub fn rate_multiplier(country: Option<&str>) -> f64 {
+    country.and_then(|country| RATE_MULTIPLIERS.get(country)).copied().unwrap_or(1.0)
+}
+
+#[cfg(not(feature = "geoip"))]
+mod maxmind {
+    use std::net::IpAddr;
+
+    pub fn init(_path: &str) -> Result<(), String> {
+        Err("\"GEOIP_DATABASE\" is set, but this build doesn't include GeoIP support - rebuild with `--features geoip`".to_string())
+    }
+
+    pub fn country(_ip: IpAddr) -> Option<String> {
+        None
+    }
+}
+
+#[cfg(feature = "geoip")]
+mod maxmind {
+    use std::net::IpAddr;
+    use std::sync::OnceLock;
+    use log::{info, warn};
+    use maxminddb::Reader;
+    use serde::Deserialize;
+
+    static DATABASE: OnceLock<Reader<Vec<u8>>> = OnceLock::new();
+
+    /// The part of a GeoLite2 Country or City record we need
+    #[derive(Deserialize)]
+    struct Record {
+        country: Option<Country>,
+    }
+
+    #[derive(Deserialize)]
+    struct Country {
+        iso_code: Option<String>,
+    }
+
+    pub fn init(path: &str) -> Result<(), String> {
+        let reader = Reader::open_readfile(path)
+            .map_err(|e| format!("Couldn't open GeoIP database {}: {}", path, e))?;
+        info!("Looking up submitters' countries in {} ({})", path, reader.metadata().database_type);
+        let _ = DATABASE.set(reader);
+        Ok(())
+    }
+
+    pub fn country(ip: IpAddr) -> Option<String> {
+        let record = match DATABASE.get()?.lookup(ip).and_then(|result| result.decode::<Record>()) {
+            Ok(record) => record?,
+            Err(e) => {
+                warn!("Couldn't look up {} in the GeoIP database: {}", ip, e);
+                return None;
+            }
+        };
+        record.country?.iso_code
+    }
+}
@@ -0,0 +1,525 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use axum::Json;
+use axum::extract::{ConnectInfo, OriginalUri, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Redirect, Response};
+use log::{error, info, warn};
+use crate::{alert, api_keys, attachments, broker, caps, client_ip, concurrency, confirm, csrf, digest, discord, extra_headers, field_mapping, geoip, i18n, language, mailing_list, maintenance, metadata, page, pow, ratelimit, redirect, referrer, response, retry, rotation, sheets, signing, slack, spam, stats, telegram, threading, validation, vcard, webhook, widget};
+use crate::{ContactFormError, FormData, ResponseData, ResponseStatus, TO};
+use crate::concurrency::Slot;
+use crate::provider::{self, Email, MailProvider, ProviderError};
+use crate::multipart::FormBody;
+use crate::ratelimit::Quota;
+use crate::senders;
+use crate::service::AppState;
+use crate::store::{self, DeliveryStatus, Submission};
+
+#[utoipa::path(
+    post,
+    path = "/",
+    tag = "form",
+    request_body(description = "Multipart bodies can also have files attached, if `ATTACHMENTS_S3_BUCKET` is set", content(
+        (FormData = "application/x-www-form-urlencoded"),
+        (FormData = "multipart/form-data"),
+    )),
+    responses(
+        (status = 200, description = "Submission sent", body = ResponseData),
+        (status = 202, description = "Submission queued to be sent later - in the next digest, once the mail provider stops rate limiting, once maintenance is over, once a send cap resets, or once the submitter follows the link in the confirmation email", body = ResponseData),
+        (status = 303, description = "Submission handled, redirecting a plain HTML form to the success or error page"),
+        (status = 400, description = "The body couldn't be parsed", body = ResponseData),
+        (status = 413, description = "The body (with any attachments) is too large", body = ResponseData),
+        (status = 415, description = "The body wasn't form-encoded or multipart", body = ResponseData),
+        (status = 401, description = "API keys are configured, and the key is missing, unknown or used from an origin it isn't allowed from", body = ResponseData),
+        (status = 403, description = "Tokens are enabled and `_token` is missing, invalid, expired or already used, proof-of-work is enabled and the challenge isn't solved, a signing secret is set and the hidden configuration fields are unsigned or tampered with, or the submitter's country or email address is blocked (or not allowed), or a processor rejected the submission", body = ResponseData),
+        (status = 422, description = "Some fields are missing or invalid - see `errors`", body = ResponseData),
+        (status = 429, description = "The API key, IP address or sender's email address has been used too often, or a daily or monthly send cap has been reached - see `retry_after` and the `Retry-After` header", body = ResponseData),
+        (status = 500, description = "Internal error, or the mail agent rejected our credentials", body = ResponseData),
+        (status = 502, description = "The mail agent or notification service returned an error, Mailgun wouldn't add the submitter to the mailing list, or the attachments couldn't be stored", body = ResponseData),
+        (status = 503, description = "Maintenance mode is on, and submissions aren't being held, or too many emails are already being sent - see `retry_after` and the `Retry-After` header", body = ResponseData),
+    ),
+)]
+pub async fn send_form(State(state): State<AppState>, peer: Option<ConnectInfo<SocketAddr>>, OriginalUri(uri): OriginalUri, headers: HeaderMap, form: Result<FormBody, (StatusCode, String)>) -> Response {
+    let (fields, files) = match form {
+        Ok(FormBody { fields, files }) => (field_mapping::apply(fields), files),
+        Err((status, message)) => {
+            let data = ResponseData { status: ResponseStatus::InvalidRequest, message: Some(message), errors: None, retry_after: None };
+            return (status, Json(data)).into_response();
+        }
+    };
+    // Unless they're being held, there's no point checking submissions that can't be sent
+    if maintenance::active() && !*maintenance::HOLD {
+        let data = ResponseData { status: ResponseStatus::Maintenance, message: Some(maintenance::message()), errors: None, retry_after: None };
+        return respond(&headers, &fields, StatusCode::SERVICE_UNAVAILABLE, data, false, None);
+    }
+    let file_errors = attachments::check(&files);
+    let mut req = match validation::validate(&fields) {
+        Ok(req) if file_errors.is_empty() => req,
+        result => {
+            let mut errors = result.err().unwrap_or_default();
+            errors.extend(file_errors);
+            field_mapping::unmap(fields.get("_form").map(|form| form.as_str()), &mut errors);
+            info!("Rejecting submission with {} invalid field(s)", errors.len());
+            let data = ResponseData { status: ResponseStatus::ValidationError, message: Some("validation_error".to_string()), errors: Some(errors), retry_after: None };
+            return respond(&headers, &fields, StatusCode::UNPROCESSABLE_ENTITY, data, false, None);
+        }
+    };
+    // Checked after validation, so a visitor fixing a typo doesn't also need a new token
+    let token = match csrf::verify(&headers, fields.get("_token").map(|token| token.as_str())) {
+        Ok(token) => token,
+        Err(e) => return invalid_token(&headers, &fields, e),
+    };
+    if let Err(e) = pow::verify(fields.get("_challenge").map(|c| c.as_str()), fields.get("_solution").map(|s| s.as_str())) {
+        info!("Rejecting submission with a proof-of-work challenge that's {}", e.describe());
+        let data = ResponseData { status: ResponseStatus::InvalidChallenge, message: Some("invalid_challenge".to_string()), errors: None, retry_after: None };
+        return respond(&headers, &fields, StatusCode::FORBIDDEN, data, false, None);
+    }
+    let signed = match signing::verify(&fields) {
+        Ok(signed) => signed,
+        Err(()) => {
+            info!("Rejecting submission with a missing or invalid signature");
+            let data = ResponseData { status: ResponseStatus::InvalidSignature, message: Some("invalid_signature".to_string()), errors: None, retry_after: None };
+            return respond(&headers, &fields, StatusCode::FORBIDDEN, data, false, None);
+        }
+    };
+    let mut quotas = match api_keys::check(&headers, fields.get("_key").map(|key| key.as_str())) {
+        Ok(quotas) => quotas,
+        Err(e) => {
+            info!("Rejecting submission with an API key that's {}", e.describe());
+            return match e {
+                api_keys::KeyError::RateLimited(quota) | api_keys::KeyError::QuotaExceeded(quota) => rate_limited(&headers, &fields, "rate_limited", quota),
+                _ => {
+                    let data = ResponseData { status: ResponseStatus::InvalidApiKey, message: Some("invalid_api_key".to_string()), errors: None, retry_after: None };
+                    respond(&headers, &fields, StatusCode::UNAUTHORIZED, data, false, None)
+                }
+            };
+        }
+    };
+    // Only unknown if the service has been mounted in an app that doesn't provide it
+    let ip = peer.map(|ConnectInfo(peer)| client_ip::resolve(peer.ip(), &headers));
+    if let Some(ip) = ip {
+        let country = geoip::country(ip);
+        if !geoip::allowed(country.as_deref()) {
+            info!("Rejecting submission from {} in {}", ip, country.as_deref().unwrap_or_default());
+            let data = ResponseData { status: ResponseStatus::Blocked, message: Some("blocked".to_string()), errors: None, retry_after: None };
+            return respond(&headers, &fields, StatusCode::FORBIDDEN, data, false, None);
+        }
+        match ratelimit::check_ip(ip, geoip::rate_multiplier(country.as_deref())) {
+            Ok(quota) => quotas.extend(quota),
+            Err(quota) => {
+                info!("Rejecting submission from {}, which has sent too many", ip);
+                return rate_limited(&headers, &fields, "rate_limited", quota);
+            }
+        }
+    }
+    if let Err(refusal) = senders::senders().check(&req.from_email) {
+        info!("Rejecting submission from {}, who is {:?}", req.from_email, refusal);
+        let data = ResponseData { status: ResponseStatus::Blocked, message: Some("sender_blocked".to_string()), errors: None, retry_after: None };
+        return with_quotas(respond(&headers, &fields, StatusCode::FORBIDDEN, data, false, None), &quotas);
+    }
+    match ratelimit::check_sender(&req.from_email) {
+        Ok(quota) => quotas.extend(quota),
+        Err(quota) => {
+            info!("Rejecting submission from {}, who has sent too many", req.from_email);
+            return rate_limited(&headers, &fields, "sender_rate_limited", quota);
+        }
+    }
+    // Only once nothing else can turn the submission away, so it can be sent again with the same token
+    if let Some(Err(e)) = token.map(csrf::Token::consume) {
+        return with_quotas(invalid_token(&headers, &fields, e), &quotas);
+    }
+    if let Some(to) = fields.get("_to") {
+        if let Some(address) = signing::resolve_recipient(to) {
+            req.to = Some(address.to_string());
+        } else if signed {
+            req.to = Some(to.clone());
+        } else {
+            info!("Ignoring unsigned recipient override");
+        }
+    }
+
+    let mut submission = Submission::new(&req);
+    let page_url = referrer::resolve(req.form.as_deref(), req.page_url.as_deref(), &headers);
+    if submission.to.is_none() {
+        submission.to = page_url.as_deref().and_then(|url| referrer::route(submission.form.as_deref(), url))
+            .or_else(|| submission.language.as_deref().and_then(|lang| language::route(submission.form.as_deref(), lang)))
+            .or_else(|| rotation::pick(submission.form.as_deref()));
+    }
+    submission.metadata = metadata::collect(req.form.as_deref(), ip, &headers, page_url);
+    if !files.is_empty() {
+        match attachments::upload(&submission.id, files).await {
+            Ok(stored) => submission.attachments = stored,
+            Err(e) => {
+                error!("Couldn't store the attachments for submission {}: {}", submission.id, e);
+                let data = ResponseData { status: ResponseStatus::AttachmentError, message: Some("attachment_error".to_string()), errors: None, retry_after: None };
+                return with_quotas(respond(&headers, &fields, StatusCode::BAD_GATEWAY, data, signed, None), &quotas);
+            }
+        }
+    }
+    for processor in state.processors.iter() {
+        if let Err(rejection) = processor.before_send(&mut submission).await {
+            if rejection.quarantine {
+                info!("Submission quarantined by the {} processor", processor.name());
+                stats::record(submission.form.as_deref(), stats::Event::Received);
+                quarantine(&mut submission, rejection.message);
+                let data = ResponseData { status: ResponseStatus::Ok, message: None, errors: None, retry_after: None };
+                return with_quotas(respond(&headers, &fields, StatusCode::OK, data, signed, Some(&submission.id)), &quotas);
+            }
+            info!("Submission rejected by the {} processor: {}", processor.name(), rejection.message);
+            attachments::discard(std::mem::take(&mut submission.attachments));
+            let data = ResponseData { status: ResponseStatus::Rejected, message: Some(rejection.message), errors: None, retry_after: None };
+            return with_quotas(respond(&headers, &fields, StatusCode::FORBIDDEN, data, signed, None), &quotas);
+        }
+    }
+    stats::record(submission.form.as_deref(), stats::Event::Received);
+    submission.spam_score = spam::classifier().and_then(|classifier| classifier.score(&submission));
+    if let Some(score) = submission.spam_score.filter(|score| spam::is_spam(*score)) {
+        // Don't let spammers know they've been caught
+        quarantine(&mut submission, format!("scored {:.3} as spam", score));
+        let data = ResponseData { status: ResponseStatus::Ok, message: None, errors: None, retry_after: None };
+        return with_quotas(respond(&headers, &fields, StatusCode::OK, data, signed, Some(&submission.id)), &quotas);
+    }
+    if let Some(provider) = state.provider.as_ref().filter(|_| confirm::required(submission.form.as_deref())) {
+        let endpoint = widget::endpoint(&headers, uri.path());
+        let lang = i18n::negotiate(fields.get("lang").map(|lang| lang.as_str()), &headers);
+        submission.status = DeliveryStatus::Unconfirmed;
+        if let Some(store) = store::get() {
+            store.insert(submission.clone());
+        }
+        if let Err(e) = confirm::request(provider, &submission, &endpoint, lang).await {
+            error!("Couldn't send a confirmation email for submission {}: {}", submission.id, e);
+            if let Some(store) = store::get() {
+                store.update_status(&submission.id, DeliveryStatus::Failed, Some(e), "handler");
+            }
+            let data = ResponseData { status: ResponseStatus::MailAgentError, message: Some("mail_agent_error".to_string()), errors: None, retry_after: None };
+            return with_quotas(respond(&headers, &fields, StatusCode::BAD_GATEWAY, data, signed, Some(&submission.id)), &quotas);
+        }
+        info!("Holding submission {} until it's confirmed", submission.id);
+        let data = ResponseData { status: ResponseStatus::Ok, message: Some("confirmation_sent".to_string()), errors: None, retry_after: None };
+        return with_quotas(respond(&headers, &fields, StatusCode::ACCEPTED, data, signed, Some(&submission.id)), &quotas);
+    }
+    if maintenance::holding() {
+        info!("Holding submission {} until maintenance is over", submission.id);
+        if let Some(store) = store::get() {
+            store.insert(submission.clone());
+        }
+        maintenance::hold(submission.clone());
+        let data = ResponseData { status: ResponseStatus::Ok, message: None, errors: None, retry_after: None };
+        return with_quotas(respond(&headers, &fields, StatusCode::ACCEPTED, data, signed, Some(&submission.id)), &quotas);
+    }
+    // Before it's stored or passed on anywhere, so one that's turned away hasn't half happened
+    let slot = match sends_now(&state, submission.form.as_deref()) {
+        true => match concurrency::acquire().await {
+            Ok(slot) => slot,
+            Err(busy) => {
+                attachments::discard(std::mem::take(&mut submission.attachments));
+                let data = ResponseData { status: ResponseStatus::Busy, message: Some("busy".to_string()), errors: None, retry_after: Some(busy.retry_after) };
+                return with_quotas(respond(&headers, &fields, StatusCode::SERVICE_UNAVAILABLE, data, signed, None), &quotas);
+            }
+        },
+        false => None,
+    };
+    if let Some(store) = store::get() {
+        store.insert(submission.clone());
+    }
+    let (status, data) = process_with(&state, &submission, "handler", slot).await;
+    with_quotas(respond(&headers, &fields, status, data, signed, Some(&submission.id)), &quotas)
+}
+
+/// Whether the submission's emailed as soon as it's processed, so needs a send slot - rather than
+/// being queued for a digest, only signed up to a mailing list, or email being turned off
+fn sends_now(state: &AppState, form: Option<&str>) -> bool {
+    let list_only = mailing_list::list(form).is_some() && mailing_list::only(form);
+    state.provider.is_some() && !digest::enabled() && !list_only
+}
+
+/// Stores the submission for an admin to review, rather than sending it
+fn quarantine(submission: &mut Submission, reason: String) {
+    info!("Quarantining submission {}: {}", submission.id, reason);
+    submission.status = DeliveryStatus::Quarantined;
+    submission.status_message = Some(reason);
+    stats::record_status(submission.form.as_deref(), DeliveryStatus::Quarantined);
+    if let Some(store) = store::get() {
+        store.insert(submission.clone());
+    }
+}
+
+/// Sends a stored submission everywhere it's going, records the outcome (as `actor`, for the audit
+/// log), and runs the processors' `after_send`. Waits as long as it takes for a send slot, as it's
+/// for submissions sent in the background, or by an admin.
+pub(crate) async fn process(state: &AppState, submission: &Submission, actor: &str) -> (StatusCode, ResponseData) {
+    let slot = match sends_now(state, submission.form.as_deref()) {
+        true => concurrency::wait().await,
+        false => None,
+    };
+    process_with(state, submission, actor, slot).await
+}
+
+/// [process], with the send slot (if [sends_now]) already taken, and held until it's been sent
+async fn process_with(state: &AppState, submission: &Submission, actor: &str, _slot: Slot) -> (StatusCode, ResponseData) {
+    let form = submission.form.as_deref();
+    if let Err(cap) = caps::hit(form) {
+        let over = format!("over the {} send cap", cap.period.describe());
+        if caps::queueing(form) {
+            warn!("Queueing submission {} until it resets, as it's {}", submission.id, over);
+            if let Some(store) = store::get() {
+                store.update_status(&submission.id, DeliveryStatus::Pending, Some(format!("queued, as it's {}", over)), actor);
+            }
+            caps::queue(submission.clone(), cap.reset_secs);
+            return (StatusCode::ACCEPTED, ResponseData { status: ResponseStatus::Ok, message: None, errors: None, retry_after: None });
+        }
+        warn!("Not sending submission {}, as it's {}", submission.id, over);
+        if let Some(store) = store::get() {
+            store.update_status(&submission.id, DeliveryStatus::Failed, Some(over), actor);
+        }
+        return (StatusCode::TOO_MANY_REQUESTS, ResponseData { status: ResponseStatus::SendCapReached, message: Some("send_cap_reached".to_string()), errors: None, retry_after: Some(cap.reset_secs) });
+    }
+    webhook::dispatch(submission);
+    broker::publish(submission);
+    sheets::append(submission);
+
+    let result = deliver(state, submission).await;
+    let (delivery_status, status_message) = match &result {
+        // Queued for a digest or retry, which will update it once sent
+        Ok((StatusCode::ACCEPTED, _)) => (DeliveryStatus::Pending, None),
+        Ok((status, _)) if status.is_success() => (DeliveryStatus::Sent, None),
+        Ok((_, Json(data))) => (DeliveryStatus::Failed, data.message.as_deref().map(|key| i18n::text("en", key))),
+        Err(e) => (DeliveryStatus::Failed, Some(format!("{}", e))),
+    };
+    stats::record_status(submission.form.as_deref(), delivery_status);
+    if delivery_status == DeliveryStatus::Failed {
+        alert::failure(status_message.as_deref().unwrap_or("unknown error"));
+    }
+    if let Some(store) = store::get() {
+        if delivery_status != DeliveryStatus::Pending {
+            store.update_status(&submission.id, delivery_status, status_message.clone(), actor);
+        }
+    }
+    if !state.processors.is_empty() {
+        let processors = state.processors.clone();
+        let mut submission = submission.clone();
+        submission.status = delivery_status;
+        submission.status_message = status_message;
+        tokio::spawn(async move {
+            for processor in processors.iter() {
+                processor.after_send(&submission).await;
+            }
+        });
+    }
+    match result {
+        Ok((status, Json(data))) => (status, data),
+        Err(e) => e.into_parts(),
+    }
+}
+
+fn invalid_token(headers: &HeaderMap, fields: &HashMap<String, String>, e: csrf::TokenError) -> Response {
+    info!("Rejecting submission with a token that's {}", e.describe());
+    let data = ResponseData { status: ResponseStatus::InvalidToken, message: Some("invalid_token".to_string()), errors: None, retry_after: None };
+    respond(headers, fields, StatusCode::FORBIDDEN, data, false, None)
+}
+
+/// A `429` response, telling the client to try again once the quota resets
+fn rate_limited(headers: &HeaderMap, fields: &HashMap<String, String>, message: &str, quota: Quota) -> Response {
+    let data = ResponseData { status: ResponseStatus::RateLimited, message: Some(message.to_string()), errors: None, retry_after: Some(quota.reset_secs) };
+    let mut response = respond(headers, fields, StatusCode::TOO_MANY_REQUESTS, data, false, None);
+    quota.apply(response.headers_mut());
+    response
+}
+
+/// Adds the `RateLimit-*` headers for whichever of the limits the submission counted against is
+/// closest to running out
+fn with_quotas(mut response: Response, quotas: &[Quota]) -> Response {
+    if let Some(quota) = Quota::tightest(quotas) {
+        quota.apply(response.headers_mut());
+    }
+    response
+}
+
+/// Translates the response, then sends it in whichever form the client asked for. Takes the raw
+/// fields rather than `FormData`, as it's also used when they couldn't be validated. If the fields
+/// were signed, `_redirect` is trusted even if it isn't allowlisted.
+/// Sends `retry_after` as the `Retry-After` header too, if it's set
+fn respond(headers: &HeaderMap, fields: &HashMap<String, String>, status: StatusCode, data: ResponseData, signed: bool, submission_id: Option<&str>) -> Response {
+    let retry_after = data.retry_after;
+    let mut response = render(headers, fields, status, data, signed, submission_id);
+    if let Some(secs) = retry_after {
+        response.headers_mut().insert(header::RETRY_AFTER, secs.into());
+    }
+    response
+}
+
+fn render(headers: &HeaderMap, fields: &HashMap<String, String>, status: StatusCode, mut data: ResponseData, signed: bool, submission_id: Option<&str>) -> Response {
+    let field = |name: &str| fields.get(name).map(|value| value.as_str());
+    if status == StatusCode::TOO_MANY_REQUESTS {
+        stats::record(field("_form"), stats::Event::RateLimited);
+    } else if status.is_client_error() || status == StatusCode::SERVICE_UNAVAILABLE {
+        stats::record(field("_form"), stats::Event::Rejected);
+    }
+    let lang = i18n::negotiate(field("lang"), headers);
+    data.message = data.message.map(|key| i18n::text(lang, &key));
+    for error in data.errors.iter_mut().flatten() {
+        error.message = i18n::text(lang, &error.message);
+    }
+
+    if redirect::requested(headers, field("_redirect")) {
+        if let Some(url) = redirect::target(field("_form"), field("_redirect"), signed, status.is_success(), data.message.as_deref()) {
+            return Redirect::to(&url).into_response();
+        }
+        // Nowhere to redirect to, but the visitor still shouldn't be shown raw JSON
+        let back_url = headers.get(header::REFERER)
+            .and_then(|referer| referer.to_str().ok())
+            .filter(|referer| referer.starts_with("https://") || referer.starts_with("http://"))
+            .unwrap_or("javascript:history.back()");
+        let extra: Vec<(String, String)> = validation::extra_fields(fields).into_iter()
+            .map(|(name, value)| (format!("field.{}", name), value))
+            .collect();
+        let language = language::detect(&format!("{}\n{}", field("title").unwrap_or(""), field("body").unwrap_or("")));
+        let page_url = referrer::resolve(field("_form"), field("page_url"), headers);
+        let mut values = vec![
+            ("name", field("from_name").unwrap_or("")),
+            ("title", field("title").unwrap_or("")),
+            ("language", language.unwrap_or("")),
+            ("page_url", page_url.as_deref().unwrap_or("")),
+            ("message", data.message.as_deref().unwrap_or("")),
+            ("back_url", back_url),
+        ];
+        values.extend(extra.iter().map(|(name, value)| (name.as_str(), value.as_str())));
+        return page::render_response(status, field("_form"), lang, &values);
+    }
+    (status, Json(response::shape(data, submission_id))).into_response()
+}
+
+async fn deliver(state: &AppState, submission: &Submission) -> Result<(StatusCode, Json<ResponseData>), ContactFormError> {
+    if let Some(list) = mailing_list::list(submission.form.as_deref()) {
+        let subscribed = mailing_list::subscribe(&list, submission).await;
+        if mailing_list::only(submission.form.as_deref()) {
+            return match subscribed {
+                Ok(()) => Ok((StatusCode::OK, Json(ResponseData { status: ResponseStatus::Ok, message: None, errors: None, retry_after: None }))),
+                Err(e) => Err(ContactFormError::ListError(e)),
+            };
+        }
+        // Still sent on, so the signup failing isn't worth failing the submission for
+        if let Err(e) = subscribed {
+            error!("Error adding to mailing list: {}", e);
+        }
+    }
+    // Only unset if email is turned off
+    let provider = match state.provider.as_ref() {
+        Some(provider) => provider,
+        None => {
+            let (attempted, errors) = send_notifications(submission).await;
+            for e in errors.iter() {
+                error!("Error sending notification: {}", e);
+            }
+            // Someone has still been told about the submission as long as one notification got through
+            return match attempted {
+                0 => Err(ContactFormError::NotifierError("no notifications configured for this form".to_string())),
+                attempted if errors.len() == attempted => Err(ContactFormError::NotifierError(errors.join("; "))),
+                _ => Ok((StatusCode::OK, Json(ResponseData { status: ResponseStatus::Ok, message: None, errors: None, retry_after: None }))),
+            };
+        }
+    };
+    // Email is the primary channel, so don't hold the response up (or fail it) because of notifications
+    let notification_submission = submission.clone();
+    tokio::spawn(async move {
+        for e in send_notifications(&notification_submission).await.1 {
+            error!("Error sending notification: {}", e);
+        }
+    });
+    if digest::enabled() {
+        digest::queue(submission.clone());
+        return Ok((StatusCode::ACCEPTED, Json(ResponseData { status: ResponseStatus::Ok, message: None, errors: None, retry_after: None })));
+    }
+    send_email(provider, submission).await
+}
+
+/// Sends the submission to every configured notifier other than email, returning how many were
+/// attempted along with the errors from any that failed
+async fn send_notifications(submission: &Submission) -> (usize, Vec<String>) {
+    let mut attempted = 0;
+    let mut errors = Vec::new();
+    if slack::WEBHOOK_URL.is_some() {
+        attempted += 1;
+        if let Err(e) = slack::notify(submission).await {
+            errors.push(e);
+        }
+    }
+    if let Some(url) = discord::webhook_url(submission.form.as_deref()) {
+        attempted += 1;
+        if let Err(e) = discord::notify(&url, submission).await {
+            errors.push(e);
+        }
+    }
+    if let Some(chat_id) = telegram::chat_id(submission.form.as_deref()) {
+        attempted += 1;
+        if let Err(e) = telegram::notify(&chat_id, submission).await {
+            errors.push(e);
+        }
+    }
+    (attempted, errors)
+}
+
+async fn send_email(provider: &Arc<dyn MailProvider>, submission: &Submission) -> Result<(StatusCode, Json<ResponseData>), ContactFormError> {
+    let email = Email {
+        from: provider::mailbox(&submission.from_name, &submission.from_email),
+        to: submission.to.clone().unwrap_or_else(|| TO.clone()),
+        subject: language::subject(submission),
+        text: submission.text(),
+        submission_ids: vec![submission.id.clone()],
+        headers: threading::headers(&submission.id, &submission.from_email),
+        attachments: vcard::attach(submission),
+    };
+    let mut email = metadata::attach(email, submission);
+    email.headers.extend(extra_headers::configured(submission.form.as_deref()));
+    info!("Sending mail from [{}]", email.from);
+
+    match provider.send(&email).await {
+        Ok(()) => {
+            info!("Mail sent successfully");
+            Ok((StatusCode::OK, Json(ResponseData { status: ResponseStatus::Ok, message: None, errors: None, retry_after: None })))
+        }
+        Err(ProviderError::RateLimited(retry_after)) => {
+            retry::schedule(provider.clone(), email, submission.form.clone(), retry_after);
+            Ok((StatusCode::ACCEPTED, Json(ResponseData { status: ResponseStatus::Ok, message: None, errors: None, retry_after: None })))
+        }
+        Err(ProviderError::Unauthorized(body)) => {
+            info!("Received a 401 error trying to call the mail provider: {}", body);
+            Ok((StatusCode::INTERNAL_SERVER_ERROR, Json(ResponseData { status: ResponseStatus::MailAgentError, message: Some("mail_agent_error".to_string()), errors: None, retry_after: None })))
+        }
+        Err(ProviderError::Rejected(message)) => {
+            error!("Mail provider error: {}", message);
+            Ok((StatusCode::BAD_GATEWAY, Json(ResponseData { status: ResponseStatus::MailAgentError, message: Some("mail_agent_error".to_string()), errors: None, retry_after: None })))
+        }
+        Err(e) => Err(ContactFormError::MailError(e)),
+    }
+}
+
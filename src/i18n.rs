@@ -0,0 +1,154 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use axum::http::{header, HeaderMap};
+use lazy_static::lazy_static;
+use log::info;
+
+/// Language code -> message key -> text
+type Catalogs = HashMap<String, HashMap<String, String>>;
+
+const BUILT_IN: &[(&str, &str)] = &[
+    ("en", include_str!("../locales/en.json")),
+    ("de", include_str!("../locales/de.json")),
+    ("fr", include_str!("../locales/fr.json")),
+];
+const FALLBACK_LANGUAGE: &str = "en";
+
+lazy_static!(
+    static ref DEFAULT_LANGUAGE: String = std::env::var("DEFAULT_LANGUAGE")
+        .map(|lang| lang.to_lowercase())
+        .unwrap_or(FALLBACK_LANGUAGE.to_string());
+);
+
+static CATALOGS: OnceLock<Catalogs> = OnceLock::new();
+
+/// Loads translations now, so problems with `LOCALES_DIR` stop the service starting
+pub fn init() -> Result<(), String> {
+    if CATALOGS.get().is_none() {
+        let _ = CATALOGS.set(load_catalogs()?);
+    }
+    info!("Responding in {} by default, with translations for {:?}", *DEFAULT_LANGUAGE, catalogs().keys().collect::<Vec<_>>());
+    Ok(())
+}
+
+/// Only the built-in translations, if [init] hasn't been called
+fn catalogs() -> &'static Catalogs {
+    CATALOGS.get_or_init(built_in)
+}
+
+fn built_in() -> Catalogs {
+    BUILT_IN.iter()
+        .map(|(lang, json)| (lang.to_string(), serde_json::from_str(json).expect("built-in translations are valid")))
+        .collect()
+}
+
+/// Starts with the built-in translations, then merges in any `<language>.json` files from
+/// `LOCALES_DIR`, so they can both override built-in messages and add new languages
+fn load_catalogs() -> Result<Catalogs, String> {
+    let mut catalogs = built_in();
+    let dir = match std::env::var("LOCALES_DIR") {
+        Ok(dir) => dir,
+        Err(_) => return Ok(catalogs),
+    };
+    let entries = std::fs::read_dir(&dir).map_err(|e| format!("Unable to read LOCALES_DIR {}: {}", dir, e))?;
+    for entry in entries {
+        let path = entry.map_err(|e| format!("Unable to read LOCALES_DIR {}: {}", dir, e))?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+            continue;
+        }
+        let lang = match path.file_stem().and_then(|stem| stem.to_str()) {
+            Some(stem) => stem.to_lowercase(),
+            None => continue,
+        };
+        let contents = std::fs::read_to_string(&path).map_err(|e| format!("Unable to read {}: {}", path.display(), e))?;
+        let messages: HashMap<String, String> = serde_json::from_str(&contents)
+            .map_err(|e| format!("Unable to parse {} - it should be an object of message keys to text: {}", path.display(), e))?;
+        info!("Loaded {} translation(s) for {} from {}", messages.len(), lang, path.display());
+        catalogs.entry(lang).or_default().extend(messages);
+    }
+    Ok(catalogs)
+}
+
+/// Matches `de-CH` exactly if there's a catalog for it, and otherwise falls back to `de`
+fn available(tag: &str) -> Option<&'static str> {
+    let tag = tag.trim().to_lowercase();
+    let primary = tag.split('-').next().unwrap_or("");
+    catalogs().get_key_value(tag.as_str())
+        .or_else(|| catalogs().get_key_value(primary))
+        .map(|(lang, _)| lang.as_str())
+}
+
+/// Picks the language to respond in - an explicit `lang` field wins, then the most preferred
+/// language in `Accept-Language` that we have translations for, then `DEFAULT_LANGUAGE`
+pub fn negotiate(lang_field: Option<&str>, headers: &HeaderMap) -> &'static str {
+    if let Some(lang) = lang_field.and_then(available) {
+        return lang;
+    }
+    let accept = headers.get(header::ACCEPT_LANGUAGE).and_then(|accept| accept.to_str().ok()).unwrap_or("");
+    let mut ranges: Vec<(&str, f32)> = accept.split(',')
+        .filter_map(|range| {
+            let mut parts = range.split(';');
+            let tag = parts.next()?.trim();
+            let quality = parts.find_map(|part| part.trim().strip_prefix("q="))
+                .and_then(|quality| quality.parse().ok())
+                .unwrap_or(1.0);
+            Some((tag, quality))
+        })
+        .filter(|(tag, quality)| !tag.is_empty() && *tag != "*" && *quality > 0.0)
+        .collect();
+    // Stable, so languages with the same quality keep the order the client listed them in
+    ranges.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal));
+    ranges.iter()
+        .find_map(|(tag, _)| available(tag))
+        .or_else(|| available(&DEFAULT_LANGUAGE))
+        .unwrap_or(FALLBACK_LANGUAGE)
+}
+
+/// The text for the key in the given language, falling back to the default language, then English,
+/// then the key itself
+pub fn text(lang: &str, key: &str) -> String {
+    [lang, DEFAULT_LANGUAGE.as_str(), FALLBACK_LANGUAGE].iter()
+        .find_map(|lang| catalogs().get(*lang).and_then(|messages| messages.get(key)))
+        .cloned()
+        .unwrap_or(key.to_string())
+}
+
+/// Every message in the given language as `t.<key>` template placeholders
+pub fn template_values(lang: &str) -> Vec<(String, String)> {
+    let mut keys: Vec<&String> = catalogs().get(FALLBACK_LANGUAGE).into_iter()
+        .chain(catalogs().get(lang))
+        .flat_map(|messages| messages.keys())
+        .collect();
+    keys.sort();
+    keys.dedup();
+    keys.into_iter().map(|key| (format!("t.{}", key), text(lang, key))).collect()
+}
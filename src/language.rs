@@ -0,0 +1,104 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+//! Working out what language a submission is written in, so it can be tagged and routed to
+//! someone who speaks it
+
+use std::collections::BTreeMap;
+use log::{error, info};
+use crate::{form_flag, form_var};
+use crate::store::Submission;
+
+/// Common words that are (mostly) particular to each language. Short messages rarely contain many
+/// distinctive words, so a handful per language goes a long way.
+const STOPWORDS: &[(&str, &[&str])] = &[
+    ("de", &["der", "die", "das", "und", "ist", "ich", "nicht", "sie", "mit", "für", "ein", "eine", "zu", "wir", "haben", "bitte", "ihre", "auf", "mein", "meine", "danke", "wie", "können", "hallo", "guten"]),
+    ("en", &["the", "and", "is", "are", "you", "your", "to", "of", "for", "with", "this", "that", "have", "would", "please", "can", "my", "it", "be", "we", "hi", "hello", "thanks"]),
+    ("es", &["el", "los", "las", "y", "es", "yo", "usted", "para", "con", "una", "por", "que", "mi", "gracias", "hola", "del", "su", "como", "está", "quiero"]),
+    ("fr", &["le", "la", "les", "et", "est", "je", "vous", "pour", "avec", "une", "des", "pas", "que", "nous", "mon", "ma", "merci", "bonjour", "du", "sur", "ce", "votre", "suis"]),
+    ("it", &["il", "lo", "gli", "e", "è", "io", "per", "con", "una", "non", "che", "mio", "grazie", "ciao", "della", "sono", "come", "questo", "vorrei"]),
+    ("nl", &["de", "het", "een", "en", "ik", "niet", "u", "voor", "met", "dat", "wij", "mijn", "bedankt", "graag", "van", "op", "zijn", "hoe", "hallo"]),
+    ("pt", &["o", "os", "e", "é", "eu", "você", "para", "com", "uma", "não", "que", "meu", "obrigado", "obrigada", "olá", "do", "da", "em", "como", "está"]),
+];
+
+/// Fewer matching words than this is too little to go on
+const MIN_MATCHES: usize = 2;
+
+/// The language (as a two-letter code) most of the text's common words belong to, if it's clear
+/// enough
+pub fn detect(text: &str) -> Option<&'static str> {
+    let words: Vec<String> = text.split(|c: char| !c.is_alphabetic())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .collect();
+    let mut scores: Vec<(&'static str, usize)> = STOPWORDS.iter()
+        .map(|(lang, stopwords)| (*lang, words.iter().filter(|word| stopwords.contains(&word.as_str())).count()))
+        .collect();
+    scores.sort_by_key(|(_, score)| std::cmp::Reverse(*score));
+    match scores.as_slice() {
+        [(lang, best), (_, second), ..] if *best >= MIN_MATCHES && best > second => Some(lang),
+        _ => None,
+    }
+}
+
+fn parse(json: &str) -> Result<BTreeMap<String, String>, String> {
+    serde_json::from_str(json).map_err(|e| format!("must be a JSON object of language codes to email addresses: {}", e))
+}
+
+/// Checks every `LANGUAGE_ROUTES` now, rather than on the first submission in each language
+pub fn init() -> Result<(), String> {
+    for (name, json) in std::env::vars() {
+        if name == "LANGUAGE_ROUTES" || (name.starts_with("FORM_") && name.ends_with("_LANGUAGE_ROUTES")) {
+            let routes = parse(&json).map_err(|e| format!("\"{}\" {}", name, e))?;
+            info!("Routing submissions in {} to their own recipients (from {})", routes.keys().cloned().collect::<Vec<_>>().join(", "), name);
+        }
+    }
+    Ok(())
+}
+
+/// Who the form's `LANGUAGE_ROUTES` sends submissions in the language to, if anyone
+pub fn route(form: Option<&str>, lang: &str) -> Option<String> {
+    match form_var(form, "LANGUAGE_ROUTES").map(|json| parse(&json)) {
+        Some(Ok(mut routes)) => routes.remove(lang),
+        Some(Err(e)) => {
+            // Only possible if the environment's changed since startup
+            error!("Ignoring \"LANGUAGE_ROUTES\", which {}", e);
+            None
+        }
+        None => None,
+    }
+}
+
+/// The submission's title, prefixed with its language (like `[FR] Bonjour`) if the form's
+/// `LANGUAGE_SUBJECT_TAG` is set
+pub fn subject(submission: &Submission) -> String {
+    match submission.language.as_deref() {
+        Some(lang) if form_flag(submission.form.as_deref(), "LANGUAGE_SUBJECT_TAG", false) => format!("[{}] {}", lang.to_uppercase(), submission.title),
+        _ => submission.title.clone(),
+    }
+}
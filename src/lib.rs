@@ -0,0 +1,314 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+//! Receives contact form submissions and sends them on via email (and optionally Slack, Discord,
+//! webhooks and more). Usually run as a standalone service, but can also be mounted inside an
+//! existing [axum] application:
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+//! use mailgun_contact_form::{ContactFormService, MailgunProvider};
+//!
+//! let service = ContactFormService::builder()
+//!     .provider(MailgunProvider::new("key-...", "mg.example.com"))
+//!     .build()
+//!     .await?;
+//! let app = axum::Router::new().nest("/contact", service.router());
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Everything other than the provider is configured by the same environment variables as the
+//! service - see the README.
+
+mod admin;
+mod alert;
+mod api_keys;
+mod assets;
+mod attachments;
+mod audit;
+mod broker;
+mod caps;
+mod client;
+mod client_ip;
+mod concurrency;
+mod confirm;
+mod csrf;
+mod digest;
+mod discord;
+mod encryption;
+mod events;
+mod extra_headers;
+mod field_mapping;
+mod geoip;
+mod handler;
+mod i18n;
+mod language;
+mod mailgun;
+pub mod mailgun_api;
+mod mailgun_webhook;
+mod mailing_list;
+mod maintenance;
+mod memory;
+mod metadata;
+mod multipart;
+mod openapi;
+mod outbox;
+mod page;
+mod pow;
+mod processor;
+mod profanity;
+mod provider;
+mod ratelimit;
+mod redirect;
+mod referrer;
+mod response;
+mod retention;
+mod retry;
+mod rotation;
+mod s3;
+mod script;
+mod senders;
+mod service;
+mod sheets;
+mod signing;
+mod spam;
+mod stats;
+mod slack;
+mod store;
+mod suppression;
+mod telegram;
+mod threading;
+mod timestamps;
+mod validation;
+mod vcard;
+mod webhook;
+mod widget;
+
+pub use mailgun::MailgunProvider;
+pub use memory::{MemoryProvider, SentEmail};
+pub use processor::{Rejection, SubmissionProcessor};
+pub use provider::{Email, EmailAttachment, MailProvider, ProviderError};
+pub use service::{ContactFormService, ContactFormServiceBuilder};
+pub use store::{DeliveryStatus, Submission};
+
+use std::collections::BTreeMap;
+use axum::http::StatusCode;
+use log::error;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use validation::FieldError;
+
+#[derive(Clone, Deserialize, ToSchema)]
+struct FormData {
+    from_name: String,
+    from_email: String,
+    title: String,
+    body: String,
+    /// Optional hidden field identifying which form was submitted, for per-form configuration
+    #[serde(rename = "_form")]
+    form: Option<String>,
+    /// Optional hidden field asking for a redirect to the given URL (which must be allowlisted)
+    /// instead of a JSON response
+    #[serde(rename = "_redirect")]
+    #[allow(dead_code)] // Read from the raw fields when responding, but kept here to document it
+    redirect: Option<String>,
+    /// Whether the submitter agreed to their message being stored and processed. Required if
+    /// `REQUIRE_CONSENT` is set.
+    #[serde(default)]
+    consent: bool,
+    /// Optional language to respond in, overriding `Accept-Language`
+    #[allow(dead_code)] // As for `redirect`
+    lang: Option<String>,
+    /// Optional URL of the page the form was on, if the `Referer` header won't do. Must be from one
+    /// of `PAGE_URL_ORIGINS`, if set.
+    page_url: Option<String>,
+    /// Optional hidden field overriding the address to send the email to. Only honoured if signed.
+    #[serde(rename = "_to")]
+    to: Option<String>,
+    /// HMAC of the hidden configuration fields - see [signing::verify]
+    #[serde(rename = "_signature")]
+    #[allow(dead_code)] // As for `redirect`
+    signature: Option<String>,
+    /// Any other fields (like `phone` or `company`), if allowed by `EXTRA_FIELDS`
+    #[serde(skip)]
+    extra: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, ToSchema)]
+enum ResponseStatus {
+    Ok,
+    MailAgentError,
+    InternalError,
+    NotificationError,
+    /// The attachments couldn't be stored
+    AttachmentError,
+    InvalidRequest,
+    ValidationError,
+    InvalidToken,
+    InvalidSignature,
+    InvalidChallenge,
+    InvalidApiKey,
+    Blocked,
+    Rejected,
+    RateLimited,
+    /// The daily or monthly send cap has been reached, and submissions aren't being queued
+    SendCapReached,
+    /// Too many emails are already being sent, so try again shortly
+    Busy,
+    /// Maintenance mode is on, and submissions aren't being held
+    Maintenance,
+    Unauthorized,
+    NotFound,
+}
+
+#[derive(Serialize, ToSchema)]
+struct ResponseData {
+    status: ResponseStatus,
+    /// For the form endpoint, this starts out as a message key, and is translated just before the
+    /// response is sent
+    message: Option<String>,
+    /// Only present for `ValidationError`s
+    #[serde(skip_serializing_if = "Option::is_none")]
+    errors: Option<Vec<FieldError>>,
+    /// Only present for `RateLimited`, `SendCapReached` and `Busy`, giving how many seconds to wait before trying again
+    /// (as does the `Retry-After` header)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    retry_after: Option<u64>,
+}
+
+lazy_static!(
+    /// Only optional with the memory provider, when it's never really sent to
+    static ref TO: String = std::env::var("MAILGUN_TO_ADDRESS").unwrap_or("you@localhost".to_string());
+    static ref CLIENT: reqwest::Client = client::configured();
+    /// Set to `false` to only send notifications via e.g. Slack, in which case no mail provider (or
+    /// any of the Mailgun variables) is needed
+    static ref SEND_EMAIL: bool = env_flag("SEND_EMAIL", true);
+    /// Enables things that are only useful (or safe) when developing locally
+    static ref DEV_MODE: bool = env_flag("DEV_MODE", false);
+);
+
+/// Treats anything other than `false`, `no`, or `0` as enabled, and a missing variable as the default
+pub fn env_flag(name: &str, default: bool) -> bool {
+    match std::env::var(name) {
+        Ok(value) => !matches!(value.to_lowercase().as_str(), "false" | "no" | "0"),
+        Err(_) => default,
+    }
+}
+
+/// Truncates to at most `max_chars` characters (not bytes) - including the ellipsis marking the cut
+fn truncate(text: &str, max_chars: usize) -> String {
+    if text.chars().count() <= max_chars {
+        return text.to_string();
+    }
+    let mut truncated: String = text.chars().take(max_chars.saturating_sub(1)).collect();
+    truncated.push('…');
+    truncated
+}
+
+/// Looks up `FORM_<FORM>_<NAME>` for the given form (upper-cased, with anything that isn't
+/// alphanumeric replaced with `_`), falling back to plain `<NAME>` if the form doesn't override it
+fn form_var(form: Option<&str>, name: &str) -> Option<String> {
+    form_override(form, name).or_else(|| std::env::var(name).ok())
+}
+
+/// [form_var], without the fallback - for settings where the form's value and the plain one both apply
+fn form_override(form: Option<&str>, name: &str) -> Option<String> {
+    form.and_then(|form| {
+        let form: String = form.chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+            .collect();
+        std::env::var(format!("FORM_{}_{}", form, name)).ok()
+    })
+}
+
+/// [env_flag], but per-form
+fn form_flag(form: Option<&str>, name: &str, default: bool) -> bool {
+    match form_var(form, name) {
+        Some(value) => !matches!(value.to_lowercase().as_str(), "false" | "no" | "0"),
+        None => default,
+    }
+}
+
+/// Checks that an environment variable, if it's set, parses as what it's meant to be - `expected`,
+/// like "a number of seconds". For `init` functions (and the server binary), so a typo stops the
+/// service from starting, rather than panicking (or being ignored) when the lazy static reading it
+/// is first used.
+pub fn check_var<T: std::str::FromStr>(name: &str, expected: &str) -> Result<Option<T>, String> {
+    match std::env::var(name) {
+        Ok(value) => value.trim().parse().map(Some).map_err(|_| format!("\"{}\" must be {}, not {}", name, expected, value)),
+        Err(_) => Ok(None),
+    }
+}
+
+/// [check_var], for variables holding JSON
+fn check_json_var<T: serde::de::DeserializeOwned>(name: &str, expected: &str) -> Result<Option<T>, String> {
+    match std::env::var(name) {
+        Ok(value) => serde_json::from_str(&value).map(Some).map_err(|e| format!("\"{}\" must be {}: {}", name, expected, e)),
+        Err(_) => Ok(None),
+    }
+}
+
+#[allow(clippy::enum_variant_names)]
+enum ContactFormError {
+    MailError(ProviderError),
+    NotifierError(String),
+    /// Mailgun wouldn't add the submitter to the mailing list
+    ListError(String),
+}
+
+impl std::fmt::Display for ContactFormError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ContactFormError::MailError(e) => write!(f, "{}", e),
+            ContactFormError::NotifierError(e) => write!(f, "{}", e),
+            ContactFormError::ListError(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl ContactFormError {
+    fn into_parts(self) -> (StatusCode, ResponseData) {
+        match self {
+            ContactFormError::MailError(e) => {
+                error!("Error sending mail: {}", e);
+                (StatusCode::INTERNAL_SERVER_ERROR, ResponseData { status: ResponseStatus::InternalError, message: Some("internal_error".to_string()), errors: None, retry_after: None })
+            }
+            ContactFormError::NotifierError(e) => {
+                error!("Error sending notification: {}", e);
+                (StatusCode::BAD_GATEWAY, ResponseData { status: ResponseStatus::NotificationError, message: Some("notification_error".to_string()), errors: None, retry_after: None })
+            }
+            ContactFormError::ListError(e) => {
+                error!("Error adding to mailing list: {}", e);
+                (StatusCode::BAD_GATEWAY, ResponseData { status: ResponseStatus::MailAgentError, message: Some("mail_agent_error".to_string()), errors: None, retry_after: None })
+            }
+        }
+    }
+}
+
@@ -0,0 +1,133 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+use std::collections::BTreeMap;
+use async_trait::async_trait;
+use log::{error, info, warn};
+use crate::mailgun_api::{ApiVersion, MailgunClient, MailgunError, Message, MessageAttachment};
+use crate::provider::{Email, MailProvider, ProviderError};
+
+/// Sends email via [Mailgun](https://www.mailgun.com)'s API
+pub struct MailgunProvider {
+    domain: String,
+    client: MailgunClient,
+}
+
+impl MailgunProvider {
+    pub fn new(api_key: impl Into<String>, domain: impl Into<String>) -> Self {
+        MailgunProvider { domain: domain.into(), client: MailgunClient::new(api_key) }
+    }
+
+    /// Configures the provider from `MAILGUN_API_KEY`, `MAILGUN_DOMAIN`, `MAILGUN_API_BASE_URL` and
+    /// `MAILGUN_API_VERSION`
+    pub fn from_env() -> Result<Self, String> {
+        let api_key = std::env::var("MAILGUN_API_KEY").map_err(|_| "Environment variable \"MAILGUN_API_KEY\" must be present")?;
+        let domain = std::env::var("MAILGUN_DOMAIN").map_err(|_| "Environment variable \"MAILGUN_DOMAIN\" must be present")?;
+        info!("Will be sending mail via domain {}, with API key starting with {}", domain, &api_key[0..6]);
+        Ok(MailgunProvider { domain, client: MailgunClient::from_env(api_key)? })
+    }
+
+    /// Sends to a different API server - e.g. `https://api.eu.mailgun.net` for domains in the EU
+    /// region, or a fake one for testing
+    pub fn base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.client = self.client.base_url(base_url);
+        self
+    }
+
+    /// Uses the given version's endpoints wherever Mailgun has more than one
+    pub fn version(mut self, version: ApiVersion) -> Self {
+        self.client = self.client.version(version);
+        self
+    }
+
+    /// Sends requests with the given client, e.g. one with different timeouts
+    pub fn client(mut self, client: reqwest::Client) -> Self {
+        self.client = self.client.client(client);
+        self
+    }
+}
+
+impl From<MailgunError> for ProviderError {
+    fn from(e: MailgunError) -> Self {
+        match e {
+            MailgunError::Unauthorized(message) => ProviderError::Unauthorized(message),
+            MailgunError::RateLimited(retry_after) => ProviderError::RateLimited(retry_after),
+            MailgunError::NotFound(message) => ProviderError::Rejected(message),
+            MailgunError::Rejected { message, .. } => ProviderError::Rejected(message),
+            MailgunError::Unavailable(message) => ProviderError::Unavailable(message),
+        }
+    }
+}
+
+#[async_trait]
+impl MailProvider for MailgunProvider {
+    async fn send(&self, email: &Email) -> Result<(), ProviderError> {
+        let mut variables = BTreeMap::new();
+        if !email.submission_ids.is_empty() {
+            // Comes back in the `user-variables` of webhook events, so they can be matched to submissions
+            variables.insert("v:submission-ids".to_string(), email.submission_ids.join(","));
+        }
+        let headers = email.headers.iter().map(|(name, value)| (format!("h:{}", name), value.as_str())).collect();
+        let attachments = email.attachments.iter()
+            .map(|attachment| MessageAttachment { file_name: &attachment.file_name, content_type: &attachment.content_type, content: attachment.content.as_bytes() })
+            .collect();
+        let message = Message { from: &email.from, to: &email.to, subject: &email.subject, text: &email.text, variables, headers, attachments };
+        self.client.send_message(&self.domain, &message).await?;
+        Ok(())
+    }
+
+    /// Looks the domain up, to catch a wrong API key or an unverified or disabled domain before the
+    /// first submission fails because of it
+    async fn check(&self) {
+        let data = match self.client.domain(&self.domain).await {
+            Ok(data) => data,
+            Err(MailgunError::Unauthorized(_)) => {
+                error!("Mailgun rejected the API key - check \"MAILGUN_API_KEY\" is a private API key (or a sending key for {})", self.domain);
+                return;
+            }
+            Err(MailgunError::NotFound(_)) => {
+                error!("Mailgun has no domain {} - check \"MAILGUN_DOMAIN\", and that \"MAILGUN_API_BASE_URL\" is set for domains in the EU region", self.domain);
+                return;
+            }
+            Err(e) => {
+                warn!("Unable to check Mailgun domain {}: {}", self.domain, e);
+                return;
+            }
+        };
+        if data.domain.is_disabled {
+            error!("Mailgun has disabled sending from {} - see the domain's page in Mailgun's dashboard", self.domain);
+        } else if data.domain.state != "active" {
+            error!("Mailgun domain {} is {}, so mail won't be sent until it's verified. Add these DNS records:", self.domain, data.domain.state);
+            for record in data.sending_dns_records.iter().filter(|record| record.valid != "valid") {
+                error!("    {} {} {}", record.record_type, record.name, record.value);
+            }
+        } else {
+            info!("Mailgun domain {} is verified", self.domain);
+        }
+    }
+}
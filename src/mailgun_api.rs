@@ -0,0 +1,392 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+//! A small typed client for the parts of [Mailgun's API](https://documentation.mailgun.com/docs/mailgun/api-reference/)
+//! the service uses - sending, domains, events, suppressions and mailing lists - which can also be
+//! used on its own:
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), mailgun_contact_form::mailgun_api::MailgunError> {
+//! use mailgun_contact_form::mailgun_api::{EventQuery, MailgunClient};
+//!
+//! let client = MailgunClient::new("key-...");
+//! let failures = client.events("mg.example.com", &EventQuery { event: Some("failed".to_string()), ..Default::default() }).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+use axum::http::{header, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::CLIENT;
+
+pub const DEFAULT_BASE_URL: &str = "https://api.mailgun.net";
+/// Enough of an unexpected (e.g. HTML) error body to tell what it was
+const MAX_ERROR_BODY_CHARS: usize = 200;
+
+/// Which version of the endpoints to use, where Mailgun has more than one. Sending, events,
+/// suppressions and lists only have `v3` endpoints, so always use those.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ApiVersion {
+    #[default]
+    V3,
+    V4,
+}
+
+impl ApiVersion {
+    /// Parses `v3` or `v4`, as in `MAILGUN_API_VERSION`
+    pub fn parse(version: &str) -> Option<Self> {
+        match version.trim().to_lowercase().as_str() {
+            "v3" | "3" => Some(ApiVersion::V3),
+            "v4" | "4" => Some(ApiVersion::V4),
+            _ => None,
+        }
+    }
+
+    fn path(self) -> &'static str {
+        match self {
+            ApiVersion::V3 => "v3",
+            ApiVersion::V4 => "v4",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum MailgunError {
+    /// The API key was rejected, or isn't allowed to do this
+    Unauthorized(String),
+    /// There's no such domain, list or other resource
+    NotFound(String),
+    /// Mailgun would like us to wait this long (if it said) before trying again
+    RateLimited(Option<Duration>),
+    /// Mailgun refused the request, with its reason
+    Rejected { status: StatusCode, message: String },
+    /// Mailgun couldn't be reached, or responded with something we didn't understand
+    Unavailable(String),
+}
+
+impl std::fmt::Display for MailgunError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MailgunError::Unauthorized(message) => write!(f, "unauthorized: {}", message),
+            MailgunError::NotFound(message) => write!(f, "not found: {}", message),
+            MailgunError::RateLimited(_) => write!(f, "rate limited"),
+            MailgunError::Rejected { status, message } => write!(f, "{} ({})", message, status),
+            MailgunError::Unavailable(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for MailgunError {}
+
+/// An email to send, as Mailgun's form fields
+#[derive(Debug, Serialize)]
+pub struct Message<'a> {
+    pub from: &'a str,
+    pub to: &'a str,
+    pub subject: &'a str,
+    pub text: &'a str,
+    /// Custom variables, as `v:<name>`, which come back in webhook events' `user-variables`
+    #[serde(flatten)]
+    pub variables: BTreeMap<String, String>,
+    /// Each header as `h:<name>`
+    #[serde(flatten)]
+    pub headers: BTreeMap<String, &'a str>,
+    /// Sent as a multipart form if there are any, rather than a URL-encoded one
+    #[serde(skip)]
+    pub attachments: Vec<MessageAttachment<'a>>,
+}
+
+#[derive(Debug)]
+pub struct MessageAttachment<'a> {
+    pub file_name: &'a str,
+    pub content_type: &'a str,
+    pub content: &'a [u8],
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MessageQueued {
+    /// The `Message-Id` it'll be sent with
+    pub id: String,
+    pub message: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DomainResponse {
+    pub domain: Domain,
+    #[serde(default)]
+    pub sending_dns_records: Vec<DnsRecord>,
+    #[serde(default)]
+    pub receiving_dns_records: Vec<DnsRecord>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Domain {
+    pub name: String,
+    /// `active` once verified, or `unverified`
+    pub state: String,
+    #[serde(default)]
+    pub is_disabled: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DnsRecord {
+    pub record_type: String,
+    #[serde(default)]
+    pub name: String,
+    pub value: String,
+    /// `valid` once Mailgun has seen it
+    #[serde(default)]
+    pub valid: String,
+}
+
+/// One page of results, with links to the others
+#[derive(Debug, Deserialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    #[serde(default)]
+    pub paging: Paging,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct Paging {
+    pub next: Option<String>,
+    pub previous: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct EventQuery {
+    /// Like `delivered`, `failed` or `complained`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub event: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recipient: Option<String>,
+    /// Up to 300
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Event {
+    pub id: String,
+    pub event: String,
+    /// Seconds since the Unix epoch
+    pub timestamp: f64,
+    pub recipient: Option<String>,
+    #[serde(rename = "user-variables", default)]
+    pub user_variables: BTreeMap<String, Value>,
+    #[serde(rename = "delivery-status")]
+    pub delivery_status: Option<Value>,
+    /// Everything else, which varies by event
+    #[serde(flatten)]
+    pub other: BTreeMap<String, Value>,
+}
+
+/// An address on one of the domain's suppression lists - bounces, unsubscribes or complaints
+#[derive(Debug, Deserialize)]
+pub struct Suppression {
+    pub address: String,
+    pub created_at: String,
+    /// Bounces only - the SMTP error code and message
+    pub code: Option<Value>,
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum SuppressionList {
+    Bounces,
+    Unsubscribes,
+    Complaints,
+}
+
+impl SuppressionList {
+    fn path(self) -> &'static str {
+        match self {
+            SuppressionList::Bounces => "bounces",
+            SuppressionList::Unsubscribes => "unsubscribes",
+            SuppressionList::Complaints => "complaints",
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ListMember<'a> {
+    pub address: &'a str,
+    pub name: &'a str,
+    /// A JSON object, so it can be used when sending to the list
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vars: Option<String>,
+    pub subscribed: &'static str,
+    /// Updates anyone already on the list rather than failing
+    pub upsert: &'static str,
+}
+
+/// Mailgun's errors are usually `{"message": "..."}`, but some endpoints use other names for it, and
+/// some add other fields - including more than one of these, so the first one there wins
+const ERROR_FIELDS: &[&str] = &["message", "Message", "error", "Error"];
+
+/// The message from an error body, if it's JSON with one
+fn error_message(body: &str) -> Option<String> {
+    let error = serde_json::from_str::<serde_json::Value>(body).ok()?;
+    ERROR_FIELDS.iter().find_map(|field| error.get(field).and_then(|message| message.as_str()).map(String::from))
+}
+
+/// Something (like a load balancer) in front of Mailgun may respond with plain text or HTML, or
+/// nothing at all
+fn unexpected(status: StatusCode, body: &str) -> String {
+    match body.trim() {
+        "" => format!("Mailgun responded with {}", status),
+        body => format!("Mailgun responded with {}: {}", status, crate::truncate(body, MAX_ERROR_BODY_CHARS)),
+    }
+}
+
+pub struct MailgunClient {
+    api_key: String,
+    base_url: String,
+    version: ApiVersion,
+    client: reqwest::Client,
+}
+
+impl MailgunClient {
+    pub fn new(api_key: impl Into<String>) -> Self {
+        MailgunClient { api_key: api_key.into(), base_url: DEFAULT_BASE_URL.to_string(), version: ApiVersion::default(), client: CLIENT.clone() }
+    }
+
+    /// Configures the client for the given key from `MAILGUN_API_BASE_URL` and `MAILGUN_API_VERSION`
+    pub fn from_env(api_key: impl Into<String>) -> Result<Self, String> {
+        let mut client = MailgunClient::new(api_key);
+        if let Ok(base_url) = std::env::var("MAILGUN_API_BASE_URL") {
+            client = client.base_url(base_url);
+        }
+        if let Ok(version) = std::env::var("MAILGUN_API_VERSION") {
+            let version = ApiVersion::parse(&version).ok_or_else(|| format!("\"MAILGUN_API_VERSION\" must be `v3` or `v4`, not {}", version))?;
+            client = client.version(version);
+        }
+        Ok(client)
+    }
+
+    /// Uses a different API server - e.g. `https://api.eu.mailgun.net` for domains in the EU
+    /// region, or a fake one for testing
+    pub fn base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = base_url.into().trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Uses the given version's endpoints wherever there's a choice
+    pub fn version(mut self, version: ApiVersion) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// Sends requests with the given client, e.g. one with different timeouts
+    pub fn client(mut self, client: reqwest::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    async fn parse<T: DeserializeOwned>(request: reqwest::RequestBuilder) -> Result<T, MailgunError> {
+        let response = request.send().await.map_err(|e| MailgunError::Unavailable(e.to_string()))?;
+        let status = response.status();
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            // Mailgun only ever sends a number of seconds, never a date
+            let retry_after = response.headers().get(header::RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.trim().parse().ok())
+                .map(Duration::from_secs);
+            return Err(MailgunError::RateLimited(retry_after));
+        }
+        let body = response.text().await.map_err(|e| MailgunError::Unavailable(e.to_string()))?;
+        match status {
+            status if status.is_success() => serde_json::from_str(&body)
+                .map_err(|e| MailgunError::Unavailable(format!("unexpected response from Mailgun: {}", e))),
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Err(MailgunError::Unauthorized(error_message(&body).unwrap_or(body))),
+            StatusCode::NOT_FOUND => Err(MailgunError::NotFound(error_message(&body).unwrap_or_else(|| status.to_string()))),
+            status => match error_message(&body) {
+                Some(message) => Err(MailgunError::Rejected { status, message }),
+                None => Err(MailgunError::Unavailable(unexpected(status, &body))),
+            },
+        }
+    }
+
+    fn get(&self, path: &str) -> reqwest::RequestBuilder {
+        self.client.get(format!("{}{}", self.base_url, path)).basic_auth("api", Some(&self.api_key))
+    }
+
+    fn post(&self, path: &str) -> reqwest::RequestBuilder {
+        self.client.post(format!("{}{}", self.base_url, path)).basic_auth("api", Some(&self.api_key))
+    }
+
+    pub async fn send_message(&self, domain: &str, message: &Message<'_>) -> Result<MessageQueued, MailgunError> {
+        let request = self.post(&format!("/v3/{}/messages", domain));
+        if message.attachments.is_empty() {
+            return Self::parse(request.form(message)).await;
+        }
+        let mut form = reqwest::multipart::Form::new();
+        let fields = serde_json::to_value(message).expect("a message can be serialized");
+        for (name, value) in fields.as_object().into_iter().flatten() {
+            if let Some(value) = value.as_str() {
+                form = form.text(name.clone(), value.to_string());
+            }
+        }
+        for attachment in message.attachments.iter() {
+            let part = reqwest::multipart::Part::bytes(attachment.content.to_vec())
+                .file_name(attachment.file_name.to_string())
+                .mime_str(attachment.content_type)
+                .map_err(|e| MailgunError::Rejected { status: StatusCode::BAD_REQUEST, message: format!("invalid attachment content type: {}", e) })?;
+            form = form.part("attachment", part);
+        }
+        Self::parse(request.multipart(form)).await
+    }
+
+    pub async fn domain(&self, domain: &str) -> Result<DomainResponse, MailgunError> {
+        Self::parse(self.get(&format!("/{}/domains/{}", self.version.path(), domain))).await
+    }
+
+    /// The first page of the domain's events, newest first
+    pub async fn events(&self, domain: &str, query: &EventQuery) -> Result<Page<Event>, MailgunError> {
+        Self::parse(self.get(&format!("/v3/{}/events", domain)).query(query)).await
+    }
+
+    /// The first page of one of the domain's suppression lists
+    pub async fn suppressions(&self, domain: &str, list: SuppressionList) -> Result<Page<Suppression>, MailgunError> {
+        Self::parse(self.get(&format!("/v3/{}/{}", domain, list.path()))).await
+    }
+
+    /// The page at one of a page's `paging` URLs
+    pub async fn page<T: DeserializeOwned>(&self, url: &str) -> Result<Page<T>, MailgunError> {
+        Self::parse(self.client.get(url).basic_auth("api", Some(&self.api_key))).await
+    }
+
+    /// Adds (or, with `upsert`, updates) a member of the mailing list with the given address
+    pub async fn add_list_member(&self, list: &str, member: &ListMember<'_>) -> Result<(), MailgunError> {
+        Self::parse::<Value>(self.post(&format!("/v3/lists/{}/members", list)).form(member)).await.map(|_| ())
+    }
+}
@@ -0,0 +1,207 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use axum::Json;
+use axum::http::StatusCode;
+use hmac::{Hmac, Mac};
+use lazy_static::lazy_static;
+use log::{info, warn};
+use serde::Deserialize;
+use sha2::Sha256;
+use utoipa::ToSchema;
+use crate::{audit, stats};
+use crate::store::{self, DeliveryStatus};
+use crate::suppression::{self, SuppressionReason};
+
+lazy_static!(
+    /// The "HTTP webhook signing key" from Mailgun's dashboard. Events are only accepted when set.
+    pub static ref SIGNING_KEY: Option<String> = std::env::var("MAILGUN_WEBHOOK_SIGNING_KEY").ok();
+    /// Tokens of events that have been accepted, and their timestamps - after MAX_AGE_SECS they'd
+    /// be rejected anyway, so can be forgotten
+    static ref SEEN: Mutex<HashMap<String, i64>> = Mutex::new(HashMap::new());
+);
+
+/// How old an event's timestamp can be before it's treated as a replay
+const MAX_AGE_SECS: i64 = 15 * 60;
+/// How many tokens are remembered at most, forgetting the oldest first, so a flood of events can't
+/// use up the memory
+const MAX_SEEN: usize = 10_000;
+
+#[derive(Deserialize, ToSchema)]
+pub struct WebhookSignature {
+    timestamp: String,
+    token: String,
+    signature: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct DeliveryState {
+    #[serde(default)]
+    message: Option<String>,
+    #[serde(default)]
+    description: Option<String>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct EventData {
+    event: String,
+    /// `permanent` or `temporary`, for failures
+    #[serde(default)]
+    severity: Option<String>,
+    #[serde(default)]
+    recipient: Option<String>,
+    #[serde(default, rename = "user-variables")]
+    user_variables: HashMap<String, serde_json::Value>,
+    #[serde(default, rename = "delivery-status")]
+    delivery_status: Option<DeliveryState>,
+}
+
+/// Only the parts of [Mailgun's event](https://documentation.mailgun.com/docs/mailgun/user-manual/tracking-messages/)
+/// that are used
+#[derive(Deserialize, ToSchema)]
+pub struct WebhookEvent {
+    signature: WebhookSignature,
+    #[serde(rename = "event-data")]
+    event_data: EventData,
+}
+
+/// Mailgun signs the timestamp followed by the token, as hex-encoded HMAC-SHA256. The token is
+/// different for every event, so one that's been seen before is a replay.
+fn verify(key: &str, signature: &WebhookSignature) -> bool {
+    let now = chrono::Utc::now().timestamp();
+    let timestamp = match signature.timestamp.parse::<i64>() {
+        Ok(timestamp) if (now - timestamp).abs() <= MAX_AGE_SECS => timestamp,
+        _ => return false,
+    };
+    let provided = match hex::decode(&signature.signature) {
+        Ok(provided) => provided,
+        Err(_) => return false,
+    };
+    let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(signature.timestamp.as_bytes());
+    mac.update(signature.token.as_bytes());
+    if mac.verify_slice(&provided).is_err() {
+        return false;
+    }
+    let mut seen = SEEN.lock().unwrap();
+    seen.retain(|_, timestamp| (now - *timestamp).abs() <= MAX_AGE_SECS);
+    if seen.len() >= MAX_SEEN {
+        if let Some(oldest) = seen.iter().min_by_key(|(_, timestamp)| **timestamp).map(|(token, _)| token.clone()) {
+            seen.remove(&oldest);
+        }
+    }
+    seen.insert(signature.token.clone(), timestamp).is_none()
+}
+
+impl EventData {
+    /// A human-readable reason, for failures and complaints
+    fn reason(&self) -> Option<String> {
+        let status = self.delivery_status.as_ref()?;
+        [&status.description, &status.message].into_iter()
+            .flatten()
+            .find(|reason| !reason.is_empty())
+            .cloned()
+    }
+
+    /// What the submission's status should become. Temporary failures are left alone, as Mailgun
+    /// will keep retrying, as are events (opens, clicks, etc.) that say nothing about delivery.
+    fn delivery_status(&self) -> Option<DeliveryStatus> {
+        match (self.event.as_str(), self.severity.as_deref()) {
+            ("delivered", _) => Some(DeliveryStatus::Delivered),
+            ("failed", Some("temporary")) => None,
+            ("failed", _) => Some(DeliveryStatus::Bounced),
+            ("complained", _) => Some(DeliveryStatus::Complained),
+            _ => None,
+        }
+    }
+
+    fn submission_ids(&self) -> Vec<String> {
+        match self.user_variables.get("submission-ids") {
+            Some(serde_json::Value::String(ids)) => ids.split(',').filter(|id| !id.is_empty()).map(|id| id.to_string()).collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Receives delivery events from Mailgun, and records them against the submissions they're for
+#[utoipa::path(
+    post,
+    path = "/webhooks/mailgun",
+    tag = "webhooks",
+    request_body = WebhookEvent,
+    responses(
+        (status = 200, description = "Event recorded (or ignored, if it's not about delivery)"),
+        (status = 406, description = "Signature missing, invalid, too old or already used"),
+    ),
+)]
+pub async fn receive(Json(event): Json<WebhookEvent>) -> StatusCode {
+    let key = SIGNING_KEY.as_deref().expect("the Mailgun webhook is only mounted when a signing key is set");
+    if !verify(key, &event.signature) {
+        warn!("Rejecting Mailgun webhook event with an invalid (or replayed) signature");
+        // Mailgun won't retry after a 406
+        return StatusCode::NOT_ACCEPTABLE;
+    }
+    let event = event.event_data;
+    let recipient = event.recipient.as_deref().unwrap_or("unknown recipient");
+    let status = match event.delivery_status() {
+        Some(status) => status,
+        None => {
+            if event.event == "failed" {
+                warn!("Temporary failure delivering to {}, Mailgun will retry: {}", recipient, event.reason().unwrap_or_default());
+            }
+            return StatusCode::OK;
+        }
+    };
+    let reason = event.reason();
+    match status {
+        DeliveryStatus::Delivered => info!("Mail to {} was delivered", recipient),
+        DeliveryStatus::Complained => warn!("{} marked mail as spam", recipient),
+        _ => warn!("Mail to {} bounced: {}", recipient, reason.as_deref().unwrap_or("no reason given")),
+    }
+    let suppress = match status {
+        DeliveryStatus::Bounced => Some(SuppressionReason::Bounced),
+        DeliveryStatus::Complained => Some(SuppressionReason::Complained),
+        _ => None,
+    };
+    if let (Some(suppress), Some(recipient)) = (suppress, event.recipient.as_deref()) {
+        info!("Adding {} to the suppression list", recipient);
+        suppression::suppressions().add(recipient, suppress, reason.clone());
+        audit::record("mailgun", "suppression.added", None, Some(recipient.to_string()));
+    }
+    let reason = if status == DeliveryStatus::Delivered { None } else { reason };
+    for id in event.submission_ids() {
+        let form = store::get().and_then(|store| store.get(&id)).and_then(|submission| submission.form);
+        stats::record_status(form.as_deref(), status);
+        if let Some(store) = store::get() {
+            store.update_status(&id, status, reason.clone(), "mailgun");
+        }
+    }
+    StatusCode::OK
+}
@@ -0,0 +1,77 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+//! Adding submitters to a Mailgun mailing list, so a form can double as a newsletter signup
+
+use std::sync::OnceLock;
+use log::info;
+use crate::{form_flag, form_var};
+use crate::mailgun_api::{ListMember, MailgunClient};
+use crate::store::Submission;
+
+/// Set by [init] if there are any lists - with the same credentials as for sending, as lists belong
+/// to the account rather than a domain
+static CLIENT: OnceLock<MailgunClient> = OnceLock::new();
+
+/// Lists can only be joined through Mailgun's API, so there has to be a key for it
+pub fn init() -> Result<(), String> {
+    for (name, list) in std::env::vars() {
+        if name == "MAILING_LIST" || (name.starts_with("FORM_") && name.ends_with("_MAILING_LIST")) {
+            let api_key = std::env::var("MAILGUN_API_KEY")
+                .map_err(|_| format!("\"{}\" is set, but \"MAILGUN_API_KEY\" isn't, so nobody can be added to the list", name))?;
+            if CLIENT.get().is_none() {
+                let _ = CLIENT.set(MailgunClient::from_env(api_key)?);
+            }
+            info!("Adding submitters to the mailing list {} (from {})", list, name);
+        }
+    }
+    Ok(())
+}
+
+/// The (per-form) `MAILING_LIST` to add submitters to
+pub fn list(form: Option<&str>) -> Option<String> {
+    form_var(form, "MAILING_LIST").filter(|list| !list.is_empty())
+}
+
+/// Whether submissions to the form should only sign the submitter up, rather than also being sent on
+pub fn only(form: Option<&str>) -> bool {
+    form_flag(form, "MAILING_LIST_ONLY", false)
+}
+
+/// Adds the submitter to the list, or updates them if they're already on it
+pub async fn subscribe(list: &str, submission: &Submission) -> Result<(), String> {
+    let client = CLIENT.get().ok_or("no Mailgun API key")?;
+    let vars = Some(&submission.extra)
+        .filter(|extra| !extra.is_empty())
+        .map(|extra| serde_json::to_string(extra).expect("a map of strings can be serialized"));
+    let member = ListMember { address: &submission.from_email, name: &submission.from_name, vars, subscribed: "yes", upsert: "yes" };
+    client.add_list_member(list, &member).await
+        .map_err(|e| format!("Mailgun couldn't add them to {}: {}", list, e))?;
+    info!("Added submission {}'s sender to the mailing list {}", submission.id, list);
+    Ok(())
+}
@@ -26,149 +26,274 @@
  * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
  */
 
-use env_logger::{Builder, Target};
 use std::error::Error;
-use axum::{Form, Json, Router};
-use axum::http::{Method, StatusCode};
-use axum::response::{IntoResponse, Response};
-use axum::routing::post;
-use log::{error, info};
-use lazy_static::lazy_static;
-use serde::{Deserialize, Serialize};
-use tower_http::cors::{Any, CorsLayer};
-
-#[derive(Deserialize)]
-struct FormData {
-    from_name: String,
-    from_email: String,
-    title: String,
-    body: String,
-}
+use std::future::Future;
+use std::io;
+use std::net::{SocketAddr, TcpListener};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use axum::body::Body;
+use axum::extract::connect_info::Connected;
+use axum::http::Request;
+use env_logger::{Builder, Target};
+use hyper::server::accept::Accept;
+use hyper::server::conn::{AddrIncoming, AddrStream};
+use log::{info, warn};
+use mailgun_contact_form::{check_var, env_flag, ContactFormService};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::Sleep;
+use tower_http::timeout::TimeoutLayer;
 
-#[derive(Serialize)]
-struct MailGunData<'a> {
-    from: &'a str,
-    to: &'a str,
-    subject: &'a str,
-    text: &'a str,
+const DEFAULT_PORT: &str = "8088";
+const DEFAULT_BIND_ADDRESS: &str = "0.0.0.0";
+/// How long to spend sending what's waiting in the outbox when shutting down
+const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+/// The first file descriptor systemd passes sockets as
+#[cfg(unix)]
+const SD_LISTEN_FDS_START: i32 = 3;
+
+/// The socket systemd passed us, if we were socket-activated
+#[cfg(unix)]
+fn systemd_listener() -> Result<Option<TcpListener>, Box<dyn Error + Send + Sync>> {
+    use std::os::fd::FromRawFd;
+
+    let pid = std::env::var("LISTEN_PID").ok().and_then(|pid| pid.parse::<u32>().ok());
+    let fds = std::env::var("LISTEN_FDS").ok().and_then(|fds| fds.parse::<i32>().ok());
+    match (pid, fds) {
+        // The variables are inherited by child processes, so check they were meant for us
+        (Some(pid), Some(fds)) if pid == std::process::id() && fds >= 1 => {
+            if fds > 1 {
+                return Err("systemd passed more than one socket - only one is supported".into());
+            }
+            // Safety: systemd guarantees the descriptor is open, and it's never used elsewhere
+            let listener = unsafe { TcpListener::from_raw_fd(SD_LISTEN_FDS_START) };
+            listener.set_nonblocking(true)?;
+            Ok(Some(listener))
+        }
+        _ => Ok(None),
+    }
 }
 
-#[derive(Serialize)]
-enum ResponseStatus {
-    Ok,
-    MailAgentError,
-    InternalError,
+#[cfg(not(unix))]
+fn systemd_listener() -> Result<Option<TcpListener>, Box<dyn Error + Send + Sync>> {
+    Ok(None)
 }
 
-#[derive(Serialize)]
-struct ResponseData {
-    status: ResponseStatus,
-    message: Option<String>,
+fn secs_var(name: &str) -> Result<Option<Duration>, String> {
+    Ok(check_var::<u64>(name, "a number of seconds")?.map(Duration::from_secs))
 }
 
-#[derive(Deserialize)]
-struct MailGunErrorResponse {
-    message: String,
+type Acquire = Pin<Box<dyn Future<Output = OwnedSemaphorePermit> + Send>>;
+
+/// Accepts connections, waiting for one to close first when there are already `MAX_CONNECTIONS`
+struct Incoming {
+    inner: AddrIncoming,
+    connections: Option<Arc<Semaphore>>,
+    acquiring: Option<Acquire>,
+    permit: Option<OwnedSemaphorePermit>,
+    idle_timeout: Option<Duration>,
 }
 
-lazy_static!(
-    static ref API_KEY: String = std::env::var("MAILGUN_API_KEY").unwrap();
-    static ref DOMAIN: String = std::env::var("MAILGUN_DOMAIN").unwrap();
-    static ref TO: String = std::env::var("MAILGUN_TO_ADDRESS").unwrap();
-    static ref HOST: String = format!("https://api.mailgun.net/v3/{}/messages", DOMAIN.as_str());
-    static ref CLIENT: reqwest::Client = reqwest::Client::new();
-);
+impl Accept for Incoming {
+    type Conn = Connection;
+    type Error = io::Error;
+
+    fn poll_accept(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Connection, io::Error>>> {
+        let this = &mut *self;
+        if let (Some(connections), None) = (this.connections.as_ref(), this.permit.as_ref()) {
+            let connections = connections.clone();
+            let acquiring = this.acquiring.get_or_insert_with(|| Box::pin(async move {
+                connections.acquire_owned().await.expect("the semaphore is never closed")
+            }));
+            match acquiring.as_mut().poll(cx) {
+                Poll::Ready(permit) => {
+                    this.acquiring = None;
+                    this.permit = Some(permit);
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        match Pin::new(&mut this.inner).poll_accept(cx) {
+            Poll::Ready(Some(Ok(stream))) => Poll::Ready(Some(Ok(Connection {
+                stream,
+                _permit: this.permit.take(),
+                idle: this.idle_timeout.map(|timeout| (timeout, Box::pin(tokio::time::sleep(timeout)))),
+            }))),
+            Poll::Ready(Some(Err(e))) => Poll::Ready(Some(Err(e))),
+            Poll::Ready(None) => Poll::Ready(None),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
 
-enum ContactFormError {
-    MailGunError(reqwest::Error),
+/// A connection that gives up its slot when dropped, and is closed if it's idle for too long
+struct Connection {
+    stream: AddrStream,
+    _permit: Option<OwnedSemaphorePermit>,
+    idle: Option<(Duration, Pin<Box<Sleep>>)>,
 }
 
-impl From<reqwest::Error> for ContactFormError {
-    fn from(e: reqwest::Error) -> Self {
-        ContactFormError::MailGunError(e)
+impl Connection {
+    fn active(&mut self) {
+        if let Some((timeout, sleep)) = self.idle.as_mut() {
+            sleep.as_mut().reset(tokio::time::Instant::now() + *timeout);
+        }
     }
 }
 
-impl IntoResponse for ContactFormError {
-    fn into_response(self) -> Response {
-        match self {
-            ContactFormError::MailGunError(e) => {
-                error!("Error sending mail: {}", e);
-                (StatusCode::INTERNAL_SERVER_ERROR, Json(ResponseData { status: ResponseStatus::InternalError, message: Some(format!("{}", e)) })).into_response()
+impl AsyncRead for Connection {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        match Pin::new(&mut self.stream).poll_read(cx, buf) {
+            Poll::Ready(result) => {
+                self.active();
+                Poll::Ready(result)
+            }
+            Poll::Pending => {
+                let timed_out = self.idle.as_mut().map(|(_, sleep)| sleep.as_mut().poll(cx).is_ready()).unwrap_or(false);
+                match timed_out {
+                    true => Poll::Ready(Err(io::ErrorKind::TimedOut.into())),
+                    false => Poll::Pending,
+                }
             }
         }
     }
 }
 
-async fn send_form(Form(req): Form<FormData>) -> Result<impl IntoResponse, ContactFormError> {
-    let base_from = format!("{} <{}>", req.from_name, req.from_email);
-    info!("Sending mail from [{}]", base_from.as_str());
-    let from = base_from.as_str();
-    let data = MailGunData {
-        from,
-        to: &TO,
-        subject: &req.title,
-        text: &req.body,
-    };
-    let response = CLIENT.post(HOST.as_str())
-        .basic_auth("api", Some(API_KEY.as_str()))
-        .header("Content-Type", "application/x-www-form-urlencoded")
-        .form(&data)
-        .send()
-        .await?;
-
-    match response {
-        response if response.status().is_success() => {
-            info!("Mail sent successfully");
-            Ok((StatusCode::OK, Json(ResponseData { status: ResponseStatus::Ok, message: None })))
+impl AsyncWrite for Connection {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let result = Pin::new(&mut self.stream).poll_write(cx, buf);
+        if result.is_ready() {
+            self.active();
         }
-        response if response.status() == StatusCode::UNAUTHORIZED => {
-            let body = response.text().await?;
-            info!("Received a 401 error trying to call MailGun: {}", body);
-            Ok((StatusCode::INTERNAL_SERVER_ERROR, Json(ResponseData { status: ResponseStatus::MailAgentError, message: Some("error communicating with mail agent".to_string()) })))
+        result
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+
+impl Connected<&Connection> for SocketAddr {
+    fn connect_info(target: &Connection) -> Self {
+        target.stream.remote_addr()
+    }
+}
+
+/// Resolves once no requests have been received for `timeout`
+async fn idle(timeout: Duration, last_request: Arc<AtomicI64>) {
+    loop {
+        let idle_for = chrono::Utc::now().timestamp() - last_request.load(Ordering::Relaxed);
+        let remaining = timeout.as_secs() as i64 - idle_for;
+        if remaining <= 0 {
+            info!("No requests for {}s, shutting down", timeout.as_secs());
+            return;
         }
-        response => {
-            let data = response.json::<MailGunErrorResponse>().await?;
-            error!("Mailgun error: {}", data.message);
-            Ok((StatusCode::BAD_GATEWAY, Json(ResponseData { status: ResponseStatus::MailAgentError, message: Some("error communicating with mail agent".to_string()) })))
+        tokio::time::sleep(Duration::from_secs(remaining as u64)).await;
+    }
+}
+
+/// Resolves on Ctrl+C, or (on Unix) when asked to stop with `SIGTERM` - as systemd, Docker and
+/// Kubernetes all do
+async fn stop_requested() {
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(e) => {
+                warn!("Unable to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
         }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate => {}
     }
+    info!("Shutting down");
 }
 
-const DEFAULT_PORT: &'static str = "8088";
-const DEFAULT_BIND_ADDRESS: &'static str = "0.0.0.0";
+/// Resolves once stopping is requested, or once idle for `timeout` (if set)
+async fn shutdown(idle_timeout: Option<Duration>, last_request: Arc<AtomicI64>) {
+    match idle_timeout {
+        Some(timeout) => tokio::select! {
+            _ = stop_requested() => {}
+            _ = idle(timeout, last_request) => {}
+        },
+        None => stop_requested().await,
+    }
+}
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
+async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
     let mut builder = Builder::from_default_env();
     builder.target(Target::Stdout);
 
     builder.init();
-    // Check env vars now so we don't get a panic later!
-    std::env::var("MAILGUN_API_KEY").map_err(|_| "Environment variable \"MAILGUN_API_KEY\" must be present")?;
-    std::env::var("MAILGUN_DOMAIN").map_err(|_| "Environment variable \"MAILGUN_DOMAIN\" must be present")?;
-    std::env::var("MAILGUN_TO_ADDRESS").map_err(|_| "Environment variable \"MAILGUN_TO_ADDRESS\" must be present")?;
-
-    // Load lazy statics right away - they're only lazy because they can't be evaluated at compile time!
-    info!("Will be sending mail via domain {}, to address {}, with API key starting with {}", *DOMAIN, *TO, &API_KEY[0..6]);
+    let service = ContactFormService::builder().build().await?;
 
-    let bind_address = std::env::var("BIND_ADDRESS").unwrap_or(DEFAULT_BIND_ADDRESS.to_string());
-    let port = std::env::var("PORT").unwrap_or(DEFAULT_PORT.to_string());
+    let incoming = match systemd_listener()? {
+        Some(listener) => {
+            info!("Listening on the socket from systemd ({})", listener.local_addr()?);
+            AddrIncoming::from_listener(tokio::net::TcpListener::from_std(listener)?)?
+        }
+        None => {
+            let bind_address = std::env::var("BIND_ADDRESS").unwrap_or(DEFAULT_BIND_ADDRESS.to_string());
+            let port = std::env::var("PORT").unwrap_or(DEFAULT_PORT.to_string());
 
-    info!("Binding to {}:{}", bind_address, port);
+            info!("Binding to {}:{}", bind_address, port);
+            AddrIncoming::bind(&format!("{}:{}", bind_address, port).parse().unwrap())?
+        }
+    };
 
-    let cors = CorsLayer::new()
-        // allow `GET` and `POST` when accessing the resource
-        .allow_methods([Method::GET, Method::POST])
-        // allow requests from any origin
-        .allow_origin(Any);
+    let max_connections = check_var::<usize>("MAX_CONNECTIONS", "a whole number")?;
+    // Zero turns keep-alive off altogether
+    let keep_alive_timeout = secs_var("KEEP_ALIVE_TIMEOUT_SECS")?;
+    let keep_alive = keep_alive_timeout != Some(Duration::ZERO);
+    let incoming = Incoming {
+        inner: incoming,
+        connections: max_connections.map(|max| Arc::new(Semaphore::new(max))),
+        acquiring: None,
+        permit: None,
+        idle_timeout: keep_alive_timeout.filter(|_| keep_alive),
+    };
+    let mut server = axum::Server::builder(incoming)
+        .http1_keepalive(keep_alive)
+        // HTTP/2 without TLS, for reverse proxies that speak it to their backends
+        .http1_only(!env_flag("HTTP2", false));
+    if let Some(timeout) = secs_var("HEADER_READ_TIMEOUT_SECS")? {
+        server = server.http1_header_read_timeout(timeout);
+    }
 
-    let app = Router::new().route("/", post(send_form)).layer(cors);
+    let idle_timeout = secs_var("IDLE_TIMEOUT_SECS")?;
+    let last_request = Arc::new(AtomicI64::new(chrono::Utc::now().timestamp()));
+    let touched = last_request.clone();
+    let mut app = service.router().layer(axum::middleware::map_request(move |request: Request<Body>| {
+        touched.store(chrono::Utc::now().timestamp(), Ordering::Relaxed);
+        async move { request }
+    }));
+    if let Some(timeout) = secs_var("REQUEST_TIMEOUT_SECS")? {
+        app = app.layer(TimeoutLayer::new(timeout));
+    }
 
-    axum::Server::bind(&format!("{}:{}", bind_address, port).parse().unwrap())
-        .serve(app.into_make_service())
-        .await
-        .unwrap();
+    let drain_timeout = secs_var("SHUTDOWN_DRAIN_TIMEOUT_SECS")?.unwrap_or(DEFAULT_DRAIN_TIMEOUT);
+    server.serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(shutdown(idle_timeout, last_request))
+        .await?;
+    if tokio::time::timeout(drain_timeout, service.drain()).await.is_err() {
+        warn!("Gave up sending what was waiting in the outbox after {}s", drain_timeout.as_secs());
+    }
 
     Ok(())
 }
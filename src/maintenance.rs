@@ -0,0 +1,127 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+//! Turning submissions away (or holding on to them) while the mail setup is being worked on, rather
+//! than letting them fail with confusing errors
+
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicBool, Ordering};
+use lazy_static::lazy_static;
+use chrono::Utc;
+use log::info;
+use crate::{admin, env_flag, handler};
+use crate::outbox::{outbox, Pending};
+use crate::service::AppState;
+use crate::store::Submission;
+
+lazy_static!(
+    /// Starts out as `MAINTENANCE_MODE`, and can then be changed through the admin API
+    static ref ACTIVE: AtomicBool = AtomicBool::new(env_flag("MAINTENANCE_MODE", false));
+    /// Accept submissions during maintenance, and send them once it's over, instead of turning them away
+    pub static ref HOLD: bool = env_flag("MAINTENANCE_HOLD_SUBMISSIONS", false);
+    static ref MESSAGE: Option<String> = std::env::var("MAINTENANCE_MESSAGE").ok();
+);
+
+/// What held submissions are sent with once maintenance is over
+static STATE: OnceLock<AppState> = OnceLock::new();
+
+/// Maintenance can only be ended (without a restart) through the admin API, so there's no point
+/// holding submissions without it. Anything held when the service last stopped is sent now, unless
+/// it's starting in maintenance mode again.
+pub fn init(state: &AppState) -> Result<(), String> {
+    if *HOLD && !admin::enabled() {
+        return Err("\"MAINTENANCE_HOLD_SUBMISSIONS\" is set, but held submissions can only be released through the admin API, which isn't enabled".to_string());
+    }
+    // Only the first service built in a process gets to send held submissions
+    if STATE.set(state.clone()).is_err() {
+        return Ok(());
+    }
+    if active() {
+        info!("Starting in maintenance mode - submissions will be {}", if *HOLD { "held until it's over" } else { "turned away" });
+    } else {
+        release();
+    }
+    Ok(())
+}
+
+pub fn active() -> bool {
+    ACTIVE.load(Ordering::Relaxed)
+}
+
+/// Whether submissions should be held rather than sent
+pub fn holding() -> bool {
+    active() && *HOLD
+}
+
+/// `MAINTENANCE_MESSAGE`, or else the message key for the built-in one
+pub fn message() -> String {
+    MESSAGE.clone().unwrap_or("maintenance".to_string())
+}
+
+pub fn hold(submission: Submission) {
+    outbox().add(format!("maintenance:{}", submission.id), Utc::now(), Pending::Held { submission: Box::new(submission) });
+}
+
+/// How many submissions are waiting for maintenance to end, not counting any being sent now it has
+pub fn held() -> usize {
+    outbox().entries().iter()
+        .filter(|entry| matches!(entry.pending, Pending::Held { .. }) && !outbox().claimed(&entry.id))
+        .count()
+}
+
+pub fn start() {
+    info!("Maintenance mode on");
+    ACTIVE.store(true, Ordering::Relaxed);
+}
+
+/// Turns maintenance mode off, and sends every held submission in the background
+pub fn end() {
+    info!("Maintenance mode off");
+    ACTIVE.store(false, Ordering::Relaxed);
+    release();
+}
+
+fn release() {
+    let state = match STATE.get() {
+        Some(state) => state.clone(),
+        None => return,
+    };
+    let held = outbox().claim_all(|entry| matches!(entry.pending, Pending::Held { .. }));
+    if held.is_empty() {
+        return;
+    }
+    info!("Sending {} submission(s) held during maintenance", held.len());
+    tokio::spawn(async move {
+        for entry in held {
+            if let Pending::Held { submission } = entry.pending {
+                handler::process(&state, &submission, "maintenance").await;
+            }
+            outbox().complete(&entry.id);
+        }
+    });
+}
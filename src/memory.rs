@@ -0,0 +1,105 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+use std::sync::{Arc, Mutex};
+use async_trait::async_trait;
+use axum::Json;
+use axum::extract::State;
+use axum::http::{header, HeaderMap};
+use axum::response::{Html, IntoResponse, Response};
+use chrono::{DateTime, Utc};
+use log::info;
+use serde::Serialize;
+use crate::page::escape_html;
+use crate::provider::{Email, MailProvider, ProviderError};
+
+const MAILBOX_TEMPLATE: &str = include_str!("../templates/mailbox.html");
+
+#[derive(Clone, Serialize)]
+pub struct SentEmail {
+    pub sent_at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub email: Email,
+}
+
+/// Keeps emails in memory instead of sending them, for local development and tests
+#[derive(Default)]
+pub struct MemoryProvider {
+    sent: Mutex<Vec<SentEmail>>,
+}
+
+impl MemoryProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Everything that's been "sent", oldest first
+    pub fn sent(&self) -> Vec<SentEmail> {
+        self.sent.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl MailProvider for MemoryProvider {
+    async fn send(&self, email: &Email) -> Result<(), ProviderError> {
+        info!("Keeping email to {} in the mailbox, rather than sending it", email.to);
+        self.sent.lock().unwrap().push(SentEmail { sent_at: Utc::now(), email: email.clone() });
+        Ok(())
+    }
+}
+
+/// Lists the emails in the mailbox, newest first - as a page for browsers, or JSON otherwise
+pub async fn mailbox(State(provider): State<Arc<MemoryProvider>>, headers: HeaderMap) -> Response {
+    let mut sent = provider.sent();
+    sent.reverse();
+    let wants_html = headers.get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/html"));
+    if !wants_html {
+        return Json(sent).into_response();
+    }
+    let messages = if sent.is_empty() {
+        "<p>Nothing yet.</p>".to_string()
+    } else {
+        sent.iter()
+            .map(|sent| format!(
+                "<article><dl><dt>From</dt><dd>{}</dd><dt>To</dt><dd>{}</dd><dt>Subject</dt><dd>{}</dd><dt>Sent</dt><dd>{}</dd></dl><pre>{}</pre>{}</article>",
+                escape_html(&sent.email.from),
+                escape_html(&sent.email.to),
+                escape_html(&sent.email.subject),
+                sent.sent_at.to_rfc2822(),
+                escape_html(&sent.email.text),
+                sent.email.attachments.iter()
+                    .map(|attachment| format!("<details><summary>{}</summary><pre>{}</pre></details>", escape_html(&attachment.file_name), escape_html(&attachment.content)))
+                    .collect::<String>(),
+            ))
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+    Html(MAILBOX_TEMPLATE.replace("{{messages}}", &messages)).into_response()
+}
@@ -0,0 +1,106 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+//! Details of the request a submission came in, for triaging suspicious ones. Only collected if
+//! asked for, as they're more personal than what the submitter chose to share.
+
+use std::net::IpAddr;
+use axum::http::{header, HeaderMap};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use crate::{form_var, timestamps};
+use crate::provider::Email;
+use crate::store::Submission;
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, ToSchema)]
+pub struct RequestMetadata {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+    /// The `page_url` field, or the `Referer` header if it's missing. Unlike the rest, it's kept
+    /// whenever it's from one of `PAGE_URL_ORIGINS`, and only needs asking for to be emailed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub page_url: Option<String>,
+}
+
+/// Whether the (per-form) comma-separated `EMAIL_METADATA` includes the given item
+fn wanted(form: Option<&str>, item: &str) -> bool {
+    form_var(form, "EMAIL_METADATA").map(|items| items.split(',').any(|wanted| wanted.trim() == item)).unwrap_or(false)
+}
+
+/// `page_url` has already been checked by [crate::referrer::resolve]
+pub fn collect(form: Option<&str>, ip: Option<IpAddr>, headers: &HeaderMap, page_url: Option<String>) -> RequestMetadata {
+    let header = |name| headers.get(name).and_then(|value| value.to_str().ok()).map(|value| value.to_string());
+    RequestMetadata {
+        ip: ip.filter(|_| wanted(form, "ip")).map(|ip| ip.to_string()),
+        user_agent: header(header::USER_AGENT).filter(|_| wanted(form, "user_agent")),
+        page_url,
+    }
+}
+
+/// Adds the metadata to the email for the submission - as `X-Contact-Form-*` headers if
+/// `EMAIL_METADATA_AS` is `headers`, or otherwise at the end of the text
+pub fn attach(mut email: Email, submission: &Submission) -> Email {
+    let form = submission.form.as_deref();
+    let mut items = Vec::new();
+    let as_headers = form_var(form, "EMAIL_METADATA_AS").as_deref() == Some("headers");
+    if wanted(form, "received_at") {
+        let received_at = match as_headers {
+            true => timestamps::rfc3339(submission.received_at),
+            false => timestamps::format(submission.received_at),
+        };
+        items.push(("Received-At", "Received", received_at));
+    }
+    if wanted(form, "language") {
+        if let Some(lang) = submission.language.as_deref() {
+            items.push(("Language", "Language", lang.to_string()));
+        }
+    }
+    let metadata = &submission.metadata;
+    let page_url = metadata.page_url.clone().filter(|_| wanted(form, "page_url"));
+    for (name, label, value) in [("IP", "IP address", &metadata.ip), ("User-Agent", "User agent", &metadata.user_agent), ("Page-URL", "Page", &page_url)] {
+        if let Some(value) = value {
+            items.push((name, label, value.clone()));
+        }
+    }
+    if items.is_empty() {
+        return email;
+    }
+    if as_headers {
+        for (name, _, value) in items {
+            email.headers.insert(format!("X-Contact-Form-{}", name), value);
+        }
+    } else {
+        email.text.push_str("\n\n--\n");
+        for (_, label, value) in items {
+            email.text.push_str(&format!("{}: {}\n", label, value));
+        }
+    }
+    email
+}
@@ -0,0 +1,99 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+//! Accepts the form as `multipart/form-data` as well as URL-encoded, so it can carry attachments.
+//! Multipart bodies are parsed by [Multipart], and like URL-encoded ones are limited by the
+//! route's `DefaultBodyLimit`.
+
+use std::collections::HashMap;
+use async_trait::async_trait;
+use axum::{BoxError, Form};
+use axum::body::{Bytes, HttpBody};
+use axum::extract::{FromRequest, Multipart};
+use axum::http::{header, Request, StatusCode};
+
+/// A file from a `multipart/form-data` submission
+pub struct Upload {
+    /// The name of the form field it came from
+    pub field: String,
+    pub file_name: String,
+    pub content_type: String,
+    pub data: Bytes,
+}
+
+/// The submitted fields, and any files - which only a multipart body can have
+pub struct FormBody {
+    pub fields: HashMap<String, String>,
+    pub files: Vec<Upload>,
+}
+
+#[async_trait]
+impl<S, B> FromRequest<S, B> for FormBody
+where
+    B: HttpBody + Send + 'static,
+    B::Data: Send + Into<Bytes>,
+    B::Error: Into<BoxError>,
+    S: Send + Sync,
+{
+    /// The status and message of whatever went wrong
+    type Rejection = (StatusCode, String);
+
+    async fn from_request(req: Request<B>, state: &S) -> Result<Self, Self::Rejection> {
+        let multipart = req.headers().get(header::CONTENT_TYPE)
+            .and_then(|content_type| content_type.to_str().ok())
+            .and_then(|content_type| content_type.split(';').next())
+            .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("multipart/form-data"));
+        if multipart {
+            let multipart = Multipart::from_request(req, state).await.map_err(|e| (e.status(), e.body_text()))?;
+            return parse(multipart).await;
+        }
+        let Form(fields) = Form::<HashMap<String, String>>::from_request(req, state).await.map_err(|e| (e.status(), e.body_text()))?;
+        Ok(FormBody { fields, files: Vec::new() })
+    }
+}
+
+async fn parse(mut multipart: Multipart) -> Result<FormBody, (StatusCode, String)> {
+    let invalid = |message: String| (StatusCode::BAD_REQUEST, format!("Failed to parse the multipart body: {}", message));
+    let mut form = FormBody { fields: HashMap::new(), files: Vec::new() };
+    while let Some(field) = multipart.next_field().await.map_err(|e| (e.status(), e.body_text()))? {
+        let name = field.name().ok_or_else(|| invalid("a part has no name".to_string()))?.to_string();
+        let file_name = field.file_name().map(|file_name| file_name.to_string());
+        let content_type = field.content_type().unwrap_or("application/octet-stream").to_string();
+        let data = field.bytes().await.map_err(|e| (e.status(), e.body_text()))?;
+        match file_name {
+            // What browsers send for a file input with nothing chosen
+            Some(file_name) if file_name.is_empty() && data.is_empty() => {}
+            Some(file_name) => form.files.push(Upload { field: name, file_name, content_type, data }),
+            None => {
+                let value = String::from_utf8(data.to_vec()).map_err(|_| invalid(format!("field {} isn't UTF-8", name)))?;
+                form.fields.insert(name, value);
+            }
+        }
+    }
+    Ok(form)
+}
@@ -0,0 +1,103 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+use axum::Json;
+use axum::response::Html;
+use lazy_static::lazy_static;
+use utoipa::{Modify, OpenApi};
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use crate::env_flag;
+
+lazy_static!(
+    pub static ref SWAGGER_UI: bool = env_flag("SWAGGER_UI", false);
+);
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Mailgun Contact Form", description = "Receives contact form submissions and sends them on via email and other channels"),
+    paths(
+        crate::handler::send_form,
+        crate::confirm::confirm,
+        crate::csrf::issue,
+        crate::pow::issue,
+        crate::widget::index,
+        crate::widget::script,
+        crate::admin::list_submissions,
+        crate::admin::erase_submissions,
+        crate::admin::get_submission,
+        crate::admin::mark_spam,
+        crate::admin::mark_ham,
+        crate::admin::stats,
+        crate::admin::audit_log,
+        crate::admin::event_stream,
+        crate::admin::list_suppressions,
+        crate::admin::add_suppression,
+        crate::admin::remove_suppression,
+        crate::admin::list_senders,
+        crate::admin::add_sender,
+        crate::admin::remove_sender,
+        crate::admin::list_recipients,
+        crate::admin::list_quarantine,
+        crate::admin::purge_quarantine,
+        crate::admin::release_submission,
+        crate::admin::purge_submission,
+        crate::admin::get_maintenance,
+        crate::admin::start_maintenance,
+        crate::admin::end_maintenance,
+        crate::mailgun_webhook::receive,
+        crate::stats::get,
+    ),
+    modifiers(&AdminToken),
+    tags(
+        (name = "form", description = "Submitting the contact form"),
+        (name = "widget", description = "The example form and embeddable widget"),
+        (name = "admin", description = "Only available when `ADMIN_TOKEN` or `ADMIN_TOKENS` is set"),
+        (name = "webhooks", description = "Only available when `MAILGUN_WEBHOOK_SIGNING_KEY` is set"),
+        (name = "stats", description = "Only available when `STATS` or `STATS_TOKEN` is set"),
+    ),
+)]
+struct ApiDoc;
+
+struct AdminToken;
+
+impl Modify for AdminToken {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        if let Some(components) = openapi.components.as_mut() {
+            components.add_security_scheme("admin_token", SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()));
+        }
+    }
+}
+
+pub async fn spec() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+/// Loads Swagger UI from a CDN rather than bundling it, as it's only meant for developers
+pub async fn swagger_ui() -> Html<&'static str> {
+    Html(include_str!("../templates/swagger.html"))
+}
@@ -0,0 +1,252 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+//! Emails waiting to be sent later - retries after being rate limited, and submissions waiting for
+//! the next digest, for a send cap to reset, for maintenance to end, or to be confirmed - kept in a
+//! file (if `OUTBOX_FILE` is set) so a restart doesn't lose them.
+//!
+//! Delivery is at least once: an email only leaves the outbox after its submissions have been
+//! marked sent, so one that was being sent when the service stopped is sent again when it starts.
+//! Each email is claimed while it's being sent, so it can't be sent twice at once (say, by its retry
+//! and by draining the outbox on shutdown), and anything already marked sent is dropped on startup.
+
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+use chrono::{DateTime, Utc};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use crate::encryption;
+use crate::provider::Email;
+use crate::store::{self, DeliveryStatus, Submission};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Pending {
+    /// An email the provider rate limited, with how many times it's been retried so far
+    Retry { email: Email, form: Option<String>, attempts: u32 },
+    /// A submission waiting for the next digest
+    Digest { submission: Box<Submission> },
+    /// A submission over a send cap, waiting for it to reset
+    Capped { submission: Box<Submission> },
+    /// A submission received during maintenance, waiting for it to end
+    Held { submission: Box<Submission> },
+    /// A submission waiting for the submitter to confirm it, until it's due to expire
+    Unconfirmed { submission: Box<Submission> },
+}
+
+impl Pending {
+    fn submission_ids(&self) -> Vec<&str> {
+        match self {
+            Pending::Retry { email, .. } => email.submission_ids.iter().map(|id| id.as_str()).collect(),
+            Pending::Digest { submission } | Pending::Capped { submission } | Pending::Held { submission } | Pending::Unconfirmed { submission } => {
+                vec![submission.id.as_str()]
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Entry {
+    pub id: String,
+    /// When it's next due to be sent
+    pub due_at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub pending: Pending,
+}
+
+/// Like the submissions store, the whole outbox is written out after every change - it only ever
+/// holds a handful of emails
+pub struct Outbox {
+    path: Option<PathBuf>,
+    entries: Mutex<Vec<Entry>>,
+    /// The entries being sent right now, which have to be left alone until they're done
+    claimed: Mutex<HashSet<String>>,
+}
+
+impl Outbox {
+    fn open(path: Option<PathBuf>) -> Result<Self, String> {
+        let entries: Vec<Entry> = match &path {
+            Some(path) if path.exists() => {
+                let contents = std::fs::read(path)
+                    .map_err(|e| format!("Unable to read outbox file {}: {}", path.display(), e))?;
+                let contents = encryption::open(contents)
+                    .map_err(|e| format!("Unable to decrypt outbox file {}, as {}", path.display(), e))?;
+                serde_json::from_slice(&contents)
+                    .map_err(|e| format!("Unable to parse outbox file {}: {}", path.display(), e))?
+            }
+            _ => Vec::new(),
+        };
+        let before = entries.len();
+        // Sent, but the service stopped before they could be taken out of the outbox
+        let entries: Vec<Entry> = entries.into_iter().filter(|entry| !already_sent(&entry.pending)).collect();
+        let outbox = Outbox { path, entries: Mutex::new(Vec::new()), claimed: Mutex::new(HashSet::new()) };
+        if entries.len() < before {
+            info!("Dropping {} email(s) from the outbox that were already sent", before - entries.len());
+            outbox.save(&entries);
+        }
+        *outbox.entries.lock().unwrap() = entries;
+        Ok(outbox)
+    }
+
+    /// Adds an email to send at `due_at`. Anything already in the outbox with the same ID is left as
+    /// it is, so nothing's queued twice.
+    pub fn add(&self, id: String, due_at: DateTime<Utc>, pending: Pending) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.iter().any(|entry| entry.id == id) {
+            return;
+        }
+        entries.push(Entry { id, due_at, pending });
+        self.save(&entries);
+    }
+
+    pub fn entries(&self) -> Vec<Entry> {
+        self.entries.lock().unwrap().clone()
+    }
+
+    /// Takes the entry for sending, unless it's already being sent or has gone
+    pub fn claim(&self, id: &str) -> Claim {
+        let entries = self.entries.lock().unwrap();
+        let entry = match entries.iter().find(|entry| entry.id == id) {
+            Some(entry) => entry,
+            None => return Claim::Gone,
+        };
+        if !self.claimed.lock().unwrap().insert(id.to_string()) {
+            return Claim::Busy;
+        }
+        Claim::Claimed(Box::new(entry.clone()))
+    }
+
+    /// Claims every entry that isn't already claimed and matches the filter
+    pub fn claim_all(&self, filter: impl Fn(&Entry) -> bool) -> Vec<Entry> {
+        let entries = self.entries.lock().unwrap();
+        let mut claimed = self.claimed.lock().unwrap();
+        entries.iter()
+            .filter(|entry| filter(entry) && claimed.insert(entry.id.clone()))
+            .cloned()
+            .collect()
+    }
+
+    pub fn claimed(&self, id: &str) -> bool {
+        self.claimed.lock().unwrap().contains(id)
+    }
+
+    /// Puts a claimed entry back, to send again at `due_at`
+    pub fn release(&self, entry: Entry) {
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(existing) = entries.iter_mut().find(|existing| existing.id == entry.id) {
+            self.claimed.lock().unwrap().remove(&entry.id);
+            *existing = entry;
+            self.save(&entries);
+        }
+    }
+
+    /// Removes a claimed entry that's been sent, or given up on. Its submissions' statuses should
+    /// already have been updated, so it won't be sent again if the service stops part-way through.
+    pub fn complete(&self, id: &str) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|entry| entry.id != id);
+        self.claimed.lock().unwrap().remove(id);
+        self.save(&entries);
+    }
+
+    pub fn persistent(&self) -> bool {
+        self.path.is_some()
+    }
+
+    fn save(&self, entries: &[Entry]) {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return,
+        };
+        // Write to a temporary file first so a crash part-way through can't truncate the real one
+        let tmp = path.with_extension("tmp");
+        let result = serde_json::to_vec(entries)
+            .map_err(|e| e.to_string())
+            .and_then(encryption::seal)
+            .and_then(|contents| std::fs::write(&tmp, contents).map_err(|e| e.to_string()))
+            .and_then(|_| std::fs::rename(&tmp, path).map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            error!("Unable to save the outbox to {}: {}", path.display(), e);
+        }
+    }
+}
+
+pub enum Claim {
+    Claimed(Box<Entry>),
+    /// Something else is sending it right now
+    Busy,
+    /// It's been sent (or given up on) already
+    Gone,
+}
+
+/// Whether the stored submissions say this was sent, which can only be known if they're stored
+fn already_sent(pending: &Pending) -> bool {
+    let store = match store::get() {
+        Some(store) => store,
+        None => return false,
+    };
+    let ids = pending.submission_ids();
+    !ids.is_empty() && ids.iter().all(|id| store.get(id).is_some_and(|submission| matches!(
+        submission.status,
+        DeliveryStatus::Sent | DeliveryStatus::Delivered | DeliveryStatus::Bounced | DeliveryStatus::Complained,
+    )))
+}
+
+static OUTBOX: OnceLock<Outbox> = OnceLock::new();
+
+/// Loads the outbox from `OUTBOX_FILE`, if it's set
+pub fn init() -> Result<(), String> {
+    if OUTBOX.get().is_some() {
+        return Ok(());
+    }
+    let path = std::env::var("OUTBOX_FILE").ok().map(PathBuf::from);
+    if let Some(path) = path.as_ref() {
+        info!("Persisting emails waiting to be sent to {}", path.display());
+    }
+    let outbox = Outbox::open(path)?;
+    let waiting = outbox.entries().len();
+    if waiting > 0 {
+        info!("{} email(s) waiting to be sent from before the last restart", waiting);
+    }
+    let _ = OUTBOX.set(outbox);
+    Ok(())
+}
+
+/// Only kept in memory, if [init] hasn't been called
+pub fn outbox() -> &'static Outbox {
+    OUTBOX.get_or_init(|| Outbox::open(None).expect("there's no file to read"))
+}
+
+/// Logs what's about to be lost, if the outbox isn't kept in a file
+pub fn warn_if_lost() {
+    let waiting = outbox().entries().len();
+    if waiting > 0 && !outbox().persistent() {
+        warn!("{} email(s) still waiting to be sent will be lost, as there's no \"OUTBOX_FILE\"", waiting);
+    }
+}
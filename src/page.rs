@@ -0,0 +1,103 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+use axum::http::StatusCode;
+use axum::response::{Html, IntoResponse, Response};
+use log::error;
+use crate::{form_var, i18n};
+
+const DEFAULT_SUCCESS_TEMPLATE: &str = include_str!("../templates/success.html");
+const DEFAULT_ERROR_TEMPLATE: &str = include_str!("../templates/error.html");
+
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#x27;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Replaces each `{{name}}` in the template with the HTML-escaped value. Unknown placeholders are
+/// left alone, so typos are obvious on the rendered page. This is a single pass, so placeholders
+/// inside the values themselves are never expanded.
+pub fn render(template: &str, values: &[(&str, &str)]) -> String {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        rendered.push_str(&rest[..start]);
+        let placeholder = &rest[start..];
+        let end = match placeholder.find("}}") {
+            Some(end) => end + 2,
+            None => break,
+        };
+        let name = placeholder[2..end - 2].trim();
+        match values.iter().find(|(value_name, _)| *value_name == name) {
+            Some((_, value)) => rendered.push_str(&escape_html(value)),
+            None => rendered.push_str(&placeholder[..end]),
+        }
+        rest = &placeholder[end..];
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+/// Reads the template from the path in the given (per-form) variable on every request, so it can
+/// be edited without a restart, falling back to the built-in template if it isn't set or can't be read
+fn template(form: Option<&str>, name: &str, default: &str) -> String {
+    match form_var(form, name) {
+        Some(path) => std::fs::read_to_string(&path).unwrap_or_else(|e| {
+            error!("Unable to read {} {}, using the default: {}", name, path, e);
+            default.to_string()
+        }),
+        None => default.to_string(),
+    }
+}
+
+/// Renders the translated text into the template first, so translations can use the same
+/// placeholders as the template itself (e.g. "Thanks, {{name}}!")
+pub fn render_response(status: StatusCode, form: Option<&str>, lang: &str, values: &[(&str, &str)]) -> Response {
+    let template = if status.is_success() {
+        template(form, "SUCCESS_PAGE_TEMPLATE", DEFAULT_SUCCESS_TEMPLATE)
+    } else {
+        template(form, "ERROR_PAGE_TEMPLATE", DEFAULT_ERROR_TEMPLATE)
+    };
+    let translations = i18n::template_values(lang);
+    let translations: Vec<(&str, &str)> = translations.iter().map(|(name, text)| (name.as_str(), text.as_str())).collect();
+    let translated = render(&template, &translations);
+
+    let mut values = values.to_vec();
+    values.push(("lang", lang));
+    (status, Html(render(&translated, &values))).into_response()
+}
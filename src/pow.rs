@@ -0,0 +1,161 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use axum::Json;
+use axum::http::header;
+use axum::response::{IntoResponse, Response};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use lazy_static::lazy_static;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use utoipa::ToSchema;
+use crate::check_var;
+
+lazy_static!(
+    /// How many leading zero bits the hash of a solution must have. Challenges are only issued,
+    /// and required, when this is set.
+    pub static ref DIFFICULTY: Option<u32> = std::env::var("POW_DIFFICULTY").ok()
+        .and_then(|bits| bits.trim().parse().ok());
+    static ref TTL_SECS: i64 = std::env::var("POW_CHALLENGE_TTL_SECS").ok()
+        .and_then(|secs| secs.trim().parse().ok())
+        .unwrap_or(DEFAULT_TTL_SECS);
+    /// Challenges only need to survive until they're solved, so there's no need for a configured
+    /// secret - restarting just means visitors part way through have to solve a new one
+    static ref SECRET: String = uuid::Uuid::new_v4().simple().to_string();
+    /// Nonces of challenges that have been solved, and when they expire
+    static ref USED: Mutex<HashMap<String, i64>> = Mutex::new(HashMap::new());
+);
+
+const DEFAULT_TTL_SECS: i64 = 600;
+
+pub fn init() -> Result<(), String> {
+    check_var::<u32>("POW_DIFFICULTY", "a number of bits")?;
+    check_var::<u32>("POW_CHALLENGE_TTL_SECS", "a number of seconds")?;
+    Ok(())
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ChallengeResponse {
+    /// Send back as `_challenge`
+    challenge: String,
+    /// Find a `_solution` such that the SHA-256 of the challenge followed by the solution starts
+    /// with this many zero bits
+    difficulty: u32,
+    expires_at: DateTime<Utc>,
+}
+
+pub enum ChallengeError {
+    Missing,
+    Invalid,
+    Expired,
+    Unsolved,
+    AlreadyUsed,
+}
+
+impl ChallengeError {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            ChallengeError::Missing => "missing",
+            ChallengeError::Invalid => "invalid",
+            ChallengeError::Expired => "expired",
+            ChallengeError::Unsolved => "not solved",
+            ChallengeError::AlreadyUsed => "already used",
+        }
+    }
+}
+
+fn mac(expires: i64, nonce: &str, difficulty: u32) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(SECRET.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(format!("{}|{}|{}", expires, nonce, difficulty).as_bytes());
+    mac
+}
+
+fn leading_zero_bits(hash: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in hash {
+        bits += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    bits
+}
+
+#[utoipa::path(
+    get,
+    path = "/challenge",
+    tag = "form",
+    responses(
+        (status = 200, description = "A proof-of-work challenge to solve before submitting the form", body = ChallengeResponse),
+        (status = 404, description = "Proof-of-work isn't enabled"),
+    ),
+)]
+pub async fn issue() -> Response {
+    let difficulty = DIFFICULTY.expect("the challenge endpoint is only mounted when a difficulty is set");
+    let expires = Utc::now().timestamp() + *TTL_SECS;
+    let nonce = uuid::Uuid::new_v4().simple().to_string();
+    let challenge = format!("{}.{}.{}.{}", expires, nonce, difficulty, hex::encode(mac(expires, &nonce, difficulty).finalize().into_bytes()));
+    let expires_at = DateTime::from_timestamp(expires, 0).unwrap_or_default();
+    ([(header::CACHE_CONTROL, "no-store")], Json(ChallengeResponse { challenge, difficulty, expires_at })).into_response()
+}
+
+/// Checks the solution to the challenge, and marks the challenge as used if it's right. Always
+/// succeeds if proof-of-work isn't enabled.
+pub fn verify(challenge: Option<&str>, solution: Option<&str>) -> Result<(), ChallengeError> {
+    if DIFFICULTY.is_none() {
+        return Ok(());
+    }
+    let (challenge, solution) = challenge.zip(solution).ok_or(ChallengeError::Missing)?;
+    let mut parts = challenge.splitn(4, '.');
+    let (expires, nonce, difficulty, signature) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(expires), Some(nonce), Some(difficulty), Some(signature)) => (expires, nonce, difficulty, signature),
+        _ => return Err(ChallengeError::Invalid),
+    };
+    let expires: i64 = expires.parse().map_err(|_| ChallengeError::Invalid)?;
+    let difficulty: u32 = difficulty.parse().map_err(|_| ChallengeError::Invalid)?;
+    let signature = hex::decode(signature).map_err(|_| ChallengeError::Invalid)?;
+    mac(expires, nonce, difficulty).verify_slice(&signature).map_err(|_| ChallengeError::Invalid)?;
+
+    let now = Utc::now().timestamp();
+    if expires < now {
+        return Err(ChallengeError::Expired);
+    }
+    let hash = Sha256::new().chain_update(challenge).chain_update(solution).finalize();
+    if leading_zero_bits(&hash) < difficulty {
+        return Err(ChallengeError::Unsolved);
+    }
+    let mut used = USED.lock().unwrap();
+    used.retain(|_, expires| *expires >= now);
+    if used.insert(nonce.to_string(), expires).is_some() {
+        return Err(ChallengeError::AlreadyUsed);
+    }
+    Ok(())
+}
@@ -0,0 +1,138 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+use std::sync::Arc;
+use async_trait::async_trait;
+use log::info;
+use crate::profanity::Profanity;
+use crate::script::Scripts;
+use crate::store::Submission;
+
+/// Why a processor refused a submission. The message is shown to the submitter, translated if it's
+/// a message key (see the README's section on translations).
+#[derive(Debug)]
+pub struct Rejection {
+    pub message: String,
+    /// Keep the submission for an admin to review (and release or purge) instead of turning it
+    /// away, in which case the submitter is told it was sent
+    pub quarantine: bool,
+}
+
+impl Rejection {
+    pub fn new(message: impl Into<String>) -> Self {
+        Rejection { message: message.into(), quarantine: false }
+    }
+
+    /// A rejection that quarantines the submission, giving the reason for the admin reviewing it
+    pub fn quarantine(reason: impl Into<String>) -> Self {
+        Rejection { message: reason.into(), quarantine: true }
+    }
+}
+
+/// A step in the submission pipeline. Processors run in the order they're registered, once a
+/// submission has passed validation and the other built-in checks.
+#[async_trait]
+pub trait SubmissionProcessor: Send + Sync {
+    /// Used in logs
+    fn name(&self) -> &str;
+
+    /// Called before the submission is stored or sent anywhere. Can change it, or reject it, in
+    /// which case later processors aren't called and nothing is sent.
+    async fn before_send(&self, _submission: &mut Submission) -> Result<(), Rejection> {
+        Ok(())
+    }
+
+    /// Called in the background once the submission's been handled, with its `status` and
+    /// `status_message` set to the outcome. A `pending` status means it's waiting for a digest.
+    async fn after_send(&self, _submission: &Submission) {}
+}
+
+/// Trims leading and trailing whitespace from every field
+struct Trim;
+
+#[async_trait]
+impl SubmissionProcessor for Trim {
+    fn name(&self) -> &str {
+        "trim"
+    }
+
+    async fn before_send(&self, submission: &mut Submission) -> Result<(), Rejection> {
+        for field in [&mut submission.from_name, &mut submission.from_email, &mut submission.title, &mut submission.body] {
+            let trimmed = field.trim();
+            if trimmed.len() != field.len() {
+                *field = trimmed.to_string();
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Logs the outcome of every submission
+struct Log;
+
+#[async_trait]
+impl SubmissionProcessor for Log {
+    fn name(&self) -> &str {
+        "log"
+    }
+
+    async fn after_send(&self, submission: &Submission) {
+        info!(
+            "Submission {} from {} <{}> ({}): {:?}{}",
+            submission.id,
+            submission.from_name,
+            submission.from_email,
+            submission.form.as_deref().unwrap_or("no form"),
+            submission.status,
+            submission.status_message.as_deref().map(|message| format!(" - {}", message)).unwrap_or_default(),
+        );
+    }
+}
+
+/// The built-in processors listed in `PROCESSORS`, in order
+pub fn from_env() -> Result<Vec<Arc<dyn SubmissionProcessor>>, String> {
+    let names = match std::env::var("PROCESSORS") {
+        Ok(names) => names,
+        Err(_) => return Ok(Vec::new()),
+    };
+    names.split(',')
+        .map(|name| name.trim())
+        .filter(|name| !name.is_empty())
+        .map(builtin)
+        .collect()
+}
+
+fn builtin(name: &str) -> Result<Arc<dyn SubmissionProcessor>, String> {
+    match name {
+        "trim" => Ok(Arc::new(Trim)),
+        "log" => Ok(Arc::new(Log)),
+        "profanity" => Ok(Arc::new(Profanity::from_env()?)),
+        "script" => Ok(Arc::new(Scripts::from_env()?)),
+        _ => Err(format!("\"PROCESSORS\" includes {}, which isn't a built-in processor", name)),
+    }
+}
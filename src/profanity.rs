@@ -0,0 +1,155 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+use std::collections::{HashMap, HashSet};
+use async_trait::async_trait;
+use log::info;
+use crate::processor::{Rejection, SubmissionProcessor};
+use crate::store::Submission;
+
+const DEFAULT_TAG: &str = "[Flagged]";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Action {
+    /// Refuse the submission
+    Reject,
+    /// Hold the submission for an admin to review
+    Quarantine,
+    /// Replace each offending word with asterisks
+    Mask,
+    /// Prefix the subject, so it can be filtered or handled with care
+    Tag,
+}
+
+/// Looks for words from the configured wordlists in the subject and message - from the list for the
+/// language the submission's written in, so a word that's only rude in another language isn't
+/// caught, or from every list if there isn't one for its language (or it isn't clear).
+pub struct Profanity {
+    /// Language code -> words
+    lists: HashMap<String, HashSet<String>>,
+    action: Action,
+    tag: String,
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '\''
+}
+
+impl Profanity {
+    /// Loads the wordlists from `PROFANITY_WORDLISTS`, e.g. `en=/etc/words/en.txt,de=/etc/words/de.txt`.
+    /// Each file has one word per line, with blank lines and `#` comments ignored.
+    pub fn from_env() -> Result<Self, String> {
+        let config = std::env::var("PROFANITY_WORDLISTS")
+            .map_err(|_| "The profanity processor needs \"PROFANITY_WORDLISTS\" to be set")?;
+        let mut lists: HashMap<String, HashSet<String>> = HashMap::new();
+        for list in config.split(',').map(|list| list.trim()).filter(|list| !list.is_empty()) {
+            let (lang, path) = list.split_once('=')
+                .ok_or_else(|| format!("\"PROFANITY_WORDLISTS\" entries must look like `en=/path/to/words.txt`, not {}", list))?;
+            let contents = std::fs::read_to_string(path)
+                .map_err(|e| format!("Unable to read the {} profanity wordlist {}: {}", lang, path, e))?;
+            let words = lists.entry(lang.trim().to_lowercase()).or_default();
+            let before = words.len();
+            words.extend(contents.lines()
+                .map(|line| line.trim())
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(|word| word.to_lowercase()));
+            info!("Loaded {} word(s) into the {} profanity wordlist", words.len() - before, lang);
+        }
+        let action = match std::env::var("PROFANITY_ACTION").as_deref() {
+            Ok("reject") => Action::Reject,
+            Ok("quarantine") => Action::Quarantine,
+            Ok("mask") => Action::Mask,
+            Ok("tag") | Err(_) => Action::Tag,
+            Ok(other) => return Err(format!("\"PROFANITY_ACTION\" must be `reject`, `quarantine`, `mask` or `tag`, not {}", other)),
+        };
+        let tag = std::env::var("PROFANITY_TAG").unwrap_or(DEFAULT_TAG.to_string());
+        Ok(Profanity { lists, action, tag })
+    }
+
+    fn is_profane(&self, word: &str, language: Option<&str>) -> bool {
+        let word = word.to_lowercase();
+        match language.and_then(|language| self.lists.get(language)) {
+            Some(words) => words.contains(&word),
+            None => self.lists.values().any(|words| words.contains(&word)),
+        }
+    }
+
+    fn contains_profanity(&self, text: &str, language: Option<&str>) -> bool {
+        text.split(|c: char| !is_word_char(c)).any(|word| self.is_profane(word, language))
+    }
+
+    /// Masks whole words only, so e.g. "Scunthorpe" survives
+    fn mask(&self, text: &str, language: Option<&str>) -> String {
+        let mut masked = String::with_capacity(text.len());
+        let mut word = String::new();
+        let flush = |word: &mut String, masked: &mut String| {
+            if self.is_profane(word, language) {
+                masked.extend(word.chars().map(|_| '*'));
+            } else {
+                masked.push_str(word);
+            }
+            word.clear();
+        };
+        for c in text.chars() {
+            if is_word_char(c) {
+                word.push(c);
+            } else {
+                flush(&mut word, &mut masked);
+                masked.push(c);
+            }
+        }
+        flush(&mut word, &mut masked);
+        masked
+    }
+}
+
+#[async_trait]
+impl SubmissionProcessor for Profanity {
+    fn name(&self) -> &str {
+        "profanity"
+    }
+
+    async fn before_send(&self, submission: &mut Submission) -> Result<(), Rejection> {
+        let language = submission.language.as_deref();
+        if !self.contains_profanity(&submission.title, language) && !self.contains_profanity(&submission.body, language) {
+            return Ok(());
+        }
+        match self.action {
+            Action::Reject => return Err(Rejection::new("profanity")),
+            Action::Quarantine => return Err(Rejection::quarantine("contains profanity")),
+            Action::Mask => {
+                let language = submission.language.clone();
+                submission.title = self.mask(&submission.title, language.as_deref());
+                submission.body = self.mask(&submission.body, language.as_deref());
+            }
+            Action::Tag => submission.title = format!("{} {}", self.tag, submission.title),
+        }
+        info!("Submission {} contains profanity, action taken: {:?}", submission.id, self.action);
+        Ok(())
+    }
+}
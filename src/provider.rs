@@ -0,0 +1,111 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+/// An email to send, on behalf of a submitter or (for digests) the service itself
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Email {
+    pub from: String,
+    pub to: String,
+    pub subject: String,
+    pub text: String,
+    /// The submissions the email is about, so delivery events can be traced back to them
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub submission_ids: Vec<String>,
+    /// Extra headers, like `Message-Id`, by name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    /// Files generated to go with it, like a vCard of the submitter
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<EmailAttachment>,
+}
+
+/// Formats a name and address as `Name <address>`, quoting the name if it has anything in it that
+/// could be read as part of the address list - like a `,` or `<` - and dropping control characters,
+/// so a submitter can't add recipients or headers through their name
+pub(crate) fn mailbox(name: &str, address: &str) -> String {
+    let name: String = name.chars().filter(|c| !c.is_control()).collect();
+    let name = name.trim();
+    if name.is_empty() {
+        return address.to_string();
+    }
+    if name.chars().all(|c| c.is_alphanumeric() || c == ' ' || "!#$%&'*+-/=?^_`{|}~".contains(c)) {
+        format!("{} <{}>", name, address)
+    } else {
+        format!("\"{}\" <{}>", name.replace('\\', "\\\\").replace('"', "\\\""), address)
+    }
+}
+
+/// A file attached to an email. Only ever text, as files submitters upload are linked to rather than
+/// attached (see `ATTACHMENTS_S3_BUCKET`).
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EmailAttachment {
+    pub file_name: String,
+    pub content_type: String,
+    pub content: String,
+}
+
+#[derive(Debug)]
+pub enum ProviderError {
+    /// The provider didn't accept our credentials, so nothing will get through until they're fixed
+    Unauthorized(String),
+    /// The provider refused to send this email
+    Rejected(String),
+    /// The provider is limiting how quickly we can send, and would like us to wait this long (if it
+    /// said) before trying again
+    RateLimited(Option<Duration>),
+    /// The provider couldn't be reached, or responded with something we didn't understand
+    Unavailable(String),
+}
+
+impl std::fmt::Display for ProviderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProviderError::Unauthorized(e) => write!(f, "credentials rejected: {}", e),
+            ProviderError::Rejected(e) => write!(f, "email rejected: {}", e),
+            ProviderError::RateLimited(_) => write!(f, "rate limited"),
+            ProviderError::Unavailable(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for ProviderError {}
+
+/// Something that can send emails, such as [crate::MailgunProvider] or [crate::MemoryProvider]
+#[async_trait]
+pub trait MailProvider: Send + Sync {
+    async fn send(&self, email: &Email) -> Result<(), ProviderError>;
+
+    /// Called once at startup to check the provider is set up to send, logging anything that would
+    /// stop it. Shouldn't fail startup, as the provider may only be unreachable for now.
+    async fn check(&self) {}
+}
@@ -0,0 +1,138 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue};
+use lazy_static::lazy_static;
+use crate::check_var;
+
+/// Where a key stands against its limit in the current window, as reported in the `RateLimit-*`
+/// headers
+#[derive(Clone, Copy, Debug)]
+pub struct Quota {
+    pub limit: u32,
+    pub remaining: u32,
+    /// Seconds until the window resets
+    pub reset_secs: u64,
+}
+
+impl Quota {
+    /// The quota that will run out first, of all those a request counted against
+    pub fn tightest(quotas: &[Quota]) -> Option<Quota> {
+        quotas.iter().copied().min_by_key(|quota| (quota.remaining, quota.reset_secs))
+    }
+
+    /// Sets the `RateLimit-Limit`, `RateLimit-Remaining` and `RateLimit-Reset` headers
+    pub fn apply(&self, headers: &mut HeaderMap) {
+        headers.insert(HeaderName::from_static("ratelimit-limit"), HeaderValue::from(self.limit));
+        headers.insert(HeaderName::from_static("ratelimit-remaining"), HeaderValue::from(self.remaining));
+        headers.insert(HeaderName::from_static("ratelimit-reset"), HeaderValue::from(self.reset_secs));
+    }
+}
+
+/// The headers browsers must be allowed to read for frontends to show how long to wait
+pub const EXPOSED_HEADERS: [HeaderName; 4] = [
+    header::RETRY_AFTER,
+    HeaderName::from_static("ratelimit-limit"),
+    HeaderName::from_static("ratelimit-remaining"),
+    HeaderName::from_static("ratelimit-reset"),
+];
+
+/// Counts hits per key in fixed windows, aligned to the Unix epoch (so a window of a day resets at
+/// midnight UTC)
+pub struct RateLimiter {
+    window_secs: u64,
+    /// The window each key was last hit in, and how many times
+    counts: Mutex<HashMap<String, (u64, u32)>>,
+}
+
+impl RateLimiter {
+    pub fn new(window_secs: u64) -> Self {
+        RateLimiter { window_secs: window_secs.max(1), counts: Mutex::new(HashMap::new()) }
+    }
+
+    /// Counts a hit against `key`, unless it's already been hit `limit` times in the current
+    /// window. Either way, returns where it stands afterwards.
+    pub fn hit(&self, key: &str, limit: u32) -> Result<Quota, Quota> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).expect("the clock is after 1970").as_secs();
+        let window = now / self.window_secs;
+        let reset_secs = self.window_secs - now % self.window_secs;
+        let mut counts = self.counts.lock().unwrap();
+        counts.retain(|_, (hit_window, _)| *hit_window == window);
+        let (_, count) = counts.entry(key.to_string()).or_insert((window, 0));
+        if *count >= limit {
+            return Err(Quota { limit, remaining: 0, reset_secs });
+        }
+        *count += 1;
+        Ok(Quota { limit, remaining: limit - *count, reset_secs })
+    }
+}
+
+lazy_static!(
+    /// How many submissions each IP address may make per hour, if limited
+    static ref PER_IP_HOURLY: Option<u32> = std::env::var("IP_RATE_LIMIT").ok()
+        .and_then(|limit| limit.trim().parse().ok());
+    static ref BY_IP: RateLimiter = RateLimiter::new(60 * 60);
+    /// How many submissions may be made with each `from_email` per window, if limited
+    static ref PER_SENDER: Option<u32> = std::env::var("SENDER_RATE_LIMIT").ok()
+        .and_then(|limit| limit.trim().parse().ok());
+    static ref BY_SENDER: RateLimiter = RateLimiter::new(std::env::var("SENDER_RATE_LIMIT_WINDOW_SECS").ok()
+        .and_then(|secs| secs.trim().parse().ok())
+        .unwrap_or(60 * 60));
+);
+
+pub fn init() -> Result<(), String> {
+    check_var::<u32>("IP_RATE_LIMIT", "a whole number")?;
+    check_var::<u32>("SENDER_RATE_LIMIT", "a whole number")?;
+    if check_var::<u64>("SENDER_RATE_LIMIT_WINDOW_SECS", "a number of seconds")? == Some(0) {
+        return Err("\"SENDER_RATE_LIMIT_WINDOW_SECS\" must be at least 1".to_string());
+    }
+    Ok(())
+}
+
+/// Counts a submission from the address, with its limit scaled by `multiplier`. Always succeeds
+/// (with no quota) if IP addresses aren't limited.
+pub fn check_ip(ip: IpAddr, multiplier: f64) -> Result<Option<Quota>, Quota> {
+    match *PER_IP_HOURLY {
+        Some(limit) => BY_IP.hit(&ip.to_string(), (limit as f64 * multiplier).round() as u32).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Counts a submission from the sender's address. Always succeeds (with no quota) if senders
+/// aren't limited.
+pub fn check_sender(email: &str) -> Result<Option<Quota>, Quota> {
+    match *PER_SENDER {
+        // Case-insensitive, as spammers can vary it freely
+        Some(limit) => BY_SENDER.hit(&email.trim().to_lowercase(), limit).map(Some),
+        None => Ok(None),
+    }
+}
@@ -0,0 +1,105 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+use axum::http::{header, HeaderMap};
+use lazy_static::lazy_static;
+use log::warn;
+use reqwest::Url;
+use crate::form_var;
+
+lazy_static!(
+    /// URLs that a `_redirect` field may point at (or below)
+    static ref ALLOWLIST: Vec<Url> = entries().iter().filter_map(|url| Url::parse(url).ok()).collect();
+);
+
+fn entries() -> Vec<String> {
+    std::env::var("REDIRECT_ALLOWLIST").unwrap_or_default()
+        .split(',')
+        .map(|url| url.trim().to_string())
+        .filter(|url| !url.is_empty())
+        .collect()
+}
+
+/// Parses `REDIRECT_ALLOWLIST` now, so mistakes are reported at startup
+pub fn init() -> Result<(), String> {
+    for url in entries() {
+        Url::parse(&url).map_err(|e| format!("\"REDIRECT_ALLOWLIST\" has an invalid URL {}: {}", url, e))?;
+    }
+    lazy_static::initialize(&ALLOWLIST);
+    Ok(())
+}
+
+/// Plain HTML forms are submitted with an `Accept` header that asks for HTML, whereas `fetch` and
+/// friends default to `*/*`. A `_redirect` field is an explicit request regardless.
+pub fn requested(headers: &HeaderMap, redirect_field: Option<&str>) -> bool {
+    redirect_field.is_some() || headers.get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .map(|accept| accept.contains("text/html"))
+        .unwrap_or(false)
+}
+
+/// Requires the same scheme, host and port as an allowlisted URL, and a path at or below its path,
+/// so e.g. `https://example.com` can't be used to allow `https://example.com.evil.com`
+fn allowed(url: &Url) -> bool {
+    ALLOWLIST.iter().any(|allowed| allowed.origin() == url.origin() && url.path().starts_with(allowed.path()))
+}
+
+/// Where to send the visitor once the submission has been handled. Returns `None` if there's no
+/// suitable target, in which case the normal JSON response should be sent instead. A `trusted`
+/// (i.e. signed) redirect field doesn't need to be allowlisted.
+pub fn target(form: Option<&str>, redirect_field: Option<&str>, trusted: bool, success: bool, message: Option<&str>) -> Option<String> {
+    let requested = redirect_field.and_then(|requested| match Url::parse(requested) {
+        Ok(url) if trusted || allowed(&url) => Some(url),
+        _ => {
+            warn!("Ignoring redirect to {}, as it isn't in REDIRECT_ALLOWLIST", requested);
+            None
+        }
+    });
+    let configured = |name| form_var(form, name).and_then(|url| match Url::parse(&url) {
+        Ok(url) => Some(url),
+        Err(e) => {
+            warn!("Ignoring invalid {} {}: {}", name, url, e);
+            None
+        }
+    });
+    let success_url = requested.or_else(|| configured("REDIRECT_SUCCESS_URL"));
+
+    let mut url = if success {
+        success_url?
+    } else {
+        configured("REDIRECT_ERROR_URL").or(success_url)?
+    };
+    {
+        let mut query = url.query_pairs_mut();
+        query.append_pair("status", if success { "ok" } else { "error" });
+        if let (false, Some(message)) = (success, message) {
+            query.append_pair("message", message);
+        }
+    }
+    Some(url.into())
+}
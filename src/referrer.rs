@@ -0,0 +1,109 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+//! Which page a submission was sent from, for sites with the same form on many pages - taken from
+//! the `page_url` field, or the `Referer` header if there isn't one
+
+use std::collections::BTreeMap;
+use axum::http::{header, HeaderMap};
+use log::{error, info};
+use reqwest::Url;
+use crate::form_var;
+
+/// Longer than any page URL worth keeping
+const MAX_LENGTH: usize = 2048;
+
+fn parse_routes(json: &str) -> Result<BTreeMap<String, String>, String> {
+    serde_json::from_str(json).map_err(|e| format!("must be a JSON object of URL prefixes to email addresses: {}", e))
+}
+
+/// Checks every `PAGE_URL_ORIGINS` and `PAGE_ROUTES` now, rather than on the first submission
+pub fn init() -> Result<(), String> {
+    let configured = |name: &str, setting: &str| name == setting || (name.starts_with("FORM_") && name.ends_with(&format!("_{}", setting)));
+    for (name, value) in std::env::vars() {
+        if configured(&name, "PAGE_URL_ORIGINS") {
+            for origin in origins(&value) {
+                match Url::parse(&origin) {
+                    Ok(url) if url.origin().is_tuple() => {}
+                    _ => return Err(format!("\"{}\" must be a comma-separated list of origins like https://example.com, not {}", name, origin)),
+                }
+            }
+        } else if configured(&name, "PAGE_ROUTES") {
+            let routes = parse_routes(&value).map_err(|e| format!("\"{}\" {}", name, e))?;
+            info!("Routing submissions from {} to their own recipients (from {})", routes.keys().cloned().collect::<Vec<_>>().join(", "), name);
+        }
+    }
+    Ok(())
+}
+
+fn origins(value: &str) -> Vec<String> {
+    value.split(',').map(|origin| origin.trim().trim_end_matches('/').to_string()).filter(|origin| !origin.is_empty()).collect()
+}
+
+/// Whether `url` is an `http` or `https` URL from one of the form's `PAGE_URL_ORIGINS`, or any
+/// such URL if there aren't any
+pub fn allowed(form: Option<&str>, url: &str) -> bool {
+    parse(form, url).is_some()
+}
+
+/// `url` as [Url::parse] normalises it, if it's [allowed] - which also drops any tabs or newlines,
+/// so it's the only version safe to put in a header
+fn parse(form: Option<&str>, url: &str) -> Option<Url> {
+    let parsed = Url::parse(url).ok().filter(|parsed| url.len() <= MAX_LENGTH && matches!(parsed.scheme(), "http" | "https"))?;
+    match form_var(form, "PAGE_URL_ORIGINS") {
+        Some(allowed) if !origins(&allowed).contains(&parsed.origin().ascii_serialization()) => None,
+        _ => Some(parsed),
+    }
+}
+
+/// The page the submission came from, if either the field or the `Referer` header gives an allowed
+/// one. An invalid field is a validation error, so by now shouldn't be here at all.
+pub fn resolve(form: Option<&str>, page_url: Option<&str>, headers: &HeaderMap) -> Option<String> {
+    let referer = headers.get(header::REFERER).and_then(|referer| referer.to_str().ok());
+    page_url.filter(|url| !url.trim().is_empty())
+        .or(referer)
+        .and_then(|url| parse(form, url))
+        .map(String::from)
+}
+
+/// Who the form's `PAGE_ROUTES` sends submissions from the page to, if anyone. The longest matching
+/// prefix wins, so a whole section can be routed along with exceptions for particular pages.
+pub fn route(form: Option<&str>, page_url: &str) -> Option<String> {
+    match form_var(form, "PAGE_ROUTES").map(|json| parse_routes(&json)) {
+        Some(Ok(routes)) => routes.into_iter()
+            .filter(|(prefix, _)| page_url.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, to)| to),
+        Some(Err(e)) => {
+            // Only possible if the environment's changed since startup
+            error!("Ignoring \"PAGE_ROUTES\", which {}", e);
+            None
+        }
+        None => None,
+    }
+}
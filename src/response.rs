@@ -0,0 +1,78 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+use lazy_static::lazy_static;
+use serde_json::{Map, Value};
+use crate::{check_json_var, env_flag, ResponseData};
+
+lazy_static!(
+    static ref STATUS_FIELD: String = std::env::var("RESPONSE_STATUS_FIELD").unwrap_or("status".to_string());
+    static ref MESSAGE_FIELD: String = std::env::var("RESPONSE_MESSAGE_FIELD").unwrap_or("message".to_string());
+    static ref INCLUDE_MESSAGE: bool = env_flag("RESPONSE_INCLUDE_MESSAGE", true);
+    static ref ID_FIELD: Option<String> = std::env::var("RESPONSE_ID_FIELD").ok();
+    static ref STATUS_VALUES: Map<String, Value> = json_object("RESPONSE_STATUS_VALUES");
+    static ref EXTRA_FIELDS: Map<String, Value> = json_object("RESPONSE_EXTRA_FIELDS");
+);
+
+fn json_object(name: &str) -> Map<String, Value> {
+    check_json_var(name, "a JSON object").ok().flatten().unwrap_or_default()
+}
+
+/// Parses the JSON settings now, so mistakes are reported at startup
+pub fn init() -> Result<(), String> {
+    check_json_var::<Map<String, Value>>("RESPONSE_STATUS_VALUES", "a JSON object")?;
+    check_json_var::<Map<String, Value>>("RESPONSE_EXTRA_FIELDS", "a JSON object")?;
+    lazy_static::initialize(&STATUS_VALUES);
+    lazy_static::initialize(&EXTRA_FIELDS);
+    Ok(())
+}
+
+/// Builds the body of the form endpoint's response, in whatever shape has been configured. With
+/// no configuration this is the same as serializing the `ResponseData` directly.
+pub fn shape(data: ResponseData, submission_id: Option<&str>) -> Value {
+    let mut body = EXTRA_FIELDS.clone();
+    let status = serde_json::to_value(&data.status).expect("statuses are always serializable");
+    let status = status.as_str()
+        .and_then(|name| STATUS_VALUES.get(name))
+        .cloned()
+        .unwrap_or(status);
+    body.insert(STATUS_FIELD.clone(), status);
+    if *INCLUDE_MESSAGE {
+        body.insert(MESSAGE_FIELD.clone(), data.message.map(Value::String).unwrap_or(Value::Null));
+    }
+    if let Some(errors) = data.errors {
+        body.insert("errors".to_string(), serde_json::to_value(errors).expect("errors are always serializable"));
+    }
+    if let Some(retry_after) = data.retry_after {
+        body.insert("retry_after".to_string(), Value::from(retry_after));
+    }
+    if let (Some(field), Some(id)) = (ID_FIELD.as_ref(), submission_id) {
+        body.insert(field.clone(), Value::String(id.to_string()));
+    }
+    Value::Object(body)
+}
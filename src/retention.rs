@@ -0,0 +1,74 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+use std::time::Duration;
+use chrono::Utc;
+use lazy_static::lazy_static;
+use log::info;
+use crate::{audit, check_var};
+use crate::store;
+
+lazy_static!(
+    /// How many days to keep submissions for. Kept forever if this isn't set.
+    static ref DAYS: Option<i64> = std::env::var("RETENTION_DAYS").ok()
+        .and_then(|days| days.trim().parse().ok());
+    static ref MODE: String = std::env::var("RETENTION_MODE").unwrap_or("delete".to_string());
+);
+
+/// How often to look for submissions that have passed the retention period
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Starts purging old submissions in the background, if there are any being kept and a retention
+/// period has been set
+pub fn start() -> Result<(), String> {
+    if check_var::<i64>("RETENTION_DAYS", "a number of days")?.is_some_and(|days| days < 1) {
+        return Err("\"RETENTION_DAYS\" must be at least 1".to_string());
+    }
+    let (days, store) = match (*DAYS, store::get()) {
+        (Some(days), Some(store)) => (days, store),
+        _ => return Ok(()),
+    };
+    let anonymize = match MODE.as_str() {
+        "delete" => false,
+        "anonymize" => true,
+        other => return Err(format!("\"RETENTION_MODE\" must be `delete` or `anonymize`, not {}", other)),
+    };
+    info!("{} submissions after {} day(s)", if anonymize { "Anonymizing" } else { "Deleting" }, days);
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(PURGE_INTERVAL);
+        loop {
+            ticks.tick().await;
+            let purged = store.purge_before(Utc::now() - chrono::Duration::days(days), anonymize);
+            if purged > 0 {
+                audit::record("retention", if anonymize { "submissions.anonymized" } else { "submissions.purged" }, None, Some(format!("{} submission(s)", purged)));
+                info!("{} {} submission(s) older than {} day(s)", if anonymize { "Anonymized" } else { "Deleted" }, purged, days);
+            }
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,160 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+use std::sync::Arc;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use log::{error, info, warn};
+use crate::{alert, check_var, concurrency, stats};
+use crate::outbox::{outbox, Claim, Pending};
+use crate::provider::{Email, MailProvider, ProviderError};
+use crate::store::{self, DeliveryStatus};
+
+lazy_static!(
+    static ref MAX_ATTEMPTS: u32 = std::env::var("MAIL_RETRY_MAX_ATTEMPTS").ok()
+        .and_then(|attempts| attempts.trim().parse().ok())
+        .unwrap_or(DEFAULT_MAX_ATTEMPTS)
+        .max(1);
+);
+
+const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+/// How long to wait when the provider doesn't say
+pub const DEFAULT_DELAY: Duration = Duration::from_secs(60);
+/// Don't hold on to submissions indefinitely because of a silly `Retry-After`
+const MAX_DELAY: Duration = Duration::from_secs(60 * 60);
+/// How long to wait for someone else to finish sending a retry, before checking whether it's gone
+const BUSY_DELAY: Duration = Duration::from_secs(1);
+
+pub fn init() -> Result<(), String> {
+    check_var::<u32>("MAIL_RETRY_MAX_ATTEMPTS", "a positive number")?;
+    Ok(())
+}
+
+fn update_status(email: &Email, form: Option<&str>, status: DeliveryStatus, message: Option<String>) {
+    for _ in email.submission_ids.iter() {
+        stats::record_status(form, status);
+    }
+    if let Some(store) = store::get() {
+        for id in email.submission_ids.iter() {
+            store.update_status(id, status, message.clone(), "retry");
+        }
+    }
+}
+
+/// Sends the email again after `delay`, for as long as the provider keeps rate limiting us (up to
+/// `MAIL_RETRY_MAX_ATTEMPTS` times). The email's submissions are marked as rate limited until then.
+/// Retries are kept in the outbox, so are picked up again by [resume] if the service restarts.
+pub fn schedule(provider: Arc<dyn MailProvider>, email: Email, form: Option<String>, delay: Option<Duration>) {
+    let delay = delay.unwrap_or(DEFAULT_DELAY).min(MAX_DELAY);
+    warn!("Mail provider is rate limiting us, will try sending to {} again in {}s", email.to, delay.as_secs());
+    update_status(&email, form.as_deref(), DeliveryStatus::RateLimited, Some(format!("retrying in {}s", delay.as_secs())));
+    let id = uuid::Uuid::new_v4().to_string();
+    outbox().add(id.clone(), due_at(delay), Pending::Retry { email, form, attempts: 0 });
+    tokio::spawn(retry(provider, id, delay));
+}
+
+/// Picks up the retries that were waiting when the service last stopped, sending any that are
+/// overdue straight away
+pub fn resume(provider: Arc<dyn MailProvider>) {
+    for entry in outbox().entries() {
+        if let Pending::Retry { email, .. } = &entry.pending {
+            let delay = (entry.due_at - Utc::now()).to_std().unwrap_or(Duration::ZERO);
+            info!("Resuming retries sending to {}, with the next in {}s", email.to, delay.as_secs());
+            tokio::spawn(retry(provider.clone(), entry.id, delay));
+        }
+    }
+}
+
+/// Tries every waiting retry once more, without waiting for it to be due - for when the service is
+/// shutting down. Any that are still rate limited stay in the outbox.
+pub async fn drain(provider: &dyn MailProvider) {
+    let ids: Vec<String> = outbox().entries().into_iter()
+        .filter(|entry| matches!(entry.pending, Pending::Retry { .. }))
+        .map(|entry| entry.id)
+        .collect();
+    for id in ids {
+        attempt(provider, &id).await;
+    }
+}
+
+fn due_at(delay: Duration) -> DateTime<Utc> {
+    Utc::now() + chrono::Duration::from_std(delay).unwrap_or(chrono::Duration::zero())
+}
+
+async fn retry(provider: Arc<dyn MailProvider>, id: String, mut delay: Duration) {
+    loop {
+        tokio::time::sleep(delay).await;
+        match attempt(provider.as_ref(), &id).await {
+            Some(next) => delay = next,
+            None => return,
+        }
+    }
+}
+
+/// Sends the retry, returning how long to wait before trying again, if it needs to be
+async fn attempt(provider: &dyn MailProvider, id: &str) -> Option<Duration> {
+    let mut entry = match outbox().claim(id) {
+        Claim::Claimed(entry) => entry,
+        // Being sent by [drain], so check back after it's finished
+        Claim::Busy => return Some(BUSY_DELAY),
+        Claim::Gone => return None,
+    };
+    let (email, form, attempts) = match &mut entry.pending {
+        Pending::Retry { email, form, attempts } => (email.clone(), form.clone(), attempts),
+        _ => return None,
+    };
+    *attempts += 1;
+    let attempt = *attempts;
+    let sent = {
+        let _slot = concurrency::wait().await;
+        provider.send(&email).await
+    };
+    match sent {
+        Ok(()) => {
+            info!("Mail to {} sent successfully after being rate limited", email.to);
+            update_status(&email, form.as_deref(), DeliveryStatus::Sent, None);
+            outbox().complete(id);
+            None
+        }
+        Err(ProviderError::RateLimited(retry_after)) if attempt < *MAX_ATTEMPTS => {
+            let delay = retry_after.unwrap_or(DEFAULT_DELAY).min(MAX_DELAY);
+            warn!("Still rate limited sending to {}, will try again in {}s", email.to, delay.as_secs());
+            entry.due_at = due_at(delay);
+            outbox().release(*entry);
+            Some(delay)
+        }
+        Err(e) => {
+            error!("Giving up sending to {} after {} retries: {}", email.to, attempt, e);
+            update_status(&email, form.as_deref(), DeliveryStatus::Failed, Some(e.to_string()));
+            alert::failure(&e.to_string());
+            outbox().complete(id);
+            None
+        }
+    }
+}
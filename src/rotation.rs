@@ -0,0 +1,212 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+//! Sharing submissions out across a team - by whoever's on call, or in turn - rather than sending
+//! them all to `MAILGUN_TO_ADDRESS`
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use chrono::{Datelike, NaiveDateTime, NaiveTime, Utc, Weekday};
+use lazy_static::lazy_static;
+use log::{error, info};
+use serde::Deserialize;
+use crate::{form_var, timestamps};
+
+const DAYS: [(&str, Weekday); 7] = [
+    ("mon", Weekday::Mon), ("tue", Weekday::Tue), ("wed", Weekday::Wed), ("thu", Weekday::Thu),
+    ("fri", Weekday::Fri), ("sat", Weekday::Sat), ("sun", Weekday::Sun),
+];
+
+lazy_static!(
+    /// How far through each rotation we are, by its configuration - the current weight of each
+    /// recipient, as in smooth weighted round-robin. Starts again on restart.
+    static ref TURNS: Mutex<HashMap<String, Vec<i64>>> = Mutex::new(HashMap::new());
+);
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RotationConfig {
+    /// Everyone gets the same share
+    Equal(Vec<String>),
+    /// Everyone gets a share in proportion to their weight
+    Weighted(BTreeMap<String, u32>),
+}
+
+fn parse_rotation(json: &str) -> Result<Vec<(String, u32)>, String> {
+    let config: RotationConfig = serde_json::from_str(json)
+        .map_err(|_| "must be a JSON array of addresses, or an object of addresses to weights".to_string())?;
+    let recipients: Vec<(String, u32)> = match config {
+        RotationConfig::Equal(addresses) => addresses.into_iter().map(|address| (address, 1)).collect(),
+        RotationConfig::Weighted(weights) => weights.into_iter().collect(),
+    };
+    if recipients.is_empty() {
+        return Err("has no one in it".to_string());
+    }
+    match recipients.iter().find(|(_, weight)| *weight == 0) {
+        Some((address, _)) => Err(format!("gives {} a weight of 0 - leave them out instead", address)),
+        None => Ok(recipients),
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ShiftConfig {
+    to: String,
+    days: Option<String>,
+    from: Option<String>,
+    until: Option<String>,
+}
+
+/// A time someone's on call - on the given days, from `from` until `until`. A shift ending earlier
+/// in the day than it starts runs past midnight, and one ending when it starts lasts all day.
+struct Shift {
+    to: String,
+    days: Vec<Weekday>,
+    from: NaiveTime,
+    until: NaiveTime,
+}
+
+impl Shift {
+    fn covers(&self, now: NaiveDateTime) -> bool {
+        let (day, time) = (now.weekday(), now.time());
+        if self.from == self.until {
+            self.days.contains(&day)
+        } else if self.from < self.until {
+            self.days.contains(&day) && time >= self.from && time < self.until
+        } else {
+            (self.days.contains(&day) && time >= self.from) || (self.days.contains(&day.pred()) && time < self.until)
+        }
+    }
+}
+
+fn parse_day(day: &str) -> Result<Weekday, String> {
+    DAYS.iter().find(|(name, _)| day.trim().eq_ignore_ascii_case(name)).map(|(_, day)| *day)
+        .ok_or_else(|| format!("has a day of {}, rather than one of mon, tue, wed, thu, fri, sat or sun", day.trim()))
+}
+
+/// Days like `mon-fri` or `mon,wed,fri-sun`. Ranges can wrap around the weekend, like `fri-mon`.
+fn parse_days(days: &str) -> Result<Vec<Weekday>, String> {
+    let mut parsed = Vec::new();
+    for part in days.split(',') {
+        match part.split_once('-') {
+            Some((first, last)) => {
+                let (mut day, last) = (parse_day(first)?, parse_day(last)?);
+                parsed.push(day);
+                while day != last {
+                    day = day.succ();
+                    parsed.push(day);
+                }
+            }
+            None => parsed.push(parse_day(part)?),
+        }
+    }
+    Ok(parsed)
+}
+
+fn parse_time(time: Option<&str>) -> Result<NaiveTime, String> {
+    match time {
+        Some(time) => NaiveTime::parse_from_str(time.trim(), "%H:%M").map_err(|_| format!("has a time of {}, rather than one like 09:00", time)),
+        None => Ok(NaiveTime::MIN),
+    }
+}
+
+fn parse_schedule(json: &str) -> Result<Vec<Shift>, String> {
+    let shifts: Vec<ShiftConfig> = serde_json::from_str(json)
+        .map_err(|e| format!("must be a JSON array of shifts, like {{\"to\": \"alice@example.com\", \"days\": \"mon-fri\", \"from\": \"09:00\", \"until\": \"17:00\"}}: {}", e))?;
+    shifts.into_iter()
+        .map(|shift| Ok(Shift {
+            days: match shift.days.as_deref() {
+                Some(days) => parse_days(days)?,
+                None => DAYS.iter().map(|(_, day)| *day).collect(),
+            },
+            from: parse_time(shift.from.as_deref())?,
+            until: parse_time(shift.until.as_deref())?,
+            to: shift.to,
+        }))
+        .collect()
+}
+
+/// Checks `RECIPIENT_ROTATION` and `ON_CALL_SCHEDULE`, and every per-form override of them, now,
+/// so mistakes are reported at startup rather than sending everything to `MAILGUN_TO_ADDRESS`
+pub fn init() -> Result<(), String> {
+    for (name, json) in std::env::vars() {
+        if name == "RECIPIENT_ROTATION" || (name.starts_with("FORM_") && name.ends_with("_RECIPIENT_ROTATION")) {
+            let recipients = parse_rotation(&json).map_err(|e| format!("\"{}\" {}", name, e))?;
+            info!("Sharing submissions between {} recipient(s) in turn (from {})", recipients.len(), name);
+        }
+        if name == "ON_CALL_SCHEDULE" || (name.starts_with("FORM_") && name.ends_with("_ON_CALL_SCHEDULE")) {
+            let shifts = parse_schedule(&json).map_err(|e| format!("\"{}\" {}", name, e))?;
+            info!("Sending submissions to whoever's on call, from {} shift(s) (from {})", shifts.len(), name);
+        }
+    }
+    Ok(())
+}
+
+/// Who the form's `ON_CALL_SCHEDULE` says is on call now, or else whose turn it is in its
+/// `RECIPIENT_ROTATION` - if either is set
+pub fn pick(form: Option<&str>) -> Option<String> {
+    on_call(form).or_else(|| next_in_turn(form))
+}
+
+fn on_call(form: Option<&str>) -> Option<String> {
+    match form_var(form, "ON_CALL_SCHEDULE").map(|json| parse_schedule(&json)) {
+        Some(Ok(shifts)) => {
+            let now = timestamps::local(Utc::now());
+            shifts.into_iter().find(|shift| shift.covers(now)).map(|shift| shift.to)
+        }
+        Some(Err(e)) => {
+            // Only possible if the environment's changed since startup
+            error!("Ignoring \"ON_CALL_SCHEDULE\", which {}", e);
+            None
+        }
+        None => None,
+    }
+}
+
+/// Smooth weighted round-robin, so someone with a weight of 2 gets every other submission rather
+/// than two in a row
+fn next_in_turn(form: Option<&str>) -> Option<String> {
+    let json = form_var(form, "RECIPIENT_ROTATION")?;
+    let recipients = match parse_rotation(&json) {
+        Ok(recipients) => recipients,
+        Err(e) => {
+            error!("Ignoring \"RECIPIENT_ROTATION\", which {}", e);
+            return None;
+        }
+    };
+    let total: i64 = recipients.iter().map(|(_, weight)| *weight as i64).sum();
+    let mut turns = TURNS.lock().unwrap();
+    let current = turns.entry(json).or_insert_with(|| vec![0; recipients.len()]);
+    for (current, (_, weight)) in current.iter_mut().zip(recipients.iter()) {
+        *current += *weight as i64;
+    }
+    // The first of any tied for the highest
+    let (next, _) = current.iter().enumerate().rev().max_by_key(|(_, current)| **current)?;
+    current[next] -= total;
+    Some(recipients[next].0.clone())
+}
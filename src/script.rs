@@ -0,0 +1,249 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+//! Per-form business rules, as a small [Rhai](https://rhai.rs) script run against each submission -
+//! for the checks that are too particular to a site to be worth a setting, without needing a
+//! processor written in Rust.
+//!
+//! Scripts can only change the submission, and run with limits on how much work they can do, so a
+//! script always finishes, and can't reach anything outside of it. See the README for what's
+//! available to them.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use async_trait::async_trait;
+use log::{debug, error, info};
+use rhai::module_resolvers::DummyModuleResolver;
+use rhai::{Dynamic, Engine, EvalAltResult, Position, Scope, AST};
+use crate::form_var;
+use crate::processor::{Rejection, SubmissionProcessor};
+use crate::store::Submission;
+
+/// Plenty for a page of rules
+const MAX_SCRIPT_BYTES: usize = 64 * 1024;
+/// Far more than any set of rules needs, but stops a runaway loop in a few milliseconds
+const MAX_OPERATIONS: u64 = 100_000;
+/// Room for building a new body out of the old one
+const MAX_STRING_BYTES: usize = 1024 * 1024;
+const MAX_EXPR_DEPTH: usize = 32;
+const MAX_CALL_LEVELS: usize = 16;
+const MAX_COLLECTION_SIZE: usize = 10_000;
+/// Fields that are variables in scripts, as every submission has them. Other fields are read with
+/// `field(name)`, as they may not be there.
+const VARIABLES: &[&str] = &["from_name", "from_email", "title", "body", "form", "language", "page_url", "to"];
+/// Fields scripts can read but not change
+const READ_ONLY: &[&str] = &["form", "language", "page_url", "to"];
+
+/// What a script decided
+#[derive(Debug, Default, PartialEq)]
+struct Outcome {
+    /// Every field, with any changes
+    fields: HashMap<String, String>,
+    changed: Vec<String>,
+    to: Option<String>,
+    rejection: Option<(String, bool)>,
+}
+
+/// An engine with the limits, and nothing that reaches outside of the script - scripts are
+/// compiled and run with the same limits
+fn engine() -> Engine {
+    let mut engine = Engine::new();
+    engine.set_max_operations(MAX_OPERATIONS)
+        .set_max_string_size(MAX_STRING_BYTES)
+        .set_max_expr_depths(MAX_EXPR_DEPTH, MAX_EXPR_DEPTH)
+        .set_max_call_levels(MAX_CALL_LEVELS)
+        .set_max_array_size(MAX_COLLECTION_SIZE)
+        .set_max_map_size(MAX_COLLECTION_SIZE)
+        // Otherwise `import` reads other scripts from disk
+        .set_module_resolver(DummyModuleResolver::new())
+        .on_print(|text| debug!("Script printed {}", text))
+        .on_debug(|text, _, position| debug!("Script debug at {}: {}", position, text));
+    engine
+}
+
+/// Stops the script, once `reject` or `quarantine` has recorded why
+fn stop() -> Result<(), Box<EvalAltResult>> {
+    Err(Box::new(EvalAltResult::ErrorTerminated(Dynamic::UNIT, Position::NONE)))
+}
+
+/// [engine], with the functions for changing `outcome`
+fn engine_for(outcome: &Arc<Mutex<Outcome>>) -> Engine {
+    let mut engine = engine();
+    let state = outcome.clone();
+    engine.register_fn("field", move |name: &str| state.lock().unwrap().fields.get(name).cloned().unwrap_or_default());
+    // Fields are always text, so this is how to compare them as numbers. Anything that isn't one
+    // is NaN, which every comparison is false for.
+    engine.register_fn("number", |text: &str| text.trim().parse::<f64>().unwrap_or(f64::NAN));
+    let state = outcome.clone();
+    engine.register_fn("set", move |field: &str, value: Dynamic| -> Result<(), Box<EvalAltResult>> {
+        if READ_ONLY.contains(&field) {
+            return Err(format!("{} can't be changed", field).into());
+        }
+        let mut outcome = state.lock().unwrap();
+        outcome.fields.insert(field.to_string(), value.to_string());
+        if !outcome.changed.iter().any(|changed| changed == field) {
+            outcome.changed.push(field.to_string());
+        }
+        Ok(())
+    });
+    let state = outcome.clone();
+    engine.register_fn("route", move |to: &str| -> Result<(), Box<EvalAltResult>> {
+        if !to.contains('@') {
+            return Err(format!("can't route to {}, which isn't an email address", to).into());
+        }
+        let mut outcome = state.lock().unwrap();
+        outcome.fields.insert("to".to_string(), to.to_string());
+        outcome.to = Some(to.to_string());
+        Ok(())
+    });
+    let state = outcome.clone();
+    engine.register_fn("reject", move |message: &str| {
+        state.lock().unwrap().rejection = Some((message.to_string(), false));
+        stop()
+    });
+    let state = outcome.clone();
+    engine.register_fn("quarantine", move |reason: &str| {
+        state.lock().unwrap().rejection = Some((reason.to_string(), true));
+        stop()
+    });
+    engine
+}
+
+/// A compiled script
+pub struct Script {
+    ast: AST,
+}
+
+impl Script {
+    fn parse(source: &str) -> Result<Self, String> {
+        if source.len() > MAX_SCRIPT_BYTES {
+            return Err(format!("is over {} bytes", MAX_SCRIPT_BYTES));
+        }
+        let ast = engine().compile(source).map_err(|e| format!("can't be parsed: {}", e))?;
+        Ok(Script { ast })
+    }
+
+    /// Runs the script against `fields`, stopping at the first `reject` or `quarantine`
+    fn run(&self, fields: HashMap<String, String>) -> Result<Outcome, String> {
+        let mut scope = Scope::new();
+        for name in VARIABLES {
+            scope.push_constant(*name, fields.get(*name).cloned().unwrap_or_default());
+        }
+        let outcome = Arc::new(Mutex::new(Outcome { fields, ..Outcome::default() }));
+        let result = engine_for(&outcome).run_ast_with_scope(&mut scope, &self.ast);
+        let outcome = std::mem::take(&mut *outcome.lock().unwrap());
+        match result {
+            Err(e) if !matches!(*e, EvalAltResult::ErrorTerminated(..)) || outcome.rejection.is_none() => Err(e.to_string()),
+            _ => Ok(outcome),
+        }
+    }
+}
+
+/// Runs the (per-form) `SCRIPT_FILE` on each submission
+pub struct Scripts {
+    /// By path, so forms sharing a script share its compiled form too
+    scripts: HashMap<String, Script>,
+}
+
+impl Scripts {
+    /// Compiles every `SCRIPT_FILE` now, so mistakes are found at startup rather than by submitters
+    pub fn from_env() -> Result<Self, String> {
+        let mut scripts = HashMap::new();
+        for (name, path) in std::env::vars() {
+            if name == "SCRIPT_FILE" || (name.starts_with("FORM_") && name.ends_with("_SCRIPT_FILE")) {
+                let source = std::fs::read_to_string(&path).map_err(|e| format!("Unable to read the script {} (from {}): {}", path, name, e))?;
+                let script = Script::parse(&source).map_err(|e| format!("The script {} (from {}) {}", path, name, e))?;
+                info!("Loaded the script {}", path);
+                scripts.insert(path, script);
+            }
+        }
+        if scripts.is_empty() {
+            return Err("The script processor needs \"SCRIPT_FILE\" (or a form's) to be set".to_string());
+        }
+        Ok(Scripts { scripts })
+    }
+}
+
+fn fields_of(submission: &Submission) -> HashMap<String, String> {
+    let mut fields = submission.extra.clone().into_iter().collect::<HashMap<_, _>>();
+    for (name, value) in [
+        ("from_name", Some(&submission.from_name)),
+        ("from_email", Some(&submission.from_email)),
+        ("title", Some(&submission.title)),
+        ("body", Some(&submission.body)),
+        ("form", submission.form.as_ref()),
+        ("language", submission.language.as_ref()),
+        ("page_url", submission.metadata.page_url.as_ref()),
+        ("to", submission.to.as_ref()),
+    ] {
+        fields.insert(name.to_string(), value.cloned().unwrap_or_default());
+    }
+    fields
+}
+
+#[async_trait]
+impl SubmissionProcessor for Scripts {
+    fn name(&self) -> &str {
+        "script"
+    }
+
+    async fn before_send(&self, submission: &mut Submission) -> Result<(), Rejection> {
+        let script = match form_var(submission.form.as_deref(), "SCRIPT_FILE").and_then(|path| self.scripts.get(&path)) {
+            Some(script) => script,
+            None => return Ok(()),
+        };
+        let mut outcome = match script.run(fields_of(submission)) {
+            Ok(outcome) => outcome,
+            Err(e) => {
+                // A broken rule shouldn't cost the site its enquiries
+                error!("Script failed on submission {}, so sending it unchanged: {}", submission.id, e);
+                return Ok(());
+            }
+        };
+        for field in outcome.changed {
+            let value = outcome.fields.remove(&field).unwrap_or_default();
+            match field.as_str() {
+                "from_name" => submission.from_name = value,
+                "from_email" => submission.from_email = value,
+                "title" => submission.title = value,
+                "body" => submission.body = value,
+                _ => {
+                    submission.extra.insert(field, value);
+                }
+            }
+        }
+        if let Some(to) = outcome.to {
+            submission.to = Some(to);
+        }
+        match outcome.rejection {
+            Some((reason, true)) => Err(Rejection::quarantine(reason)),
+            Some((message, false)) => Err(Rejection::new(message)),
+            None => Ok(()),
+        }
+    }
+}
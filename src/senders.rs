@@ -0,0 +1,238 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+//! Lists of senders (by address or whole domain) to always turn away, or to only accept
+//! submissions from. Kept in a hand-editable JSON file, which is re-read whenever it changes, and
+//! managed through the admin API.
+
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+use std::time::SystemTime;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use crate::audit;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ListKind {
+    Allow,
+    Block,
+}
+
+/// Each entry is either an address, like `jo@example.com`, or a domain, like `example.com`, which
+/// covers its subdomains too
+#[derive(Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct SenderLists {
+    /// If there's anything here, only these senders are accepted
+    #[serde(default)]
+    pub allow: BTreeSet<String>,
+    #[serde(default)]
+    pub block: BTreeSet<String>,
+}
+
+impl SenderLists {
+    fn list(&mut self, kind: ListKind) -> &mut BTreeSet<String> {
+        match kind {
+            ListKind::Allow => &mut self.allow,
+            ListKind::Block => &mut self.block,
+        }
+    }
+}
+
+/// Why a sender was turned away
+#[derive(Debug)]
+pub enum Refusal {
+    Blocked,
+    NotAllowed,
+}
+
+/// Matches addresses case-insensitively, and domains with or without a leading `@`
+fn normalise(entry: &str) -> String {
+    entry.trim().trim_start_matches('@').to_lowercase()
+}
+
+/// The address itself, then its domain, then each parent domain
+fn candidates(address: &str) -> Vec<String> {
+    let address = normalise(address);
+    let mut candidates = vec![address.clone()];
+    if let Some((_, domain)) = address.rsplit_once('@') {
+        let mut domain = domain;
+        loop {
+            candidates.push(domain.to_string());
+            match domain.split_once('.') {
+                Some((_, parent)) if !parent.is_empty() => domain = parent,
+                _ => break,
+            }
+        }
+    }
+    candidates
+}
+
+pub struct SenderStore {
+    path: Option<PathBuf>,
+    /// The lists, and the file's version as of reading them
+    lists: Mutex<(SenderLists, Option<Version>)>,
+}
+
+/// When the file was last modified, and its size - as two quick edits can leave it with the same
+/// modification time
+type Version = (SystemTime, u64);
+
+fn modified(path: &PathBuf) -> Option<Version> {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+fn read(path: &PathBuf) -> Result<SenderLists, String> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| format!("Unable to read sender lists file {}: {}", path.display(), e))?;
+    let lists: SenderLists = serde_json::from_str(&contents)
+        .map_err(|e| format!("Unable to parse sender lists file {}: {}", path.display(), e))?;
+    Ok(SenderLists {
+        allow: lists.allow.iter().map(|entry| normalise(entry)).collect(),
+        block: lists.block.iter().map(|entry| normalise(entry)).collect(),
+    })
+}
+
+impl SenderStore {
+    pub fn open(path: Option<PathBuf>) -> Result<Self, String> {
+        let lists = match &path {
+            Some(path) if path.exists() => (read(path)?, modified(path)),
+            _ => (SenderLists::default(), None),
+        };
+        Ok(SenderStore { path, lists: Mutex::new(lists) })
+    }
+
+    /// Re-reads the file if it's been changed since it was last read. A broken file is reported,
+    /// and the lists from before it was changed kept.
+    fn reload(&self, lists: &mut (SenderLists, Option<Version>)) {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return,
+        };
+        let modified = modified(path);
+        if modified == lists.1 {
+            return;
+        }
+        lists.1 = modified;
+        match read(path) {
+            Ok(read) => {
+                info!("Reloaded sender lists from {}: {} allowed, {} blocked", path.display(), read.allow.len(), read.block.len());
+                audit::record("senders_file", "senders.reloaded", None, Some(format!("{} allowed, {} blocked", read.allow.len(), read.block.len())));
+                lists.0 = read;
+            }
+            Err(e) => {
+                error!("{} - keeping the previous sender lists", e);
+                audit::record("senders_file", "senders.reload_failed", None, Some(e));
+            }
+        }
+    }
+
+    pub fn get(&self) -> SenderLists {
+        let mut lists = self.lists.lock().unwrap();
+        self.reload(&mut lists);
+        lists.0.clone()
+    }
+
+    /// Blocking takes precedence over allowing, so a whole domain can be allowed apart from a few
+    /// addresses
+    pub fn check(&self, address: &str) -> Result<(), Refusal> {
+        let mut lists = self.lists.lock().unwrap();
+        self.reload(&mut lists);
+        let candidates = candidates(address);
+        if candidates.iter().any(|candidate| lists.0.block.contains(candidate)) {
+            return Err(Refusal::Blocked);
+        }
+        if !lists.0.allow.is_empty() && !candidates.iter().any(|candidate| lists.0.allow.contains(candidate)) {
+            return Err(Refusal::NotAllowed);
+        }
+        Ok(())
+    }
+
+    /// Returns whether the entry is new
+    pub fn add(&self, kind: ListKind, entry: &str) -> bool {
+        let mut lists = self.lists.lock().unwrap();
+        self.reload(&mut lists);
+        let added = lists.0.list(kind).insert(normalise(entry));
+        if added {
+            self.save(&mut lists);
+        }
+        added
+    }
+
+    /// Returns whether there was anything to remove
+    pub fn remove(&self, kind: ListKind, entry: &str) -> bool {
+        let mut lists = self.lists.lock().unwrap();
+        self.reload(&mut lists);
+        let removed = lists.0.list(kind).remove(&normalise(entry));
+        if removed {
+            self.save(&mut lists);
+        }
+        removed
+    }
+
+    fn save(&self, lists: &mut (SenderLists, Option<Version>)) {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return,
+        };
+        let tmp = path.with_extension("tmp");
+        let result = serde_json::to_vec_pretty(&lists.0)
+            .map_err(|e| e.to_string())
+            .and_then(|json| std::fs::write(&tmp, json).map_err(|e| e.to_string()))
+            .and_then(|_| std::fs::rename(&tmp, path).map_err(|e| e.to_string()));
+        match result {
+            // So our own change isn't mistaken for someone editing the file
+            Ok(()) => lists.1 = modified(path),
+            Err(e) => error!("Unable to save sender lists to {}: {}", path.display(), e),
+        }
+    }
+}
+
+static SENDERS: OnceLock<SenderStore> = OnceLock::new();
+
+/// Reads the sender lists from `SENDER_LISTS_FILE`, if it's set
+pub fn init() -> Result<(), String> {
+    if SENDERS.get().is_some() {
+        return Ok(());
+    }
+    let path = std::env::var("SENDER_LISTS_FILE").ok().map(PathBuf::from);
+    if let Some(path) = path.as_ref() {
+        info!("Reading sender allow and block lists from {}", path.display());
+    }
+    let _ = SENDERS.set(SenderStore::open(path)?);
+    Ok(())
+}
+
+/// Always present, but only persisted (and reloadable) if [init] has been called with a file
+/// configured
+pub fn senders() -> &'static SenderStore {
+    SENDERS.get_or_init(|| SenderStore::open(None).expect("there's no file to read"))
+}
@@ -0,0 +1,281 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+use std::error::Error;
+use std::sync::Arc;
+use axum::{BoxError, Router};
+use axum::error_handling::HandleErrorLayer;
+use axum::extract::DefaultBodyLimit;
+use axum::http::{header, HeaderName, Method, StatusCode};
+use axum::routing::{get, post};
+use lazy_static::lazy_static;
+use log::{info, warn};
+use tower::ServiceBuilder;
+use tower_http::compression::CompressionLayer;
+use tower_http::compression::predicate::{DefaultPredicate, NotForContentType, Predicate};
+use tower_http::cors::{Any, CorsLayer};
+use tower_http::decompression::RequestDecompressionLayer;
+use crate::{admin, alert, api_keys, assets, attachments, audit, broker, caps, client, client_ip, concurrency, confirm, csrf, digest, discord, encryption, extra_headers, field_mapping, geoip, handler, i18n, language, mailgun_webhook, mailing_list, maintenance, memory, openapi, outbox, pow, processor, ratelimit, redirect, referrer, response, retention, senders, retry, rotation, sheets, signing, slack, spam, stats, store, suppression, telegram, timestamps, webhook, widget};
+use crate::{env_flag, DEV_MODE, SEND_EMAIL, TO};
+use crate::mailgun::MailgunProvider;
+use crate::memory::MemoryProvider;
+use crate::processor::SubmissionProcessor;
+use crate::provider::MailProvider;
+
+lazy_static!(
+    static ref COMPRESSION: bool = env_flag("COMPRESSION", true);
+    /// Whether to check the mail provider's setup at startup
+    static ref CHECK_PROVIDER: bool = env_flag("MAILGUN_CHECK_DOMAIN", true);
+);
+
+/// What the handlers need that isn't global configuration
+#[derive(Clone)]
+pub struct AppState {
+    /// Unset only when email is turned off
+    pub provider: Option<Arc<dyn MailProvider>>,
+    pub processors: Arc<Vec<Arc<dyn SubmissionProcessor>>>,
+}
+
+/// The contact form service, ready to be served or mounted in another app
+pub struct ContactFormService {
+    state: AppState,
+    /// Set when using the memory provider, so its contents can be shown
+    mailbox: Option<Arc<MemoryProvider>>,
+}
+
+#[derive(Default)]
+pub struct ContactFormServiceBuilder {
+    provider: Option<Arc<dyn MailProvider>>,
+    processors: Vec<Arc<dyn SubmissionProcessor>>,
+}
+
+impl ContactFormServiceBuilder {
+    /// Sends email with the given provider. Defaults to Mailgun, configured by `MAILGUN_API_KEY`
+    /// and `MAILGUN_DOMAIN`.
+    pub fn provider(mut self, provider: impl MailProvider + 'static) -> Self {
+        self.provider = Some(Arc::new(provider));
+        self
+    }
+
+    /// Adds a processor to the end of the pipeline, after any built-ins listed in `PROCESSORS`
+    pub fn processor(mut self, processor: impl SubmissionProcessor + 'static) -> Self {
+        self.processors.push(Arc::new(processor));
+        self
+    }
+
+    /// Checks the configuration, connects to anything that needs connecting to, and starts any
+    /// background tasks. Must be called from within a Tokio runtime.
+    pub async fn build(self) -> Result<ContactFormService, Box<dyn Error + Send + Sync>> {
+        client::init()?;
+        // Before anything asks whether the admin API is enabled, or reads the rest of the settings
+        admin::init()?;
+        csrf::init()?;
+        pow::init()?;
+        ratelimit::init()?;
+        retry::init()?;
+        digest::init()?;
+        assets::init()?;
+        attachments::init()?;
+        let mut mailbox = None;
+        let provider = if *SEND_EMAIL {
+            let provider = match self.provider {
+                Some(provider) => provider,
+                None if std::env::var("MAIL_PROVIDER").as_deref() == Ok("memory") => {
+                    info!("Keeping emails in memory rather than sending them");
+                    let memory = Arc::new(MemoryProvider::new());
+                    mailbox = Some(memory.clone());
+                    memory
+                }
+                None => match std::env::var("MAIL_PROVIDER").as_deref() {
+                    Ok("mailgun") | Err(_) => Arc::new(MailgunProvider::from_env()?),
+                    Ok(other) => return Err(format!("\"MAIL_PROVIDER\" must be `mailgun` or `memory`, not {}", other).into()),
+                },
+            };
+            // Check env vars now so we don't get a panic later!
+            if mailbox.is_none() {
+                std::env::var("MAILGUN_TO_ADDRESS").map_err(|_| "Environment variable \"MAILGUN_TO_ADDRESS\" must be present")?;
+            }
+            // Load lazy statics right away - they're only lazy because they can't be evaluated at compile time!
+            info!("Will be sending mail to address {}", *TO);
+            if *CHECK_PROVIDER {
+                provider.check().await;
+            }
+            Some(provider)
+        } else if slack::WEBHOOK_URL.is_none() && !discord::configured() && telegram::BOT_TOKEN.is_none() {
+            return Err("\"SEND_EMAIL\" is false, but no Slack, Discord or Telegram notifications are configured, so there's nowhere to send submissions".into());
+        } else {
+            None
+        };
+        if slack::WEBHOOK_URL.is_some() {
+            info!("Will be sending Slack notifications");
+        }
+        if discord::configured() {
+            info!("Will be sending Discord notifications");
+        }
+        if telegram::BOT_TOKEN.is_some() {
+            info!("Will be sending Telegram notifications");
+        }
+        webhook::init()?;
+        encryption::init()?;
+        store::init()?;
+        audit::init()?;
+        suppression::init()?;
+        senders::init()?;
+        // After the store, so anything it says was already sent can be dropped
+        outbox::init()?;
+        retention::start()?;
+        spam::init()?;
+        i18n::init()?;
+        timestamps::init()?;
+        concurrency::init()?;
+        response::init()?;
+        redirect::init()?;
+        api_keys::init()?;
+        extra_headers::init()?;
+        field_mapping::init()?;
+        language::init()?;
+        referrer::init()?;
+        rotation::init()?;
+        signing::init()?;
+        mailing_list::init()?;
+        broker::init().await?;
+        sheets::init()?;
+        geoip::init()?;
+        client_ip::init()?;
+        alert::init()?;
+        if let Some(provider) = provider.as_ref() {
+            digest::start(provider.clone())?;
+            retry::resume(provider.clone());
+        }
+        let mut processors = processor::from_env()?;
+        processors.extend(self.processors);
+        if !processors.is_empty() {
+            info!("Processing submissions with {}", processors.iter().map(|p| p.name()).collect::<Vec<_>>().join(", "));
+        }
+        let state = AppState { provider, processors: Arc::new(processors) };
+        maintenance::init(&state)?;
+        caps::init(&state)?;
+        confirm::init(state.provider.as_ref())?;
+        Ok(ContactFormService { state, mailbox })
+    }
+}
+
+impl ContactFormService {
+    pub fn builder() -> ContactFormServiceBuilder {
+        ContactFormServiceBuilder::default()
+    }
+
+    /// Sends what's waiting in the outbox now, rather than when it's due - the next digest, and one
+    /// more try at anything being retried. Call it when shutting down; anything that still can't be
+    /// sent is kept in the `OUTBOX_FILE` (if set) for next time.
+    pub async fn drain(&self) {
+        if let Some(provider) = self.state.provider.as_ref() {
+            if let Some(from) = digest::FROM.as_deref().filter(|_| digest::enabled()) {
+                digest::send(provider.as_ref(), from).await;
+            }
+            retry::drain(provider.as_ref()).await;
+        }
+        outbox::warn_if_lost();
+    }
+
+    /// All of the service's routes, as configured. Client IP addresses are only known (for rate
+    /// limiting and GeoIP) if it's served with `into_make_service_with_connect_info::<SocketAddr>()`.
+    pub fn router(&self) -> Router {
+        let cors = CorsLayer::new()
+            // allow `GET` and `POST` when accessing the resource, and `PUT` and `DELETE` for the admin API
+            .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
+            // allow the admin token and JSON bodies to be sent by browser-based admin UIs, and API keys
+            // by forms
+            .allow_headers([header::AUTHORIZATION, header::CONTENT_TYPE, HeaderName::from_static(api_keys::HEADER)])
+            // let the widget (and other frontends) see how long until they can submit again
+            .expose_headers(ratelimit::EXPOSED_HEADERS)
+            // allow requests from any origin
+            .allow_origin(Any);
+
+        let mut send_form = post(handler::send_form);
+        if attachments::enabled() {
+            send_form = send_form.layer(DefaultBodyLimit::max(attachments::body_limit()));
+        }
+        let mut form = Router::new()
+            // Only the form takes a body big enough to be worth compressing
+            .route("/", send_form.layer(ServiceBuilder::new()
+                // The handler can't fail, so neither can decompressing its body
+                .layer(HandleErrorLayer::new(|_: BoxError| async { StatusCode::INTERNAL_SERVER_ERROR }))
+                .layer(RequestDecompressionLayer::new())));
+        if confirm::enabled() {
+            info!("Submissions to forms with double opt-in will be sent once confirmed at /confirm/{{token}}");
+            form = form.route("/confirm/:token", get(confirm::confirm));
+        }
+        let mut app: Router = form
+            .with_state(self.state.clone())
+            .route("/widget.js", get(widget::script))
+            .route("/openapi.json", get(openapi::spec));
+        if !assets::replaces_index() {
+            app = app.route("/", get(widget::index));
+        }
+        app = assets::mount(app);
+        if csrf::SECRET.is_some() {
+            info!("Submissions will require a token from /token");
+            app = app.route("/token", get(csrf::issue));
+        }
+        if let Some(difficulty) = *pow::DIFFICULTY {
+            info!("Submissions will require a solution to a {}-bit proof-of-work challenge from /challenge", difficulty);
+            app = app.route("/challenge", get(pow::issue));
+        }
+        if *openapi::SWAGGER_UI {
+            info!("Swagger UI enabled at /docs");
+            app = app.route("/docs", get(openapi::swagger_ui));
+        }
+        match (self.mailbox.as_ref(), *DEV_MODE) {
+            (Some(mailbox), true) => {
+                info!("Mailbox enabled at /_dev/mailbox");
+                app = app.route("/_dev/mailbox", get(memory::mailbox).with_state(mailbox.clone()));
+            }
+            (Some(_), false) => warn!("Using the memory provider, but \"DEV_MODE\" isn't set, so the mailbox can't be viewed"),
+            _ => {}
+        }
+        if mailgun_webhook::SIGNING_KEY.is_some() {
+            info!("Receiving Mailgun delivery events at /webhooks/mailgun");
+            app = app.route("/webhooks/mailgun", post(mailgun_webhook::receive));
+        }
+        if *stats::ENABLED {
+            info!("Stats enabled at /stats");
+            app = app.route("/stats", get(stats::get));
+        }
+        if admin::enabled() {
+            info!("Admin API enabled at /admin");
+            app = app.nest("/admin", admin::router(self.state.clone()));
+        }
+        if *COMPRESSION {
+            // Compressing the admin event stream would hold events back until a compressed block fills
+            let predicate = DefaultPredicate::new().and(NotForContentType::const_new("text/event-stream"));
+            app = app.layer(CompressionLayer::new().compress_when(predicate));
+        }
+        app.layer(cors)
+    }
+}
@@ -0,0 +1,218 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+use crate::store::Submission;
+
+/// Loads the service account credentials, if configured. Must be called before the server starts.
+pub fn init() -> Result<(), String> {
+    match std::env::var("GOOGLE_SHEETS_CREDENTIALS_FILE") {
+        Ok(path) => google::init(&path),
+        Err(_) => Ok(()),
+    }
+}
+
+/// Appends the submission to the form's spreadsheet in the background, if one is configured
+pub fn append(submission: &Submission) {
+    google::append(submission)
+}
+
+#[cfg(not(feature = "google-sheets"))]
+mod google {
+    use crate::store::Submission;
+
+    pub fn init(_path: &str) -> Result<(), String> {
+        Err("\"GOOGLE_SHEETS_CREDENTIALS_FILE\" is set, but this build doesn't include Google Sheets support - rebuild with `--features google-sheets`".to_string())
+    }
+
+    pub fn append(_submission: &Submission) {}
+}
+
+#[cfg(feature = "google-sheets")]
+mod google {
+    use std::sync::OnceLock;
+    use std::time::{Duration, Instant};
+    use chrono::Utc;
+    use jsonwebtoken::{Algorithm, EncodingKey, Header};
+    use log::{error, info};
+    use reqwest::Url;
+    use serde::{Deserialize, Serialize};
+    use tokio::sync::Mutex;
+    use crate::{form_var, CLIENT};
+    use crate::store::Submission;
+
+    const SCOPE: &str = "https://www.googleapis.com/auth/spreadsheets";
+    const GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:jwt-bearer";
+    const DEFAULT_RANGE: &str = "Sheet1";
+    /// Google won't issue tokens for any longer than this
+    const TOKEN_LIFETIME_SECS: i64 = 3600;
+    /// Renew tokens this long before they expire, so they can't expire part-way through a request
+    const TOKEN_RENEWAL_MARGIN: Duration = Duration::from_secs(60);
+
+    static SHEETS: OnceLock<GoogleSheets> = OnceLock::new();
+
+    /// The fields we need from the JSON key file Google provides for service accounts
+    #[derive(Deserialize)]
+    struct ServiceAccount {
+        client_email: String,
+        private_key: String,
+        token_uri: String,
+    }
+
+    #[derive(Serialize)]
+    struct Claims<'a> {
+        iss: &'a str,
+        scope: &'static str,
+        aud: &'a str,
+        iat: i64,
+        exp: i64,
+    }
+
+    #[derive(Deserialize)]
+    struct TokenResponse {
+        access_token: String,
+        expires_in: u64,
+    }
+
+    #[derive(Serialize)]
+    struct ValueRange {
+        values: Vec<Vec<String>>,
+    }
+
+    struct GoogleSheets {
+        account: ServiceAccount,
+        key: EncodingKey,
+        token: Mutex<Option<(String, Instant)>>,
+    }
+
+    impl GoogleSheets {
+        async fn access_token(&self) -> Result<String, String> {
+            let mut token = self.token.lock().await;
+            if let Some((token, expires)) = token.as_ref() {
+                if Instant::now() < *expires {
+                    return Ok(token.clone());
+                }
+            }
+
+            let now = Utc::now().timestamp();
+            let claims = Claims {
+                iss: &self.account.client_email,
+                scope: SCOPE,
+                aud: &self.account.token_uri,
+                iat: now,
+                exp: now + TOKEN_LIFETIME_SECS,
+            };
+            let assertion = jsonwebtoken::encode(&Header::new(Algorithm::RS256), &claims, &self.key)
+                .map_err(|e| format!("Unable to sign Google token request: {}", e))?;
+            let response = CLIENT.post(&self.account.token_uri)
+                .form(&[("grant_type", GRANT_TYPE), ("assertion", &assertion)])
+                .send()
+                .await
+                .map_err(|e| format!("Error requesting Google access token: {}", e))?;
+            if !response.status().is_success() {
+                let status = response.status();
+                let body = response.text().await.unwrap_or_default();
+                return Err(format!("Google returned {} when requesting an access token: {}", status, body));
+            }
+            let data = response.json::<TokenResponse>()
+                .await
+                .map_err(|e| format!("Unable to parse Google access token response: {}", e))?;
+
+            let expires = Instant::now() + Duration::from_secs(data.expires_in).saturating_sub(TOKEN_RENEWAL_MARGIN);
+            *token = Some((data.access_token.clone(), expires));
+            Ok(data.access_token)
+        }
+
+        async fn append(&self, spreadsheet: &str, range: &str, submission: &Submission) -> Result<(), String> {
+            let token = self.access_token().await?;
+            let mut url = Url::parse("https://sheets.googleapis.com/v4/spreadsheets").unwrap();
+            url.path_segments_mut()
+                .unwrap()
+                .push(spreadsheet)
+                .push("values")
+                .push(&format!("{}:append", range));
+            // RAW stops Sheets from evaluating anything that looks like a formula
+            url.query_pairs_mut()
+                .append_pair("valueInputOption", "RAW")
+                .append_pair("insertDataOption", "INSERT_ROWS");
+            let row = vec![
+                submission.received_at.to_rfc3339(),
+                submission.id.clone(),
+                submission.form.clone().unwrap_or_default(),
+                submission.from_name.clone(),
+                submission.from_email.clone(),
+                submission.title.clone(),
+                submission.body.clone(),
+            ];
+            let response = CLIENT.post(url)
+                .bearer_auth(token)
+                .json(&ValueRange { values: vec![row] })
+                .send()
+                .await
+                .map_err(|e| format!("Error calling Google Sheets: {}", e))?;
+
+            if response.status().is_success() {
+                Ok(())
+            } else {
+                let status = response.status();
+                let body = response.text().await.unwrap_or_default();
+                Err(format!("Google Sheets returned {}: {}", status, body))
+            }
+        }
+    }
+
+    pub fn init(path: &str) -> Result<(), String> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("Unable to read Google credentials file {}: {}", path, e))?;
+        let account: ServiceAccount = serde_json::from_str(&contents)
+            .map_err(|e| format!("Unable to parse Google credentials file {}: {}", path, e))?;
+        let key = EncodingKey::from_rsa_pem(account.private_key.as_bytes())
+            .map_err(|e| format!("Invalid private key in Google credentials file {}: {}", path, e))?;
+        info!("Will be appending submissions to Google Sheets as {}", account.client_email);
+        SHEETS.set(GoogleSheets { account, key, token: Mutex::new(None) })
+            .map_err(|_| "Google Sheets already initialised".to_string())
+    }
+
+    pub fn append(submission: &Submission) {
+        let sheets = match SHEETS.get() {
+            Some(sheets) => sheets,
+            None => return,
+        };
+        let spreadsheet = match form_var(submission.form.as_deref(), "GOOGLE_SHEETS_SPREADSHEET_ID") {
+            Some(spreadsheet) => spreadsheet,
+            None => return,
+        };
+        let range = form_var(submission.form.as_deref(), "GOOGLE_SHEETS_RANGE").unwrap_or(DEFAULT_RANGE.to_string());
+        let submission = submission.clone();
+        tokio::spawn(async move {
+            match sheets.append(&spreadsheet, &range, &submission).await {
+                Ok(()) => info!("Submission {} appended to Google Sheet", submission.id),
+                Err(e) => error!("Error appending submission {} to Google Sheet: {}", submission.id, e),
+            }
+        });
+    }
+}
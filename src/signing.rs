@@ -0,0 +1,115 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+use std::collections::{BTreeMap, HashMap};
+use hmac::{Hmac, Mac};
+use lazy_static::lazy_static;
+use log::info;
+use sha2::Sha256;
+use crate::check_json_var;
+
+lazy_static!(
+    pub static ref SECRET: Option<String> = std::env::var("SIGNING_SECRET").ok();
+    /// Addresses `_to` may be set to with a recipient token, by name
+    pub static ref RECIPIENTS: BTreeMap<String, String> = std::env::var("RECIPIENTS")
+        .ok()
+        .and_then(|recipients| serde_json::from_str(&recipients).ok())
+        .unwrap_or_default();
+);
+
+/// Recipient tokens can't be checked without the secret, so there's no point configuring them without it
+pub fn init() -> Result<(), String> {
+    check_json_var::<BTreeMap<String, String>>("RECIPIENTS", "a JSON object of names to email addresses")?;
+    if RECIPIENTS.is_empty() {
+        return Ok(());
+    }
+    if SECRET.is_none() {
+        return Err("\"RECIPIENTS\" is set, but there's no \"SIGNING_SECRET\" to sign recipient tokens with".to_string());
+    }
+    info!("Accepting recipient tokens for {} recipient(s)", RECIPIENTS.len());
+    Ok(())
+}
+
+fn mac(secret: &str, message: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(message.as_bytes());
+    mac
+}
+
+/// `<name>.<signature>`, where the signature is the hex-encoded HMAC-SHA256 of `recipient:<name>`.
+/// Only the name is in the token, so the page it's in doesn't give away the address.
+pub fn recipient_token(name: &str) -> Option<String> {
+    let secret = SECRET.as_deref()?;
+    Some(format!("{}.{}", name, hex::encode(mac(secret, &format!("recipient:{}", name)).finalize().into_bytes())))
+}
+
+/// The address a `_to` field holding a recipient token stands for, if it's a valid token for one of
+/// the `RECIPIENTS`
+pub fn resolve_recipient(token: &str) -> Option<&'static str> {
+    let secret = SECRET.as_deref()?;
+    let (name, signature) = token.trim().rsplit_once('.')?;
+    let address = RECIPIENTS.get(name)?;
+    let signature = hex::decode(signature).ok()?;
+    mac(secret, &format!("recipient:{}", name)).verify_slice(&signature).ok()?;
+    Some(address.as_str())
+}
+
+/// The hidden fields that configure how a submission is handled, in the order they're signed
+pub const SIGNED_FIELDS: &[&str] = &["_form", "_redirect", "_to"];
+
+/// What's signed: each of the signed fields as `<name>=<value>` (using an empty string for any that
+/// are missing), prefixed with its length in bytes and a `:`, so no value can be shifted into another
+fn message(fields: &HashMap<String, String>) -> String {
+    SIGNED_FIELDS.iter()
+        .map(|field| format!("{}={}", field, fields.get(*field).map(|value| value.as_str()).unwrap_or("")))
+        .map(|pair| format!("{}:{}", pair.len(), pair))
+        .collect()
+}
+
+/// Checks `_signature`, which must be the hex-encoded HMAC-SHA256 of the signed fields' [message].
+/// Returns whether the fields were signed, or an error if they should have been but weren't (or
+/// were tampered with).
+/// A `_to` holding a recipient token is already signed, so doesn't need a `_signature` of its own.
+pub fn verify(fields: &HashMap<String, String>) -> Result<bool, ()> {
+    let secret = match SECRET.as_deref() {
+        Some(secret) => secret,
+        None => return Ok(false),
+    };
+    let needs_signing = |field: &str| match field {
+        "_to" => fields.get(field).is_some_and(|to| resolve_recipient(to).is_none()),
+        _ => fields.contains_key(field),
+    };
+    let signature = match fields.get("_signature") {
+        Some(signature) => signature,
+        // Nothing to verify if there's nothing that needs signing
+        None if !SIGNED_FIELDS.iter().any(|field| needs_signing(field)) => return Ok(false),
+        None => return Err(()),
+    };
+    let signature = hex::decode(signature.trim()).map_err(|_| ())?;
+    mac(secret, &message(fields)).verify_slice(&signature).map(|_| true).map_err(|_| ())
+}
@@ -0,0 +1,78 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+use lazy_static::lazy_static;
+use log::info;
+use serde::Serialize;
+use crate::{truncate, CLIENT};
+use crate::store::Submission;
+
+lazy_static!(
+    pub static ref WEBHOOK_URL: Option<String> = std::env::var("SLACK_WEBHOOK_URL").ok();
+    static ref CHANNEL: Option<String> = std::env::var("SLACK_CHANNEL").ok();
+);
+
+const MAX_BODY_LENGTH: usize = 500;
+
+#[derive(Serialize)]
+struct SlackMessage<'a> {
+    text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    channel: Option<&'a str>,
+}
+
+/// Slack only requires these three characters to be escaped - everything else is passed through
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+pub async fn notify(submission: &Submission) -> Result<(), String> {
+    let url = WEBHOOK_URL.as_deref().ok_or("Slack webhook URL not configured")?;
+    let email = escape(&submission.from_email);
+    let message = SlackMessage {
+        text: format!(
+            "*New contact form submission*\n*From:* {} <mailto:{}|{}>\n*Subject:* {}\n>>> {}",
+            escape(&submission.from_name), email, email, escape(&submission.title), escape(&truncate(&submission.body, MAX_BODY_LENGTH)),
+        ),
+        channel: CHANNEL.as_deref(),
+    };
+    let response = CLIENT.post(url)
+        .json(&message)
+        .send()
+        .await
+        .map_err(|e| format!("Error calling Slack: {}", e))?;
+
+    if response.status().is_success() {
+        info!("Slack notification sent successfully");
+        Ok(())
+    } else {
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        Err(format!("Slack returned {}: {}", status, body))
+    }
+}
@@ -0,0 +1,207 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+use lazy_static::lazy_static;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use crate::{check_var, env_flag};
+use crate::store::Submission;
+
+lazy_static!(
+    static ref ENABLED: bool = env_flag("SPAM_CLASSIFIER", false);
+    static ref THRESHOLD: f64 = std::env::var("SPAM_THRESHOLD").ok()
+        .and_then(|threshold| threshold.trim().parse().ok())
+        .unwrap_or(DEFAULT_THRESHOLD);
+    /// Scoring with only a handful of examples would quarantine far too much, so wait for this
+    /// many of each
+    static ref MIN_TRAINING: u32 = std::env::var("SPAM_MIN_TRAINING").ok()
+        .and_then(|min| min.trim().parse().ok())
+        .unwrap_or(DEFAULT_MIN_TRAINING);
+);
+
+static CLASSIFIER: OnceLock<Classifier> = OnceLock::new();
+
+const DEFAULT_THRESHOLD: f64 = 0.9;
+const DEFAULT_MIN_TRAINING: u32 = 10;
+const MIN_TOKEN_LENGTH: usize = 2;
+const MAX_TOKEN_LENGTH: usize = 30;
+
+/// Word counts from the submissions that have been marked as spam or ham (not spam)
+#[derive(Default, Serialize, Deserialize)]
+struct Training {
+    spam_documents: u32,
+    ham_documents: u32,
+    /// How many spam / ham submissions each token appeared in
+    spam_tokens: HashMap<String, u32>,
+    ham_tokens: HashMap<String, u32>,
+    /// How each submission was marked, so marking it again (the other way) can undo the first
+    marked: HashMap<String, bool>,
+}
+
+/// A naive Bayes classifier over the words in submissions. Kept the same way as the submission
+/// store - in memory, and written out in full to a file (if configured) after every change.
+pub struct Classifier {
+    path: Option<PathBuf>,
+    training: Mutex<Training>,
+}
+
+/// The distinct words in the subject and message, along with the sender's domain - spam tends to
+/// come from the same few
+fn tokens(submission: &Submission) -> HashSet<String> {
+    let mut tokens: HashSet<String> = format!("{} {}", submission.title, submission.body)
+        .split(|c: char| !c.is_alphanumeric() && c != '\'')
+        .filter(|token| (MIN_TOKEN_LENGTH..=MAX_TOKEN_LENGTH).contains(&token.chars().count()))
+        .map(|token| token.to_lowercase())
+        .collect();
+    if let Some((_, domain)) = submission.from_email.rsplit_once('@') {
+        tokens.insert(format!("from:{}", domain.to_lowercase()));
+    }
+    tokens
+}
+
+impl Classifier {
+    pub fn open(path: Option<PathBuf>) -> Result<Self, String> {
+        let training = match &path {
+            Some(path) if path.exists() => {
+                let contents = std::fs::read_to_string(path)
+                    .map_err(|e| format!("Unable to read spam classifier file {}: {}", path.display(), e))?;
+                serde_json::from_str(&contents)
+                    .map_err(|e| format!("Unable to parse spam classifier file {}: {}", path.display(), e))?
+            }
+            _ => Training::default(),
+        };
+        Ok(Classifier { path, training: Mutex::new(training) })
+    }
+
+    /// Learns from a submission marked as spam (or not). Marking the same submission again replaces
+    /// what was learned from it before.
+    pub fn train(&self, submission: &Submission, spam: bool) {
+        let mut training = self.training.lock().unwrap();
+        let tokens = tokens(submission);
+        if let Some(previous) = training.marked.insert(submission.id.clone(), spam) {
+            if previous == spam {
+                return;
+            }
+            training.count(&tokens, previous, false);
+        }
+        training.count(&tokens, spam, true);
+        self.save(&training);
+    }
+
+    /// The probability that the submission is spam, or `None` if there hasn't been enough training
+    pub fn score(&self, submission: &Submission) -> Option<f64> {
+        let training = self.training.lock().unwrap();
+        if training.spam_documents < *MIN_TRAINING || training.ham_documents < *MIN_TRAINING {
+            return None;
+        }
+        let spam_documents = training.spam_documents as f64;
+        let ham_documents = training.ham_documents as f64;
+        // Log odds avoid underflow with long messages. Laplace smoothing stops unseen words
+        // deciding everything.
+        let mut log_odds = (spam_documents / ham_documents).ln();
+        for token in tokens(submission) {
+            let in_spam = training.spam_tokens.get(&token).copied().unwrap_or(0) as f64;
+            let in_ham = training.ham_tokens.get(&token).copied().unwrap_or(0) as f64;
+            if in_spam == 0.0 && in_ham == 0.0 {
+                continue;
+            }
+            log_odds += ((in_spam + 1.0) / (spam_documents + 2.0)).ln() - ((in_ham + 1.0) / (ham_documents + 2.0)).ln();
+        }
+        Some(1.0 / (1.0 + (-log_odds).exp()))
+    }
+
+    fn save(&self, training: &Training) {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return,
+        };
+        let tmp = path.with_extension("tmp");
+        let result = serde_json::to_vec(training)
+            .map_err(|e| e.to_string())
+            .and_then(|json| std::fs::write(&tmp, json).map_err(|e| e.to_string()))
+            .and_then(|_| std::fs::rename(&tmp, path).map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            error!("Unable to save the spam classifier to {}: {}", path.display(), e);
+        }
+    }
+}
+
+impl Training {
+    /// Adds (or, if `add` is false, removes) one document's tokens
+    fn count(&mut self, tokens: &HashSet<String>, spam: bool, add: bool) {
+        let (documents, counts) = match spam {
+            true => (&mut self.spam_documents, &mut self.spam_tokens),
+            false => (&mut self.ham_documents, &mut self.ham_tokens),
+        };
+        *documents = if add { *documents + 1 } else { documents.saturating_sub(1) };
+        for token in tokens {
+            let count = counts.entry(token.clone()).or_insert(0);
+            *count = if add { *count + 1 } else { count.saturating_sub(1) };
+            if *count == 0 {
+                counts.remove(token);
+            }
+        }
+    }
+}
+
+/// Whether a submission with this score should be quarantined rather than sent
+pub fn is_spam(score: f64) -> bool {
+    score >= *THRESHOLD
+}
+
+/// The classifier can only be trained through the admin API, so there's no point without it. Its
+/// training is read now, so a file that can't be read stops the service starting.
+pub fn init() -> Result<(), String> {
+    if check_var::<f64>("SPAM_THRESHOLD", "a number between 0 and 1")?.is_some_and(|threshold| !(0.0..=1.0).contains(&threshold)) {
+        return Err("\"SPAM_THRESHOLD\" must be between 0 and 1".to_string());
+    }
+    check_var::<u32>("SPAM_MIN_TRAINING", "a whole number")?;
+    if !*ENABLED {
+        return Ok(());
+    }
+    if !crate::admin::enabled() {
+        return Err("\"SPAM_CLASSIFIER\" is set, but it can only be trained through the admin API, which isn't enabled".to_string());
+    }
+    if CLASSIFIER.get().is_none() {
+        let path = std::env::var("SPAM_CLASSIFIER_FILE").ok().map(PathBuf::from);
+        if let Some(path) = path.as_ref() {
+            info!("Persisting the spam classifier's training to {}", path.display());
+        }
+        let _ = CLASSIFIER.set(Classifier::open(path)?);
+    }
+    info!("Quarantining submissions the spam classifier scores at {} or more", *THRESHOLD);
+    Ok(())
+}
+
+/// The classifier, if it's enabled and [init] has opened it
+pub fn classifier() -> Option<&'static Classifier> {
+    CLASSIFIER.get()
+}
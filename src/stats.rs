@@ -0,0 +1,149 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+//! Counters since startup, for dashboards that don't scrape anything more elaborate
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use axum::Json;
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use serde::Serialize;
+use utoipa::ToSchema;
+use crate::{env_flag, ResponseData, ResponseStatus};
+use crate::admin::tokens_match;
+use crate::store::DeliveryStatus;
+
+lazy_static!(
+    /// Required as a bearer token to see the stats, if set
+    static ref TOKEN: Option<String> = std::env::var("STATS_TOKEN").ok();
+    pub static ref ENABLED: bool = env_flag("STATS", false) || TOKEN.is_some();
+    static ref STATS: Mutex<Stats> = Mutex::new(Stats { started_at: Utc::now(), totals: Counters::default(), per_form: BTreeMap::new() });
+);
+
+#[derive(Clone, Copy)]
+pub enum Event {
+    /// Passed every check, and is on its way to being sent (or quarantined)
+    Received,
+    /// Turned away for any reason other than rate limiting
+    Rejected,
+    RateLimited,
+    Quarantined,
+    Sent,
+    Failed,
+    Delivered,
+    Bounced,
+    Complained,
+}
+
+#[derive(Clone, Default, Serialize, ToSchema)]
+pub struct Counters {
+    received: u64,
+    rejected: u64,
+    rate_limited: u64,
+    quarantined: u64,
+    sent: u64,
+    failed: u64,
+    delivered: u64,
+    bounced: u64,
+    complained: u64,
+}
+
+impl Counters {
+    fn count(&mut self, event: Event) {
+        let counter = match event {
+            Event::Received => &mut self.received,
+            Event::Rejected => &mut self.rejected,
+            Event::RateLimited => &mut self.rate_limited,
+            Event::Quarantined => &mut self.quarantined,
+            Event::Sent => &mut self.sent,
+            Event::Failed => &mut self.failed,
+            Event::Delivered => &mut self.delivered,
+            Event::Bounced => &mut self.bounced,
+            Event::Complained => &mut self.complained,
+        };
+        *counter += 1;
+    }
+}
+
+#[derive(Clone, Serialize, ToSchema)]
+pub struct Stats {
+    started_at: DateTime<Utc>,
+    totals: Counters,
+    /// Only submissions with a `_form` field
+    per_form: BTreeMap<String, Counters>,
+}
+
+pub fn record(form: Option<&str>, event: Event) {
+    if !*ENABLED {
+        return;
+    }
+    let mut stats = STATS.lock().unwrap();
+    stats.totals.count(event);
+    if let Some(form) = form {
+        stats.per_form.entry(form.to_string()).or_default().count(event);
+    }
+}
+
+/// Records a submission's (final, or at least interesting) delivery status
+pub fn record_status(form: Option<&str>, status: DeliveryStatus) {
+    let event = match status {
+        DeliveryStatus::Sent => Event::Sent,
+        DeliveryStatus::Failed => Event::Failed,
+        DeliveryStatus::Quarantined => Event::Quarantined,
+        DeliveryStatus::Delivered => Event::Delivered,
+        DeliveryStatus::Bounced => Event::Bounced,
+        DeliveryStatus::Complained => Event::Complained,
+        // Still on their way
+        DeliveryStatus::Pending | DeliveryStatus::RateLimited | DeliveryStatus::Unconfirmed => return,
+    };
+    record(form, event);
+}
+
+#[utoipa::path(
+    get,
+    path = "/stats",
+    tag = "stats",
+    responses(
+        (status = 200, description = "Counts since startup", body = Stats),
+        (status = 401, description = "`STATS_TOKEN` is set, and wasn't given as a bearer token", body = ResponseData),
+    ),
+)]
+pub async fn get(headers: HeaderMap) -> Response {
+    if let Some(token) = TOKEN.as_deref() {
+        let provided = headers.get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        if !provided.is_some_and(|provided| tokens_match(token, provided)) {
+            return (StatusCode::UNAUTHORIZED, Json(ResponseData { status: ResponseStatus::Unauthorized, message: Some("missing or invalid stats token".to_string()), errors: None, retry_after: None })).into_response();
+        }
+    }
+    Json(STATS.lock().unwrap().clone()).into_response()
+}
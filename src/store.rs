@@ -0,0 +1,403 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+use chrono::{DateTime, NaiveDate, Utc};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use crate::{attachments, audit, encryption, events, form_var, language, FormData};
+use crate::attachments::Attachment;
+use crate::events::SubmissionEvent;
+use crate::metadata::RequestMetadata;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryStatus {
+    Pending,
+    /// Accepted by the mail provider
+    Sent,
+    Failed,
+    /// Waiting to be retried, as the mail provider is rate limiting us
+    RateLimited,
+    /// Not sent, as the spam classifier scored it too highly
+    Quarantined,
+    /// Reported delivered by Mailgun
+    Delivered,
+    /// Reported as permanently undeliverable by Mailgun
+    Bounced,
+    /// The recipient marked it as spam
+    Complained,
+    /// Waiting for the submitter to follow the link in the confirmation email
+    Unconfirmed,
+}
+
+/// A record of the submitter agreeing to their message being stored and processed
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct Consent {
+    pub given_at: DateTime<Utc>,
+    /// `CONSENT_TEXT_VERSION` at the time, identifying exactly what they agreed to
+    pub text_version: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct Submission {
+    pub id: String,
+    pub received_at: DateTime<Utc>,
+    pub from_name: String,
+    pub from_email: String,
+    pub title: String,
+    pub body: String,
+    #[serde(default)]
+    pub form: Option<String>,
+    /// The recipient, if it was overridden by a signed `_to` field or `LANGUAGE_ROUTES`
+    #[serde(default)]
+    pub to: Option<String>,
+    /// Fields other than the usual four, like `phone` or `company` (see `EXTRA_FIELDS`)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub extra: BTreeMap<String, String>,
+    /// Files uploaded with it, which are kept in the `ATTACHMENTS_S3_BUCKET`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<Attachment>,
+    #[serde(default)]
+    pub consent: Option<Consent>,
+    /// Whatever `EMAIL_METADATA` asks for about the request it came in
+    #[serde(default)]
+    pub metadata: RequestMetadata,
+    /// The language the title and body are written in (as a two-letter code), if it's clear
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    /// The probability the spam classifier gave of it being spam, if it's enabled and trained
+    #[serde(default)]
+    pub spam_score: Option<f64>,
+    pub status: DeliveryStatus,
+    pub status_message: Option<String>,
+}
+
+impl Submission {
+    pub(crate) fn new(req: &FormData) -> Self {
+        let received_at = Utc::now();
+        let consent = req.consent.then(|| Consent {
+            given_at: received_at,
+            text_version: form_var(req.form.as_deref(), "CONSENT_TEXT_VERSION"),
+        });
+        Submission {
+            id: uuid::Uuid::new_v4().to_string(),
+            received_at,
+            from_name: req.from_name.clone(),
+            from_email: req.from_email.clone(),
+            title: req.title.clone(),
+            body: req.body.clone(),
+            form: req.form.clone(),
+            to: req.to.clone(),
+            extra: req.extra.clone(),
+            attachments: Vec::new(),
+            consent,
+            metadata: RequestMetadata::default(),
+            language: language::detect(&format!("{}\n{}", req.title, req.body)).map(|lang| lang.to_string()),
+            spam_score: None,
+            status: DeliveryStatus::Pending,
+            status_message: None,
+        }
+    }
+
+    /// The body, followed by any extra fields as `Label: value` lines and links to any attachments,
+    /// for plain-text messages
+    pub fn text(&self) -> String {
+        let mut text = self.body.clone();
+        if !self.extra.is_empty() {
+            text.push_str("\n\n");
+            for (name, value) in self.extra.iter() {
+                text.push_str(&format!("{}: {}\n", label(name), value));
+            }
+        }
+        if !self.attachments.is_empty() {
+            text.push_str("\n\n");
+            text.push_str(&attachments::links(&self.attachments));
+        }
+        text
+    }
+
+    fn is_anonymized(&self) -> bool {
+        self.from_email.is_empty()
+    }
+
+    /// Keeps only what's needed for stats - when it was received, which form, and what happened
+    fn anonymize(&mut self) {
+        self.from_name.clear();
+        self.from_email.clear();
+        self.title.clear();
+        self.body.clear();
+        self.to = None;
+        self.extra.clear();
+        attachments::discard(std::mem::take(&mut self.attachments));
+        self.metadata = RequestMetadata::default();
+        self.status_message = None;
+    }
+
+    fn matches(&self, query: &SearchQuery) -> bool {
+        if query.form.is_some() && self.form != query.form {
+            return false;
+        }
+        if let Some(status) = query.status {
+            if self.status != status {
+                return false;
+            }
+        }
+        if let Some(since) = query.since {
+            if self.received_at.date_naive() < since {
+                return false;
+            }
+        }
+        if let Some(until) = query.until {
+            if self.received_at.date_naive() > until {
+                return false;
+            }
+        }
+        match &query.q {
+            Some(q) => {
+                let q = q.to_lowercase();
+                [&self.from_name, &self.from_email, &self.title, &self.body]
+                    .into_iter()
+                    .chain(self.extra.values())
+                    .any(|field| field.to_lowercase().contains(&q))
+            }
+            None => true,
+        }
+    }
+}
+
+/// Turns a field name like `budget_range` into `Budget range`
+fn label(name: &str) -> String {
+    let name = name.replace(['_', '-'], " ");
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => name,
+    }
+}
+
+#[derive(Default, Deserialize, IntoParams)]
+pub struct SearchQuery {
+    /// Case-insensitive substring match against the name, email, title, body and any extra fields
+    pub q: Option<String>,
+    pub status: Option<DeliveryStatus>,
+    pub form: Option<String>,
+    /// Inclusive lower bound on the (UTC) date the submission was received
+    pub since: Option<NaiveDate>,
+    /// Inclusive upper bound on the (UTC) date the submission was received
+    pub until: Option<NaiveDate>,
+    pub offset: Option<usize>,
+    pub limit: Option<usize>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct DailyCount {
+    pub date: NaiveDate,
+    pub total: usize,
+    pub by_status: BTreeMap<DeliveryStatus, usize>,
+}
+
+const DEFAULT_SEARCH_LIMIT: usize = 50;
+
+/// Keeps every submission in memory, and - if a file has been configured - writes the full set out
+/// to that file as JSON after every change. Contact forms don't see enough traffic for that to
+/// matter, and it means the file can be inspected (or fixed) with nothing more than a text editor.
+pub struct SubmissionStore {
+    path: Option<PathBuf>,
+    submissions: Mutex<Vec<Submission>>,
+}
+
+impl SubmissionStore {
+    pub fn open(path: Option<PathBuf>) -> Result<Self, String> {
+        let submissions = match &path {
+            Some(path) if path.exists() => {
+                let contents = std::fs::read(path)
+                    .map_err(|e| format!("Unable to read submissions file {}: {}", path.display(), e))?;
+                let contents = encryption::open(contents)
+                    .map_err(|e| format!("Unable to decrypt submissions file {}, as {}", path.display(), e))?;
+                serde_json::from_slice(&contents)
+                    .map_err(|e| format!("Unable to parse submissions file {}: {}", path.display(), e))?
+            }
+            _ => Vec::new(),
+        };
+        Ok(SubmissionStore { path, submissions: Mutex::new(submissions) })
+    }
+
+    pub fn insert(&self, submission: Submission) {
+        // Nothing about the submitter, as the audit log outlives erasure and retention
+        audit::record("submitter", "submission.received", Some(&submission.id), submission.form.clone());
+        events::publish(SubmissionEvent::Received { submission: Box::new(submission.clone()) });
+        let mut submissions = self.submissions.lock().unwrap();
+        submissions.push(submission);
+        self.save(&submissions);
+    }
+
+    /// `actor` is the part of the service making the change, for the audit log
+    pub fn update_status(&self, id: &str, status: DeliveryStatus, message: Option<String>, actor: &str) {
+        let mut submissions = self.submissions.lock().unwrap();
+        if let Some(submission) = submissions.iter_mut().find(|s| s.id == id) {
+            let detail = match message.as_deref() {
+                Some(message) => format!("{:?}: {}", status, message),
+                None => format!("{:?}", status),
+            };
+            audit::record(actor, "submission.status_changed", Some(id), Some(detail));
+            events::publish(SubmissionEvent::StatusChanged { id: id.to_string(), form: submission.form.clone(), status, message: message.clone() });
+            submission.status = status;
+            submission.status_message = message;
+            self.save(&submissions);
+        }
+    }
+
+    /// Deletes, or strips the submitter's details from, everything received before `cutoff`.
+    /// Returns how many submissions were changed.
+    pub fn purge_before(&self, cutoff: DateTime<Utc>, anonymize: bool) -> usize {
+        let mut submissions = self.submissions.lock().unwrap();
+        let purged = if anonymize {
+            let mut anonymized = 0;
+            for submission in submissions.iter_mut().filter(|s| s.received_at < cutoff && !s.is_anonymized()) {
+                submission.anonymize();
+                anonymized += 1;
+            }
+            anonymized
+        } else {
+            delete_where(&mut submissions, |s| s.received_at < cutoff)
+        };
+        if purged > 0 {
+            self.save(&submissions);
+        }
+        purged
+    }
+
+    /// Deletes every submission from the given address, returning how many there were
+    pub fn erase(&self, email: &str) -> usize {
+        let mut submissions = self.submissions.lock().unwrap();
+        let erased = delete_where(&mut submissions, |s| s.from_email.eq_ignore_ascii_case(email.trim()));
+        if erased > 0 {
+            self.save(&submissions);
+        }
+        erased
+    }
+
+    /// Deletes the submissions with the given status - all of them, or just the one with the given
+    /// ID - returning how many there were
+    pub fn remove(&self, status: DeliveryStatus, id: Option<&str>) -> usize {
+        let mut submissions = self.submissions.lock().unwrap();
+        let removed = delete_where(&mut submissions, |s| s.status == status && id.is_none_or(|id| s.id == id));
+        if removed > 0 {
+            self.save(&submissions);
+        }
+        removed
+    }
+
+    pub fn get(&self, id: &str) -> Option<Submission> {
+        self.submissions.lock().unwrap().iter().find(|s| s.id == id).cloned()
+    }
+
+    /// Returns the total number of matches, along with the requested page of them, newest first
+    pub fn search(&self, query: &SearchQuery) -> (usize, Vec<Submission>) {
+        let submissions = self.submissions.lock().unwrap();
+        let matching: Vec<&Submission> = submissions.iter().rev().filter(|s| s.matches(query)).collect();
+        let page = matching.iter()
+            .skip(query.offset.unwrap_or(0))
+            .take(query.limit.unwrap_or(DEFAULT_SEARCH_LIMIT))
+            .map(|s| (*s).clone())
+            .collect();
+        (matching.len(), page)
+    }
+
+    pub fn daily_counts(&self) -> Vec<DailyCount> {
+        let submissions = self.submissions.lock().unwrap();
+        let mut days: BTreeMap<NaiveDate, DailyCount> = BTreeMap::new();
+        for submission in submissions.iter() {
+            let date = submission.received_at.date_naive();
+            let count = days.entry(date).or_insert_with(|| DailyCount { date, total: 0, by_status: BTreeMap::new() });
+            count.total += 1;
+            *count.by_status.entry(submission.status).or_insert(0) += 1;
+        }
+        days.into_values().collect()
+    }
+
+    fn save(&self, submissions: &[Submission]) {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return,
+        };
+        // Write to a temporary file first so a crash part-way through can't truncate the real one
+        let tmp = path.with_extension("tmp");
+        let result = serde_json::to_vec(submissions)
+            .map_err(|e| e.to_string())
+            .and_then(encryption::seal)
+            .and_then(|contents| std::fs::write(&tmp, contents).map_err(|e| e.to_string()))
+            .and_then(|_| std::fs::rename(&tmp, path).map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            error!("Unable to save submissions to {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// Deletes the matching submissions along with their attachments, returning how many there were
+fn delete_where(submissions: &mut Vec<Submission>, matches: impl Fn(&Submission) -> bool) -> usize {
+    let (deleted, kept): (Vec<_>, Vec<_>) = std::mem::take(submissions).into_iter().partition(|s| matches(s));
+    *submissions = kept;
+    let count = deleted.len();
+    attachments::discard(deleted.into_iter().flat_map(|s| s.attachments).collect());
+    count
+}
+
+/// Only set if either a submissions file or an admin token has been configured - there's no point
+/// holding on to submissions if nothing is ever going to read them.
+static STORE: OnceLock<SubmissionStore> = OnceLock::new();
+
+/// Opens the submissions file now, so one that can't be read (or decrypted) stops the service
+/// starting, rather than the first submission
+pub fn init() -> Result<(), String> {
+    if STORE.get().is_some() {
+        return Ok(());
+    }
+    let store = match std::env::var("SUBMISSIONS_FILE") {
+        Ok(path) => {
+            info!("Persisting submissions to {}", path);
+            SubmissionStore::open(Some(PathBuf::from(path)))?
+        }
+        Err(_) if crate::admin::enabled() => {
+            warn!("No SUBMISSIONS_FILE set - submissions will only be kept in memory, and will be lost on restart");
+            SubmissionStore::open(None)?
+        }
+        Err(_) => return Ok(()),
+    };
+    let _ = STORE.set(store);
+    Ok(())
+}
+
+/// The store, if [init] has opened one
+pub fn get() -> Option<&'static SubmissionStore> {
+    STORE.get()
+}
@@ -0,0 +1,152 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+use chrono::{DateTime, Utc};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SuppressionReason {
+    Bounced,
+    Complained,
+    /// Added through the admin API
+    Manual,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct Suppression {
+    pub address: String,
+    pub reason: SuppressionReason,
+    pub added_at: DateTime<Utc>,
+    #[serde(default)]
+    pub note: Option<String>,
+}
+
+/// Addresses that mail isn't sent to, because they bounced or complained. Sending to them anyway
+/// would only hurt the sending domain's reputation. Kept the same way as the submission store.
+pub struct SuppressionList {
+    path: Option<PathBuf>,
+    entries: Mutex<BTreeMap<String, Suppression>>,
+}
+
+/// Compares just the address, case-insensitively, so `Jo <JO@example.com>` matches `jo@example.com`
+fn normalise(address: &str) -> String {
+    let address = match (address.rfind('<'), address.rfind('>')) {
+        (Some(start), Some(end)) if start < end => &address[start + 1..end],
+        _ => address,
+    };
+    address.trim().to_lowercase()
+}
+
+impl SuppressionList {
+    pub fn open(path: Option<PathBuf>) -> Result<Self, String> {
+        let entries: Vec<Suppression> = match &path {
+            Some(path) if path.exists() => {
+                let contents = std::fs::read_to_string(path)
+                    .map_err(|e| format!("Unable to read suppressions file {}: {}", path.display(), e))?;
+                serde_json::from_str(&contents)
+                    .map_err(|e| format!("Unable to parse suppressions file {}: {}", path.display(), e))?
+            }
+            _ => Vec::new(),
+        };
+        let entries = entries.into_iter().map(|entry| (normalise(&entry.address), entry)).collect();
+        Ok(SuppressionList { path, entries: Mutex::new(entries) })
+    }
+
+    /// Adds (or replaces) the entry for the address
+    pub fn add(&self, address: &str, reason: SuppressionReason, note: Option<String>) -> Suppression {
+        let address = normalise(address);
+        let entry = Suppression { address: address.clone(), reason, added_at: Utc::now(), note };
+        let mut entries = self.entries.lock().unwrap();
+        entries.insert(address, entry.clone());
+        self.save(&entries);
+        entry
+    }
+
+    /// Returns whether there was anything to remove
+    pub fn remove(&self, address: &str) -> bool {
+        let mut entries = self.entries.lock().unwrap();
+        let removed = entries.remove(&normalise(address)).is_some();
+        if removed {
+            self.save(&entries);
+        }
+        removed
+    }
+
+    pub fn get(&self, address: &str) -> Option<Suppression> {
+        self.entries.lock().unwrap().get(&normalise(address)).cloned()
+    }
+
+    pub fn list(&self) -> Vec<Suppression> {
+        self.entries.lock().unwrap().values().cloned().collect()
+    }
+
+    fn save(&self, entries: &BTreeMap<String, Suppression>) {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return,
+        };
+        let tmp = path.with_extension("tmp");
+        let result = serde_json::to_vec(&entries.values().collect::<Vec<_>>())
+            .map_err(|e| e.to_string())
+            .and_then(|json| std::fs::write(&tmp, json).map_err(|e| e.to_string()))
+            .and_then(|_| std::fs::rename(&tmp, path).map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            error!("Unable to save suppressions to {}: {}", path.display(), e);
+        }
+    }
+}
+
+static SUPPRESSIONS: OnceLock<SuppressionList> = OnceLock::new();
+
+/// Loads suppressed addresses from `SUPPRESSIONS_FILE`, if it's set
+pub fn init() -> Result<(), String> {
+    if SUPPRESSIONS.get().is_some() {
+        return Ok(());
+    }
+    let path = std::env::var("SUPPRESSIONS_FILE").ok().map(PathBuf::from);
+    if let Some(path) = path.as_ref() {
+        info!("Persisting suppressed addresses to {}", path.display());
+    }
+    let _ = SUPPRESSIONS.set(SuppressionList::open(path)?);
+    Ok(())
+}
+
+/// Always present, but only kept in memory if [init] hasn't been called
+pub fn suppressions() -> &'static SuppressionList {
+    SUPPRESSIONS.get_or_init(|| SuppressionList::open(None).expect("there's no file to read"))
+}
+
+pub fn is_suppressed(address: &str) -> bool {
+    suppressions().get(address).is_some()
+}
@@ -0,0 +1,103 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+use std::time::Duration;
+use lazy_static::lazy_static;
+use log::info;
+use serde::{Deserialize, Serialize};
+use crate::{form_var, truncate, CLIENT};
+use crate::store::Submission;
+
+lazy_static!(
+    pub static ref BOT_TOKEN: Option<String> = std::env::var("TELEGRAM_BOT_TOKEN").ok();
+);
+
+/// Telegram allows 4096 characters per message - this leaves room for the other fields, and for
+/// the escaping, which can make the body longer than it started out
+const MAX_BODY_LENGTH: usize = 3000;
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Serialize)]
+struct SendMessage<'a> {
+    chat_id: &'a str,
+    text: String,
+    parse_mode: &'static str,
+}
+
+#[derive(Deserialize)]
+struct TelegramResponse {
+    ok: bool,
+    description: Option<String>,
+}
+
+/// Escapes everything MarkdownV2 treats as special, per https://core.telegram.org/bots/api#markdownv2-style
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "_*[]()~`>#+-=|{}.!\\".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// The chat to send the given form's submissions to, falling back to the global `TELEGRAM_CHAT_ID`
+pub fn chat_id(form: Option<&str>) -> Option<String> {
+    BOT_TOKEN.as_ref().and(form_var(form, "TELEGRAM_CHAT_ID"))
+}
+
+pub async fn notify(chat_id: &str, submission: &Submission) -> Result<(), String> {
+    let token = BOT_TOKEN.as_deref().ok_or("Telegram bot token not configured")?;
+    let message = SendMessage {
+        chat_id,
+        text: format!(
+            "*New contact form submission*\n*From:* {} \\<{}\\>\n*Subject:* {}\n\n{}",
+            escape(&submission.from_name), escape(&submission.from_email), escape(&submission.title), escape(&truncate(&submission.body, MAX_BODY_LENGTH)),
+        ),
+        parse_mode: "MarkdownV2",
+    };
+    // Don't include the URL in errors, as it contains the bot token
+    let response = CLIENT.post(format!("https://api.telegram.org/bot{}/sendMessage", token))
+        .timeout(TIMEOUT)
+        .json(&message)
+        .send()
+        .await
+        .map_err(|e| format!("Error calling Telegram: {}", e.without_url()))?;
+
+    let status = response.status();
+    let data = response.json::<TelegramResponse>()
+        .await
+        .map_err(|e| format!("Unable to parse Telegram response ({}): {}", status, e.without_url()))?;
+    if data.ok {
+        info!("Telegram notification sent successfully");
+        Ok(())
+    } else {
+        Err(format!("Telegram returned {}: {}", status, data.description.unwrap_or_default()))
+    }
+}
@@ -0,0 +1,65 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+//! `Message-Id`s derived from submission IDs, and `In-Reply-To`/`References` pointing at a thread
+//! per submitter, so every submission from the same person shows up as one conversation
+
+use std::collections::BTreeMap;
+use lazy_static::lazy_static;
+use sha2::{Digest, Sha256};
+use crate::TO;
+
+lazy_static!(
+    /// The part after the `@` in generated IDs, which should be a domain we control so they don't
+    /// clash with anyone else's
+    static ref DOMAIN: String = std::env::var("MESSAGE_ID_DOMAIN")
+        .or_else(|_| std::env::var("MAILGUN_DOMAIN"))
+        .unwrap_or_else(|_| TO.rsplit_once('@').map(|(_, domain)| domain.trim_end_matches('>').to_string()).unwrap_or("localhost".to_string()));
+);
+
+/// The `Message-Id` of the email for a submission
+fn message_id(submission_id: &str) -> String {
+    format!("<submission-{}@{}>", submission_id, *DOMAIN)
+}
+
+/// The ID of a message that's never actually sent, that every email about a submitter's
+/// submissions replies to. Hashed so their email address doesn't appear in the headers.
+fn thread_id(from_email: &str) -> String {
+    let hash = Sha256::digest(from_email.trim().to_lowercase().as_bytes());
+    format!("<thread-{}@{}>", hex::encode(&hash[..16]), *DOMAIN)
+}
+
+/// The headers to thread the email for a submission
+pub fn headers(submission_id: &str, from_email: &str) -> BTreeMap<String, String> {
+    let thread = thread_id(from_email);
+    BTreeMap::from([
+        ("Message-Id".to_string(), message_id(submission_id)),
+        ("In-Reply-To".to_string(), thread.clone()),
+        ("References".to_string(), thread),
+    ])
+}
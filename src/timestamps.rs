@@ -0,0 +1,96 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+//! Showing timestamps to people in their own timezone, rather than UTC
+
+use chrono::{DateTime, FixedOffset, Local, NaiveDateTime, Utc};
+use chrono::format::{Item, StrftimeItems};
+use lazy_static::lazy_static;
+use log::info;
+
+/// Like RFC 2822, which is how email clients show dates
+const DEFAULT_FORMAT: &str = "%a, %-d %b %Y %H:%M:%S %z";
+
+enum Zone {
+    Utc,
+    /// The host's timezone, which follows the standard `TZ` variable (daylight saving included)
+    Local,
+    Fixed(FixedOffset),
+}
+
+lazy_static!(
+    static ref ZONE: Result<Zone, String> = match std::env::var("TIMEZONE").as_deref() {
+        Err(_) | Ok("UTC") | Ok("utc") => Ok(Zone::Utc),
+        Ok("local") => Ok(Zone::Local),
+        Ok(offset) => offset.parse().map(Zone::Fixed)
+            .map_err(|_| format!("\"TIMEZONE\" must be `UTC`, `local` or an offset like `+10:00`, not {}", offset)),
+    };
+    static ref FORMAT: String = std::env::var("TIMESTAMP_FORMAT").unwrap_or(DEFAULT_FORMAT.to_string());
+);
+
+/// Checks the timezone and format now, rather than on the first submission
+pub fn init() -> Result<(), String> {
+    let zone = ZONE.as_ref()?;
+    if StrftimeItems::new(&FORMAT).any(|item| matches!(item, Item::Error)) {
+        return Err(format!("\"TIMESTAMP_FORMAT\" isn't a valid strftime format: {}", *FORMAT));
+    }
+    match zone {
+        Zone::Utc => {}
+        Zone::Local => info!("Showing timestamps in the local timezone, currently {}", Local::now().offset()),
+        Zone::Fixed(offset) => info!("Showing timestamps at UTC{}", offset),
+    }
+    Ok(())
+}
+
+/// For people to read, in `TIMESTAMP_FORMAT`
+pub fn format(timestamp: DateTime<Utc>) -> String {
+    match ZONE.as_ref() {
+        Ok(Zone::Local) => timestamp.with_timezone(&Local).format(&FORMAT).to_string(),
+        Ok(Zone::Fixed(offset)) => timestamp.with_timezone(offset).format(&FORMAT).to_string(),
+        // Only an error if `init` wasn't called
+        Ok(Zone::Utc) | Err(_) => timestamp.format(&FORMAT).to_string(),
+    }
+}
+
+/// For machines to read (e.g. in headers), but still in the configured timezone
+pub fn rfc3339(timestamp: DateTime<Utc>) -> String {
+    match ZONE.as_ref() {
+        Ok(Zone::Local) => timestamp.with_timezone(&Local).to_rfc3339(),
+        Ok(Zone::Fixed(offset)) => timestamp.with_timezone(offset).to_rfc3339(),
+        Ok(Zone::Utc) | Err(_) => timestamp.to_rfc3339(),
+    }
+}
+
+/// The wall-clock time in the configured timezone, for things like on-call schedules
+pub fn local(timestamp: DateTime<Utc>) -> NaiveDateTime {
+    match ZONE.as_ref() {
+        Ok(Zone::Local) => timestamp.with_timezone(&Local).naive_local(),
+        Ok(Zone::Fixed(offset)) => timestamp.with_timezone(offset).naive_local(),
+        Ok(Zone::Utc) | Err(_) => timestamp.naive_utc(),
+    }
+}
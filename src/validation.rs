@@ -0,0 +1,155 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+use std::collections::{BTreeMap, HashMap};
+use serde::Serialize;
+use utoipa::ToSchema;
+use crate::{form_flag, form_var, referrer, FormData};
+
+const REQUIRED_FIELDS: &[&str] = &["from_name", "from_email", "title", "body"];
+/// Fields with a meaning of their own, which are never treated as extra fields. Neither are any
+/// starting with `_`, which are hidden configuration fields.
+const KNOWN_FIELDS: &[&str] = &["from_name", "from_email", "title", "body", "consent", "lang", "page_url"];
+
+#[derive(Clone, Copy, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldProblem {
+    Missing,
+    InvalidEmail,
+    /// `REQUIRE_CONSENT` is set, but the `consent` box wasn't ticked
+    ConsentRequired,
+    /// `page_url` isn't an `http` or `https` URL, or isn't from one of `PAGE_URL_ORIGINS`
+    InvalidPageUrl,
+    /// Files were attached, but `ATTACHMENTS_S3_BUCKET` isn't set
+    AttachmentsNotAccepted,
+    /// More files than `ATTACHMENTS_MAX_FILES` were attached
+    TooManyAttachments,
+    /// The files add up to more than `ATTACHMENTS_MAX_BYTES`
+    AttachmentsTooLarge,
+}
+
+impl FieldProblem {
+    fn message_key(self) -> &'static str {
+        match self {
+            FieldProblem::Missing => "field_missing",
+            FieldProblem::InvalidEmail => "field_invalid_email",
+            FieldProblem::ConsentRequired => "field_consent_required",
+            FieldProblem::InvalidPageUrl => "field_invalid_page_url",
+            FieldProblem::AttachmentsNotAccepted => "field_attachments_not_accepted",
+            FieldProblem::TooManyAttachments => "field_too_many_attachments",
+            FieldProblem::AttachmentsTooLarge => "field_attachments_too_large",
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct FieldError {
+    pub field: String,
+    pub problem: FieldProblem,
+    /// Starts out as a message key, and is translated along with the rest of the response
+    pub message: String,
+}
+
+impl FieldError {
+    pub(crate) fn new(field: &str, problem: FieldProblem) -> Self {
+        FieldError { field: field.to_string(), problem, message: problem.message_key().to_string() }
+    }
+}
+
+/// Deliberately lenient - this is only meant to catch obvious typos, and Mailgun will reject
+/// anything it can't actually use. Only a single, bare address gets through, though, as it's used
+/// as a recipient (of confirmation emails) - so nothing that could separate or wrap addresses, like
+/// `,` or `<`, and no whitespace.
+fn looks_like_email(email: &str) -> bool {
+    let email = email.trim();
+    if email.chars().any(|c| c.is_whitespace() || c.is_control() || ",;<>\"()[]\\".contains(c)) {
+        return false;
+    }
+    match email.rsplit_once('@') {
+        Some((local, domain)) => !local.is_empty() && domain.contains('.') && !domain.starts_with('.')
+            && !domain.ends_with('.'),
+        None => false,
+    }
+}
+
+/// Every other non-empty field, if it's in the (per-form) `EXTRA_FIELDS` allowlist, or there isn't one
+pub fn extra_fields(fields: &HashMap<String, String>) -> BTreeMap<String, String> {
+    let allowed: Option<Vec<String>> = form_var(fields.get("_form").map(|form| form.as_str()), "EXTRA_FIELDS")
+        .map(|names| names.split(',').map(|name| name.trim().to_string()).filter(|name| !name.is_empty()).collect());
+    fields.iter()
+        .filter(|(name, value)| !name.starts_with('_') && !KNOWN_FIELDS.contains(&name.as_str()) && !value.trim().is_empty())
+        .filter(|(name, _)| allowed.as_ref().map(|allowed| allowed.contains(name)).unwrap_or(true))
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect()
+}
+
+/// Checks every field (rather than stopping at the first problem, as deserializing does) so the
+/// frontend can highlight all of the offending inputs at once
+pub fn validate(fields: &HashMap<String, String>) -> Result<FormData, Vec<FieldError>> {
+    let mut errors: Vec<FieldError> = REQUIRED_FIELDS.iter()
+        .filter(|field| fields.get(**field).map(|value| value.trim().is_empty()).unwrap_or(true))
+        .map(|field| FieldError::new(field, FieldProblem::Missing))
+        .collect();
+    if let Some(email) = fields.get("from_email").filter(|email| !email.trim().is_empty()) {
+        if !looks_like_email(email) {
+            errors.push(FieldError::new("from_email", FieldProblem::InvalidEmail));
+        }
+    }
+    // Checkboxes are sent as `on` by default, and left out entirely when unticked
+    let consent = fields.get("consent")
+        .map(|consent| matches!(consent.trim().to_lowercase().as_str(), "on" | "true" | "yes" | "1"))
+        .unwrap_or(false);
+    if !consent && form_flag(fields.get("_form").map(|form| form.as_str()), "REQUIRE_CONSENT", false) {
+        errors.push(FieldError::new("consent", FieldProblem::ConsentRequired));
+    }
+    let page_url = fields.get("page_url").map(|url| url.trim()).filter(|url| !url.is_empty());
+    if let Some(url) = page_url {
+        if !referrer::allowed(fields.get("_form").map(|form| form.as_str()), url) {
+            errors.push(FieldError::new("page_url", FieldProblem::InvalidPageUrl));
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    Ok(FormData {
+        from_name: fields["from_name"].clone(),
+        from_email: fields["from_email"].trim().to_string(),
+        title: fields["title"].clone(),
+        body: fields["body"].clone(),
+        form: fields.get("_form").cloned(),
+        redirect: fields.get("_redirect").cloned(),
+        lang: fields.get("lang").cloned(),
+        page_url: page_url.map(|url| url.to_string()),
+        consent,
+        // Only set once the signature has been checked
+        to: None,
+        signature: fields.get("_signature").cloned(),
+        extra: extra_fields(fields),
+    })
+}
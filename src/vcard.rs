@@ -0,0 +1,119 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+//! A vCard of the submitter, attached to emails so they can be added to an address book or CRM in
+//! one click
+
+use chrono::SecondsFormat;
+use crate::form_flag;
+use crate::provider::EmailAttachment;
+use crate::store::Submission;
+
+/// vCard lines longer than this (in bytes, not counting the line break) have to be folded
+const MAX_LINE_BYTES: usize = 75;
+
+/// The (per-form) `ATTACH_VCARD`, as an attachment for the submission's email if it's set
+pub fn attach(submission: &Submission) -> Vec<EmailAttachment> {
+    if !form_flag(submission.form.as_deref(), "ATTACH_VCARD", false) {
+        return Vec::new();
+    }
+    vec![EmailAttachment { file_name: file_name(&submission.from_name), content_type: "text/vcard".to_string(), content: render(submission) }]
+}
+
+/// Version 3.0, as it's what Outlook and most CRMs import most reliably
+fn render(submission: &Submission) -> String {
+    let name = submission.from_name.trim();
+    // Only a guess, but the structured name is required, and address books sort by it
+    let (given, family) = match name.rsplit_once(' ') {
+        Some((given, family)) => (given.trim(), family),
+        None => (name, ""),
+    };
+    let mut lines = vec![
+        "BEGIN:VCARD".to_string(),
+        "VERSION:3.0".to_string(),
+        format!("FN:{}", escape(name)),
+        format!("N:{};{};;;", escape(family), escape(given)),
+        format!("EMAIL;TYPE=INTERNET:{}", escape(submission.from_email.trim())),
+    ];
+    if let Some(phone) = field(submission, "phone") {
+        lines.push(format!("TEL;TYPE=VOICE:{}", escape(phone)));
+    }
+    if let Some(company) = field(submission, "company") {
+        lines.push(format!("ORG:{}", escape(company)));
+    }
+    let mut note = format!("Sent via the contact form on {}", submission.received_at.to_rfc3339_opts(SecondsFormat::Secs, true));
+    if let Some(form) = submission.form.as_deref() {
+        note.push_str(&format!(" ({})", form));
+    }
+    lines.push(format!("NOTE:{}", escape(&note)));
+    lines.push("END:VCARD".to_string());
+    lines.iter().map(|line| fold(line)).collect::<Vec<_>>().join("")
+}
+
+fn field<'a>(submission: &'a Submission, name: &str) -> Option<&'a str> {
+    submission.extra.get(name).map(|value| value.trim()).filter(|value| !value.is_empty())
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\")
+        .replace(',', "\\,")
+        .replace(';', "\\;")
+        .replace("\r\n", "\\n")
+        .replace(['\r', '\n'], "\\n")
+}
+
+/// Breaks the line up so no part of it is longer than [MAX_LINE_BYTES], continuing each part on a
+/// line starting with a space - without splitting any characters
+fn fold(line: &str) -> String {
+    let mut folded = String::new();
+    let mut length = 0;
+    for c in line.chars() {
+        if length + c.len_utf8() > MAX_LINE_BYTES {
+            folded.push_str("\r\n ");
+            // The space counts towards the continuation line's length
+            length = 1;
+        }
+        folded.push(c);
+        length += c.len_utf8();
+    }
+    folded.push_str("\r\n");
+    folded
+}
+
+/// Named after the submitter, like `jo-bloggs.vcf`
+fn file_name(name: &str) -> String {
+    let slug: String = name.trim().to_lowercase().chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '-' })
+        .collect();
+    let slug = slug.split('-').filter(|part| !part.is_empty()).collect::<Vec<_>>().join("-");
+    if slug.is_empty() {
+        "contact.vcf".to_string()
+    } else {
+        format!("{}.vcf", slug)
+    }
+}
@@ -0,0 +1,167 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use lazy_static::lazy_static;
+use log::{error, info, warn};
+use serde::Serialize;
+use sha2::Sha256;
+use crate::{check_var, CLIENT};
+use crate::store::Submission;
+
+lazy_static!(
+    pub static ref URLS: Vec<String> = std::env::var("WEBHOOK_URLS")
+        .map(|urls| urls.split(',').map(|url| url.trim().to_string()).filter(|url| !url.is_empty()).collect())
+        .unwrap_or_default();
+    static ref SECRET: Option<String> = std::env::var("WEBHOOK_SECRET").ok();
+    pub static ref MAX_ATTEMPTS: u32 = std::env::var("WEBHOOK_MAX_ATTEMPTS").ok()
+        .and_then(|attempts| attempts.trim().parse().ok())
+        .unwrap_or(DEFAULT_MAX_ATTEMPTS)
+        .max(1);
+);
+
+const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+const INITIAL_BACKOFF: Duration = Duration::from_secs(2);
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+pub fn init() -> Result<(), String> {
+    check_var::<u32>("WEBHOOK_MAX_ATTEMPTS", "a positive number")?;
+    if !URLS.is_empty() {
+        info!("Will be forwarding submissions to {} webhook(s), with up to {} attempt(s) each", URLS.len(), *MAX_ATTEMPTS);
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct WebhookPayload<'a> {
+    event: &'static str,
+    id: &'a str,
+    received_at: DateTime<Utc>,
+    form: Option<&'a str>,
+    from_name: &'a str,
+    from_email: &'a str,
+    title: &'a str,
+    body: &'a str,
+    /// Any extra fields, by name
+    fields: &'a BTreeMap<String, String>,
+    /// The detected language, if it's clear
+    #[serde(skip_serializing_if = "Option::is_none")]
+    language: Option<&'a str>,
+    /// The page it was sent from, if known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    page_url: Option<&'a str>,
+}
+
+/// Signs `<timestamp>.<body>` rather than just the body, so receivers can reject replayed requests
+/// by checking the timestamp
+fn sign(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(format!("{}.{}", timestamp, body).as_bytes());
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Sends a single attempt, returning whether it's worth retrying on failure
+async fn send(url: &str, id: &str, body: &str) -> Result<(), (String, bool)> {
+    let timestamp = Utc::now().timestamp();
+    let mut request = CLIENT.post(url)
+        .timeout(TIMEOUT)
+        .header("Content-Type", "application/json")
+        .header("X-Webhook-Id", id)
+        .header("X-Webhook-Timestamp", timestamp.to_string());
+    if let Some(secret) = SECRET.as_deref() {
+        request = request.header("X-Webhook-Signature", sign(secret, timestamp, body));
+    }
+    let response = request.body(body.to_string())
+        .send()
+        .await
+        .map_err(|e| (e.to_string(), true))?;
+
+    let status = response.status();
+    if status.is_success() {
+        Ok(())
+    } else {
+        // Anything other than a server error or rate limit is a problem with the request, and
+        // sending it again won't change that
+        Err((format!("received {}", status), status.is_server_error() || status.as_u16() == 429))
+    }
+}
+
+async fn deliver(url: String, id: String, body: String) {
+    let mut backoff = INITIAL_BACKOFF;
+    for attempt in 1..=*MAX_ATTEMPTS {
+        match send(&url, &id, &body).await {
+            Ok(()) => {
+                info!("Submission {} sent to webhook {}", id, url);
+                return;
+            }
+            Err((e, true)) if attempt < *MAX_ATTEMPTS => {
+                warn!("Attempt {} to send submission {} to webhook {} failed ({}), retrying in {:?}", attempt, id, url, e, backoff);
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+            }
+            Err((e, _)) => {
+                error!("Giving up sending submission {} to webhook {} after {} attempt(s): {}", id, url, attempt, e);
+                return;
+            }
+        }
+    }
+}
+
+/// The JSON representation of a submission sent to webhooks, which other integrations reuse so
+/// consumers only need to understand one format
+pub fn event_json(submission: &Submission) -> String {
+    let payload = WebhookPayload {
+        event: "submission.received",
+        id: &submission.id,
+        received_at: submission.received_at,
+        form: submission.form.as_deref(),
+        from_name: &submission.from_name,
+        from_email: &submission.from_email,
+        title: &submission.title,
+        body: &submission.body,
+        fields: &submission.extra,
+        language: submission.language.as_deref(),
+        page_url: submission.metadata.page_url.as_deref(),
+    };
+    serde_json::to_string(&payload).expect("payload is always serializable")
+}
+
+/// Sends the submission to every configured webhook in the background, so slow or failing
+/// receivers never hold up the response
+pub fn dispatch(submission: &Submission) {
+    if URLS.is_empty() {
+        return;
+    }
+    let body = event_json(submission);
+    for url in URLS.iter() {
+        tokio::spawn(deliver(url.clone(), submission.id.clone(), body.clone()));
+    }
+}
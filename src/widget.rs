@@ -0,0 +1,75 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2018, Andrew Thorburn All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without modification, are permitted
+ * provided that the following conditions are met:
+ *
+ * Redistributions of source code must retain the above copyright notice, this list of conditions
+ * and the following disclaimer.
+ *
+ * Redistributions in binary form must reproduce the above copyright notice, this list of conditions
+ * and the following disclaimer in the documentation and/or other materials provided with the
+ * distribution.
+ *
+ * Neither the name of the copyright holder nor the names of its contributors may be used to endorse
+ * or promote products derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR
+ * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND
+ * FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+ * CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ * DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+ * WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY
+ * WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+use axum::extract::OriginalUri;
+use axum::http::{header, HeaderMap};
+use axum::response::{Html, IntoResponse};
+use lazy_static::lazy_static;
+use crate::page;
+
+const INDEX_TEMPLATE: &str = include_str!("../templates/index.html");
+const WIDGET_JS: &str = include_str!("../templates/widget.js");
+
+lazy_static!(
+    /// The URL this service is reachable at, for when it can't be worked out reliably from the
+    /// request (e.g. behind a proxy that rewrites the `Host` header)
+    static ref PUBLIC_URL: Option<String> = std::env::var("PUBLIC_URL").ok();
+);
+
+/// Always ends with a `/`, so it's both the form endpoint and the base for other paths. `path` is
+/// where the index is being served from, which is the endpoint's path too (even if the service has
+/// been mounted somewhere other than `/`).
+pub(crate) fn endpoint(headers: &HeaderMap, path: &str) -> String {
+    if let Some(url) = PUBLIC_URL.as_deref() {
+        return format!("{}/", url.trim_end_matches('/'));
+    }
+    let host = headers.get(header::HOST).and_then(|host| host.to_str().ok()).unwrap_or("localhost");
+    let scheme = headers.get("X-Forwarded-Proto").and_then(|proto| proto.to_str().ok()).unwrap_or("http");
+    format!("{}://{}{}/", scheme, host, path.trim_end_matches('/'))
+}
+
+#[utoipa::path(
+    get,
+    path = "/",
+    tag = "widget",
+    responses((status = 200, description = "An example form, with instructions for embedding it", content_type = "text/html", body = String)),
+)]
+pub async fn index(OriginalUri(uri): OriginalUri, headers: HeaderMap) -> Html<String> {
+    Html(page::render(INDEX_TEMPLATE, &[("endpoint", &endpoint(&headers, uri.path()))]))
+}
+
+/// The widget works out the endpoint from its own URL, so it can be served as-is
+#[utoipa::path(
+    get,
+    path = "/widget.js",
+    tag = "widget",
+    responses((status = 200, description = "Script that inserts the contact form where it's included", content_type = "application/javascript", body = String)),
+)]
+pub async fn script() -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, "application/javascript; charset=utf-8"), (header::CACHE_CONTROL, "public, max-age=3600")], WIDGET_JS)
+}
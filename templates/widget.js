@@ -0,0 +1,169 @@
+/*
+ * Drop-in contact form. Include with
+ *
+ *     <script src="https://<this service>/widget.js" async></script>
+ *
+ * and the form is inserted where the script tag is. Optional attributes on the script tag:
+ *   data-form:   Sent as the hidden `_form` field, to pick up per-form settings
+ *   data-target: A CSS selector for an element to insert the form into instead
+ *   data-key:    The site's API key, if the service requires one
+ *   data-consent: The label for a consent checkbox, which must be ticked to send, if there should be one
+ */
+(function () {
+    var script = document.currentScript;
+    var endpoint = new URL('./', script.src).href;
+    var formName = script.getAttribute('data-form');
+    var target = script.getAttribute('data-target');
+    var apiKey = script.getAttribute('data-key');
+    var consentText = script.getAttribute('data-consent');
+
+    var style = document.createElement('style');
+    style.textContent =
+        '.mcf-widget { font-family: system-ui, sans-serif; max-width: 32em; }' +
+        '.mcf-widget label { display: block; margin: 0.75em 0 0.25em; }' +
+        '.mcf-widget input, .mcf-widget textarea { box-sizing: border-box; width: 100%; padding: 0.5em; font: inherit;' +
+        ' border: 1px solid #bbb; border-radius: 4px; }' +
+        '.mcf-widget textarea { min-height: 8em; }' +
+        '.mcf-widget .mcf-consent input { width: auto; margin-right: 0.5em; }' +
+        '.mcf-widget button { margin-top: 1em; padding: 0.5em 1.5em; font: inherit; border: 0; border-radius: 4px;' +
+        ' background: #0366d6; color: #fff; cursor: pointer; }' +
+        '.mcf-widget button:disabled { opacity: 0.6; cursor: default; }' +
+        '.mcf-widget .mcf-status { margin-top: 1em; }' +
+        '.mcf-widget .mcf-error { color: #b00020; }';
+    document.head.appendChild(style);
+
+    var form = document.createElement('form');
+    form.className = 'mcf-widget';
+    form.innerHTML =
+        '<label>Name <input name="from_name" required autocomplete="name"></label>' +
+        '<label>Email <input name="from_email" type="email" required autocomplete="email"></label>' +
+        '<label>Subject <input name="title" required></label>' +
+        '<label>Message <textarea name="body" required></textarea></label>' +
+        '<button type="submit">Send</button>' +
+        '<div class="mcf-status" role="status"></div>';
+    if (consentText) {
+        var consent = document.createElement('label');
+        consent.className = 'mcf-consent';
+        var checkbox = document.createElement('input');
+        checkbox.type = 'checkbox';
+        checkbox.name = 'consent';
+        checkbox.required = true;
+        consent.appendChild(checkbox);
+        consent.appendChild(document.createTextNode(consentText));
+        form.insertBefore(consent, form.querySelector('button'));
+    }
+    if (formName) {
+        var hidden = document.createElement('input');
+        hidden.type = 'hidden';
+        hidden.name = '_form';
+        hidden.value = formName;
+        form.appendChild(hidden);
+    }
+
+    var button = form.querySelector('button');
+    var status = form.querySelector('.mcf-status');
+    function showStatus(text, isError) {
+        status.textContent = text;
+        status.className = 'mcf-status' + (isError ? ' mcf-error' : '');
+    }
+
+    // Only some deployments require a token, and those that don't have no /token endpoint
+    function fetchToken() {
+        return fetch(endpoint + 'token')
+            .then(function (response) {
+                return response.ok ? response.json().then(function (data) { return data.token; }) : null;
+            })
+            .catch(function () {
+                return null;
+            });
+    }
+
+    // Likewise for proof-of-work challenges. Resolves to the fields to send, if there is one.
+    function solveChallenge() {
+        return fetch(endpoint + 'challenge')
+            .then(function (response) {
+                return response.ok ? response.json() : null;
+            })
+            .catch(function () {
+                return null;
+            })
+            .then(function (challenge) {
+                if (!challenge) {
+                    return null;
+                }
+                var encoder = new TextEncoder();
+                return new Promise(function (resolve, reject) {
+                    (function attempt(counter) {
+                        var solution = String(counter);
+                        crypto.subtle.digest('SHA-256', encoder.encode(challenge.challenge + solution))
+                            .then(function (hash) {
+                                if (leadingZeroBits(new Uint8Array(hash)) >= challenge.difficulty) {
+                                    resolve({ _challenge: challenge.challenge, _solution: solution });
+                                } else {
+                                    attempt(counter + 1);
+                                }
+                            }, reject);
+                    })(0);
+                });
+            });
+    }
+
+    function leadingZeroBits(bytes) {
+        var bits = 0;
+        for (var i = 0; i < bytes.length; i++) {
+            if (bytes[i] === 0) {
+                bits += 8;
+                continue;
+            }
+            return bits + Math.clz32(bytes[i]) - 24;
+        }
+        return bits;
+    }
+
+    form.addEventListener('submit', function (event) {
+        event.preventDefault();
+        button.disabled = true;
+        showStatus('Sending...', false);
+        Promise.all([fetchToken(), solveChallenge()])
+            .then(function (results) {
+                var token = results[0];
+                var challenge = results[1];
+                var body = new URLSearchParams(new FormData(form));
+                if (token) {
+                    body.set('_token', token);
+                }
+                if (challenge) {
+                    body.set('_challenge', challenge._challenge);
+                    body.set('_solution', challenge._solution);
+                }
+                if (apiKey) {
+                    body.set('_key', apiKey);
+                }
+                return fetch(endpoint, { method: 'POST', body: body });
+            })
+            .then(function (response) {
+                return response.json().then(function (data) {
+                    if (!response.ok) {
+                        throw new Error(data.message || 'something went wrong');
+                    }
+                });
+            })
+            .then(function () {
+                form.reset();
+                showStatus('Thanks - your message has been sent.', false);
+            })
+            .catch(function (error) {
+                showStatus('Sorry, your message couldn\'t be sent: ' + error.message, true);
+            })
+            .then(function () {
+                button.disabled = false;
+            });
+    });
+
+    var container = target && document.querySelector(target);
+    if (container) {
+        container.appendChild(form);
+    } else {
+        script.parentNode.insertBefore(form, script.nextSibling);
+    }
+})();
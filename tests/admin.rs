@@ -0,0 +1,27 @@
+//! Only enabling the admin API for tokens that can't be guessed
+
+mod common;
+
+use axum::body::Body;
+use axum::http::{header, Request, StatusCode};
+use mailgun_contact_form::ContactFormService;
+use common::call;
+
+#[tokio::test]
+async fn ignores_empty_admin_tokens() {
+    std::env::set_var("MAILGUN_TO_ADDRESS", "owner@example.com");
+    std::env::set_var("MAIL_PROVIDER", "memory");
+    std::env::set_var("DEV_MODE", "true");
+    std::env::set_var("ADMIN_TOKEN", "");
+    std::env::set_var("ADMIN_TOKENS", r#"{"alice": " "}"#);
+    let app = ContactFormService::builder().build().await.unwrap().router();
+
+    for token in ["", " "] {
+        let request = Request::get("/admin/submissions")
+            .header(header::AUTHORIZATION, format!("Bearer {}", token))
+            .body(Body::empty())
+            .unwrap();
+        let (status, _) = call(&app, request).await;
+        assert_eq!(status, StatusCode::NOT_FOUND, "the admin API was enabled by an empty token");
+    }
+}
@@ -0,0 +1,44 @@
+//! Alerts about failing deliveries, sent to a webhook once failures pass the threshold
+
+mod common;
+
+use std::time::Duration;
+use axum::http::StatusCode;
+use mailgun_contact_form::{ContactFormService, MailgunProvider};
+use tower::ServiceExt;
+use wiremock::matchers::{body_partial_json, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+use common::post_form;
+
+#[tokio::test]
+async fn alerts_once_failures_pass_the_threshold() {
+    let mailgun = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/v3/mg.example.com/messages"))
+        .respond_with(ResponseTemplate::new(400).set_body_json(serde_json::json!({ "message": "to parameter is not a valid address" })))
+        .mount(&mailgun)
+        .await;
+    let alerts = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/alerts"))
+        .and(body_partial_json(serde_json::json!({ "event": "delivery.failures", "failures": 2 })))
+        .respond_with(ResponseTemplate::new(204))
+        .expect(1)
+        .mount(&alerts)
+        .await;
+
+    std::env::set_var("MAILGUN_TO_ADDRESS", "owner@example.com");
+    std::env::set_var("ALERT_FAILURE_THRESHOLD", "2");
+    std::env::set_var("ALERT_WEBHOOK_URL", format!("{}/alerts", alerts.uri()));
+    let provider = MailgunProvider::new("key-0123456789", "mg.example.com").base_url(mailgun.uri());
+    let app = ContactFormService::builder().provider(provider).build().await.unwrap().router();
+
+    // Only the second failure reaches the threshold, and the third is within the same window
+    for _ in 0..3 {
+        let request = post_form("from_name=Jo&from_email=jo%40example.com&title=Hello&body=Hi");
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+    }
+    // The alert is sent in the background
+    tokio::time::sleep(Duration::from_millis(200)).await;
+}
@@ -0,0 +1,61 @@
+//! Anonymizing submissions once they're past the retention period, instead of deleting them
+
+mod common;
+
+use std::time::Duration;
+use chrono::Utc;
+use mailgun_contact_form::{ContactFormService, MemoryProvider};
+use serde_json::json;
+use common::{ADMIN_TOKEN, admin, call};
+
+#[tokio::test]
+async fn anonymizes_old_submissions() {
+    let path = std::env::temp_dir().join(format!("contact-form-anonymize-{}.json", std::process::id()));
+    let submissions = json!([
+        {
+            "id": "expired",
+            "received_at": Utc::now() - chrono::Duration::days(45),
+            "from_name": "Jo Bloggs",
+            "from_email": "jo@example.com",
+            "title": "Hello",
+            "body": "Is this thing on?",
+            "form": "contact",
+            "status": "sent",
+        },
+        {
+            "id": "recent",
+            "received_at": Utc::now() - chrono::Duration::days(5),
+            "from_name": "Sam Smith",
+            "from_email": "sam@example.org",
+            "title": "Hi",
+            "body": "Anyone there?",
+            "status": "sent",
+        },
+    ]);
+    std::fs::write(&path, submissions.to_string()).unwrap();
+    std::env::set_var("MAILGUN_TO_ADDRESS", "owner@example.com");
+    std::env::set_var("ADMIN_TOKEN", ADMIN_TOKEN);
+    std::env::set_var("SUBMISSIONS_FILE", &path);
+    std::env::set_var("RETENTION_DAYS", "30");
+    std::env::set_var("RETENTION_MODE", "anonymize");
+    let app = ContactFormService::builder().provider(MemoryProvider::new()).build().await.unwrap().router();
+
+    let mut expired = json!(null);
+    for _ in 0..50 {
+        expired = call(&app, admin("GET", "/admin/submissions/expired")).await.1;
+        if expired["from_email"] == "" {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    // Still counted, but nothing about who sent it or what they said
+    assert_eq!(expired["from_name"], "");
+    assert_eq!(expired["from_email"], "");
+    assert_eq!(expired["title"], "");
+    assert_eq!(expired["body"], "");
+    assert_eq!(expired["form"], "contact");
+    assert_eq!(expired["status"], "sent");
+    let (_, recent) = call(&app, admin("GET", "/admin/submissions/recent")).await;
+    assert_eq!(recent["from_email"], "sam@example.org");
+    std::fs::remove_file(&path).unwrap();
+}
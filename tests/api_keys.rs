@@ -0,0 +1,54 @@
+//! Requiring an API key with each submission, in the `X-Api-Key` header or the `_key` field
+
+mod common;
+
+use axum::body::Body;
+use axum::http::{header, Request, StatusCode};
+use mailgun_contact_form::ContactFormService;
+use common::{VALID_FORM, call, mailbox, post_form};
+
+fn with_key(key: &str, origin: Option<&str>) -> Request<Body> {
+    let mut request = post_form(VALID_FORM);
+    request.headers_mut().insert("x-api-key", key.parse().unwrap());
+    if let Some(origin) = origin {
+        request.headers_mut().insert(header::ORIGIN, origin.parse().unwrap());
+    }
+    request
+}
+
+#[tokio::test]
+async fn accepts_only_known_keys() {
+    std::env::set_var("MAILGUN_TO_ADDRESS", "owner@example.com");
+    std::env::set_var("MAIL_PROVIDER", "memory");
+    std::env::set_var("DEV_MODE", "true");
+    std::env::set_var("API_KEYS", r#"{
+        "site-key": {"origins": ["https://example.com/"], "per_minute": 2},
+        "server-key": {}
+    }"#);
+    let app = ContactFormService::builder().build().await.unwrap().router();
+
+    let (status, _) = call(&app, with_key("server-key", None)).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = call(&app, post_form(format!("{}&_key=server-key", VALID_FORM))).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = call(&app, with_key("site-key", Some("https://example.com"))).await;
+    assert_eq!(status, StatusCode::OK);
+
+    for request in [
+        post_form(VALID_FORM),
+        with_key("stolen-key", None),
+        with_key("site-key", Some("https://example.org")),
+        with_key("site-key", None),
+    ] {
+        let (status, body) = call(&app, request).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["status"], "InvalidApiKey");
+    }
+
+    let (status, _) = call(&app, with_key("site-key", Some("https://example.com"))).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, body) = call(&app, with_key("site-key", Some("https://example.com"))).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert!(body["retry_after"].as_u64().is_some());
+    assert_eq!(mailbox(&app).await.len(), 4);
+}
@@ -0,0 +1,53 @@
+//! Serving a frontend from `STATIC_DIR` alongside the service's own routes
+
+use axum::Router;
+use axum::body::Body;
+use axum::http::{header, Request, Response, StatusCode};
+use mailgun_contact_form::ContactFormService;
+use tower::ServiceExt;
+
+async fn get(app: &Router, path: &str, if_modified_since: Option<&str>) -> Response<axum::body::BoxBody> {
+    let mut request = Request::get(path);
+    if let Some(since) = if_modified_since {
+        request = request.header(header::IF_MODIFIED_SINCE, since);
+    }
+    app.clone().oneshot(request.body(Body::empty()).unwrap()).await.unwrap()
+}
+
+fn content_type<B>(response: &Response<B>) -> &str {
+    response.headers().get(header::CONTENT_TYPE).unwrap().to_str().unwrap()
+}
+
+#[tokio::test]
+async fn serves_files_with_their_types_and_modification_times() {
+    let dir = std::env::temp_dir().join(format!("contact-form-static-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("index.html"), "<h1>Contact us</h1>").unwrap();
+    std::fs::write(dir.join("style.css"), "h1 { color: teal; }").unwrap();
+    std::fs::write(dir.join("form.js"), "console.log('hi');").unwrap();
+    std::env::set_var("MAILGUN_TO_ADDRESS", "owner@example.com");
+    std::env::set_var("MAIL_PROVIDER", "memory");
+    std::env::set_var("STATIC_DIR", &dir);
+    let app = ContactFormService::builder().build().await.unwrap().router();
+
+    for (path, expected) in [("/", "text/html"), ("/style.css", "text/css"), ("/form.js", "text/javascript")] {
+        let response = get(&app, path, None).await;
+        assert_eq!(response.status(), StatusCode::OK, "{}", path);
+        assert!(content_type(&response).starts_with(expected), "{}: {}", path, content_type(&response));
+        let modified = response.headers().get(header::LAST_MODIFIED).expect("no Last-Modified").to_str().unwrap().to_string();
+        // So browsers can check whether their copy is still current
+        let response = get(&app, path, Some(&modified)).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED, "{}", path);
+    }
+    let response = get(&app, "/index.html", None).await;
+    assert!(content_type(&response).starts_with("text/html"));
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    assert_eq!(body, "<h1>Contact us</h1>");
+
+    assert_eq!(get(&app, "/missing.css", None).await.status(), StatusCode::NOT_FOUND);
+    // The service's own routes still win
+    let response = get(&app, "/openapi.json", None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(content_type(&response).starts_with("application/json"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
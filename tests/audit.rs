@@ -0,0 +1,59 @@
+//! Recording who did what, with the audit log showing through the admin API
+
+mod common;
+
+use axum::body::Body;
+use axum::http::{header, Request, StatusCode};
+use mailgun_contact_form::{ContactFormService, MemoryProvider};
+use serde_json::Value;
+use common::{VALID_FORM, call, post_form};
+
+const ALICE_TOKEN: &str = "alice-token";
+
+fn as_alice(method: &str, path: &str) -> Request<Body> {
+    Request::builder()
+        .method(method)
+        .uri(path)
+        .header(header::AUTHORIZATION, format!("Bearer {}", ALICE_TOKEN))
+        .body(Body::empty())
+        .unwrap()
+}
+
+#[tokio::test]
+async fn records_admin_actions() {
+    let path = std::env::temp_dir().join(format!("contact-form-audit-{}.jsonl", std::process::id()));
+    std::env::set_var("MAILGUN_TO_ADDRESS", "owner@example.com");
+    std::env::set_var("ADMIN_TOKENS", format!(r#"{{"alice": "{}"}}"#, ALICE_TOKEN));
+    std::env::set_var("AUDIT_LOG_FILE", &path);
+    std::env::set_var("RESPONSE_ID_FIELD", "submission_id");
+    let app = ContactFormService::builder().provider(MemoryProvider::new()).build().await.unwrap().router();
+
+    let (_, body) = call(&app, post_form(VALID_FORM)).await;
+    let id = body["submission_id"].as_str().unwrap().to_string();
+    let (status, _) = call(&app, as_alice("PUT", "/admin/suppressions/jo%40example.com")).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = call(&app, as_alice("DELETE", "/admin/submissions?email=jo%40example.com")).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _) = call(&app, Request::get("/admin/audit").body(Body::empty()).unwrap()).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = call(&app, Request::get("/admin/audit").header(header::AUTHORIZATION, "Bearer wrong").body(Body::empty()).unwrap()).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, log) = call(&app, as_alice("GET", "/admin/audit?actor=alice")).await;
+    assert_eq!(status, StatusCode::OK);
+    let actions: Vec<&str> = log["entries"].as_array().unwrap().iter().map(|entry| entry["action"].as_str().unwrap()).collect();
+    assert_eq!(actions, ["submissions.erased", "suppression.added"]);
+    assert_eq!(log["entries"][0]["detail"], "1 submission(s)");
+    // Who it was about is recorded, but not their address - which would undo the erasure
+    let (_, log) = call(&app, as_alice("GET", &format!("/admin/audit?submission_id={}", id))).await;
+    assert_eq!(log["entries"].as_array().unwrap().last().unwrap()["action"], "submission.received");
+    assert!(!log.to_string().contains("jo@example.com"));
+
+    // And appended to the file, oldest first
+    let lines: Vec<Value> = std::fs::read_to_string(&path).unwrap().lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    let actors: Vec<&str> = lines.iter().map(|entry| entry["actor"].as_str().unwrap()).collect();
+    assert_eq!(actors.first(), Some(&"submitter"));
+    assert_eq!(lines.last().unwrap()["action"], "submissions.erased");
+    std::fs::remove_file(&path).unwrap();
+}
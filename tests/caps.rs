@@ -0,0 +1,49 @@
+//! Turning away, or queueing, submissions once a send cap has been reached
+
+mod common;
+
+use axum::Router;
+use axum::http::{header, StatusCode};
+use mailgun_contact_form::{ContactFormService, MemoryProvider};
+use serde_json::Value;
+use tower::ServiceExt;
+use common::{VALID_FORM, post_form};
+
+async fn submit(app: &Router, form: &str) -> (StatusCode, Option<String>, Value) {
+    let request = post_form(form);
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let retry_after = response.headers().get(header::RETRY_AFTER).map(|value| value.to_str().unwrap().to_string());
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, retry_after, serde_json::from_slice(&body).unwrap())
+}
+
+#[tokio::test]
+async fn enforces_overall_and_per_form_caps() {
+    std::env::set_var("MAILGUN_TO_ADDRESS", "owner@example.com");
+    std::env::set_var("SEND_CAP_DAILY", "2");
+    std::env::set_var("FORM_PAUSED_SEND_CAP_DAILY", "0");
+    std::env::set_var("FORM_PAUSED_SEND_CAP_ACTION", "queue");
+    let path = std::env::temp_dir().join(format!("contact-form-capped-{}.json", std::process::id()));
+    std::env::set_var("OUTBOX_FILE", &path);
+    let app = ContactFormService::builder().provider(MemoryProvider::new()).build().await.unwrap().router();
+
+    // Queued, without counting against the overall cap, and kept through a restart
+    let (status, _, _) = submit(&app, &format!("_form=paused&{}", VALID_FORM)).await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    let outbox: Value = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+    assert_eq!(outbox[0]["kind"], "capped");
+    assert_eq!(outbox[0]["submission"]["form"], "paused");
+
+    for _ in 0..2 {
+        let (status, _, _) = submit(&app, VALID_FORM).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+    let (status, retry_after, body) = submit(&app, VALID_FORM).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(body["status"], "SendCapReached");
+    let retry_after: u64 = retry_after.expect("a Retry-After header").parse().unwrap();
+    assert!(retry_after > 0 && retry_after <= 24 * 60 * 60);
+    assert_eq!(body["retry_after"], retry_after);
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,43 @@
+//! Believing only the configured forwarding header, and only from trusted proxies
+
+mod common;
+
+use std::net::SocketAddr;
+use axum::Router;
+use axum::extract::ConnectInfo;
+use axum::http::StatusCode;
+use mailgun_contact_form::ContactFormService;
+use common::{VALID_FORM, call, mailbox, post_form};
+
+/// The IP address the submission's email says it came from
+async fn submit(app: &Router, peer: &str, headers: &[(&'static str, &str)]) -> String {
+    let mut request = post_form(VALID_FORM);
+    request.extensions_mut().insert(ConnectInfo(format!("{}:4711", peer).parse::<SocketAddr>().unwrap()));
+    for (name, value) in headers {
+        request.headers_mut().insert(*name, value.parse().unwrap());
+    }
+    let (status, _) = call(app, request).await;
+    assert_eq!(status, StatusCode::OK);
+    let sent = mailbox(app).await;
+    let text = sent[0]["text"].as_str().unwrap();
+    text.lines().find_map(|line| line.strip_prefix("IP address: ")).unwrap().to_string()
+}
+
+#[tokio::test]
+async fn ignores_spoofed_forwarding_headers() {
+    std::env::set_var("MAILGUN_TO_ADDRESS", "owner@example.com");
+    std::env::set_var("MAIL_PROVIDER", "memory");
+    std::env::set_var("DEV_MODE", "true");
+    std::env::set_var("EMAIL_METADATA", "ip");
+    std::env::set_var("TRUSTED_PROXIES", "10.0.0.1");
+    let app = ContactFormService::builder().build().await.unwrap().router();
+
+    // The proxy only adds to X-Forwarded-For, so Forwarded is whatever the client sent
+    let spoofed = ("forwarded", "for=1.2.3.4");
+    assert_eq!(submit(&app, "10.0.0.1", &[spoofed, ("x-forwarded-for", "203.0.113.7")]).await, "203.0.113.7");
+    assert_eq!(submit(&app, "10.0.0.1", &[("x-forwarded-for", "1.2.3.4, 203.0.113.7")]).await, "203.0.113.7");
+    assert_eq!(submit(&app, "10.0.0.1", &[spoofed]).await, "10.0.0.1");
+
+    // Not from a trusted proxy, so neither header counts
+    assert_eq!(submit(&app, "198.51.100.9", &[spoofed, ("x-forwarded-for", "1.2.3.4")]).await, "198.51.100.9");
+}
@@ -0,0 +1,44 @@
+//! Helpers shared by the integration tests
+#![allow(dead_code)]
+
+use axum::Router;
+use axum::body::Body;
+use axum::http::{header, Request, StatusCode};
+use serde_json::Value;
+use tower::ServiceExt;
+
+pub const ADMIN_TOKEN: &str = "admin-token";
+pub const VALID_FORM: &str = "from_name=Jo+Bloggs&from_email=jo%40example.com&title=Hello&body=Is+this+thing+on%3F";
+
+/// Sends the request through the app, returning the status and the JSON body, or `null` if it wasn't
+/// JSON
+pub async fn call(app: &Router, request: Request<Body>) -> (StatusCode, Value) {
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+}
+
+/// A URL-encoded submission to `POST /`
+pub fn post_form(form: impl Into<String>) -> Request<Body> {
+    Request::post("/")
+        .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .body(Body::from(form.into()))
+        .unwrap()
+}
+
+/// An admin API request, authorised with `ADMIN_TOKEN`
+pub fn admin(method: &str, path: &str) -> Request<Body> {
+    Request::builder()
+        .method(method)
+        .uri(path)
+        .header(header::AUTHORIZATION, format!("Bearer {}", ADMIN_TOKEN))
+        .body(Body::empty())
+        .unwrap()
+}
+
+/// What the memory provider has sent, newest first
+pub async fn mailbox(app: &Router) -> Vec<Value> {
+    let (_, mailbox) = call(app, Request::get("/_dev/mailbox").body(Body::empty()).unwrap()).await;
+    mailbox.as_array().unwrap().clone()
+}
@@ -0,0 +1,73 @@
+//! Compressing responses for clients that accept it, and accepting compressed submissions
+
+mod common;
+
+use std::io::{Read, Write};
+use axum::Router;
+use axum::body::Body;
+use axum::http::{header, Request, StatusCode};
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use mailgun_contact_form::ContactFormService;
+use serde_json::Value;
+use tower::ServiceExt;
+use common::{ADMIN_TOKEN, VALID_FORM, call, mailbox};
+
+/// The response's `Content-Encoding` (if any) and body
+async fn fetch(app: &Router, path: &str, accept_encoding: Option<&str>) -> (Option<String>, Vec<u8>) {
+    let mut request = Request::get(path);
+    if let Some(accept_encoding) = accept_encoding {
+        request = request.header(header::ACCEPT_ENCODING, accept_encoding);
+    }
+    let response = app.clone().oneshot(request.body(Body::empty()).unwrap()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let encoding = response.headers().get(header::CONTENT_ENCODING).map(|encoding| encoding.to_str().unwrap().to_string());
+    (encoding, hyper::body::to_bytes(response.into_body()).await.unwrap().to_vec())
+}
+
+#[tokio::test]
+async fn negotiates_content_encoding() {
+    std::env::set_var("MAILGUN_TO_ADDRESS", "owner@example.com");
+    std::env::set_var("MAIL_PROVIDER", "memory");
+    std::env::set_var("DEV_MODE", "true");
+    std::env::set_var("ADMIN_TOKEN", ADMIN_TOKEN);
+    let app = ContactFormService::builder().build().await.unwrap().router();
+
+    let (encoding, plain) = fetch(&app, "/openapi.json", None).await;
+    assert_eq!(encoding, None);
+    let spec: Value = serde_json::from_slice(&plain).unwrap();
+
+    let (encoding, gzipped) = fetch(&app, "/openapi.json", Some("gzip")).await;
+    assert_eq!(encoding.as_deref(), Some("gzip"));
+    assert!(gzipped.len() < plain.len());
+    let mut unzipped = Vec::new();
+    GzDecoder::new(gzipped.as_slice()).read_to_end(&mut unzipped).unwrap();
+    assert_eq!(serde_json::from_slice::<Value>(&unzipped).unwrap(), spec);
+
+    let (encoding, _) = fetch(&app, "/openapi.json", Some("gzip;q=0.5, br")).await;
+    assert_eq!(encoding.as_deref(), Some("br"));
+    let (encoding, _) = fetch(&app, "/openapi.json", Some("identity")).await;
+    assert_eq!(encoding, None);
+
+    // The admin event stream never is, so events aren't held back
+    let request = Request::get("/admin/events")
+        .header(header::AUTHORIZATION, format!("Bearer {}", ADMIN_TOKEN))
+        .header(header::ACCEPT_ENCODING, "gzip")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().get(header::CONTENT_ENCODING).is_none());
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(VALID_FORM.as_bytes()).unwrap();
+    let request = Request::post("/")
+        .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .header(header::CONTENT_ENCODING, "gzip")
+        .body(Body::from(encoder.finish().unwrap()))
+        .unwrap();
+    let (status, _) = call(&app, request).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(mailbox(&app).await[0]["subject"], "Hello");
+}
@@ -0,0 +1,79 @@
+//! Turning submissions away while too many emails are already being sent
+
+mod common;
+
+use std::sync::Arc;
+use std::time::Duration;
+use async_trait::async_trait;
+use axum::Router;
+use axum::http::{header, StatusCode};
+use mailgun_contact_form::{ContactFormService, Email, MailProvider, ProviderError};
+use serde_json::Value;
+use tokio::sync::Semaphore;
+use tower::ServiceExt;
+use wiremock::matchers::method;
+use wiremock::{Mock, MockServer, ResponseTemplate};
+use common::{ADMIN_TOKEN, VALID_FORM, admin, call, post_form};
+
+/// Doesn't finish sending until the test says so
+struct StalledProvider {
+    release: Arc<Semaphore>,
+}
+
+#[async_trait]
+impl MailProvider for StalledProvider {
+    async fn send(&self, _email: &Email) -> Result<(), ProviderError> {
+        self.release.acquire().await.unwrap().forget();
+        Ok(())
+    }
+}
+
+async fn submit(app: Router) -> (StatusCode, Option<String>, Value) {
+    let request = post_form(VALID_FORM);
+    let response = app.oneshot(request).await.unwrap();
+    let status = response.status();
+    let retry_after = response.headers().get(header::RETRY_AFTER).map(|value| value.to_str().unwrap().to_string());
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, retry_after, serde_json::from_slice(&body).unwrap())
+}
+
+#[tokio::test]
+async fn turns_sends_away_once_every_slot_is_taken() {
+    std::env::set_var("MAILGUN_TO_ADDRESS", "owner@example.com");
+    std::env::set_var("MAIL_MAX_CONCURRENT_SENDS", "1");
+    std::env::set_var("MAIL_SEND_QUEUE_SECS", "0");
+    std::env::set_var("ADMIN_TOKEN", ADMIN_TOKEN);
+    let webhook = MockServer::start().await;
+    Mock::given(method("POST")).respond_with(ResponseTemplate::new(200)).mount(&webhook).await;
+    std::env::set_var("WEBHOOK_URLS", webhook.uri());
+    let release = Arc::new(Semaphore::new(0));
+    let app = ContactFormService::builder()
+        .provider(StalledProvider { release: release.clone() })
+        .build()
+        .await
+        .unwrap()
+        .router();
+
+    let first = tokio::spawn(submit(app.clone()));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let (status, retry_after, body) = submit(app.clone()).await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body["status"], "Busy");
+    assert_eq!(retry_after.as_deref(), Some("1"));
+    assert_eq!(body["retry_after"], 1);
+
+    release.add_permits(1);
+    assert_eq!(first.await.unwrap().0, StatusCode::OK);
+
+    // The slot is free again
+    release.add_permits(1);
+    assert_eq!(submit(app.clone()).await.0, StatusCode::OK);
+
+    // The one turned away was never stored, or passed on, so it doesn't count as a failure
+    let (_, stored) = call(&app, admin("GET", "/admin/submissions")).await;
+    assert_eq!(stored["total"], 2);
+    assert!(stored["submissions"].as_array().unwrap().iter().all(|submission| submission["status"] == "sent"));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(webhook.received_requests().await.unwrap().len(), 2);
+}
@@ -0,0 +1,44 @@
+//! Refusing to start with settings that don't make sense, rather than panicking on first use
+
+use mailgun_contact_form::{ContactFormService, MemoryProvider};
+
+#[tokio::test]
+async fn reports_invalid_settings_at_startup() {
+    std::env::set_var("MAILGUN_TO_ADDRESS", "owner@example.com");
+    let invalid = [
+        ("ADMIN_TOKENS", "[\"not-an-object\"]", "\"ADMIN_TOKENS\" must be a JSON object"),
+        ("CSRF_TOKEN_TTL_SECS", "an hour", "\"CSRF_TOKEN_TTL_SECS\" must be a number of seconds, not an hour"),
+        ("POW_DIFFICULTY", "hard", "\"POW_DIFFICULTY\" must be a number of bits"),
+        ("SENDER_RATE_LIMIT", "-1", "\"SENDER_RATE_LIMIT\" must be a whole number"),
+        ("CONFIRMATION_TTL_SECS", "1d", "\"CONFIRMATION_TTL_SECS\" must be a number of seconds"),
+        ("RETENTION_DAYS", "forever", "\"RETENTION_DAYS\" must be a number of days"),
+        ("RETENTION_DAYS", "0", "\"RETENTION_DAYS\" must be at least 1"),
+        ("SPAM_THRESHOLD", "1.5", "\"SPAM_THRESHOLD\" must be between 0 and 1"),
+        ("SPAM_MIN_TRAINING", "lots", "\"SPAM_MIN_TRAINING\" must be a whole number"),
+        ("RECIPIENTS", "sales@example.com", "\"RECIPIENTS\" must be a JSON object"),
+        ("TRUSTED_PROXIES", "10.0.0.0/8,localhost", "\"TRUSTED_PROXIES\" has an invalid entry: localhost"),
+        ("CLIENT_IP_HEADER", "x-real-ip", "\"CLIENT_IP_HEADER\" must be `forwarded` or `x-forwarded-for`, not x-real-ip"),
+        ("REDIRECT_ALLOWLIST", "https://example.com/thanks,/thanks", "\"REDIRECT_ALLOWLIST\" has an invalid URL /thanks"),
+        ("RESPONSE_STATUS_VALUES", "[\"ok\"]", "\"RESPONSE_STATUS_VALUES\" must be a JSON object"),
+        ("RESPONSE_EXTRA_FIELDS", "{\"source\": ", "\"RESPONSE_EXTRA_FIELDS\" must be a JSON object"),
+        ("DIGEST_INTERVAL", "weekly", "\"DIGEST_INTERVAL\" must be `hourly`, `daily` or a number of seconds"),
+    ];
+    for (name, value, expected) in invalid {
+        std::env::set_var(name, value);
+        let error = ContactFormService::builder().provider(MemoryProvider::new()).build().await.err()
+            .unwrap_or_else(|| panic!("{}={} was accepted", name, value));
+        assert!(error.to_string().starts_with(expected), "{}={}: {}", name, value, error);
+        std::env::remove_var(name);
+    }
+
+    // Encrypted with a key that isn't configured
+    let path = std::env::temp_dir().join(format!("contact-form-unreadable-{}.json", std::process::id()));
+    std::fs::write(&path, r#"{"algorithm": "AES-256-GCM", "key_id": "retired", "data_key": "00", "data": "00"}"#).unwrap();
+    std::env::set_var("SUBMISSIONS_FILE", &path);
+    let error = ContactFormService::builder().provider(MemoryProvider::new()).build().await.err().expect("an unreadable SUBMISSIONS_FILE was accepted");
+    assert!(error.to_string().starts_with("Unable to decrypt submissions file"), "{}", error);
+    std::env::remove_var("SUBMISSIONS_FILE");
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(ContactFormService::builder().provider(MemoryProvider::new()).build().await.is_ok());
+}
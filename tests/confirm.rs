@@ -0,0 +1,70 @@
+//! Double opt-in: emailing the submitter a confirmation link, and only sending the submission once
+//! it's been followed
+
+mod common;
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use mailgun_contact_form::ContactFormService;
+use common::{VALID_FORM, call, mailbox, post_form};
+
+#[tokio::test]
+async fn sends_submissions_once_confirmed() {
+    std::env::set_var("MAILGUN_TO_ADDRESS", "owner@example.com");
+    std::env::set_var("MAIL_PROVIDER", "memory");
+    std::env::set_var("DEV_MODE", "true");
+    std::env::set_var("PUBLIC_URL", "https://contact.example.com");
+    std::env::set_var("DOUBLE_OPT_IN", "true");
+    std::env::set_var("CONFIRMATION_FROM_ADDRESS", "noreply@example.com");
+    let app = ContactFormService::builder().build().await.unwrap().router();
+
+    let request = post_form(VALID_FORM);
+    let (status, _) = call(&app, request).await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    let sent = mailbox(&app).await;
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0]["to"], "Jo Bloggs <jo@example.com>");
+    let text = sent[0]["text"].as_str().unwrap();
+    let link = text.lines()
+        .find_map(|line| line.strip_prefix("https://contact.example.com"))
+        .expect("the confirmation email should contain a link")
+        .to_string();
+    assert!(link.starts_with("/confirm/"));
+
+    let (status, _) = call(&app, Request::get(link.as_str()).body(Body::empty()).unwrap()).await;
+    assert_eq!(status, StatusCode::OK);
+    let sent = mailbox(&app).await;
+    assert_eq!(sent.len(), 2);
+    // Newest first
+    assert_eq!(sent[0]["to"], "owner@example.com");
+
+    // Links only work once
+    let (status, _) = call(&app, Request::get(link.as_str()).body(Body::empty()).unwrap()).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(mailbox(&app).await.len(), 2);
+}
+
+#[tokio::test]
+async fn only_sends_confirmations_to_the_submitter() {
+    std::env::set_var("MAILGUN_TO_ADDRESS", "owner@example.com");
+    std::env::set_var("MAIL_PROVIDER", "memory");
+    std::env::set_var("DEV_MODE", "true");
+    std::env::set_var("PUBLIC_URL", "https://contact.example.com");
+    std::env::set_var("DOUBLE_OPT_IN", "true");
+    std::env::set_var("CONFIRMATION_FROM_ADDRESS", "noreply@example.com");
+    let app = ContactFormService::builder().build().await.unwrap().router();
+
+    for from_email in ["jo%40example.com%2Cvictim%40example.org", "jo%40example.com%3E%3B+%3Cvictim%40example.org", "jo%40example.com%0D%0ABcc%3A+victim%40example.org"] {
+        let form = format!("from_name=Jo+Bloggs&from_email={}&title=Hello&body=Hi", from_email);
+        let (status, body) = call(&app, post_form(form)).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["errors"][0]["field"], "from_email");
+    }
+    assert!(mailbox(&app).await.is_empty());
+
+    // Names can't add recipients either
+    let form = "from_name=victim%40example.org%2C+Jo+%22JB%22&from_email=jo%40example.com&title=Hello&body=Hi";
+    let (status, _) = call(&app, post_form(form)).await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    assert_eq!(mailbox(&app).await[0]["to"], r#""victim@example.org, Jo \"JB\"" <jo@example.com>"#);
+}
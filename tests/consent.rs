@@ -0,0 +1,37 @@
+//! Only accepting submissions that consented, and recording what they consented to
+
+mod common;
+
+use axum::http::StatusCode;
+use chrono::{DateTime, Utc};
+use mailgun_contact_form::{ContactFormService, MemoryProvider};
+use common::{ADMIN_TOKEN, VALID_FORM, admin, call, post_form};
+
+#[tokio::test]
+async fn requires_and_records_consent() {
+    std::env::set_var("MAILGUN_TO_ADDRESS", "owner@example.com");
+    std::env::set_var("ADMIN_TOKEN", ADMIN_TOKEN);
+    std::env::set_var("RESPONSE_ID_FIELD", "submission_id");
+    std::env::set_var("FORM_SIGNUP_REQUIRE_CONSENT", "true");
+    std::env::set_var("FORM_SIGNUP_CONSENT_TEXT_VERSION", "privacy-2024-01");
+    let app = ContactFormService::builder().provider(MemoryProvider::new()).build().await.unwrap().router();
+
+    for form in [format!("_form=signup&{}", VALID_FORM), format!("_form=signup&consent=off&{}", VALID_FORM)] {
+        let (status, body) = call(&app, post_form(form)).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["errors"][0]["field"], "consent");
+        assert_eq!(body["errors"][0]["problem"], "consent_required");
+    }
+    // Other forms don't need it
+    let (status, _) = call(&app, post_form(VALID_FORM)).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let before = Utc::now();
+    let (status, body) = call(&app, post_form(format!("_form=signup&consent=on&{}", VALID_FORM))).await;
+    assert_eq!(status, StatusCode::OK);
+    let id = body["submission_id"].as_str().unwrap();
+    let (_, stored) = call(&app, admin("GET", &format!("/admin/submissions/{}", id))).await;
+    assert_eq!(stored["consent"]["text_version"], "privacy-2024-01");
+    let given_at: DateTime<Utc> = stored["consent"]["given_at"].as_str().unwrap().parse().unwrap();
+    assert!(given_at >= before && given_at <= Utc::now());
+}
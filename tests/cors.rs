@@ -0,0 +1,28 @@
+//! Letting browser-based admin UIs on other origins use the admin API
+
+use axum::body::Body;
+use axum::http::{header, Request};
+use mailgun_contact_form::{ContactFormService, MemoryProvider};
+use tower::ServiceExt;
+
+#[tokio::test]
+async fn allows_admin_requests_from_other_origins() {
+    std::env::set_var("MAILGUN_TO_ADDRESS", "owner@example.com");
+    std::env::set_var("ADMIN_TOKEN", "admin-token");
+    let app = ContactFormService::builder().provider(MemoryProvider::new()).build().await.unwrap().router();
+
+    for method in ["PUT", "DELETE"] {
+        let request = Request::options("/admin/suppressions/jo@example.com")
+            .header(header::ORIGIN, "https://admin.example.com")
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, method)
+            .header(header::ACCESS_CONTROL_REQUEST_HEADERS, "authorization,content-type")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        let headers = response.headers();
+        let methods = headers.get(header::ACCESS_CONTROL_ALLOW_METHODS).unwrap().to_str().unwrap();
+        assert!(methods.contains(method), "{} not allowed: {}", method, methods);
+        let allowed = headers.get(header::ACCESS_CONTROL_ALLOW_HEADERS).unwrap().to_str().unwrap();
+        assert!(allowed.contains("content-type"), "Content-Type not allowed: {}", allowed);
+    }
+}
@@ -0,0 +1,53 @@
+//! Requiring a single-use token from `GET /token` with each submission
+
+mod common;
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::Router;
+use mailgun_contact_form::ContactFormService;
+use common::{VALID_FORM, call, mailbox, post_form};
+
+async fn token(app: &Router) -> String {
+    let (status, body) = call(app, Request::get("/token").body(Body::empty()).unwrap()).await;
+    assert_eq!(status, StatusCode::OK);
+    body["token"].as_str().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn only_uses_up_tokens_on_submissions_that_get_through() {
+    std::env::set_var("MAILGUN_TO_ADDRESS", "owner@example.com");
+    std::env::set_var("MAIL_PROVIDER", "memory");
+    std::env::set_var("DEV_MODE", "true");
+    std::env::set_var("CSRF_SECRET", "csrf-secret");
+    std::env::set_var("SIGNING_SECRET", "signing-secret");
+    std::env::set_var("SENDER_RATE_LIMIT", "1");
+    let app = ContactFormService::builder().build().await.unwrap().router();
+
+    let (status, body) = call(&app, post_form(VALID_FORM)).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["status"], "InvalidToken");
+    let (status, body) = call(&app, post_form(format!("{}&_token=1.2.3", VALID_FORM))).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["status"], "InvalidToken");
+
+    // Turned away for a missing signature, so the token can be used again once it's fixed
+    let first = token(&app).await;
+    let (status, body) = call(&app, post_form(format!("{}&_form=sales&_token={}", VALID_FORM, first))).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["status"], "InvalidSignature");
+    let (status, _) = call(&app, post_form(format!("{}&_token={}", VALID_FORM, first))).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, body) = call(&app, post_form(format!("{}&_token={}", VALID_FORM, first))).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["status"], "InvalidToken");
+
+    // And the same for being rate limited
+    let second = token(&app).await;
+    let (status, _) = call(&app, post_form(format!("{}&_token={}", VALID_FORM, second))).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    let form = format!("from_name=Sam&from_email=sam%40example.com&title=Hello&body=Hi&_token={}", second);
+    let (status, _) = call(&app, post_form(form)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(mailbox(&app).await.len(), 2);
+}
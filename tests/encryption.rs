@@ -0,0 +1,51 @@
+//! Encrypting the submissions file, including one that was written before encryption was turned on
+
+mod common;
+
+use axum::body::Body;
+use axum::http::{header, Request, StatusCode};
+use mailgun_contact_form::ContactFormService;
+use serde_json::{json, Value};
+use common::{ADMIN_TOKEN, call, post_form};
+
+const VALID_FORM: &str = "from_name=Jo+Bloggs&from_email=jo%40example.com&title=Hello&body=My+phone+number+is+555-0100";
+
+#[tokio::test]
+async fn encrypts_the_submissions_file() {
+    let path = std::env::temp_dir().join(format!("contact-form-encrypted-{}.json", std::process::id()));
+    let earlier = json!([{
+        "id": "2b1f6a52-0c56-4b7e-a5f6-7e0f6ad0f3c1",
+        "received_at": "2026-10-01T09:00:00Z",
+        "from_name": "Sam",
+        "from_email": "sam@example.com",
+        "title": "Earlier",
+        "body": "Written before encryption was turned on",
+        "status": "sent",
+        "status_message": null,
+    }]);
+    std::fs::write(&path, earlier.to_string()).unwrap();
+    std::env::set_var("MAILGUN_TO_ADDRESS", "owner@example.com");
+    std::env::set_var("MAIL_PROVIDER", "memory");
+    std::env::set_var("SUBMISSIONS_FILE", &path);
+    std::env::set_var("ADMIN_TOKEN", ADMIN_TOKEN);
+    std::env::set_var("ENCRYPTION_KEY", "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f");
+    let app = ContactFormService::builder().build().await.unwrap().router();
+
+    let request = post_form(VALID_FORM);
+    assert_eq!(call(&app, request).await.0, StatusCode::OK);
+
+    let contents = std::fs::read_to_string(&path).unwrap();
+    assert!(!contents.contains("555-0100"));
+    assert!(!contents.contains("Written before encryption"));
+    let envelope: Value = serde_json::from_str(&contents).unwrap();
+    assert_eq!(envelope["algorithm"], "AES-256-GCM");
+
+    let request = Request::get("/admin/submissions")
+        .header(header::AUTHORIZATION, format!("Bearer {}", ADMIN_TOKEN))
+        .body(Body::empty())
+        .unwrap();
+    let (_, list) = call(&app, request).await;
+    assert_eq!(list["total"], 2);
+
+    std::fs::remove_file(&path).unwrap();
+}
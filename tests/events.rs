@@ -0,0 +1,44 @@
+//! Watching submissions arrive through the admin API's live event stream
+
+mod common;
+
+use std::time::Duration;
+use axum::body::{Body, HttpBody};
+use axum::http::{header, Request, StatusCode};
+use mailgun_contact_form::{ContactFormService, MemoryProvider};
+use tower::ServiceExt;
+use common::{ADMIN_TOKEN, VALID_FORM, post_form};
+
+#[tokio::test]
+async fn streams_submissions_as_they_happen() {
+    std::env::set_var("MAILGUN_TO_ADDRESS", "owner@example.com");
+    std::env::set_var("ADMIN_TOKEN", ADMIN_TOKEN);
+    let app = ContactFormService::builder().provider(MemoryProvider::new()).build().await.unwrap().router();
+
+    let request = Request::get("/admin/events")
+        .header(header::AUTHORIZATION, format!("Bearer {}", ADMIN_TOKEN))
+        // Events must arrive as they happen, not once there are enough to compress
+        .header(header::ACCEPT_ENCODING, "gzip")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "text/event-stream");
+    assert!(response.headers().get(header::CONTENT_ENCODING).is_none());
+    let mut events = response.into_body();
+
+    let request = post_form(VALID_FORM);
+    assert_eq!(app.oneshot(request).await.unwrap().status(), StatusCode::OK);
+
+    let mut received = String::new();
+    while !received.contains("event:status_changed") {
+        let chunk = tokio::time::timeout(Duration::from_secs(5), events.data()).await
+            .expect("the stream should send an event for each change")
+            .unwrap()
+            .unwrap();
+        received.push_str(std::str::from_utf8(&chunk).unwrap());
+    }
+    assert!(received.contains("event:received\ndata:{\"type\":\"received\",\"submission\":{"));
+    assert!(received.contains("\"from_email\":\"jo@example.com\""));
+    assert!(received.contains("\"status\":\"sent\""));
+}
@@ -0,0 +1,34 @@
+//! Accepting submissions from a form built for another service, with its own field names
+
+mod common;
+
+use axum::Router;
+use axum::http::StatusCode;
+use mailgun_contact_form::ContactFormService;
+use serde_json::Value;
+use common::{call, mailbox, post_form};
+
+async fn submit(app: &Router, form: &str) -> (StatusCode, Value) {
+    let request = post_form(format!("_form=legacy&{}", form));
+    call(app, request).await
+}
+
+#[tokio::test]
+async fn renames_the_forms_fields() {
+    std::env::set_var("MAILGUN_TO_ADDRESS", "owner@example.com");
+    std::env::set_var("MAIL_PROVIDER", "memory");
+    std::env::set_var("DEV_MODE", "true");
+    std::env::set_var("FORM_LEGACY_FIELD_MAPPING", r#"{"name": "from_name", "email": "from_email", "_subject": "title", "message": "body"}"#);
+    let app = ContactFormService::builder().build().await.unwrap().router();
+
+    let (status, _) = submit(&app, "name=Jo+Bloggs&email=jo%40example.com&_subject=Hello&message=Is+this+thing+on%3F").await;
+    assert_eq!(status, StatusCode::OK);
+    let sent = mailbox(&app).await;
+    assert_eq!(sent[0]["subject"], "Hello");
+    assert!(sent[0]["text"].as_str().unwrap().starts_with("Is this thing on?"));
+
+    // Reported under the names the frontend knows them by
+    let (status, body) = submit(&app, "name=Jo+Bloggs&email=not-an-address&_subject=Hello&message=Hi").await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["errors"][0]["field"], "email");
+}
@@ -0,0 +1,394 @@
+//! Plays the form endpoint against a fake Mailgun, to check how each kind of response from it is
+//! reported back to the submitter
+
+mod common;
+
+use std::sync::Once;
+use std::time::Duration;
+use axum::Router;
+use axum::body::Body;
+use axum::http::{header, Request, StatusCode};
+use mailgun_contact_form::{ContactFormService, MailgunProvider};
+use mailgun_contact_form::mailgun_api::ApiVersion;
+use serde_json::{json, Value};
+use tower::ServiceExt;
+use wiremock::matchers::{body_string_contains, header_exists, header_regex, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+use common::{VALID_FORM, post_form};
+
+const DOMAIN: &str = "mg.example.com";
+const MESSAGES_PATH: &str = "/v3/mg.example.com/messages";
+
+static ENV: Once = Once::new();
+
+/// Everything other than the provider comes from the environment, which is shared by every test
+fn configure_env() {
+    ENV.call_once(|| {
+        std::env::set_var("MAILGUN_TO_ADDRESS", "owner@example.com");
+        std::env::set_var("TIMEZONE", "+10:00");
+    });
+}
+
+async fn app(mailgun: &MockServer) -> Router {
+    configure_env();
+    let client = reqwest::Client::builder().timeout(Duration::from_millis(500)).build().unwrap();
+    let provider = MailgunProvider::new("key-0123456789", DOMAIN)
+        .base_url(mailgun.uri())
+        .client(client);
+    ContactFormService::builder().provider(provider).build().await.unwrap().router()
+}
+
+async fn submit(app: Router, form: &str) -> (StatusCode, Value) {
+    let request = post_form(form);
+    let response = app.oneshot(request).await.unwrap();
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, serde_json::from_slice(&body).unwrap())
+}
+
+#[tokio::test]
+async fn sends_the_submission() {
+    let mailgun = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path(MESSAGES_PATH))
+        .and(header_exists("authorization"))
+        .and(body_string_contains("from=Jo+Bloggs+%3Cjo%40example.com%3E"))
+        .and(body_string_contains("to=owner%40example.com"))
+        .and(body_string_contains("subject=Hello"))
+        .and(body_string_contains("h%3AMessage-Id=%3Csubmission-"))
+        .and(body_string_contains("h%3AReferences=%3Cthread-"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "id": "<1@mg.example.com>", "message": "Queued. Thank you." })))
+        .expect(1)
+        .mount(&mailgun)
+        .await;
+
+    let (status, body) = submit(app(&mailgun).await, VALID_FORM).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, json!({ "status": "Ok", "message": null }));
+}
+
+#[tokio::test]
+async fn appends_extra_fields_to_the_body() {
+    let mailgun = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path(MESSAGES_PATH))
+        .and(body_string_contains("text=Is+this+thing+on%3F%0A%0ABudget+range%3A+%3C+%2410k%0APhone%3A+021+123+4567%0A"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "id": "<1@mg.example.com>", "message": "Queued. Thank you." })))
+        .expect(1)
+        .mount(&mailgun)
+        .await;
+
+    let form = format!("{}&phone=021+123+4567&budget_range=%3C+%2410k&_token=&company=", VALID_FORM);
+    let (status, _) = submit(app(&mailgun).await, &form).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn adds_request_metadata_as_headers() {
+    std::env::set_var("FORM_METADATA_EMAIL_METADATA", "user_agent,page_url");
+    std::env::set_var("FORM_METADATA_EMAIL_METADATA_AS", "headers");
+    let mailgun = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path(MESSAGES_PATH))
+        .and(body_string_contains("h%3AX-Contact-Form-User-Agent=Tests%2F1.0"))
+        .and(body_string_contains("h%3AX-Contact-Form-Page-URL=https%3A%2F%2Fexample.com%2Fcontact"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "id": "<1@mg.example.com>", "message": "Queued. Thank you." })))
+        .expect(1)
+        .mount(&mailgun)
+        .await;
+
+    let request = Request::post("/")
+        .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .header(header::USER_AGENT, "Tests/1.0")
+        .header(header::REFERER, "https://example.com/contact")
+        .body(Body::from(format!("{}&_form=metadata", VALID_FORM)))
+        .unwrap();
+    let response = app(&mailgun).await.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn only_emits_the_normalised_page_url() {
+    std::env::set_var("FORM_PAGE_EMAIL_METADATA", "page_url");
+    std::env::set_var("FORM_PAGE_EMAIL_METADATA_AS", "headers");
+    let mailgun = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path(MESSAGES_PATH))
+        .and(body_string_contains("h%3AX-Contact-Form-Page-URL=https%3A%2F%2Fexample.com%2Fcontact"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "id": "<1@mg.example.com>", "message": "Queued. Thank you." })))
+        .expect(1)
+        .mount(&mailgun)
+        .await;
+
+    let form = format!("{}&_form=page&page_url=https%3A%2F%2Fexample.com%2Fcon%0D%0A%09tact", VALID_FORM);
+    let (status, _) = submit(app(&mailgun).await, &form).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn adds_configured_headers() {
+    std::env::set_var("FORM_CAMPAIGN_EMAIL_HEADERS", r#"{"X-Campaign": "spring-sale", "X-Priority": "1"}"#);
+    let mailgun = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path(MESSAGES_PATH))
+        .and(body_string_contains("h%3AX-Campaign=spring-sale"))
+        .and(body_string_contains("h%3AX-Priority=1"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "id": "<1@mg.example.com>", "message": "Queued. Thank you." })))
+        .expect(1)
+        .mount(&mailgun)
+        .await;
+
+    let (status, _) = submit(app(&mailgun).await, &format!("{}&_form=campaign", VALID_FORM)).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn attaches_a_vcard_of_the_submitter() {
+    std::env::set_var("FORM_VCARD_ATTACH_VCARD", "true");
+    let mailgun = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path(MESSAGES_PATH))
+        .and(header_regex("content-type", "^multipart/form-data"))
+        .and(body_string_contains("Jo Bloggs <jo@example.com>"))
+        .and(body_string_contains(r#"name="attachment"; filename="jo-bloggs.vcf""#))
+        .and(body_string_contains("FN:Jo Bloggs\r\nN:Bloggs;Jo;;;\r\nEMAIL;TYPE=INTERNET:jo@example.com\r\n"))
+        .and(body_string_contains("TEL;TYPE=VOICE:021 123 4567\r\nORG:Bloggs\\, Bloggs & Co\r\n"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "id": "<1@mg.example.com>", "message": "Queued. Thank you." })))
+        .expect(1)
+        .mount(&mailgun)
+        .await;
+
+    let form = format!("_form=vcard&{}&phone=021+123+4567&company=Bloggs%2C+Bloggs+%26+Co", VALID_FORM);
+    let (status, _) = submit(app(&mailgun).await, &form).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn shows_timestamps_in_the_configured_timezone() {
+    std::env::set_var("FORM_TIMESTAMPS_EMAIL_METADATA", "received_at");
+    let mailgun = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path(MESSAGES_PATH))
+        .and(body_string_contains("Received%3A+"))
+        .and(body_string_contains("%2B1000"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "id": "<1@mg.example.com>", "message": "Queued. Thank you." })))
+        .expect(1)
+        .mount(&mailgun)
+        .await;
+
+    let (status, _) = submit(app(&mailgun).await, &format!("{}&_form=timestamps", VALID_FORM)).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn routes_and_tags_by_language() {
+    std::env::set_var("FORM_MULTILINGUAL_LANGUAGE_ROUTES", r#"{"fr": "equipe@example.fr"}"#);
+    std::env::set_var("FORM_MULTILINGUAL_LANGUAGE_SUBJECT_TAG", "true");
+    let mailgun = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path(MESSAGES_PATH))
+        .and(body_string_contains("to=equipe%40example.fr"))
+        .and(body_string_contains("subject=%5BFR%5D+Bonjour"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "id": "<1@mg.example.com>", "message": "Queued. Thank you." })))
+        .expect(1)
+        .mount(&mailgun)
+        .await;
+    Mock::given(method("POST"))
+        .and(path(MESSAGES_PATH))
+        .and(body_string_contains("to=owner%40example.com"))
+        .and(body_string_contains("subject=%5BEN%5D+Hello"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "id": "<2@mg.example.com>", "message": "Queued. Thank you." })))
+        .expect(1)
+        .mount(&mailgun)
+        .await;
+
+    let french = "_form=multilingual&from_name=Jo&from_email=jo%40example.com&title=Bonjour&body=Je+voudrais+savoir+si+vous+livrez+en+France%2C+merci";
+    let (status, _) = submit(app(&mailgun).await, french).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = submit(app(&mailgun).await, &format!("_form=multilingual&{}", VALID_FORM)).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn routes_by_page_and_rejects_pages_from_elsewhere() {
+    std::env::set_var("FORM_CATALOGUE_PAGE_URL_ORIGINS", "https://shop.example.com");
+    std::env::set_var("FORM_CATALOGUE_PAGE_ROUTES", r#"{"https://shop.example.com/bikes/": "bikes@example.com", "https://shop.example.com/bikes/e-bikes/": "e-bikes@example.com"}"#);
+    let mailgun = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path(MESSAGES_PATH))
+        .and(body_string_contains("to=e-bikes%40example.com"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "id": "<1@mg.example.com>", "message": "Queued. Thank you." })))
+        .expect(1)
+        .mount(&mailgun)
+        .await;
+
+    let page = "page_url=https%3A%2F%2Fshop.example.com%2Fbikes%2Fe-bikes%2Fcommuter";
+    let (status, _) = submit(app(&mailgun).await, &format!("_form=catalogue&{}&{}", page, VALID_FORM)).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let elsewhere = "page_url=https%3A%2F%2Fevil.example.com%2Fbikes%2F";
+    let (status, body) = submit(app(&mailgun).await, &format!("_form=catalogue&{}&{}", elsewhere, VALID_FORM)).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["errors"][0]["field"], "page_url");
+    assert_eq!(body["errors"][0]["problem"], "invalid_page_url");
+}
+
+#[tokio::test]
+async fn checks_the_domain_at_startup() {
+    let mailgun = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/v3/domains/mg.example.com"))
+        .and(header_exists("authorization"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "domain": { "name": DOMAIN, "state": "unverified", "is_disabled": false },
+            "sending_dns_records": [{ "record_type": "TXT", "name": DOMAIN, "value": "v=spf1 include:mailgun.org ~all", "valid": "unknown" }],
+        })))
+        .expect(1)
+        .mount(&mailgun)
+        .await;
+
+    // Problems are only logged, as they may be fixed before the first submission
+    let _ = app(&mailgun).await;
+}
+
+#[tokio::test]
+async fn checks_the_domain_with_the_configured_api_version() {
+    configure_env();
+    let mailgun = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/v4/domains/mg.example.com"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "domain": { "name": DOMAIN, "state": "active", "is_disabled": false },
+        })))
+        .expect(1)
+        .mount(&mailgun)
+        .await;
+
+    let provider = MailgunProvider::new("key-0123456789", DOMAIN)
+        .base_url(mailgun.uri())
+        .version(ApiVersion::V4);
+    ContactFormService::builder().provider(provider).build().await.unwrap();
+}
+
+#[tokio::test]
+async fn reports_rejected_credentials() {
+    let mailgun = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path(MESSAGES_PATH))
+        .respond_with(ResponseTemplate::new(401).set_body_string("Forbidden"))
+        .mount(&mailgun)
+        .await;
+
+    let (status, body) = submit(app(&mailgun).await, VALID_FORM).await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(body["status"], "MailAgentError");
+}
+
+#[tokio::test]
+async fn reports_mailgun_errors() {
+    let mailgun = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path(MESSAGES_PATH))
+        .respond_with(ResponseTemplate::new(503).set_body_json(json!({ "message": "Service unavailable" })))
+        .mount(&mailgun)
+        .await;
+
+    let (status, body) = submit(app(&mailgun).await, VALID_FORM).await;
+    assert_eq!(status, StatusCode::BAD_GATEWAY);
+    assert_eq!(body["status"], "MailAgentError");
+}
+
+#[tokio::test]
+async fn reports_errors_in_other_shapes() {
+    let mailgun = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path(MESSAGES_PATH))
+        .respond_with(ResponseTemplate::new(400).set_body_json(json!({ "Error": "to parameter is not a valid address" })))
+        .mount(&mailgun)
+        .await;
+
+    let (status, body) = submit(app(&mailgun).await, VALID_FORM).await;
+    assert_eq!(status, StatusCode::BAD_GATEWAY);
+    assert_eq!(body["status"], "MailAgentError");
+}
+
+#[tokio::test]
+async fn reports_errors_with_more_than_one_message() {
+    let mailgun = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path(MESSAGES_PATH))
+        .respond_with(ResponseTemplate::new(400).set_body_json(json!({ "message": "'to' parameter is missing", "error": "invalid request" })))
+        .mount(&mailgun)
+        .await;
+
+    let (status, body) = submit(app(&mailgun).await, VALID_FORM).await;
+    assert_eq!(status, StatusCode::BAD_GATEWAY);
+    assert_eq!(body["status"], "MailAgentError");
+}
+
+#[tokio::test]
+async fn reports_timeouts() {
+    let mailgun = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path(MESSAGES_PATH))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(5)))
+        .mount(&mailgun)
+        .await;
+
+    let (status, body) = submit(app(&mailgun).await, VALID_FORM).await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(body["status"], "InternalError");
+}
+
+#[tokio::test]
+async fn reports_malformed_responses() {
+    let mailgun = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path(MESSAGES_PATH))
+        .respond_with(ResponseTemplate::new(400).set_body_string("<html>Bad Request</html>"))
+        .mount(&mailgun)
+        .await;
+
+    let (status, body) = submit(app(&mailgun).await, VALID_FORM).await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(body["status"], "InternalError");
+}
+
+#[tokio::test]
+async fn rejects_invalid_submissions_without_sending() {
+    let mailgun = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&mailgun)
+        .await;
+
+    let (status, body) = submit(app(&mailgun).await, "from_name=Jo&from_email=not-an-email&title=&body=Hi").await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["status"], "ValidationError");
+    let fields: Vec<&str> = body["errors"].as_array().unwrap().iter().map(|error| error["field"].as_str().unwrap()).collect();
+    assert_eq!(fields, ["title", "from_email"]);
+}
+
+#[tokio::test]
+async fn retries_when_rate_limited() {
+    let mailgun = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path(MESSAGES_PATH))
+        .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "1"))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&mailgun)
+        .await;
+    Mock::given(method("POST"))
+        .and(path(MESSAGES_PATH))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "id": "<1@mg.example.com>", "message": "Queued. Thank you." })))
+        .expect(1)
+        .mount(&mailgun)
+        .await;
+
+    let (status, body) = submit(app(&mailgun).await, VALID_FORM).await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    assert_eq!(body["status"], "Ok");
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+}
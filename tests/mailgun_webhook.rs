@@ -0,0 +1,86 @@
+//! Delivery events from Mailgun, recorded against the submissions they're about
+
+mod common;
+
+use axum::Router;
+use axum::body::Body;
+use axum::http::{header, Request, StatusCode};
+use hmac::{Hmac, Mac};
+use mailgun_contact_form::{ContactFormService, MemoryProvider};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use common::{ADMIN_TOKEN, admin, call, post_form};
+
+const SIGNING_KEY: &str = "webhook-signing-key";
+
+async fn app() -> Router {
+    std::env::set_var("MAILGUN_TO_ADDRESS", "owner@example.com");
+    std::env::set_var("MAILGUN_WEBHOOK_SIGNING_KEY", SIGNING_KEY);
+    std::env::set_var("ADMIN_TOKEN", ADMIN_TOKEN);
+    std::env::set_var("RESPONSE_ID_FIELD", "submission_id");
+    ContactFormService::builder().provider(MemoryProvider::new()).build().await.unwrap().router()
+}
+
+fn signature(key: &str, timestamp: i64, token: &str) -> Value {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes()).unwrap();
+    mac.update(format!("{}{}", timestamp, token).as_bytes());
+    json!({ "timestamp": timestamp.to_string(), "token": token, "signature": hex::encode(mac.finalize().into_bytes()) })
+}
+
+fn event(signature: Value, event_data: Value) -> Request<Body> {
+    Request::post("/webhooks/mailgun")
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json!({ "signature": signature, "event-data": event_data }).to_string()))
+        .unwrap()
+}
+
+async fn submission(app: &Router, id: &str) -> Value {
+    call(app, admin("GET", &format!("/admin/submissions/{}", id))).await.1
+}
+
+fn form() -> Request<Body> {
+    post_form("from_name=Jo&from_email=jo%40example.com&title=Hello&body=Hi")
+}
+
+#[tokio::test]
+async fn records_delivery_events() {
+    let app = app().await;
+    let (status, body) = call(&app, form()).await;
+    assert_eq!(status, StatusCode::OK);
+    let id = body["submission_id"].as_str().unwrap().to_string();
+    let now = chrono::Utc::now().timestamp();
+
+    let bounced = json!({
+        "event": "failed",
+        "severity": "permanent",
+        "recipient": "owner@example.com",
+        "user-variables": { "submission-ids": id },
+        "delivery-status": { "message": "", "description": "No such mailbox" },
+    });
+    let signed = signature(SIGNING_KEY, now, "a8ce0edb2dd8301dee6c2405235584e45aa91d1e9f979f3de0");
+    let (status, _) = call(&app, event(signed.clone(), bounced)).await;
+    assert_eq!(status, StatusCode::OK);
+    let stored = submission(&app, &id).await;
+    assert_eq!(stored["status"], "bounced");
+    assert_eq!(stored["status_message"], "No such mailbox");
+
+    let forged = json!({ "event": "delivered", "user-variables": { "submission-ids": id } });
+    let (status, _) = call(&app, event(signature("some other key", now, "5d2c0f7a9e1b4c3d8a6f2e0b1c9d7a3e5f4b2c1d0e9f8a7b6c"), forged.clone())).await;
+    assert_eq!(status, StatusCode::NOT_ACCEPTABLE);
+    let (status, _) = call(&app, event(signature(SIGNING_KEY, now - 3600, "0b9a8c7d6e5f4a3b2c1d0e9f8a7b6c5d4e3f2a1b0c9d8e7f6a"), forged.clone())).await;
+    assert_eq!(status, StatusCode::NOT_ACCEPTABLE);
+    // Nor can a genuine event be sent again with something else in it
+    let (status, _) = call(&app, event(signed, forged)).await;
+    assert_eq!(status, StatusCode::NOT_ACCEPTABLE);
+    assert_eq!(submission(&app, &id).await["status"], "bounced");
+
+    // Bounced recipients go on the suppression list, but that only stops mail to visitors - the
+    // configured recipients are still sent submissions
+    let (_, suppressions) = call(&app, admin("GET", "/admin/suppressions")).await;
+    assert_eq!(suppressions[0]["address"], "owner@example.com");
+    assert_eq!(suppressions[0]["reason"], "bounced");
+    let (status, _) = call(&app, form()).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = call(&app, admin("DELETE", "/admin/suppressions/Owner%40Example.com")).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+}
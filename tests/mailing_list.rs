@@ -0,0 +1,42 @@
+//! Signing submitters up to a Mailgun mailing list, instead of sending their submissions on
+
+mod common;
+
+use axum::http::StatusCode;
+use mailgun_contact_form::ContactFormService;
+use serde_json::json;
+use wiremock::matchers::{body_string_contains, header_exists, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+use common::{call, mailbox, post_form};
+
+const SIGNUP_FORM: &str = "_form=newsletter&from_name=Jo+Bloggs&from_email=jo%40example.com&title=Signup&body=Sign+me+up";
+
+#[tokio::test]
+async fn adds_the_submitter_to_the_list() {
+    let mailgun = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/v3/lists/news@mg.example.com/members"))
+        .and(header_exists("authorization"))
+        .and(body_string_contains("address=jo%40example.com"))
+        .and(body_string_contains("name=Jo+Bloggs"))
+        .and(body_string_contains("upsert=yes"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "message": "Mailing list member has been created" })))
+        .expect(1)
+        .mount(&mailgun)
+        .await;
+    std::env::set_var("MAILGUN_TO_ADDRESS", "owner@example.com");
+    std::env::set_var("MAIL_PROVIDER", "memory");
+    std::env::set_var("DEV_MODE", "true");
+    std::env::set_var("MAILGUN_API_KEY", "key-0123456789");
+    std::env::set_var("MAILGUN_API_BASE_URL", mailgun.uri());
+    std::env::set_var("FORM_NEWSLETTER_MAILING_LIST", "news@mg.example.com");
+    std::env::set_var("FORM_NEWSLETTER_MAILING_LIST_ONLY", "true");
+    let app = ContactFormService::builder().build().await.unwrap().router();
+
+    let request = post_form(SIGNUP_FORM);
+    let (status, _) = call(&app, request).await;
+    assert_eq!(status, StatusCode::OK);
+    // Only signed up, not sent on
+    let sent = mailbox(&app).await;
+    assert_eq!(sent.len(), 0);
+}
@@ -0,0 +1,40 @@
+//! Holding submissions during maintenance, and sending them once it's over
+
+mod common;
+
+use std::time::Duration;
+use axum::http::StatusCode;
+use mailgun_contact_form::ContactFormService;
+use common::{ADMIN_TOKEN, VALID_FORM, admin, call, mailbox, post_form};
+
+#[tokio::test]
+async fn sends_held_submissions_once_over() {
+    std::env::set_var("MAILGUN_TO_ADDRESS", "owner@example.com");
+    std::env::set_var("MAIL_PROVIDER", "memory");
+    std::env::set_var("DEV_MODE", "true");
+    std::env::set_var("ADMIN_TOKEN", ADMIN_TOKEN);
+    std::env::set_var("MAINTENANCE_MODE", "true");
+    std::env::set_var("MAINTENANCE_HOLD_SUBMISSIONS", "true");
+    let app = ContactFormService::builder().build().await.unwrap().router();
+
+    let request = post_form(VALID_FORM);
+    let (status, _) = call(&app, request).await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    assert_eq!(mailbox(&app).await.len(), 0);
+    let (_, maintenance) = call(&app, admin("GET", "/admin/maintenance")).await;
+    assert_eq!(maintenance["enabled"], true);
+    assert_eq!(maintenance["held"], 1);
+
+    let (status, maintenance) = call(&app, admin("DELETE", "/admin/maintenance")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(maintenance["enabled"], false);
+    assert_eq!(maintenance["held"], 0);
+    // Held submissions are sent in the background
+    for _ in 0..50 {
+        if mailbox(&app).await.len() == 1 {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("the held submission was never sent");
+}
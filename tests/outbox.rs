@@ -0,0 +1,73 @@
+//! Keeping emails that are waiting to be sent in a file, so they survive a restart
+
+mod common;
+
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use async_trait::async_trait;
+use axum::http::StatusCode;
+use mailgun_contact_form::{ContactFormService, Email, MailProvider, ProviderError};
+use serde_json::{json, Value};
+use tower::ServiceExt;
+use common::{VALID_FORM, post_form};
+
+/// Rate limits everything while `limited` is set, and records the subject of everything else
+#[derive(Clone, Default)]
+struct FlakyProvider {
+    limited: Arc<AtomicBool>,
+    sent: Arc<Mutex<Vec<String>>>,
+}
+
+#[async_trait]
+impl MailProvider for FlakyProvider {
+    async fn send(&self, email: &Email) -> Result<(), ProviderError> {
+        if self.limited.load(Ordering::SeqCst) {
+            return Err(ProviderError::RateLimited(Some(Duration::from_secs(60 * 60))));
+        }
+        self.sent.lock().unwrap().push(email.subject.clone());
+        Ok(())
+    }
+}
+
+fn read(path: &std::path::Path) -> Value {
+    serde_json::from_slice(&std::fs::read(path).unwrap()).unwrap()
+}
+
+#[tokio::test]
+async fn resumes_on_startup_and_drains_on_shutdown() {
+    let path = std::env::temp_dir().join(format!("contact-form-outbox-{}.json", std::process::id()));
+    // Rate limited before the last restart, and overdue now
+    let waiting = json!([{
+        "id": "before-restart",
+        "due_at": "2020-01-01T00:00:00Z",
+        "kind": "retry",
+        "email": { "from": "Jo Bloggs <jo@example.com>", "to": "owner@example.com", "subject": "Restored", "text": "Still there?" },
+        "form": null,
+        "attempts": 1,
+    }]);
+    std::fs::write(&path, waiting.to_string()).unwrap();
+    std::env::set_var("MAILGUN_TO_ADDRESS", "owner@example.com");
+    std::env::set_var("OUTBOX_FILE", &path);
+    let provider = FlakyProvider::default();
+    let service = ContactFormService::builder().provider(provider.clone()).build().await.unwrap();
+
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(*provider.sent.lock().unwrap(), vec!["Restored"]);
+    assert_eq!(read(&path), json!([]));
+
+    provider.limited.store(true, Ordering::SeqCst);
+    let request = post_form(VALID_FORM);
+    let response = service.router().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    let outbox = read(&path);
+    assert_eq!(outbox[0]["kind"], "retry");
+    assert_eq!(outbox[0]["email"]["subject"], "Hello");
+
+    // Sent on shutdown, rather than in an hour
+    provider.limited.store(false, Ordering::SeqCst);
+    service.drain().await;
+    assert_eq!(*provider.sent.lock().unwrap(), vec!["Restored", "Hello"]);
+    assert_eq!(read(&path), json!([]));
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,67 @@
+//! Requiring a solved proof-of-work challenge from `GET /challenge` with each submission
+
+mod common;
+
+use std::time::Duration;
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::Router;
+use mailgun_contact_form::ContactFormService;
+use sha2::{Digest, Sha256};
+use common::{VALID_FORM, call, mailbox, post_form};
+
+async fn challenge(app: &Router) -> String {
+    let (status, body) = call(app, Request::get("/challenge").body(Body::empty()).unwrap()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["difficulty"], 8);
+    body["challenge"].as_str().unwrap().to_string()
+}
+
+/// Whether the solution's hash starts with a zero byte decides whether it's solved, for 8 bits
+fn solves(challenge: &str, solution: &str) -> bool {
+    Sha256::new().chain_update(challenge).chain_update(solution).finalize()[0] == 0
+}
+
+fn solve(challenge: &str) -> String {
+    (0u64..).map(|n| n.to_string()).find(|solution| solves(challenge, solution)).unwrap()
+}
+
+async fn submit(app: &Router, challenge: &str, solution: &str) -> StatusCode {
+    let form = format!("{}&_challenge={}&_solution={}", VALID_FORM, challenge, solution);
+    let (status, body) = call(app, post_form(form)).await;
+    if status == StatusCode::FORBIDDEN {
+        assert_eq!(body["status"], "InvalidChallenge");
+    }
+    status
+}
+
+#[tokio::test]
+async fn accepts_only_fresh_solved_challenges() {
+    std::env::set_var("MAILGUN_TO_ADDRESS", "owner@example.com");
+    std::env::set_var("MAIL_PROVIDER", "memory");
+    std::env::set_var("DEV_MODE", "true");
+    std::env::set_var("POW_DIFFICULTY", "8");
+    std::env::set_var("POW_CHALLENGE_TTL_SECS", "1");
+    let app = ContactFormService::builder().build().await.unwrap().router();
+
+    let (status, _) = call(&app, post_form(VALID_FORM)).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let first = challenge(&app).await;
+    let solution = solve(&first);
+    let unsolved = (0u64..).map(|n| n.to_string()).find(|solution| !solves(&first, solution)).unwrap();
+    assert_eq!(submit(&app, &first, &unsolved).await, StatusCode::FORBIDDEN);
+    assert_eq!(submit(&app, &first, &solution).await, StatusCode::OK);
+    assert_eq!(submit(&app, &first, &solution).await, StatusCode::FORBIDDEN, "a challenge was used twice");
+
+    // Lowering the difficulty breaks the challenge's signature
+    let second = challenge(&app).await;
+    let mut parts: Vec<&str> = second.split('.').collect();
+    parts[2] = "0";
+    assert_eq!(submit(&app, &parts.join("."), "0").await, StatusCode::FORBIDDEN);
+
+    let solution = solve(&second);
+    tokio::time::sleep(Duration::from_millis(2100)).await;
+    assert_eq!(submit(&app, &second, &solution).await, StatusCode::FORBIDDEN, "an expired challenge was accepted");
+    assert_eq!(mailbox(&app).await.len(), 1);
+}
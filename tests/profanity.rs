@@ -0,0 +1,67 @@
+//! Tagging, masking, rejecting or quarantining submissions with words from the profanity wordlists
+
+mod common;
+
+use axum::http::StatusCode;
+use mailgun_contact_form::ContactFormService;
+use common::{ADMIN_TOKEN, admin, call, mailbox, post_form};
+
+#[tokio::test]
+async fn acts_on_words_from_the_right_wordlist() {
+    let dir = std::env::temp_dir().join(format!("contact-form-profanity-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("en.txt"), "# English\ndarn\n\nHeck\n").unwrap();
+    std::fs::write(dir.join("de.txt"), "mist\n").unwrap();
+    std::env::set_var("MAILGUN_TO_ADDRESS", "owner@example.com");
+    std::env::set_var("MAIL_PROVIDER", "memory");
+    std::env::set_var("DEV_MODE", "true");
+    std::env::set_var("ADMIN_TOKEN", ADMIN_TOKEN);
+    std::env::set_var("RESPONSE_ID_FIELD", "submission_id");
+    std::env::set_var("PROCESSORS", "profanity");
+    std::env::set_var("PROFANITY_WORDLISTS", format!("en={},de={}", dir.join("en.txt").display(), dir.join("de.txt").display()));
+
+    let english = "from_name=Jo&from_email=jo%40example.com&title=Darn+printer&body=It%27s+jammed+again%2C+heck.";
+    let german = "from_name=Jo&from_email=jo%40example.com&title=Drucker&body=So+ein+Mist";
+    let clean = "from_name=Jo&from_email=jo%40example.com&title=Scunthorpe&body=Darning+socks";
+    // Only rude in German, and this is clearly English
+    let foggy = "from_name=Jo&from_email=jo%40example.com&title=Foggy+morning&body=The+mist+over+the+hills+is+lovely";
+
+    std::env::set_var("PROFANITY_ACTION", "tag");
+    std::env::set_var("PROFANITY_TAG", "[Rude]");
+    let app = ContactFormService::builder().build().await.unwrap().router();
+    for (form, subject) in [(english, "[Rude] Darn printer"), (german, "[Rude] Drucker"), (clean, "Scunthorpe"), (foggy, "Foggy morning")] {
+        let (status, _) = call(&app, post_form(form)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(mailbox(&app).await[0]["subject"], subject);
+    }
+
+    std::env::set_var("PROFANITY_ACTION", "mask");
+    let app = ContactFormService::builder().build().await.unwrap().router();
+    let (status, _) = call(&app, post_form(english)).await;
+    assert_eq!(status, StatusCode::OK);
+    let sent = mailbox(&app).await;
+    assert_eq!(sent[0]["subject"], "**** printer");
+    assert!(sent[0]["text"].as_str().unwrap().starts_with("It's jammed again, ****."));
+    call(&app, post_form(german)).await;
+    assert!(mailbox(&app).await[0]["text"].as_str().unwrap().starts_with("So ein ****"));
+
+    std::env::set_var("PROFANITY_ACTION", "reject");
+    let app = ContactFormService::builder().build().await.unwrap().router();
+    let before = mailbox(&app).await.len();
+    for form in [english, german] {
+        let (status, _) = call(&app, post_form(form)).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+    let (status, _) = call(&app, post_form(clean)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(mailbox(&app).await.len(), before + 1);
+
+    std::env::set_var("PROFANITY_ACTION", "quarantine");
+    let app = ContactFormService::builder().build().await.unwrap().router();
+    let (status, body) = call(&app, post_form(german)).await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, stored) = call(&app, admin("GET", &format!("/admin/submissions/{}", body["submission_id"].as_str().unwrap()))).await;
+    assert_eq!(stored["status"], "quarantined");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
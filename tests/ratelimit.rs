@@ -0,0 +1,36 @@
+//! The `RateLimit-*` headers on accepted submissions, and what a rate-limited one gets back
+
+mod common;
+
+use axum::http::StatusCode;
+use mailgun_contact_form::{ContactFormService, MemoryProvider};
+use serde_json::Value;
+use tower::ServiceExt;
+use common::{VALID_FORM, post_form};
+
+#[tokio::test]
+async fn reports_the_remaining_quota() {
+    std::env::set_var("MAILGUN_TO_ADDRESS", "owner@example.com");
+    std::env::set_var("SENDER_RATE_LIMIT", "2");
+    let app = ContactFormService::builder().provider(MemoryProvider::new()).build().await.unwrap().router();
+
+    let mut responses = Vec::new();
+    for _ in 0..3 {
+        let request = post_form(VALID_FORM);
+        responses.push(app.clone().oneshot(request).await.unwrap());
+    }
+    let header = |index: usize, name: &str| responses[index].headers().get(name).map(|value| value.to_str().unwrap().to_string());
+    assert_eq!(responses[0].status(), StatusCode::OK);
+    assert_eq!(header(0, "ratelimit-limit").as_deref(), Some("2"));
+    assert_eq!(header(0, "ratelimit-remaining").as_deref(), Some("1"));
+    assert_eq!(header(1, "ratelimit-remaining").as_deref(), Some("0"));
+    assert_eq!(responses[2].status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(header(2, "ratelimit-remaining").as_deref(), Some("0"));
+    let retry_after = header(2, "retry-after").unwrap();
+    assert_eq!(header(2, "ratelimit-reset"), Some(retry_after.clone()));
+
+    let body = hyper::body::to_bytes(responses.pop().unwrap().into_body()).await.unwrap();
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["status"], "RateLimited");
+    assert_eq!(body["retry_after"].to_string(), retry_after);
+}
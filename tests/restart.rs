@@ -0,0 +1,110 @@
+//! Sending submissions that were queued by a send cap, or held during maintenance, when the service
+//! last stopped - and confirming ones that were waiting to be
+
+mod common;
+
+use std::time::Duration;
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use mailgun_contact_form::ContactFormService;
+use serde_json::{json, Value};
+use common::{ADMIN_TOKEN, admin, call, mailbox};
+
+fn unconfirmed(id: &str, title: &str) -> Value {
+    let mut submission = submission(id, title);
+    submission["status"] = json!("unconfirmed");
+    submission
+}
+
+fn submission(id: &str, title: &str) -> Value {
+    json!({
+        "id": id,
+        "received_at": "2026-10-01T09:00:00Z",
+        "from_name": "Jo Bloggs",
+        "from_email": "jo@example.com",
+        "title": title,
+        "body": "Still there?",
+        "status": "pending",
+        "status_message": null,
+    })
+}
+
+#[tokio::test]
+async fn sends_what_was_queued_before_a_restart() {
+    let path = std::env::temp_dir().join(format!("contact-form-queued-{}.json", std::process::id()));
+    let waiting = json!([
+        {
+            "id": "send_cap:6c1d4f0e-3b8a-4e55-9b1e-2f7a0d9c1e01",
+            "due_at": "2026-10-02T00:00:00Z",
+            "kind": "capped",
+            "submission": submission("6c1d4f0e-3b8a-4e55-9b1e-2f7a0d9c1e01", "Over the cap"),
+        },
+        {
+            "id": "maintenance:0f9e8d7c-6b5a-4c3d-8e2f-1a0b9c8d7e02",
+            "due_at": "2026-10-01T09:00:00Z",
+            "kind": "held",
+            "submission": submission("0f9e8d7c-6b5a-4c3d-8e2f-1a0b9c8d7e02", "Held for maintenance"),
+        },
+        {
+            "id": "confirm:5b0c3d2e1f4a4b6c8d9e0f1a2b3c4d5e",
+            "due_at": "2099-01-01T00:00:00Z",
+            "kind": "unconfirmed",
+            "submission": unconfirmed("1a2b3c4d-5e6f-4a7b-8c9d-0e1f2a3b4c03", "Waiting to be confirmed"),
+        },
+        {
+            "id": "confirm:9f8e7d6c5b4a4d3c8b2a1f0e9d8c7b6a",
+            "due_at": "2026-10-01T10:00:00Z",
+            "kind": "unconfirmed",
+            "submission": unconfirmed("2b3c4d5e-6f7a-4b8c-9d0e-1f2a3b4c5d04", "Never confirmed"),
+        },
+    ]);
+    std::fs::write(&path, waiting.to_string()).unwrap();
+    // And one that was waiting to be confirmed in an outbox that wasn't kept
+    let stored = json!([
+        unconfirmed("1a2b3c4d-5e6f-4a7b-8c9d-0e1f2a3b4c03", "Waiting to be confirmed"),
+        unconfirmed("2b3c4d5e-6f7a-4b8c-9d0e-1f2a3b4c5d04", "Never confirmed"),
+        unconfirmed("3c4d5e6f-7a8b-4c9d-8e1f-2a3b4c5d6e05", "Lost"),
+    ]);
+    let submissions_path = std::env::temp_dir().join(format!("contact-form-queued-submissions-{}.json", std::process::id()));
+    std::fs::write(&submissions_path, stored.to_string()).unwrap();
+    std::env::set_var("MAILGUN_TO_ADDRESS", "owner@example.com");
+    std::env::set_var("MAIL_PROVIDER", "memory");
+    std::env::set_var("DEV_MODE", "true");
+    std::env::set_var("OUTBOX_FILE", &path);
+    std::env::set_var("SUBMISSIONS_FILE", &submissions_path);
+    std::env::set_var("ADMIN_TOKEN", ADMIN_TOKEN);
+    std::env::set_var("DOUBLE_OPT_IN", "true");
+    std::env::set_var("CONFIRMATION_FROM_ADDRESS", "noreply@example.com");
+    let app = ContactFormService::builder().build().await.unwrap().router();
+
+    // Both are sent in the background, now the cap has reset and maintenance is over
+    for _ in 0..50 {
+        if mailbox(&app).await.len() == 2 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    let mut subjects: Vec<String> = mailbox(&app).await.iter().map(|email| email["subject"].as_str().unwrap().to_string()).collect();
+    subjects.sort();
+    assert_eq!(subjects, vec!["Held for maintenance", "Over the cap"]);
+
+    // The link sent before the restart still works, unless it's expired since
+    let (status, _) = call(&app, Request::get("/confirm/9f8e7d6c5b4a4d3c8b2a1f0e9d8c7b6a").body(Body::empty()).unwrap()).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = call(&app, Request::get("/confirm/5b0c3d2e1f4a4b6c8d9e0f1a2b3c4d5e").body(Body::empty()).unwrap()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(mailbox(&app).await[0]["subject"], "Waiting to be confirmed");
+    for (id, status) in [
+        ("1a2b3c4d-5e6f-4a7b-8c9d-0e1f2a3b4c03", "sent"),
+        ("2b3c4d5e-6f7a-4b8c-9d0e-1f2a3b4c5d04", "failed"),
+        ("3c4d5e6f-7a8b-4c9d-8e1f-2a3b4c5d6e05", "failed"),
+    ] {
+        let (_, submission) = call(&app, admin("GET", &format!("/admin/submissions/{}", id))).await;
+        assert_eq!(submission["status"], status, "{}", id);
+    }
+
+    let outbox: Value = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+    assert_eq!(outbox, json!([]));
+    std::fs::remove_file(&path).unwrap();
+    std::fs::remove_file(&submissions_path).unwrap();
+}
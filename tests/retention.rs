@@ -0,0 +1,68 @@
+//! Deleting submissions once they're past the retention period, and erasing everything from a
+//! submitter on request
+
+mod common;
+
+use std::time::Duration;
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use chrono::Utc;
+use mailgun_contact_form::{ContactFormService, MemoryProvider};
+use serde_json::{json, Value};
+use common::{ADMIN_TOKEN, admin, call};
+
+fn stored(id: &str, from_email: &str, days_ago: i64) -> Value {
+    json!({
+        "id": id,
+        "received_at": Utc::now() - chrono::Duration::days(days_ago),
+        "from_name": "Jo Bloggs",
+        "from_email": from_email,
+        "title": "Hello",
+        "body": "Is this thing on?",
+        "status": "sent",
+    })
+}
+
+async fn ids(app: &axum::Router) -> Vec<String> {
+    let (_, list) = call(app, admin("GET", "/admin/submissions")).await;
+    list["submissions"].as_array().unwrap().iter().map(|submission| submission["id"].as_str().unwrap().to_string()).collect()
+}
+
+#[tokio::test]
+async fn purges_old_submissions_and_erases_by_address() {
+    let path = std::env::temp_dir().join(format!("contact-form-retention-{}.json", std::process::id()));
+    let submissions = [
+        stored("expired", "jo@example.com", 45),
+        stored("recent", "jo@example.com", 5),
+        stored("someone-else", "sam@example.org", 1),
+    ];
+    std::fs::write(&path, serde_json::to_vec(&submissions).unwrap()).unwrap();
+    std::env::set_var("MAILGUN_TO_ADDRESS", "owner@example.com");
+    std::env::set_var("ADMIN_TOKEN", ADMIN_TOKEN);
+    std::env::set_var("SUBMISSIONS_FILE", &path);
+    std::env::set_var("RETENTION_DAYS", "30");
+    let app = ContactFormService::builder().provider(MemoryProvider::new()).build().await.unwrap().router();
+
+    // The first purge happens straight away, in the background
+    for _ in 0..50 {
+        if ids(&app).await.len() == 2 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!(ids(&app).await, ["someone-else", "recent"]);
+
+    let request = Request::delete("/admin/submissions?email=jo%40example.com").body(Body::empty()).unwrap();
+    let (status, _) = call(&app, request).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(ids(&app).await.len(), 2);
+
+    let (status, erased) = call(&app, admin("DELETE", "/admin/submissions?email=JO%40example.com")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(erased["deleted"], 1);
+    assert_eq!(ids(&app).await, ["someone-else"]);
+    // Including from the file, so it doesn't come back after a restart
+    let saved: Value = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+    assert_eq!(saved.as_array().unwrap().len(), 1);
+    std::fs::remove_file(&path).unwrap();
+}
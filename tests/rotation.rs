@@ -0,0 +1,44 @@
+//! Sharing submissions out across a team, in turn or by who's on call
+
+mod common;
+
+use axum::Router;
+use axum::http::StatusCode;
+use chrono::{Datelike, Utc};
+use mailgun_contact_form::ContactFormService;
+use common::{VALID_FORM, call, mailbox, post_form};
+
+/// Submits to the given form, returning who it was sent to
+async fn submit(app: &Router, form: &str) -> String {
+    let request = post_form(format!("_form={}&{}", form, VALID_FORM));
+    let (status, _) = call(app, request).await;
+    assert_eq!(status, StatusCode::OK);
+    let sent = mailbox(app).await;
+    sent[0]["to"].as_str().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn shares_submissions_out_in_turn_or_by_who_is_on_call() {
+    // Covering tomorrow too, in case the test runs over midnight
+    let today = Utc::now().weekday();
+    let others: Vec<String> = (2..7).map(|days| (0..days).fold(today, |day, _| day.succ()).to_string()).collect();
+    std::env::set_var("MAILGUN_TO_ADDRESS", "owner@example.com");
+    std::env::set_var("MAIL_PROVIDER", "memory");
+    std::env::set_var("DEV_MODE", "true");
+    std::env::set_var("FORM_SALES_RECIPIENT_ROTATION", r#"{"alice@example.com": 2, "bob@example.com": 1}"#);
+    std::env::set_var("FORM_SUPPORT_ON_CALL_SCHEDULE", format!(
+        r#"[{{"to": "elsewhen@example.com", "days": "{}"}}, {{"to": "on-call@example.com", "days": "{},{}"}}]"#,
+        others.join(","), today, today.succ(),
+    ));
+    let app = ContactFormService::builder().build().await.unwrap().router();
+
+    let mut recipients = Vec::new();
+    for _ in 0..3 {
+        recipients.push(submit(&app, "sales").await);
+    }
+    assert_eq!(recipients, ["alice@example.com", "bob@example.com", "alice@example.com"]);
+
+    assert_eq!(submit(&app, "support").await, "on-call@example.com");
+    // Forms without either still go to the usual address
+    assert_eq!(submit(&app, "other").await, "owner@example.com");
+}
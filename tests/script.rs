@@ -0,0 +1,74 @@
+//! Per-form scripts that reject submissions, change them, or choose who they go to
+
+mod common;
+
+use axum::Router;
+use axum::http::StatusCode;
+use mailgun_contact_form::ContactFormService;
+use serde_json::Value;
+use common::{call, mailbox, post_form};
+
+const SCRIPT: &str = r#"
+let topic = field("topic");
+// Enterprise enquiries are only worth it with a decent budget
+if topic == "enterprise" && number(field("budget")) < 1000 {
+    reject("Enterprise projects start at 1000");
+}
+if topic != "" {
+    set("title", `[${topic.to_upper()}] ${title}`);
+}
+if topic == "enterprise" {
+    route("sales@example.com");
+}
+if body.to_lower().contains("crypto") {
+    quarantine("mentions crypto");
+}
+"#;
+
+async fn submit(app: &Router, form: &str) -> (StatusCode, Value) {
+    let request = post_form(format!("_form=quote&from_name=Jo+Bloggs&from_email=jo%40example.com&title=Hello&{}", form));
+    call(app, request).await
+}
+
+#[tokio::test]
+async fn applies_the_forms_rules() {
+    let script = std::env::temp_dir().join(format!("contact-form-script-{}.rhai", std::process::id()));
+    std::fs::write(&script, SCRIPT).unwrap();
+    std::env::set_var("MAILGUN_TO_ADDRESS", "owner@example.com");
+    std::env::set_var("MAIL_PROVIDER", "memory");
+    std::env::set_var("DEV_MODE", "true");
+    std::env::set_var("PROCESSORS", "script");
+    std::env::set_var("FORM_QUOTE_SCRIPT_FILE", &script);
+    let runaway = std::env::temp_dir().join(format!("contact-form-runaway-{}.rhai", std::process::id()));
+    std::fs::write(&runaway, "set(\"title\", \"Changed\"); loop { }").unwrap();
+    std::env::set_var("FORM_RUNAWAY_SCRIPT_FILE", &runaway);
+    let app = ContactFormService::builder().build().await.unwrap().router();
+
+    let (status, body) = submit(&app, "topic=enterprise&budget=500&body=Can+you+help%3F").await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["status"], "Rejected");
+    assert_eq!(body["message"], "Enterprise projects start at 1000");
+
+    let (status, _) = submit(&app, "topic=enterprise&budget=5000&body=Can+you+help%3F").await;
+    assert_eq!(status, StatusCode::OK);
+    let sent = mailbox(&app).await;
+    assert_eq!(sent[0]["to"], "sales@example.com");
+    assert_eq!(sent[0]["subject"], "[ENTERPRISE] Hello");
+
+    // Looks sent, but is held for review
+    let (status, _) = submit(&app, "body=Pay+in+crypto%3F").await;
+    assert_eq!(status, StatusCode::OK);
+    let sent = mailbox(&app).await;
+    assert_eq!(sent.len(), 1);
+
+    // Stopped by the limits, and sent unchanged
+    let request = post_form("_form=runaway&from_name=Jo+Bloggs&from_email=jo%40example.com&title=Hello&body=Hi");
+    let (status, _) = call(&app, request).await;
+    assert_eq!(status, StatusCode::OK);
+    let sent = mailbox(&app).await;
+    assert_eq!(sent.len(), 2);
+    assert_eq!(sent[0]["subject"], "Hello");
+
+    std::fs::remove_file(&script).unwrap();
+    std::fs::remove_file(&runaway).unwrap();
+}
@@ -0,0 +1,57 @@
+//! Blocking and allowing senders through the admin API, and by editing the lists file
+
+mod common;
+
+use axum::Router;
+use axum::http::StatusCode;
+use mailgun_contact_form::{ContactFormService, MemoryProvider};
+use serde_json::Value;
+use common::{ADMIN_TOKEN, admin, call, post_form};
+
+async fn submit(app: &Router, from: &str) -> StatusCode {
+    let form = format!("from_name=Someone&from_email={}&title=Hello&body=Is+this+thing+on%3F", from);
+    let request = post_form(form);
+    call(app, request).await.0
+}
+
+#[tokio::test]
+async fn blocks_and_allows_senders() {
+    let file = std::env::temp_dir().join(format!("senders-{}.json", std::process::id()));
+    std::fs::write(&file, r#"{"block": ["harasser@example.com"]}"#).unwrap();
+    std::env::set_var("MAILGUN_TO_ADDRESS", "owner@example.com");
+    std::env::set_var("ADMIN_TOKEN", ADMIN_TOKEN);
+    std::env::set_var("SENDER_LISTS_FILE", &file);
+    let app = ContactFormService::builder().provider(MemoryProvider::new()).build().await.unwrap().router();
+
+    assert_eq!(submit(&app, "HARASSER%40example.com").await, StatusCode::FORBIDDEN);
+    assert_eq!(submit(&app, "jo%40example.com").await, StatusCode::OK);
+
+    // Whole domains, including their subdomains
+    let (status, _) = call(&app, admin("PUT", "/admin/senders/block/spam.example")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(submit(&app, "anyone%40mail.spam.example").await, StatusCode::FORBIDDEN);
+
+    // Once anything's allowed, nothing else is
+    let (_, lists) = call(&app, admin("PUT", "/admin/senders/allow/example.com")).await;
+    assert_eq!(lists["allow"][0], "example.com");
+    assert_eq!(submit(&app, "jo%40example.com").await, StatusCode::OK);
+    assert_eq!(submit(&app, "jo%40example.org").await, StatusCode::FORBIDDEN);
+    let (status, _) = call(&app, admin("DELETE", "/admin/senders/allow/example.com")).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = call(&app, admin("DELETE", "/admin/senders/allow/example.com")).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    // Edits to the file take effect without a restart
+    let saved: Value = serde_json::from_str(&std::fs::read_to_string(&file).unwrap()).unwrap();
+    assert_eq!(saved["block"].as_array().unwrap().len(), 2);
+    std::fs::write(&file, r#"{"block": []}"#).unwrap();
+    assert_eq!(submit(&app, "harasser%40example.com").await, StatusCode::OK);
+    // A broken file is ignored, keeping the lists from before it
+    std::fs::write(&file, r#"{"block": ["#).unwrap();
+    assert_eq!(submit(&app, "harasser%40example.com").await, StatusCode::OK);
+    let (_, log) = call(&app, admin("GET", "/admin/audit?actor=senders_file")).await;
+    let actions: Vec<&str> = log["entries"].as_array().unwrap().iter().map(|entry| entry["action"].as_str().unwrap()).collect();
+    assert_eq!(actions, ["senders.reload_failed", "senders.reloaded"]);
+    assert_eq!(log["entries"][1]["detail"], "0 allowed, 0 blocked");
+    std::fs::remove_file(&file).unwrap();
+}
@@ -0,0 +1,83 @@
+//! Addressing submissions to one of several recipients by a token in the `_to` field, or by signing
+//! the hidden fields
+
+mod common;
+
+use axum::body::Body;
+use axum::http::{header, Request, StatusCode};
+use hmac::{Hmac, Mac};
+use mailgun_contact_form::ContactFormService;
+use sha2::Sha256;
+use common::{ADMIN_TOKEN, VALID_FORM, call, mailbox, post_form};
+
+/// The `_signature` for the hidden fields, as the README describes making it
+fn sign(form: &str, redirect: &str, to: &str) -> String {
+    let message: String = [format!("_form={}", form), format!("_redirect={}", redirect), format!("_to={}", to)]
+        .iter()
+        .map(|pair| format!("{}:{}", pair.len(), pair))
+        .collect();
+    let mut mac = Hmac::<Sha256>::new_from_slice(b"signing-secret").unwrap();
+    mac.update(message.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+#[tokio::test]
+async fn sends_to_the_recipient_in_the_token() {
+    std::env::set_var("MAILGUN_TO_ADDRESS", "owner@example.com");
+    std::env::set_var("MAIL_PROVIDER", "memory");
+    std::env::set_var("DEV_MODE", "true");
+    std::env::set_var("ADMIN_TOKEN", ADMIN_TOKEN);
+    std::env::set_var("SIGNING_SECRET", "signing-secret");
+    std::env::set_var("RECIPIENTS", r#"{"sales": "sales@example.com"}"#);
+    let app = ContactFormService::builder().build().await.unwrap().router();
+
+    let request = Request::get("/admin/recipients")
+        .header(header::AUTHORIZATION, format!("Bearer {}", ADMIN_TOKEN))
+        .body(Body::empty())
+        .unwrap();
+    let (status, recipients) = call(&app, request).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(recipients[0]["address"], "sales@example.com");
+    let token = recipients[0]["token"].as_str().unwrap();
+    assert!(token.starts_with("sales."));
+
+    let (status, _) = call(&app, post_form(format!("{}&_to={}", VALID_FORM, token))).await;
+    assert_eq!(status, StatusCode::OK);
+    // A token is only good for the recipient it was made for
+    let (status, _) = call(&app, post_form(format!("{}&_to=support.{}", VALID_FORM, &token[6..]))).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = call(&app, post_form(format!("{}&_to=someone%40example.org", VALID_FORM))).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let sent = mailbox(&app).await;
+    let recipients: Vec<&str> = sent.iter().map(|email| email["to"].as_str().unwrap()).collect();
+    assert_eq!(recipients, ["sales@example.com"]);
+}
+
+#[tokio::test]
+async fn rejects_tampered_signed_fields() {
+    std::env::set_var("MAILGUN_TO_ADDRESS", "owner@example.com");
+    std::env::set_var("MAIL_PROVIDER", "memory");
+    std::env::set_var("DEV_MODE", "true");
+    std::env::set_var("ADMIN_TOKEN", ADMIN_TOKEN);
+    std::env::set_var("SIGNING_SECRET", "signing-secret");
+    std::env::set_var("RECIPIENTS", r#"{"sales": "sales@example.com"}"#);
+    let app = ContactFormService::builder().build().await.unwrap().router();
+
+    let signature = sign("sales", "", "someone@example.org");
+    let (status, _) = call(&app, post_form(format!("{}&_form=sales&_to=someone%40example.org&_signature={}", VALID_FORM, signature))).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(mailbox(&app).await[0]["to"], "someone@example.org");
+
+    let (status, _) = call(&app, post_form(format!("{}&_form=sales&_to=attacker%40example.org&_signature={}", VALID_FORM, signature))).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = call(&app, post_form(format!("{}&_form=support&_to=someone%40example.org&_signature={}", VALID_FORM, signature))).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    // Signed as part of the form's name, which can't then be moved into the next field
+    let signature = sign("sales\nhttps://example.com/thanks", "", "attacker@example.org");
+    let form = format!("{}&_form=sales&_redirect=https%3A%2F%2Fexample.com%2Fthanks%0A&_to=attacker%40example.org&_signature={}", VALID_FORM, signature);
+    let (status, _) = call(&app, post_form(form)).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(mailbox(&app).await.len(), 1);
+}
@@ -0,0 +1,68 @@
+//! Training the spam classifier through the admin API, quarantining what it then scores as spam, and
+//! reviewing the quarantine
+
+mod common;
+
+use axum::Router;
+use axum::http::StatusCode;
+use mailgun_contact_form::{ContactFormService, MemoryProvider};
+use common::{ADMIN_TOKEN, admin, call, post_form};
+
+/// Returns the new submission's stored status
+async fn submit(app: &Router, from: &str, title: &str, body: &str) -> (String, String) {
+    let form = format!("from_name=Someone&from_email={}&title={}&body={}", from, title, body);
+    let request = post_form(form);
+    let (status, body) = call(app, request).await;
+    assert_eq!(status, StatusCode::OK);
+    let id = body["submission_id"].as_str().unwrap().to_string();
+    let (_, stored) = call(app, admin("GET", &format!("/admin/submissions/{}", id))).await;
+    (id, stored["status"].as_str().unwrap().to_string())
+}
+
+#[tokio::test]
+async fn quarantines_once_trained() {
+    std::env::set_var("MAILGUN_TO_ADDRESS", "owner@example.com");
+    std::env::set_var("ADMIN_TOKEN", ADMIN_TOKEN);
+    std::env::set_var("RESPONSE_ID_FIELD", "submission_id");
+    std::env::set_var("SPAM_CLASSIFIER", "true");
+    std::env::set_var("SPAM_MIN_TRAINING", "2");
+    let app = ContactFormService::builder().provider(MemoryProvider::new()).build().await.unwrap().router();
+
+    let spam = [
+        ("win%40casino.example", "Cheap+pills", "Buy+cheap+pills+and+win+big+at+our+casino"),
+        ("deals%40casino.example", "Casino+bonus", "Claim+your+free+casino+bonus+and+cheap+pills+now"),
+    ];
+    let ham = [
+        ("jo%40example.com", "Question+about+my+order", "Hi,+my+order+hasn't+arrived+yet.+Could+you+check+on+it?"),
+        ("sam%40example.org", "Opening+hours", "Hi,+are+you+open+on+the+weekend?+I'd+like+to+visit."),
+    ];
+    for (examples, action) in [(spam, "mark-spam"), (ham, "mark-ham")] {
+        for (from, title, body) in examples {
+            let (id, status) = submit(&app, from, title, body).await;
+            assert_eq!(status, "sent");
+            let (status, _) = call(&app, admin("POST", &format!("/admin/submissions/{}/{}", id, action))).await;
+            assert_eq!(status, StatusCode::NO_CONTENT);
+        }
+    }
+
+    let (quarantined, status) = submit(&app, "promo%40casino.example", "Free+pills", "Cheap+pills+and+a+casino+bonus+for+you").await;
+    assert_eq!(status, "quarantined");
+    let (_, status) = submit(&app, "alex%40example.net", "My+order", "Hi,+could+you+check+where+my+order+is?").await;
+    assert_eq!(status, "sent");
+
+    let (_, quarantine) = call(&app, admin("GET", "/admin/quarantine")).await;
+    assert_eq!(quarantine["total"], 1);
+    assert_eq!(quarantine["submissions"][0]["id"], quarantined.as_str());
+    let (status, released) = call(&app, admin("POST", &format!("/admin/quarantine/{}/release", quarantined))).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(released["status"], "sent");
+    // Only quarantined submissions can be released or purged
+    let (status, _) = call(&app, admin("DELETE", &format!("/admin/quarantine/{}", quarantined))).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (quarantined, _) = submit(&app, "promo%40casino.example", "Casino+pills", "Cheap+casino+pills+bonus").await;
+    let (status, _) = call(&app, admin("DELETE", &format!("/admin/quarantine/{}", quarantined))).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, _) = call(&app, admin("GET", &format!("/admin/submissions/{}", quarantined))).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
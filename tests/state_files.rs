@@ -0,0 +1,49 @@
+//! Refusing to start with a state file that can't be read, rather than panicking on first use
+
+use mailgun_contact_form::{ContactFormService, MemoryProvider};
+
+// In the order they're opened, as each is only opened once
+#[tokio::test]
+async fn reports_unreadable_state_files_at_startup() {
+    std::env::set_var("MAILGUN_TO_ADDRESS", "owner@example.com");
+    // Which the spam classifier needs
+    std::env::set_var("ADMIN_TOKEN", "secret");
+    let corrupt = [
+        ("AUDIT_LOG_FILE", "Unable to parse line 1 of audit log"),
+        ("SUPPRESSIONS_FILE", "Unable to parse suppressions file"),
+        ("SENDER_LISTS_FILE", "Unable to parse sender lists file"),
+    ];
+    for (name, expected) in corrupt {
+        let path = std::env::temp_dir().join(format!("contact-form-{}-{}", name.to_lowercase(), std::process::id()));
+        std::fs::write(&path, "not json").unwrap();
+        std::env::set_var(name, &path);
+        let error = ContactFormService::builder().provider(MemoryProvider::new()).build().await.err()
+            .unwrap_or_else(|| panic!("a corrupt {} was accepted", name));
+        assert!(error.to_string().starts_with(expected), "{}: {}", name, error);
+        std::env::remove_var(name);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    // Only read at all when the classifier is enabled
+    let path = std::env::temp_dir().join(format!("contact-form-spam-classifier-{}.json", std::process::id()));
+    std::fs::write(&path, "not json").unwrap();
+    std::env::set_var("SPAM_CLASSIFIER", "true");
+    std::env::set_var("SPAM_CLASSIFIER_FILE", &path);
+    let error = ContactFormService::builder().provider(MemoryProvider::new()).build().await.err()
+        .expect("a corrupt SPAM_CLASSIFIER_FILE was accepted");
+    assert!(error.to_string().starts_with("Unable to parse spam classifier file"), "{}", error);
+    std::env::remove_var("SPAM_CLASSIFIER_FILE");
+    std::fs::remove_file(&path).unwrap();
+
+    let dir = std::env::temp_dir().join(format!("contact-form-locales-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("fr.json"), "[\"not an object\"]").unwrap();
+    std::env::set_var("LOCALES_DIR", &dir);
+    let error = ContactFormService::builder().provider(MemoryProvider::new()).build().await.err()
+        .expect("a broken translation in LOCALES_DIR was accepted");
+    assert!(error.to_string().starts_with(&format!("Unable to parse {}", dir.join("fr.json").display())), "{}", error);
+    std::env::remove_var("LOCALES_DIR");
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert!(ContactFormService::builder().provider(MemoryProvider::new()).build().await.is_ok());
+}
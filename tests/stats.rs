@@ -0,0 +1,39 @@
+//! Counting what happens to submissions, for `GET /stats`
+
+mod common;
+
+use axum::body::Body;
+use axum::http::{header, Request, StatusCode};
+use mailgun_contact_form::{ContactFormService, MemoryProvider};
+use common::{call, post_form};
+
+const STATS_TOKEN: &str = "stats-token";
+
+#[tokio::test]
+async fn counts_submissions_by_outcome_and_form() {
+    std::env::set_var("MAILGUN_TO_ADDRESS", "owner@example.com");
+    std::env::set_var("STATS_TOKEN", STATS_TOKEN);
+    let app = ContactFormService::builder().provider(MemoryProvider::new()).build().await.unwrap().router();
+
+    let (status, _) = call(&app, post_form("from_name=Jo+Bloggs&from_email=jo%40example.com&title=Hello&body=Hi&_form=contact")).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = call(&app, post_form("from_name=Jo+Bloggs&from_email=jo%40example.com&title=Hello&body=Hi")).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = call(&app, post_form("from_name=Jo+Bloggs&from_email=not+an+address&_form=contact")).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    let (status, _) = call(&app, Request::get("/stats").body(Body::empty()).unwrap()).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let request = Request::get("/stats")
+        .header(header::AUTHORIZATION, format!("Bearer {}", STATS_TOKEN))
+        .body(Body::empty())
+        .unwrap();
+    let (status, stats) = call(&app, request).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(stats["totals"]["received"], 2);
+    assert_eq!(stats["totals"]["sent"], 2);
+    assert_eq!(stats["totals"]["rejected"], 1);
+    assert_eq!(stats["per_form"]["contact"]["received"], 1);
+    assert_eq!(stats["per_form"]["contact"]["sent"], 1);
+    assert_eq!(stats["per_form"]["contact"]["rejected"], 1);
+}